@@ -0,0 +1,15 @@
+// Build the addon and copy it to `wasm-snip.node`, where `index.js` loads it.
+const { execFileSync } = require("child_process");
+const fs = require("fs");
+const path = require("path");
+
+execFileSync("cargo", ["build", "--release"], { cwd: __dirname, stdio: "inherit" });
+
+const library = {
+  darwin: "libwasm_snip_node.dylib",
+  win32: "wasm_snip_node.dll",
+}[process.platform] || "libwasm_snip_node.so";
+fs.copyFileSync(
+  path.join(__dirname, "target", "release", library),
+  path.join(__dirname, "wasm-snip.node")
+);
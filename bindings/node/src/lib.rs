@@ -0,0 +1,54 @@
+//! Node.js bindings for `wasm-snip`, so that bundler plugins can snip wasm
+//! in-process instead of spawning the executable.
+//!
+//! ```js
+//! const { snip } = require("wasm-snip");
+//! const snipped = snip(fs.readFileSync("input.wasm"), {
+//!   snipRustFmtCode: true,
+//!   patterns: [".*alloc.*"],
+//! });
+//! ```
+
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+
+/// Snip functions from the module, component or object file in `wasm`,
+/// returning the snipped wasm.
+///
+/// `options` are the fields of `wasm_snip::Options`, spelled in camelCase or
+/// in kebab-case as in configuration files.
+#[napi]
+pub fn snip(wasm: Buffer, options: Option<serde_json::Value>) -> napi::Result<Buffer> {
+    let options = match options {
+        Some(serde_json::Value::Object(options)) => options
+            .into_iter()
+            .map(|(key, value)| (kebab_case(&key), value))
+            .collect(),
+        Some(serde_json::Value::Null) | None => serde_json::Map::new(),
+        Some(_) => return Err(napi::Error::from_reason("options must be an object")),
+    };
+    let options: wasm_snip::Options = serde_json::from_value(options.into())
+        .map_err(|e| napi::Error::from_reason(format!("invalid options: {}", e)))?;
+    wasm_snip::snip_bytes(&wasm, &options)
+        .map(Buffer::from)
+        .map_err(|e| napi::Error::from_reason(error_message(&e)))
+}
+
+/// `snipRustFmtCode` as `snip-rust-fmt-code`.
+fn kebab_case(key: &str) -> String {
+    let mut kebab = String::new();
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            kebab.push('-');
+        }
+        kebab.push(c.to_ascii_lowercase());
+    }
+    kebab
+}
+
+fn error_message(e: &wasm_snip::SnipError) -> String {
+    e.chain()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
@@ -0,0 +1,13 @@
+const assert = require("assert");
+const fs = require("fs");
+const path = require("path");
+const { snip } = require(".");
+
+const input = fs.readFileSync(path.join(__dirname, "..", "..", "tests", "hello.wasm"));
+const output = snip(input, { snipRustFmtCode: true });
+assert(Buffer.isBuffer(output));
+assert(output.length < input.length);
+assert(WebAssembly.validate(output));
+
+assert.throws(() => snip(input, { noSuchOption: true }), /invalid options/);
+assert.throws(() => snip(Buffer.from("nope")), /magic number/);
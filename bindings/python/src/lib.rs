@@ -0,0 +1,79 @@
+//! Python bindings for `wasm-snip`, for build orchestration written in
+//! Python.
+//!
+//! ```python
+//! import wasm_snip
+//!
+//! with open("input.wasm", "rb") as f:
+//!     snipped = wasm_snip.snip(f.read(), snip_rust_fmt_code=True, patterns=[".*alloc.*"])
+//! ```
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+
+/// Snip functions from the module, component or object file in `wasm`,
+/// returning the snipped wasm.
+///
+/// The keyword arguments are the fields of `wasm_snip::Options`, like
+/// `snip_rust_fmt_code=True` or `patterns=[".*alloc.*"]`.
+#[pyfunction]
+#[pyo3(signature = (wasm, **options))]
+fn snip<'py>(
+    py: Python<'py>,
+    wasm: &[u8],
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let mut fields = serde_json::Map::new();
+    if let Some(options) = options {
+        for (key, value) in options.iter() {
+            let key: String = key.extract()?;
+            fields.insert(key.replace('_', "-"), to_json(&value)?);
+        }
+    }
+    let options: wasm_snip::Options = serde_json::from_value(fields.into())
+        .map_err(|e| PyValueError::new_err(format!("invalid options: {}", e)))?;
+    let snipped = py
+        .detach(|| wasm_snip::snip_bytes(wasm, &options))
+        .map_err(|e| PyValueError::new_err(error_message(&e)))?;
+    Ok(PyBytes::new(py, &snipped))
+}
+
+/// The JSON form of an option's value, for deserializing `Options` from.
+fn to_json(value: &Bound<'_, PyAny>) -> PyResult<serde_json::Value> {
+    Ok(if value.is_none() {
+        serde_json::Value::Null
+    } else if value.is_instance_of::<PyBool>() {
+        value.extract::<bool>()?.into()
+    } else if value.is_instance_of::<PyInt>() {
+        value.extract::<i64>()?.into()
+    } else if value.is_instance_of::<PyFloat>() {
+        value.extract::<f64>()?.into()
+    } else if value.is_instance_of::<PyString>() {
+        value.extract::<String>()?.into()
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        value
+            .try_iter()?
+            .map(|item| to_json(&item?))
+            .collect::<PyResult<Vec<_>>>()?
+            .into()
+    } else {
+        return Err(PyTypeError::new_err(format!(
+            "unsupported option value: {}",
+            value.repr()?
+        )));
+    })
+}
+
+fn error_message(e: &wasm_snip::SnipError) -> String {
+    e.chain()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+#[pymodule]
+#[pyo3(name = "wasm_snip")]
+fn python(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(snip, module)?)
+}
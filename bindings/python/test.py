@@ -0,0 +1,32 @@
+"""A smoke test of the bindings, run after `maturin develop`."""
+
+import os
+import unittest
+
+import wasm_snip
+
+HELLO = os.path.join(os.path.dirname(__file__), "..", "..", "tests", "hello.wasm")
+
+
+class SnipTest(unittest.TestCase):
+    def setUp(self):
+        with open(HELLO, "rb") as f:
+            self.wasm = f.read()
+
+    def test_snip(self):
+        snipped = wasm_snip.snip(self.wasm, snip_rust_fmt_code=True)
+        self.assertIsInstance(snipped, bytes)
+        self.assertLess(len(snipped), len(self.wasm))
+        self.assertEqual(snipped[:4], b"\0asm")
+
+    def test_invalid_options(self):
+        with self.assertRaisesRegex(ValueError, "invalid options"):
+            wasm_snip.snip(self.wasm, no_such_option=True)
+
+    def test_invalid_wasm(self):
+        with self.assertRaisesRegex(ValueError, "magic number"):
+            wasm_snip.snip(b"nope")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,41 @@
+/* C bindings for wasm-snip, built with
+ * `cargo rustc --release --lib --features capi --crate-type cdylib`. */
+
+#ifndef WASM_SNIP_H
+#define WASM_SNIP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Snip the module, component or object file in the `input_len` bytes at
+ * `input`, with the options in `options_json` as JSON, or the default options
+ * if it is NULL.
+ *
+ * On success, returns 0 and stores the snipped wasm in `*output` and its
+ * length in `*output_len`, to be freed with `wasm_snip_free`. On failure,
+ * returns 1, and `wasm_snip_last_error` describes what went wrong.
+ */
+int wasm_snip_run(const uint8_t *input, size_t input_len,
+                  const char *options_json,
+                  uint8_t **output, size_t *output_len);
+
+/* Free the snipped wasm that `wasm_snip_run` returned. */
+void wasm_snip_free(uint8_t *output, size_t output_len);
+
+/*
+ * The error of the last call to `wasm_snip_run` on this thread that failed,
+ * or NULL if none has. The string lives until the next failing call on the
+ * same thread.
+ */
+const char *wasm_snip_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
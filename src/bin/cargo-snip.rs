@@ -0,0 +1,192 @@
+use serde::Deserialize;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use wasm_snip::config::Config;
+use wasm_snip::{ResultExt, SnipError};
+
+fn main() {
+    if let Err(e) = try_main() {
+        eprintln!("error: {}", e);
+        for c in e.chain().skip(1) {
+            eprintln!("  caused by {}", c);
+        }
+        process::exit(1)
+    }
+}
+
+fn try_main() -> Result<(), SnipError> {
+    let matches = parse_args();
+    let matches = matches.subcommand_matches("snip").unwrap();
+
+    let config = match matches.value_of_os("config") {
+        Some(path) => Config::from_file(path)?,
+        None => {
+            let cwd = env::current_dir().context("failed to get the current directory")?;
+            Config::discover_cargo_manifest(cwd)?.unwrap_or_default()
+        }
+    };
+
+    let target_dir = match matches
+        .value_of_os("target_dir")
+        .map(OsString::from)
+        .or_else(|| env::var_os("CARGO_TARGET_DIR"))
+    {
+        Some(dir) => PathBuf::from(dir),
+        None => cargo_target_dir()?,
+    };
+    let profile = match matches.value_of("profile") {
+        Some("dev") => "debug",
+        Some(profile) => profile,
+        None if matches.is_present("release") => "release",
+        None => "debug",
+    };
+    let artifact_dir = target_dir
+        .join(matches.value_of("target").unwrap())
+        .join(profile);
+
+    let input = find_artifact(&artifact_dir, matches.value_of("artifact"))?;
+    let output = matches
+        .value_of_os("output")
+        .map(PathBuf::from)
+        .or_else(|| config.output.clone())
+        .unwrap_or_else(|| input.with_extension("snipped.wasm"));
+
+    let opts = config.options;
+    let mut module = wasm_snip::parse_module(&wasm_snip::WasmFile::open(&input)?, &opts)?;
+
+    wasm_snip::snip(&mut module, opts.clone())
+        .context("failed to snip functions from wasm module")?;
+
+    let wasm = wasm_snip::emit_wasm(&module, &opts)?;
+    fs::write(&output, wasm)
+        .with_context(|_| format!("failed to emit snipped wasm to {}", output.display()))?;
+    eprintln!("snipped {} into {}", input.display(), output.display());
+
+    Ok(())
+}
+
+/// Ask `cargo metadata` where the current workspace's target directory is.
+fn cargo_target_dir() -> Result<PathBuf, SnipError> {
+    #[derive(Deserialize)]
+    struct Metadata {
+        target_directory: PathBuf,
+    }
+
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = Command::new(cargo)
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .output()
+        .context("failed to run `cargo metadata`")?;
+    if !output.status.success() {
+        return Err(SnipError::Io(io::Error::other(format!(
+            "`cargo metadata` failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ))));
+    }
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)
+        .context("failed to parse `cargo metadata` output")?;
+    Ok(metadata.target_directory)
+}
+
+/// Find the wasm artifact to snip in `dir`: the one with the given name if
+/// there is one, otherwise the most recently built one.
+fn find_artifact(dir: &Path, name: Option<&str>) -> Result<PathBuf, SnipError> {
+    let entries = fs::read_dir(dir)
+        .with_context(|_| format!("failed to read directory {}", dir.display()))?;
+
+    let mut candidates = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        let file_name = match path.file_name().and_then(|f| f.to_str()) {
+            Some(f) => f,
+            None => continue,
+        };
+        if !file_name.ends_with(".wasm") || file_name.ends_with(".snipped.wasm") {
+            continue;
+        }
+        let stem = &file_name[..file_name.len() - ".wasm".len()];
+        if let Some(name) = name {
+            if stem != name && stem != name.replace('-', "_") {
+                continue;
+            }
+        }
+        let modified = fs::metadata(&path)?.modified()?;
+        candidates.push((modified, path));
+    }
+
+    match candidates.into_iter().max() {
+        Some((_, path)) => Ok(path),
+        None => Err(SnipError::NoMatches(format!(
+            "no wasm artifact found in {}; build one with `cargo build --target \
+             wasm32-unknown-unknown` first",
+            dir.display()
+        ))),
+    }
+}
+
+fn parse_args() -> clap::ArgMatches<'static> {
+    clap::App::new("cargo-snip")
+        .bin_name("cargo")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .setting(clap::AppSettings::SubcommandRequired)
+        .subcommand(
+            clap::SubCommand::with_name("snip")
+                .about(
+                    "Snip functions from the wasm artifact built by `cargo build`, using the \
+                     settings in the `[package.metadata.wasm-snip]` table of Cargo.toml.",
+                )
+                .arg(clap::Arg::with_name("artifact").help(
+                    "The name of the wasm artifact to snip. Defaults to the most recently \
+                     built one.",
+                ))
+                .arg(
+                    clap::Arg::with_name("release")
+                        .long("release")
+                        .help("Snip the artifact built with the release profile."),
+                )
+                .arg(
+                    clap::Arg::with_name("profile")
+                        .long("profile")
+                        .takes_value(true)
+                        .conflicts_with("release")
+                        .help("Snip the artifact built with the given profile."),
+                )
+                .arg(
+                    clap::Arg::with_name("target")
+                        .long("target")
+                        .takes_value(true)
+                        .default_value("wasm32-unknown-unknown")
+                        .help("The target triple the artifact was built for."),
+                )
+                .arg(
+                    clap::Arg::with_name("target_dir")
+                        .long("target-dir")
+                        .takes_value(true)
+                        .help("The cargo target directory. Defaults to asking `cargo metadata`."),
+                )
+                .arg(
+                    clap::Arg::with_name("config")
+                        .short("c")
+                        .long("config")
+                        .takes_value(true)
+                        .help("Read snip settings from the given TOML file instead of Cargo.toml."),
+                )
+                .arg(
+                    clap::Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help(
+                            "The path to write the output wasm file to. Defaults to \
+                             `<artifact>.snipped.wasm` next to the artifact.",
+                        ),
+                ),
+        )
+        .get_matches()
+}
@@ -1,33 +1,100 @@
-use failure::ResultExt;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use wasm_snip::{ResultExt, SnipError};
 
 fn main() {
     if let Err(e) = try_main() {
         eprintln!("error: {}", e);
-        for c in e.iter_chain().skip(1) {
+        for c in e.chain().skip(1) {
             eprintln!("  caused by {}", c);
         }
-        eprintln!("{}", e.backtrace());
         process::exit(1)
     }
 }
 
-fn try_main() -> Result<(), failure::Error> {
+fn try_main() -> Result<(), SnipError> {
     let matches = parse_args();
+    init_logger(&matches);
+    if let Some(matches) = matches.subcommand_matches("list") {
+        return list_functions(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("why") {
+        return why(matches);
+    }
+
+    let config = if let Some(path) = matches.value_of_os("config") {
+        wasm_snip::config::Config::from_file(path)?
+    } else if matches.is_present("no_cargo_metadata") {
+        wasm_snip::config::Config::default()
+    } else {
+        let cwd = env::current_dir().context("failed to get the current directory")?;
+        wasm_snip::config::Config::discover_cargo_manifest(cwd)?.unwrap_or_default()
+    };
+    let output = matches
+        .value_of_os("output")
+        .map(PathBuf::from)
+        .or_else(|| config.output.clone());
+
+    // Flags given on the command line take precedence over the config file,
+    // and lists given on the command line are added to the config's lists.
+    let mut opts = config.options;
+    opts.functions.extend(values_of(&matches, "function"));
+    opts.patterns.extend(values_of(&matches, "pattern"));
+    opts.strip_custom_sections
+        .extend(values_of(&matches, "strip_custom_section"));
+    for index in values_of(&matches, "index") {
+        opts.indices.push(index.parse().map_err(|_| {
+            SnipError::InvalidOptions(format!("invalid function index `{}`", index))
+        })?);
+    }
+    for range in values_of(&matches, "index_range") {
+        opts.index_ranges.push(parse_index_range(&range)?);
+    }
+    opts.functions_files
+        .extend(paths_of(&matches, "functions_from_file"));
+    opts.patterns_files
+        .extend(paths_of(&matches, "patterns_from_file"));
+    opts.symbols_files.extend(paths_of(&matches, "symbols"));
+    opts.exclude_patterns
+        .extend(values_of(&matches, "exclude_pattern"));
+    opts.keep_functions
+        .extend(values_of(&matches, "keep_function"));
+    opts.keep_patterns
+        .extend(values_of(&matches, "keep_pattern"));
+    opts.snip_crates.extend(values_of(&matches, "snip_crate"));
+    opts.snip_imports.extend(values_of(&matches, "snip_import"));
+    opts.snip_import_patterns
+        .extend(values_of(&matches, "snip_import_pattern"));
+    opts.signatures.extend(values_of(&matches, "signature"));
+    opts.presets.extend(values_of(&matches, "preset"));
+    opts.preset_files.extend(paths_of(&matches, "preset_file"));
+
+    if matches.is_present("list_presets") {
+        return list_presets(&opts.preset_files);
+    }
+
+    if matches.is_present("ignore_case") {
+        opts.ignore_case = true;
+    }
 
-    let mut opts = wasm_snip::Options::default();
+    if matches.is_present("demangle") {
+        opts.demangle = true;
+    }
 
-    opts.functions = matches
-        .values_of("function")
-        .map(|fs| fs.map(|f| f.to_string()).collect())
-        .unwrap_or(vec![]);
+    if matches.is_present("demangle_cpp") {
+        opts.demangle_cpp = true;
+    }
 
-    opts.patterns = matches
-        .values_of("pattern")
-        .map(|ps| ps.map(|p| p.to_string()).collect())
-        .unwrap_or(vec![]);
+    if matches.is_present("ignore_hash") {
+        opts.ignore_hash = true;
+    }
 
     if matches.is_present("snip_rust_fmt_code") {
         opts.snip_rust_fmt_code = true;
@@ -37,37 +104,1053 @@ fn try_main() -> Result<(), failure::Error> {
         opts.snip_rust_panicking_code = true;
     }
 
+    if matches.is_present("snip_rust_float_fmt") {
+        opts.snip_rust_float_fmt = true;
+    }
+
+    if matches.is_present("snip_rust_unicode") {
+        opts.snip_rust_unicode = true;
+    }
+
+    if matches.is_present("snip_rust_backtrace") {
+        opts.snip_rust_backtrace = true;
+    }
+
+    if matches.is_present("snip_rust_alloc_error") {
+        opts.snip_rust_alloc_error = true;
+    }
+
+    if matches.is_present("snip_wasm_bindgen_internals") {
+        opts.snip_wasm_bindgen_internals = true;
+    }
+
+    if matches.is_present("snip_tinygo_prints") {
+        opts.snip_tinygo_prints = true;
+    }
+
+    if matches.is_present("snip_tinygo_reflect") {
+        opts.snip_tinygo_reflect = true;
+    }
+
+    if matches.is_present("snip_assemblyscript_abort") {
+        opts.snip_assemblyscript_abort = true;
+    }
+
+    if matches.is_present("snip_emscripten_syscalls") {
+        opts.snip_emscripten_syscalls = true;
+    }
+
+    if matches.is_present("snip_emscripten_exceptions") {
+        opts.snip_emscripten_exceptions = true;
+    }
+
+    if matches.is_present("snip_emscripten_sbrk") {
+        opts.snip_emscripten_sbrk = true;
+    }
+
+    if let Some(strategy) = matches.value_of("replace_with") {
+        opts.strategy = strategy.parse()?;
+    }
+
+    if let Some(features) = matches.value_of("target_features") {
+        opts.target_features = features.parse()?;
+    }
+
+    if let Some(path) = matches.value_of_os("replace_body") {
+        let path = PathBuf::from(path);
+        let wat = fs::read_to_string(&path)
+            .with_context(|_| format!("failed to read file {}", path.display()))?;
+        opts.replacement_body = Some(wat);
+    }
+
+    if let Some(min_size) = matches.value_of("min_size") {
+        opts.min_size = Some(parse_size(min_size)?);
+    }
+
+    if matches.is_present("stub_exports") {
+        opts.stub_exports = true;
+    }
+
+    opts.stub_functions
+        .extend(values_of(&matches, "stub_function"));
+
+    if matches.is_present("table_null") {
+        opts.table_null = true;
+    }
+
+    if matches.is_present("shrink_tables") {
+        opts.shrink_tables = true;
+    }
+
+    if matches.is_present("gc_data") {
+        opts.gc_data = true;
+    }
+
+    if matches.is_present("snip_panic_strings") {
+        opts.snip_panic_strings = true;
+    }
+
+    if matches.is_present("shrink_memory") {
+        opts.shrink_memory = true;
+    }
+
+    if matches.is_present("keep_imports") {
+        opts.keep_imports = true;
+    }
+
+    if matches.is_present("preserve_indices") {
+        opts.preserve_indices = true;
+    }
+
+    if matches.is_present("snip_start") {
+        opts.snip_start = true;
+    }
+
+    if matches.is_present("force") {
+        opts.force = true;
+    }
+
+    if matches.is_present("warn_reachable") {
+        opts.warn_reachable = true;
+    }
+
+    if matches.is_present("strict") {
+        opts.strict = true;
+    }
+
+    if matches.is_present("no_gc") {
+        opts.skip_gc = true;
+    }
+
+    if matches.is_present("name_stubs") {
+        opts.name_stubs = true;
+    }
+
+    if matches.is_present("strip_names") {
+        opts.strip_names = true;
+    }
+
+    if matches.is_present("keep_export_names") {
+        opts.keep_export_names = true;
+    }
+
+    if matches.is_present("strip_local_names") {
+        opts.strip_local_names = true;
+    }
+
+    if matches.is_present("strip_dwarf") {
+        opts.strip_dwarf = true;
+    }
+
+    if matches.is_present("update_dwarf") {
+        opts.update_dwarf = true;
+    }
+
+    if matches.is_present("update_source_map") {
+        opts.update_source_map = true;
+    }
+
     if matches.is_present("skip_producers_section") {
         opts.skip_producers_section = true;
     }
 
-    let config = walrus_config_from_options(&opts);
+    // Names may be stripped by the config rather than by `--strip-names`.
+    if opts.keep_export_names && !opts.strip_names {
+        return Err(SnipError::InvalidOptions(
+            "keeping export names only makes sense when stripping names".to_string(),
+        ));
+    }
+
+    if let Some(threads) = matches.value_of("threads") {
+        match threads.parse() {
+            Ok(threads) if threads > 0 => opts.threads = Some(threads),
+            _ => {
+                return Err(SnipError::InvalidOptions(format!(
+                    "invalid number of threads `{}`",
+                    threads
+                )))
+            }
+        }
+    }
+    if let Some(threads) = opts.threads {
+        // Size the global thread pool instead of letting each step start a
+        // pool of its own.
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(io::Error::other)
+            .with_context(|_| format!("failed to start {} threads", threads))?;
+    }
+
+    let max_output_size = matches
+        .value_of("max_output_size")
+        .map(parse_size)
+        .transpose()?;
+
     let path = matches.value_of("input").unwrap();
-    let buf = fs::read(&path).with_context(|_| format!("failed to read file {}", path))?;
-    let mut module = config.parse(&buf)?;
+    let buf = read_input(path)?;
+    if wasmparser::Parser::is_component(&buf) {
+        let snip = wasm_snip::snip_component;
+        return snip_whole(&matches, buf, &opts, output.as_deref(), "component", snip);
+    }
+    if wasm_snip::is_object_file(&buf) {
+        let snip = wasm_snip::snip_object_file;
+        return snip_whole(&matches, buf, &opts, output.as_deref(), "object file", snip);
+    }
+    if let Some(proposal) = wasm_snip::unsupported_proposal(&buf) {
+        let kind = format!("module that uses the {} proposal", proposal);
+        let snip = wasm_snip::snip_in_place;
+        return snip_whole(&matches, buf, &opts, output.as_deref(), &kind, snip);
+    }
+    let progress = Progress::new(
+        !matches.is_present("no_progress")
+            && matches.occurrences_of("verbose") == 0
+            && io::stderr().is_terminal(),
+    );
+    let started = Instant::now();
+    let mut module = wasm_snip::parse_module(&buf, &opts)?;
+    let parse_time = started.elapsed();
+    if let Some(names) = matches.values_of("simulate") {
+        progress.finish();
+        return simulate(&module, &names.collect::<Vec<_>>());
+    }
+    if matches.is_present("suggest") {
+        progress.finish();
+        return suggest(&module);
+    }
+
+    let original: Vec<_> = module
+        .funcs
+        .iter()
+        .map(|f| (f.id(), f.name.clone()))
+        .collect();
+    let graph = if matches.is_present("emit_removed_graph") || matches.is_present("report") {
+        Some(wasm_snip::callgraph::CallGraph::new(&module))
+    } else {
+        None
+    };
+
+    let mut pipeline = wasm_snip::pipeline::Pipeline::new();
+    pipeline.insert_before("select", progress.pass(Phase::Match))?;
+    pipeline.insert_before("replace", progress.pass(Phase::Rewrite))?;
+    pipeline.insert_before("gc", progress.pass(Phase::Gc))?;
+    let outcome = pipeline
+        .run(&mut module, opts.clone())
+        .context("failed to snip functions from wasm module")?;
+
+    let source_map = match (opts.update_source_map, &output) {
+        (true, Some(output)) => Some(replace_source_map_url(&mut module, path.as_ref(), output)?),
+        (true, None) => {
+            return Err(SnipError::InvalidOptions(
+                "--update-source-map requires --output".to_string(),
+            ))
+        }
+        (false, _) => None,
+    };
+
+    progress.phase(Phase::Emit);
+    let started = Instant::now();
+    let wasm = wasm_snip::emit_wasm(&module, &opts)?;
+    let emit_time = started.elapsed();
+    progress.finish();
+    if let Some((input_map, output_map)) = source_map {
+        let map = fs::read_to_string(&input_map)
+            .with_context(|_| format!("failed to read source map {}", input_map.display()))?;
+        let map = wasm_snip::update_source_map(&module, &opts, &wasm, &map)?;
+        fs::write(&output_map, map)
+            .with_context(|_| format!("failed to write source map {}", output_map.display()))?;
+    }
+    if let (Some(path), Some(graph)) = (matches.value_of_os("emit_removed_graph"), &graph) {
+        write_removed_graph(path.as_ref(), &original, graph, &module)?;
+    }
+    if let (true, Some(graph)) = (matches.is_present("report"), &graph) {
+        print_report(&original, graph, &outcome, &module);
+    }
+    if let Some(path) = matches.value_of_os("emit_remap") {
+        let indices = wasm_snip::emitted_function_indices(&module, &opts);
+        write_remap(path.as_ref(), &original, &indices)?;
+    }
+    if matches.is_present("stats") {
+        print_stats(&buf, &wasm)?;
+    }
+    // Close the input first, in case the output replaces it.
+    drop(buf);
+    if matches.is_present("timings") {
+        print_timings(parse_time, &outcome.timings, emit_time);
+    }
+    // Check the size first, so that an oversized module isn't written.
+    if let Some(max) = max_output_size {
+        if wasm.len() as u64 > max {
+            return Err(SnipError::Refused(format!(
+                "the snipped module is {} bytes, which is over the limit of {} bytes",
+                wasm.len(),
+                max
+            )));
+        }
+    }
+    write_output(output.as_deref(), &wasm)?;
+    if let Some(path) = matches.value_of_os("baseline") {
+        compare_to_baseline(path.as_ref(), &wasm, opts.strict)?;
+    }
+
+    Ok(())
+}
+
+/// The phases of snipping a module that `Progress` shows.
+#[derive(Clone, Copy, Debug)]
+enum Phase {
+    Parse,
+    Match,
+    Rewrite,
+    Gc,
+    Emit,
+}
+
+impl Phase {
+    fn describe(self) -> &'static str {
+        match self {
+            Phase::Parse => "parsing",
+            Phase::Match => "matching functions",
+            Phase::Rewrite => "rewriting calls",
+            Phase::Gc => "removing unused items",
+            Phase::Emit => "emitting",
+        }
+    }
+}
+
+/// A line on stderr showing which phase snipping is in, so that modules
+/// taking a while to snip don't leave the terminal silent.
+///
+/// The line is only drawn once snipping has taken half a second, so that small
+/// modules snip without it flickering by, and it is redrawn with the time
+/// taken so far until `finish` clears it.
+struct Progress {
+    shared: Option<Arc<(Mutex<ProgressState>, Condvar)>>,
+}
+
+struct ProgressState {
+    phase: Phase,
+    started: Instant,
+    drawn: bool,
+    finished: bool,
+}
+
+impl Progress {
+    /// Start showing progress, if `enabled`.
+    fn new(enabled: bool) -> Progress {
+        if !enabled {
+            return Progress { shared: None };
+        }
+        let shared = Arc::new((
+            Mutex::new(ProgressState {
+                phase: Phase::Parse,
+                started: Instant::now(),
+                drawn: false,
+                finished: false,
+            }),
+            Condvar::new(),
+        ));
+        let drawing = shared.clone();
+        thread::spawn(move || {
+            let (state, finished) = &*drawing;
+            let mut state = state.lock().unwrap();
+            while !state.finished {
+                let elapsed = state.started.elapsed();
+                if elapsed >= Duration::from_millis(500) {
+                    eprint!(
+                        "\r\x1b[K[{}/5] {}... {:.1}s",
+                        state.phase as usize + 1,
+                        state.phase.describe(),
+                        elapsed.as_secs_f64()
+                    );
+                    state.drawn = true;
+                }
+                state = finished
+                    .wait_timeout(state, Duration::from_millis(100))
+                    .unwrap()
+                    .0;
+            }
+        });
+        Progress {
+            shared: Some(shared),
+        }
+    }
+
+    fn phase(&self, phase: Phase) {
+        if let Some(shared) = &self.shared {
+            shared.0.lock().unwrap().phase = phase;
+        }
+    }
+
+    /// A pass that moves on to `phase`, to insert into the pipeline.
+    fn pass(&self, phase: Phase) -> ProgressPass {
+        ProgressPass(self.shared.clone(), phase)
+    }
+
+    /// Stop showing progress, clearing the line if it was drawn.
+    fn finish(&self) {
+        if let Some(shared) = &self.shared {
+            let mut state = shared.0.lock().unwrap();
+            if !state.finished && state.drawn {
+                eprint!("\r\x1b[K");
+            }
+            state.finished = true;
+            shared.1.notify_all();
+        }
+    }
+}
 
-    wasm_snip::snip(&mut module, opts).context("failed to snip functions from wasm module")?;
+/// A pass that moves the progress line on to the next phase.
+struct ProgressPass(Option<Arc<(Mutex<ProgressState>, Condvar)>>, Phase);
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // Clear the line before an error is printed, too.
+        self.finish();
+    }
+}
+
+impl wasm_snip::pipeline::SnipPass for ProgressPass {
+    fn name(&self) -> &str {
+        "progress"
+    }
+
+    fn run(
+        &mut self,
+        _module: &mut walrus::Module,
+        _context: &mut wasm_snip::pipeline::SnipContext,
+    ) -> Result<(), SnipError> {
+        if let Some(shared) = &self.0 {
+            shared.0.lock().unwrap().phase = self.1;
+        }
+        Ok(())
+    }
+}
+
+/// Log to stderr at the level that `-v` asks for.
+fn init_logger(matches: &clap::ArgMatches) {
+    let verbose = match matches.subcommand() {
+        (_, Some(matches)) => matches.occurrences_of("verbose"),
+        _ => matches.occurrences_of("verbose"),
+    };
+    // Past `-vv`, also log what walrus does.
+    let (level, dependencies) = match verbose {
+        0 => (log::LevelFilter::Warn, log::LevelFilter::Warn),
+        1 => (log::LevelFilter::Info, log::LevelFilter::Warn),
+        2 => (log::LevelFilter::Debug, log::LevelFilter::Warn),
+        _ => (log::LevelFilter::Trace, log::LevelFilter::Debug),
+    };
+    env_logger::Builder::new()
+        .filter_level(dependencies)
+        .filter_module("wasm_snip", level)
+        .format_timestamp(None)
+        .parse_default_env()
+        .init();
+}
+
+/// Snip the component, object file or module that walrus can't parse read
+/// from `buf` with `snip`, for inputs that aren't parsed as a single module.
+fn snip_whole(
+    matches: &clap::ArgMatches,
+    buf: wasm_snip::WasmFile,
+    opts: &wasm_snip::Options,
+    output: Option<&Path>,
+    kind: &str,
+    snip: fn(&[u8], &wasm_snip::Options) -> Result<Vec<u8>, SnipError>,
+) -> Result<(), SnipError> {
+    // These look at a single module.
+    for flag in &[
+        "simulate",
+        "suggest",
+        "report",
+        "emit_removed_graph",
+        "emit_remap",
+        "update_source_map",
+        "stats",
+        "timings",
+        "baseline",
+        "max_output_size",
+    ] {
+        if matches.is_present(flag) {
+            return Err(SnipError::Unsupported {
+                message: format!(
+                    "--{} isn't supported for a {}",
+                    flag.replace('_', "-"),
+                    kind
+                ),
+                source: None,
+            });
+        }
+    }
+    let wasm = snip(&buf, opts)
+        .with_context(|_| format!("failed to snip functions from wasm {}", kind))?;
+    // Close the input first, in case the output replaces it.
+    drop(buf);
+    write_output(output, &wasm)
+}
 
-    if let Some(output) = matches.value_of("output") {
-        module
-            .emit_wasm_file(output)
-            .with_context(|_| format!("failed to emit snipped wasm to {}", output))?;
+/// Read the input wasm file at `path`, or stdin if `path` is `-`.
+fn read_input(path: &str) -> Result<wasm_snip::WasmFile, SnipError> {
+    if path == "-" {
+        let mut buf = vec![];
+        io::stdin()
+            .lock()
+            .read_to_end(&mut buf)
+            .context("failed to read wasm from stdin")?;
+        Ok(buf.into())
+    } else {
+        wasm_snip::WasmFile::open(path)
+    }
+}
+
+/// Write the snipped `wasm` to `output`, or to stdout if there's no output.
+fn write_output(output: Option<&Path>, wasm: &[u8]) -> Result<(), SnipError> {
+    if let Some(output) = output {
+        fs::write(output, wasm)
+            .with_context(|_| format!("failed to emit snipped wasm to {}", output.display()))?;
     } else {
-        let wasm = module.emit_wasm();
         let stdout = io::stdout();
         let mut stdout = stdout.lock();
         stdout
-            .write_all(&wasm)
+            .write_all(wasm)
             .context("failed to write wasm to stdout")?;
     }
+    Ok(())
+}
+
+/// Point the `sourceMappingURL` section of the module read from `input` at a
+/// source map next to `output`, returning the paths of the input's source map
+/// and the output's.
+fn replace_source_map_url(
+    module: &mut walrus::Module,
+    input: &Path,
+    output: &Path,
+) -> Result<(PathBuf, PathBuf), SnipError> {
+    let section = match module.customs.remove_raw("sourceMappingURL") {
+        Some(section) => section,
+        None => {
+            return Err(SnipError::Parse(format!(
+                "{} has no `sourceMappingURL` section",
+                input.display()
+            )))
+        }
+    };
+    let url = wasmparser::BinaryReader::new(&section.data, 0)
+        .read_string()
+        .context("failed to parse `sourceMappingURL` section")?;
+    if url.contains("://") {
+        return Err(SnipError::Unsupported {
+            message: format!(
+                "can't update the source map at {}, which isn't a local file",
+                url
+            ),
+            source: None,
+        });
+    }
+    let input_map = input.parent().unwrap_or_else(|| Path::new("")).join(url);
+
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".map");
+    let output_map = output.with_file_name(&name);
+    let mut data = vec![];
+    wasm_encoder::Encode::encode(&*name.to_string_lossy(), &mut data);
+    module.customs.add(walrus::RawCustomSection {
+        name: "sourceMappingURL".to_string(),
+        data,
+    });
+    Ok((input_map, output_map))
+}
+
+fn list_presets(preset_files: &[PathBuf]) -> Result<(), SnipError> {
+    let mut presets = wasm_snip::presets::builtin();
+    for path in preset_files {
+        presets.extend(wasm_snip::presets::from_file(path)?);
+    }
+    let width = presets.iter().map(|p| p.name.len()).max().unwrap_or(0);
+    for preset in presets {
+        println!(
+            "{:width$}    {}",
+            preset.name,
+            preset.description,
+            width = width
+        );
+    }
+    Ok(())
+}
+
+/// Parse a module as walrus does by default, for looking at it rather than
+/// snipping it.
+fn parse_wasm(wasm: &[u8]) -> Result<walrus::Module, SnipError> {
+    walrus::Module::from_buffer(wasm).map_err(|e| {
+        let causes: Vec<_> = e.iter_chain().map(|c| c.to_string()).collect();
+        SnipError::Parse(causes.join(": "))
+    })
+}
+
+/// Print every function in the input with its index and size, from largest to
+/// smallest.
+fn list_functions(matches: &clap::ArgMatches) -> Result<(), SnipError> {
+    let path = matches.value_of("input").unwrap();
+    let buf = read_input(path)?;
+    let module = parse_wasm(&buf)?;
+    let filter = match matches.value_of("filter") {
+        Some(filter) => Some(regex::Regex::new(filter).context("failed to compile filter regex")?),
+        None => None,
+    };
+
+    let sizes = wasm_snip::function_sizes(&module)?;
+    let retained = if matches.is_present("retained") {
+        Some(wasm_snip::retained_sizes(&module)?)
+    } else {
+        None
+    };
+    let mut functions: Vec<_> = module
+        .funcs
+        .iter()
+        .map(|f| {
+            let name = match f.name {
+                Some(ref name) if matches.is_present("demangle") => {
+                    rustc_demangle::demangle(name).to_string()
+                }
+                Some(ref name) => name.clone(),
+                None => String::new(),
+            };
+            (
+                f.id().index(),
+                sizes.get(&f.id()).cloned().unwrap_or(0),
+                retained.as_ref().map(|retained| retained[&f.id()]),
+                name,
+            )
+        })
+        .filter(|(_, _, _, name)| filter.as_ref().is_none_or(|filter| filter.is_match(name)))
+        .collect();
+    functions.sort_by_key(|&(index, size, retained, _)| {
+        (std::cmp::Reverse(retained.unwrap_or(size)), index)
+    });
+
+    if matches.is_present("json") {
+        let functions: Vec<_> = functions
+            .into_iter()
+            .map(|(index, size, retained, name)| {
+                let mut function =
+                    serde_json::json!({ "index": index, "size": size, "name": name });
+                if let Some(retained) = retained {
+                    function["retained"] = retained.into();
+                }
+                function
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&functions)?);
+        return Ok(());
+    }
+    if retained.is_some() {
+        println!("{:>7} {:>9} {:>9}  name", "index", "size", "retained");
+    } else {
+        println!("{:>7} {:>9}  name", "index", "size");
+    }
+    for (index, size, retained, name) in functions {
+        match retained {
+            Some(retained) => println!("{:>7} {:>9} {:>9}  {}", index, size, retained, name),
+            None => println!("{:>7} {:>9}  {}", index, size, name),
+        }
+    }
+    Ok(())
+}
+
+/// Print the chain of calls that keeps a function alive.
+fn why(matches: &clap::ArgMatches) -> Result<(), SnipError> {
+    let path = matches.value_of("input").unwrap();
+    let buf = read_input(path)?;
+    let module = parse_wasm(&buf)?;
+    let function_by_name = |name: &str| match module.funcs.by_name(name) {
+        Some(f) => Ok(f),
+        None => Err(SnipError::NoMatches(format!(
+            "no function named `{}`",
+            name
+        ))),
+    };
+
+    let name = matches.value_of("function").unwrap();
+    let f = function_by_name(name)?;
+    let mut to_snip = HashSet::new();
+    for name in matches.values_of("snip").into_iter().flatten() {
+        to_snip.insert(function_by_name(name)?);
+    }
+
+    let path = match wasm_snip::retention_path(&module, f, &to_snip) {
+        Some(path) => path,
+        None => {
+            println!("`{}` is not kept alive by anything", name);
+            return Ok(());
+        }
+    };
+    let root = path[0];
+    let root = match module
+        .exports
+        .iter()
+        .find(|e| matches!(e.item, walrus::ExportItem::Function(f) if f == root))
+    {
+        Some(export) => format!("the export `{}`", export.name),
+        None if module.start == Some(root) => "the start function".to_string(),
+        None => "an exported table".to_string(),
+    };
+    println!("`{}` is kept alive by {}:", name, root);
+    for f in path {
+        println!(
+            "    {}",
+            module.funcs.get(f).name.as_deref().unwrap_or("<unnamed>")
+        );
+    }
+    Ok(())
+}
+
+/// Print which functions snipping the named functions would remove.
+fn simulate(module: &walrus::Module, names: &[&str]) -> Result<(), SnipError> {
+    let mut to_snip = HashSet::new();
+    for name in names {
+        match module.funcs.by_name(name) {
+            Some(f) => to_snip.insert(f),
+            None => {
+                return Err(SnipError::NoMatches(format!(
+                    "no function named `{}`",
+                    name
+                )))
+            }
+        };
+    }
+
+    let removal = wasm_snip::simulate_snip(module, &to_snip)?;
+    println!(
+        "snipping would remove {} functions, {} bytes of code:",
+        removal.functions.len(),
+        removal.size
+    );
+    let width = removal.size.to_string().len();
+    for (f, size) in removal.functions {
+        let name = module.funcs.get(f).name.as_deref().unwrap_or("<unnamed>");
+        println!("    {:>width$}  {}", size, name, width = width);
+    }
+    Ok(())
+}
+
+/// Print the snips that `wasm_snip::suggest` suggests, with how much code each
+/// would remove.
+fn suggest(module: &walrus::Module) -> Result<(), SnipError> {
+    let suggestions = wasm_snip::suggest::suggest(module)?;
+    if suggestions.is_empty() {
+        println!("no suggestions");
+        return Ok(());
+    }
+    let width = suggestions
+        .iter()
+        .map(|s| s.size.to_string().len())
+        .max()
+        .unwrap();
+    let name = |f| {
+        let name = module.funcs.get(f).name.as_deref().unwrap_or("<unnamed>");
+        rustc_demangle::demangle(name).to_string()
+    };
+    for suggestion in suggestions {
+        let what = match suggestion.reason {
+            wasm_snip::suggest::Reason::Preset(preset) => format!("--preset {}", preset),
+            wasm_snip::suggest::Reason::AlwaysTraps(f) => {
+                format!("{}  (always traps)", name(f))
+            }
+            wasm_snip::suggest::Reason::LargeLeaf(f) => {
+                format!("{}  (large leaf function)", name(f))
+            }
+        };
+        println!("{:>width$} bytes  {}", suggestion.size, what, width = width);
+    }
+    Ok(())
+}
+
+/// Write a Graphviz graph of the functions that snipping removed, with an edge
+/// for each call between them.
+fn write_removed_graph(
+    path: &Path,
+    original: &[(walrus::FunctionId, Option<String>)],
+    graph: &wasm_snip::callgraph::CallGraph,
+    module: &walrus::Module,
+) -> Result<(), SnipError> {
+    let kept: HashSet<_> = module.funcs.iter().map(|f| f.id()).collect();
+    let removed: HashSet<_> = original
+        .iter()
+        .map(|&(id, _)| id)
+        .filter(|id| !kept.contains(id))
+        .collect();
+
+    let mut dot = String::from("digraph removed {\n");
+    for (id, name) in original.iter().filter(|(id, _)| removed.contains(id)) {
+        let label = match name {
+            Some(name) => rustc_demangle::demangle(name).to_string(),
+            None => format!("function {}", id.index()),
+        };
+        dot.push_str(&format!("    f{} [label={:?}];\n", id.index(), label));
+    }
+    for &(id, _) in original.iter().filter(|(id, _)| removed.contains(id)) {
+        for callee in graph.callees(id).iter().filter(|f| removed.contains(f)) {
+            dot.push_str(&format!("    f{} -> f{};\n", id.index(), callee.index()));
+        }
+    }
+    dot.push_str("}\n");
+    fs::write(path, dot).with_context(|_| format!("failed to write graph {}", path.display()))?;
+    Ok(())
+}
+
+/// Print each snipped function to stderr, along with the functions that were
+/// removed because snipping it left them unused, and then what else snipping
+/// changed.
+fn print_report(
+    original: &[(walrus::FunctionId, Option<String>)],
+    graph: &wasm_snip::callgraph::CallGraph,
+    outcome: &wasm_snip::SnipOutcome,
+    module: &walrus::Module,
+) {
+    let to_snip: HashSet<_> = outcome.functions.iter().map(|&(f, _)| f).collect();
+    let kept: HashSet<_> = module.funcs.iter().map(|f| f.id()).collect();
+    let removed: HashSet<_> = original
+        .iter()
+        .map(|&(id, _)| id)
+        .filter(|id| !kept.contains(id))
+        .collect();
+    let names: HashMap<_, _> = original.iter().cloned().collect();
+    let name = |f: &walrus::FunctionId| match names[f] {
+        Some(ref name) => format!("`{}`", name),
+        None => format!("function {}", f.index()),
+    };
+
+    for (snipped, group) in wasm_snip::attribute_removals(graph, &to_snip, &removed) {
+        match snipped {
+            Some(f) if group.is_empty() => eprintln!("snipped {}", name(&f)),
+            Some(f) => eprintln!(
+                "snipped {}, which removed {}:",
+                name(&f),
+                count(group.len(), "more function")
+            ),
+            None => eprintln!(
+                "also removed {} that nothing used:",
+                count(group.len(), "function")
+            ),
+        }
+        for f in &group {
+            eprintln!("    {}", name(f));
+        }
+    }
+    eprintln!(
+        "replaced {} with `unreachable`, pointed {} at stubs, and removed {} and {}",
+        count(outcome.call_sites, "call"),
+        count(outcome.table_slots, "table element"),
+        count(outcome.removed_exports.len(), "export"),
+        count(outcome.removed_imports.len(), "import"),
+    );
+}
+
+/// Print the sizes of the input and output binaries and of their sections to
+/// stderr.
+fn print_stats(input: &[u8], output: &[u8]) -> Result<(), SnipError> {
+    let before = wasm_snip::SectionSizes::of(input)?;
+    let after = wasm_snip::SectionSizes::of(output)?;
+    let rows = [
+        ("total", before.total, after.total),
+        ("code", before.code, after.code),
+        ("data", before.data, after.data),
+        ("names", before.names, after.names),
+        ("custom", before.custom, after.custom),
+    ];
+    let width = before.total.max(after.total).to_string().len();
+    eprintln!(
+        "{:6}  {:>width$}  {:>width$}  delta",
+        "",
+        "before",
+        "after",
+        width = width.max(6)
+    );
+    for (name, before, after) in rows {
+        eprintln!(
+            "{:6}  {:>width$}  {:>width$}  {:+}",
+            name,
+            before,
+            after,
+            after as i64 - before as i64,
+            width = width.max(6)
+        );
+    }
+    Ok(())
+}
+
+/// Print how long parsing, each pass and emitting took, with the parts of a
+/// pass under it.
+fn print_timings(parse: Duration, passes: &[(String, Duration)], emit: Duration) {
+    let mut rows = vec![("parse", parse, 0)];
+    // The progress line's passes take no time worth showing.
+    for (name, time) in passes.iter().filter(|(name, _)| name != "progress") {
+        if name.contains('/') {
+            continue;
+        }
+        rows.push((name, *time, 0));
+        let prefix = format!("{}/", name);
+        for (part, time) in passes {
+            if let Some(part) = part.strip_prefix(&prefix) {
+                rows.push((part, *time, 2));
+            }
+        }
+    }
+    rows.push(("emit", emit, 0));
+    let total = parse
+        + emit
+        + passes
+            .iter()
+            .filter(|(name, _)| !name.contains('/'))
+            .map(|(_, time)| *time)
+            .sum::<Duration>();
+    rows.push(("total", total, 0));
+    for (name, time, indent) in rows {
+        eprintln!(
+            "{:indent$}{:width$}  {:>10.3?}",
+            "",
+            name,
+            time,
+            indent = indent,
+            width = 24 - indent
+        );
+    }
+}
+
+/// Warn about each function in the snipped module that is new or bigger than
+/// in the baseline module at `path`, failing if `strict` is set. Functions are
+/// matched by name, so unnamed functions are ignored.
+///
+/// Both modules are parsed from their binaries, so that their functions are
+/// measured the same way.
+fn compare_to_baseline(path: &Path, wasm: &[u8], strict: bool) -> Result<(), SnipError> {
+    let module = parse_wasm(wasm)?;
+    let buf =
+        fs::read(path).with_context(|_| format!("failed to read baseline {}", path.display()))?;
+    let baseline = parse_wasm(&buf)
+        .with_context(|_| format!("failed to parse baseline {}", path.display()))?;
+    let sizes_by_name = |module: &walrus::Module| -> Result<HashMap<String, u64>, SnipError> {
+        let sizes = wasm_snip::function_sizes(module)?;
+        Ok(module
+            .funcs
+            .iter()
+            .filter_map(|f| Some((f.name.clone()?, sizes.get(&f.id()).cloned().unwrap_or(0))))
+            .collect())
+    };
+    let before = sizes_by_name(&baseline)?;
+    let mut after: Vec<_> = sizes_by_name(&module)?.into_iter().collect();
+    after.sort();
+
+    let mut regressions = 0;
+    for (name, size) in after {
+        match before.get(&name) {
+            None => eprintln!(
+                "warning: `{}` ({} bytes) is new since the baseline",
+                name, size
+            ),
+            Some(&old) if size > old => eprintln!(
+                "warning: `{}` grew from {} to {} bytes since the baseline",
+                name, old, size
+            ),
+            Some(_) => continue,
+        }
+        regressions += 1;
+    }
+    if strict && regressions > 0 {
+        return Err(SnipError::Refused(format!(
+            "{} grew or appeared since the baseline {}",
+            count(regressions, "function"),
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Parse a half-open range of function indices, like `100..250`.
+fn parse_index_range(range: &str) -> Result<std::ops::Range<u32>, SnipError> {
+    let bounds = range
+        .split_once("..")
+        .and_then(|(start, end)| Some(start.trim().parse().ok()?..end.trim().parse().ok()?));
+    match bounds {
+        Some(bounds) => Ok(bounds),
+        None => Err(SnipError::InvalidOptions(format!(
+            "invalid function index range `{}`; expected one like `100..250`",
+            range
+        ))),
+    }
+}
+
+/// Parse a size in bytes, optionally suffixed with `K` for kibibytes or `M`
+/// for mebibytes.
+fn parse_size(size: &str) -> Result<u64, SnipError> {
+    let trimmed = size.trim();
+    let (digits, multiplier) = if let Some(digits) = trimmed.strip_suffix(['K', 'k']) {
+        (digits, 1024)
+    } else if let Some(digits) = trimmed.strip_suffix(['M', 'm']) {
+        (digits, 1024 * 1024)
+    } else {
+        (trimmed, 1)
+    };
+    match digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(size) => Ok(size),
+        None => Err(SnipError::InvalidOptions(format!(
+            "invalid size `{}`; expected bytes, like `512K` or `2M`",
+            size
+        ))),
+    }
+}
+
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
 
+/// Write a JSON file mapping each function's index in the input module to its
+/// index in the output module, or to `"removed"`.
+fn write_remap(
+    path: &Path,
+    original: &[(walrus::FunctionId, Option<String>)],
+    indices: &HashMap<walrus::FunctionId, u32>,
+) -> Result<(), SnipError> {
+    let functions: Vec<_> = original
+        .iter()
+        .map(|(id, name)| {
+            let new_index = match indices.get(id) {
+                Some(&index) => serde_json::Value::from(index),
+                None => serde_json::Value::from("removed"),
+            };
+            serde_json::json!({
+                "old-index": id.index(),
+                "name": name,
+                "new-index": new_index,
+            })
+        })
+        .collect();
+    let json = serde_json::to_string_pretty(&serde_json::json!({ "functions": functions }))?;
+    fs::write(path, json)
+        .with_context(|_| format!("failed to write remap file {}", path.display()))?;
     Ok(())
 }
 
-fn walrus_config_from_options(options: &wasm_snip::Options) -> walrus::ModuleConfig {
-    let mut config = walrus::ModuleConfig::new();
-    config.generate_producers_section(!options.skip_producers_section);
-    config
+fn values_of(matches: &clap::ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .values_of(name)
+        .map(|vs| vs.map(|v| v.to_string()).collect())
+        .unwrap_or(vec![])
+}
+
+fn paths_of(matches: &clap::ArgMatches, name: &str) -> Vec<PathBuf> {
+    matches
+        .values_of_os(name)
+        .map(|vs| vs.map(PathBuf::from).collect())
+        .unwrap_or(vec![])
 }
 
 fn parse_args() -> clap::ArgMatches<'static> {
@@ -89,6 +1172,89 @@ Very helpful when shrinking the size of WebAssembly binaries!
 [0]: https://github.com/alexcrichton/wasm-gc
 ",
         )
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .setting(clap::AppSettings::ArgsNegateSubcommands)
+        .subcommand(
+            clap::SubCommand::with_name("list")
+                .about(
+                    "List the functions in a wasm file with their indices and body sizes, from \
+                     largest to smallest.",
+                )
+                .arg(
+                    clap::Arg::with_name("input")
+                        .required(true)
+                        .help(
+                            "The input wasm file to list the functions of, or `-` \
+                             for stdin.",
+                        ),
+                )
+                .arg(
+                    clap::Arg::with_name("filter")
+                        .long("filter")
+                        .takes_value(true)
+                        .help("Only list the functions whose name matches the regex."),
+                )
+                .arg(
+                    clap::Arg::with_name("demangle")
+                        .long("demangle")
+                        .help("Print and filter demangled Rust names."),
+                )
+                .arg(
+                    clap::Arg::with_name("retained")
+                        .long("retained")
+                        .help(
+                            "Also print the retained size of each function, which is how much \
+                             code snipping it would remove, and sort by that instead.",
+                        ),
+                )
+                .arg(
+                    clap::Arg::with_name("json")
+                        .long("json")
+                        .help("Print the list as JSON."),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("why")
+                .about(
+                    "Print the chain of calls, starting at an export, the start function or an \
+                     exported table, that keeps a function alive.",
+                )
+                .arg(
+                    clap::Arg::with_name("input")
+                        .required(true)
+                        .help("The input wasm file, or `-` for stdin."),
+                )
+                .arg(
+                    clap::Arg::with_name("function")
+                        .required(true)
+                        .help("The function to explain."),
+                )
+                .arg(
+                    clap::Arg::with_name("snip")
+                        .multiple(true)
+                        .help("Functions to treat as snipped first."),
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .global(true)
+                .help(
+                    "Log what snipping does to stderr: `-v` for a summary of each pass, \
+                     `-vv` for why each function is snipped and what is removed, and `-vvv` \
+                     for what walrus does too. `RUST_LOG` takes precedence.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("no_progress")
+                .long("no-progress")
+                .help(
+                    "Don't show which phase snipping is in on stderr. Progress is only shown \
+                     when stderr is a terminal, snipping takes a while, and -v isn't given.",
+                ),
+        )
         .arg(
             clap::Arg::with_name("output")
                 .short("o")
@@ -96,10 +1262,112 @@ Very helpful when shrinking the size of WebAssembly binaries!
                 .takes_value(true)
                 .help("The path to write the output wasm file to. Defaults to stdout."),
         )
+        .arg(
+            clap::Arg::with_name("simulate")
+                .long("simulate")
+                .multiple(true)
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Instead of snipping, print which functions snipping the given function \
+                     would remove, and how much code they contain.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("baseline")
+                .long("baseline")
+                .takes_value(true)
+                .help(
+                    "Warn about functions in the snipped module that are new or bigger than in \
+                     the given baseline module. With `--strict`, fail instead.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("max_output_size")
+                .long("max-output-size")
+                .takes_value(true)
+                .help(
+                    "Fail if the snipped module is bigger than the given number of bytes. The \
+                     size may end in `K` or `M`, like `512K`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("timings")
+                .long("timings")
+                .help(
+                    "Print how long each step took to stderr: parsing, each pass of \
+                     snipping, like matching functions, rewriting calls and table elements, \
+                     and removing unused items, and emitting.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("stats")
+                .long("stats")
+                .help(
+                    "Print the sizes of the input and output, and of their code, data, name \
+                     and other custom sections, to stderr.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("report")
+                .long("report")
+                .help(
+                    "Print each snipped function to stderr, along with the functions that were \
+                     removed because snipping it left them unused.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("suggest")
+                .long("suggest")
+                .help(
+                    "Instead of snipping, suggest snips that are likely to be safe: matching \
+                     presets, functions that always trap, and large leaf functions, with how \
+                     much code each would remove.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("emit_removed_graph")
+                .long("emit-removed-graph")
+                .takes_value(true)
+                .help(
+                    "Write a Graphviz DOT graph of the functions removed from the module, with \
+                     an edge for each call between them.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("emit_remap")
+                .long("emit-remap")
+                .takes_value(true)
+                .help(
+                    "Write a JSON file mapping each function's index in the input module to \
+                     its index in the output module, or to \"removed\".",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help(
+                    "Read snip settings from the given TOML file. Flags on the command line \
+                     take precedence over the file's settings.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("no_cargo_metadata")
+                .long("no-cargo-metadata")
+                .help(
+                    "Do not read snip settings from the `[package.metadata.wasm-snip]` table \
+                     of the nearest Cargo.toml when no --config file is given.",
+                ),
+        )
         .arg(
             clap::Arg::with_name("input")
-                .required(true)
-                .help("The input wasm file containing the function(s) to snip."),
+                .required_unless("list_presets")
+                .help(
+                    "The input wasm file containing the function(s) to snip, or \
+                     `-` for stdin.",
+                ),
         )
         .arg(clap::Arg::with_name("function").multiple(true).help(
             "The specific function(s) to snip. These must match \
@@ -114,6 +1382,230 @@ Very helpful when shrinking the size of WebAssembly binaries!
                 .takes_value(true)
                 .help("Snip any function that matches the given regular expression."),
         )
+        .arg(
+            clap::Arg::with_name("symbols")
+                .required(false)
+                .multiple(true)
+                .long("symbols")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Name the functions of a module without a name section with the given \
+                     symbol map, which has `index:name` lines, or with a `.json` file mapping \
+                     indices to names.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("index")
+                .required(false)
+                .multiple(true)
+                .long("index")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Snip the function with the given index, counting imported functions \
+                     first. This works without a name section.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("index_range")
+                .required(false)
+                .multiple(true)
+                .long("index-range")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("Snip the functions with indices in the given range, like `100..250`."),
+        )
+        .arg(
+            clap::Arg::with_name("functions_from_file")
+                .required(false)
+                .multiple(true)
+                .long("functions-from-file")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Snip the functions listed in the given file, one exact name per line. \
+                     Blank lines and lines starting with '#' are ignored.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("patterns_from_file")
+                .required(false)
+                .multiple(true)
+                .long("patterns-from-file")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Snip any function that matches a regular expression listed in the given \
+                     file, one per line. Blank lines and lines starting with '#' are ignored.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("exclude_pattern")
+                .required(false)
+                .multiple(true)
+                .short("x")
+                .long("exclude-pattern")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Never snip a function that matches the given regular expression, even if \
+                     it matches a -p pattern or a --snip-rust-* preset.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("signature")
+                .required(false)
+                .multiple(true)
+                .long("signature")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Only snip functions selected by patterns, crates or presets if they have \
+                     the given type, like `(i32, i32) -> i64`. Without those, snip every \
+                     function with the given type.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("min_size")
+                .long("min-size")
+                .takes_value(true)
+                .help(
+                    "Only snip functions selected by patterns, crates or presets if their body \
+                     is at least the given number of bytes. The size may end in `K` or `M`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("threads")
+                .long("threads")
+                .takes_value(true)
+                .help(
+                    "Parse, snip and emit with the given number of threads instead of one for \
+                     each CPU. With 1, everything happens on one thread, which makes debugging \
+                     deterministic.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("keep_function")
+                .required(false)
+                .multiple(true)
+                .long("keep-function")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Never snip the given function, no matter how else it was selected. This \
+                     must match exactly.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("keep_pattern")
+                .required(false)
+                .multiple(true)
+                .long("keep-pattern")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Never snip any function that matches the given regular expression, no \
+                     matter how else it was selected.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("snip_crate")
+                .required(false)
+                .multiple(true)
+                .long("snip-crate")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("Snip every Rust function belonging to the given crate, e.g. `serde_json`."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_import")
+                .required(false)
+                .multiple(true)
+                .long("snip-import")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Snip the function imported from the given module and field, e.g. \
+                     `wasi_snapshot_preview1::fd_write`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("snip_import_pattern")
+                .required(false)
+                .multiple(true)
+                .long("snip-import-pattern")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("Snip the imported functions whose `module::field` name matches the regex."),
+        )
+        .arg(
+            clap::Arg::with_name("preset")
+                .required(false)
+                .multiple(true)
+                .long("preset")
+                .takes_value(true)
+                .number_of_values(1)
+                .use_delimiter(true)
+                .help(
+                    "Snip the functions matched by the given built-in preset. The --snip-* preset \
+                     flags are aliases for this.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("preset_file")
+                .required(false)
+                .multiple(true)
+                .long("preset-file")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Read additional presets for --preset from the given TOML or JSON file. See \
+                     https://docs.rs/wasm-snip/latest/wasm_snip/presets/index.html for the \
+                     format.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("list_presets")
+                .required(false)
+                .long("list-presets")
+                .help("List the built-in presets and exit."),
+        )
+        .arg(
+            clap::Arg::with_name("ignore_case")
+                .required(false)
+                .short("i")
+                .long("ignore-case")
+                .help("Match function names and patterns regardless of case."),
+        )
+        .arg(
+            clap::Arg::with_name("demangle")
+                .required(false)
+                .long("demangle")
+                .help(
+                    "Also match function names and patterns against demangled Rust symbol \
+                     names, e.g. `core::fmt::write::h0123456789abcdef`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("demangle_cpp")
+                .required(false)
+                .long("demangle-cpp")
+                .help(
+                    "Also match function names and patterns against demangled C++ symbol \
+                     names, e.g. `std::__2::basic_string<...>::append(char const*)`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("ignore_hash")
+                .required(false)
+                .long("ignore-hash")
+                .help(
+                    "Match exact function names against Rust symbols regardless of their \
+                     trailing hash, so that `hello::snip_me` matches \
+                     `hello::snip_me::h0123456789abcdef`.",
+                ),
+        )
         .arg(
             clap::Arg::with_name("snip_rust_fmt_code")
                 .required(false)
@@ -126,6 +1618,298 @@ Very helpful when shrinking the size of WebAssembly binaries!
                 .long("snip-rust-panicking-code")
                 .help("Snip Rust's `std::panicking` and `core::panicking` code."),
         )
+        .arg(
+            clap::Arg::with_name("snip_rust_float_fmt")
+                .required(false)
+                .long("snip-rust-float-fmt")
+                .help("Snip Rust's float formatting and parsing code."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_rust_unicode")
+                .required(false)
+                .long("snip-rust-unicode")
+                .help("Snip Rust's Unicode tables and case conversion code."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_rust_backtrace")
+                .required(false)
+                .long("snip-rust-backtrace")
+                .help("Snip Rust's backtrace capture and symbolization code."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_rust_alloc_error")
+                .required(false)
+                .long("snip-rust-alloc-error")
+                .help("Snip Rust's allocation error and out-of-memory handling code."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_wasm_bindgen_internals")
+                .required(false)
+                .long("snip-wasm-bindgen-internals")
+                .help("Snip wasm-bindgen's `__wbindgen_describe_*` functions and other internals that are only used \
+                     by the `wasm-bindgen` CLI."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_tinygo_prints")
+                .required(false)
+                .long("snip-tinygo-prints")
+                .help("Snip TinyGo's `runtime.print*` functions."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_tinygo_reflect")
+                .required(false)
+                .long("snip-tinygo-reflect")
+                .help("Snip TinyGo's `reflect` and `internal/reflectlite` packages."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_assemblyscript_abort")
+                .required(false)
+                .long("snip-assemblyscript-abort")
+                .help("Snip AssemblyScript's `abort` and `trace` builtins and the string concatenation helpers \
+                     they use."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_emscripten_syscalls")
+                .required(false)
+                .long("snip-emscripten-syscalls")
+                .help("Snip Emscripten's `__syscall_*` stubs."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_emscripten_exceptions")
+                .required(false)
+                .long("snip-emscripten-exceptions")
+                .help("Snip the C++ exception runtime, e.g. `__cxa_throw`."),
+        )
+        .arg(
+            clap::Arg::with_name("snip_emscripten_sbrk")
+                .required(false)
+                .long("snip-emscripten-sbrk")
+                .help("Snip Emscripten's `sbrk` and heap resizing code."),
+        )
+        .arg(
+            clap::Arg::with_name("replace_with")
+                .required(false)
+                .long("replace-with")
+                .takes_value(true)
+                .help(
+                    "How to replace snipped functions: remove them and make their callers trap \
+                     (`unreachable`), keep them and make them return zero or null (`default`), \
+                     or keep them and make them pass their index in the input module to the \
+                     given import before trapping (`call-import=env::snipped_hit`), or keep \
+                     them and give them the body of the WAT function of their type \
+                     (`custom-body=(func i32.const -1)`). Defaults to `unreachable`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("replace_body")
+                .required(false)
+                .long("replace-body")
+                .takes_value(true)
+                .help(
+                    "Read WAT functions from the given file, and use their bodies for snipped \
+                     functions of the same type instead of --replace-with.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("stub_exports")
+                .required(false)
+                .long("stub-exports")
+                .help(
+                    "Keep the exports of snipped functions, pointing them at stubs that trap, \
+                     instead of removing them.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("stub_function")
+                .required(false)
+                .multiple(true)
+                .long("stub-function")
+                .takes_value(true)
+                .number_of_values(1)
+                .help(
+                    "Point table elements of snipped functions at the given function, like one \
+                     that reports the hit before trapping, instead of a new stub. Give one \
+                     function for each type of snipped function in a table.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("table_null")
+                .required(false)
+                .long("table-null")
+                .help(
+                    "Empty the table elements of snipped functions, so calling them traps, \
+                     instead of pointing them at stubs.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("shrink_tables")
+                .required(false)
+                .long("shrink-tables")
+                .help(
+                    "Remove the table elements and element segments that are all stubs, and \
+                     shrink the tables that end in them where that is safe.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("gc_data")
+                .required(false)
+                .long("gc-data")
+                .help(
+                    "Remove the data that only snipped functions referred to, like their panic \
+                     messages, assuming that code refers to data by the address it starts at.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("snip_panic_strings")
+                .required(false)
+                .long("snip-panic-strings")
+                .help(
+                    "Blank the messages passed to Rust's `core::panicking::panic*` functions, \
+                     leaving them out of the data section.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("shrink_memory")
+                .required(false)
+                .long("shrink-memory")
+                .help(
+                    "Lower the minimum size of memory to what its data and the constant addresses \
+                     that code uses need.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("keep_imports")
+                .required(false)
+                .long("keep-imports")
+                .help(
+                    "Turn snipped imported functions into local functions that trap, instead of \
+                     removing them and the calls to them.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("preserve_indices")
+                .required(false)
+                .long("preserve-indices")
+                .help(
+                    "Keep function indices stable: give snipped functions bodies that trap \
+                     instead of removing them, and don't remove unused functions.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("snip_start")
+                .required(false)
+                .long("snip-start")
+                .help(
+                    "Remove the start section, so the start function doesn't run on \
+                     instantiation.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("force")
+                .required(false)
+                .long("force")
+                .help("Snip functions even if they are exported."),
+        )
+        .arg(
+            clap::Arg::with_name("warn_reachable")
+                .required(false)
+                .long("warn-reachable")
+                .help(
+                    "Warn about snipped functions that kept functions can still call, showing \
+                     how each is reached.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("strict")
+                .required(false)
+                .long("strict")
+                .help("Fail instead of warning when the snipped module would be broken."),
+        )
+        .arg(
+            clap::Arg::with_name("no_gc")
+                .required(false)
+                .long("no-gc")
+                .help(
+                    "Don't remove the functions and other items that are unused after \
+                     snipping.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("name_stubs")
+                .required(false)
+                .long("name-stubs")
+                .help(
+                    "Name the stubs that replace snipped functions in tables, exports and the \
+                     start section after their type, like \
+                     `wasm_snip::unreachable_stub::<(i32) -> i32>`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("strip_names")
+                .required(false)
+                .long("strip-names")
+                .help("Do not emit the 'name' custom section."),
+        )
+        .arg(
+            clap::Arg::with_name("keep_export_names")
+                .required(false)
+                .long("keep-export-names")
+                .help("With --strip-names, keep the names of exported functions."),
+        )
+        .arg(
+            clap::Arg::with_name("strip_local_names")
+                .required(false)
+                .long("strip-local-names")
+                .help("Do not emit the names of locals and labels."),
+        )
+        .arg(
+            clap::Arg::with_name("strip_dwarf")
+                .required(false)
+                .long("strip-dwarf")
+                .help("Do not emit the DWARF `.debug_*` custom sections."),
+        )
+        .arg(
+            clap::Arg::with_name("update_dwarf")
+                .required(false)
+                .long("update-dwarf")
+                .conflicts_with("strip_dwarf")
+                .help(
+                    "Move DWARF addresses to where their functions end up, and tombstone \
+                     those of snipped and removed functions.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("strip_custom_section")
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .long("strip-custom-section")
+                .takes_value(true)
+                .help(
+                    "Remove the custom sections whose names match the given regular \
+                     expression. Other custom sections are kept byte for byte.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("target_features")
+                .required(false)
+                .long("target-features")
+                .takes_value(true)
+                .help(
+                    "What to do with the `target_features` section: keep it (`preserve`), \
+                     remove it (`strip`), or replace it with the given features \
+                     (`set=+mutable-globals,+sign-ext`). Defaults to `preserve`.",
+                ),
+        )
+        .arg(
+            clap::Arg::with_name("update_source_map")
+                .required(false)
+                .long("update-source-map")
+                .help(
+                    "Update the source map named by the `sourceMappingURL` section for the \
+                     snipped module, and write it next to the output as `<output>.map`.",
+                ),
+        )
         .arg(
             clap::Arg::with_name("skip_producers_section")
                 .required(false)
@@ -0,0 +1,261 @@
+//! Building [`Options`](../struct.Options.html) one setting at a time.
+//!
+//! Unlike filling in the fields of `Options` directly, the builder checks the
+//! options when they are built, rather than when they are first used to snip:
+//!
+//! ```
+//! # fn main() -> Result<(), wasm_snip::SnipError> {
+//! let options = wasm_snip::Options::builder()
+//!     .pattern(".*alloc.*")
+//!     .keep_function("__rust_alloc_error_handler")
+//!     .snip_rust_fmt_code(true)
+//!     .build()?;
+//! # let _ = options;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Matcher, Options, Strategy, StrategyRule, TargetFeatures};
+use crate::{ResultExt, SnipError};
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// A builder of [`Options`](../struct.Options.html), made with
+/// [`Options::builder`](../struct.Options.html#method.builder).
+///
+/// Each method sets the field of `Options` with the same name, or for lists,
+/// adds to the field named in the plural.
+#[derive(Clone, Debug, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+/// Methods setting the `bool` fields of `Options`.
+macro_rules! flags {
+    ($($name:ident,)*) => {
+        $(
+            #[doc = concat!("Set [`Options::", stringify!($name), "`](../struct.Options.html#structfield.", stringify!($name), ").")]
+            pub fn $name(mut self, $name: bool) -> OptionsBuilder {
+                self.options.$name = $name;
+                self
+            }
+        )*
+    };
+}
+
+/// Methods adding to the list fields of `Options`.
+macro_rules! lists {
+    ($($name:ident: $field:ident($ty:ty),)*) => {
+        $(
+            #[doc = concat!("Add to [`Options::", stringify!($field), "`](../struct.Options.html#structfield.", stringify!($field), ").")]
+            pub fn $name(mut self, $name: impl Into<$ty>) -> OptionsBuilder {
+                self.options.$field.push($name.into());
+                self
+            }
+        )*
+    };
+}
+
+impl OptionsBuilder {
+    lists! {
+        function: functions(String),
+        pattern: patterns(String),
+        index: indices(u32),
+        index_range: index_ranges(Range<u32>),
+        symbols_file: symbols_files(PathBuf),
+        functions_file: functions_files(PathBuf),
+        patterns_file: patterns_files(PathBuf),
+        exclude_pattern: exclude_patterns(String),
+        keep_function: keep_functions(String),
+        keep_pattern: keep_patterns(String),
+        snip_crate: snip_crates(String),
+        snip_import: snip_imports(String),
+        snip_import_pattern: snip_import_patterns(String),
+        signature: signatures(String),
+        preset: presets(String),
+        preset_file: preset_files(PathBuf),
+        strategy_rule: strategies(StrategyRule),
+        stub_function: stub_functions(String),
+        strip_custom_section: strip_custom_sections(String),
+    }
+
+    flags! {
+        ignore_case,
+        demangle,
+        demangle_cpp,
+        ignore_hash,
+        snip_rust_fmt_code,
+        snip_rust_panicking_code,
+        snip_rust_float_fmt,
+        snip_rust_unicode,
+        snip_rust_backtrace,
+        snip_rust_alloc_error,
+        snip_wasm_bindgen_internals,
+        snip_tinygo_prints,
+        snip_tinygo_reflect,
+        snip_assemblyscript_abort,
+        snip_emscripten_syscalls,
+        snip_emscripten_exceptions,
+        snip_emscripten_sbrk,
+        stub_exports,
+        table_null,
+        shrink_tables,
+        gc_data,
+        snip_panic_strings,
+        shrink_memory,
+        keep_imports,
+        preserve_indices,
+        snip_start,
+        force,
+        warn_reachable,
+        strict,
+        name_stubs,
+        skip_gc,
+        strip_names,
+        keep_export_names,
+        strip_local_names,
+        strip_dwarf,
+        update_dwarf,
+        update_source_map,
+        skip_producers_section,
+    }
+
+    /// Set [`Options::min_size`](../struct.Options.html#structfield.min_size).
+    pub fn min_size(mut self, min_size: u64) -> OptionsBuilder {
+        self.options.min_size = Some(min_size);
+        self
+    }
+
+    /// Set [`Options::strategy`](../struct.Options.html#structfield.strategy).
+    pub fn strategy(mut self, strategy: Strategy) -> OptionsBuilder {
+        self.options.strategy = strategy;
+        self
+    }
+
+    /// Set
+    /// [`Options::replacement_body`](../struct.Options.html#structfield.replacement_body).
+    pub fn replacement_body(mut self, wat: impl Into<String>) -> OptionsBuilder {
+        self.options.replacement_body = Some(wat.into());
+        self
+    }
+
+    /// Set
+    /// [`Options::target_features`](../struct.Options.html#structfield.target_features).
+    pub fn target_features(mut self, target_features: TargetFeatures) -> OptionsBuilder {
+        self.options.target_features = target_features;
+        self
+    }
+
+    /// Set [`Options::threads`](../struct.Options.html#structfield.threads).
+    pub fn threads(mut self, threads: usize) -> OptionsBuilder {
+        self.options.threads = Some(threads);
+        self
+    }
+
+    /// Check the options and build them.
+    ///
+    /// This fails if a regex, signature, strategy or replacement body is
+    /// invalid, if a preset is unknown, or if the options contradict each
+    /// other, like a function that is both snipped and kept. Presets are only
+    /// checked when there are no `preset_files`, since those files aren't read
+    /// until snipping.
+    pub fn build(self) -> Result<Options, SnipError> {
+        validate(&self.options)?;
+        Ok(self.options)
+    }
+}
+
+fn validate(options: &Options) -> Result<(), SnipError> {
+    let lists = [
+        ("pattern", &options.patterns),
+        ("exclude pattern", &options.exclude_patterns),
+        ("keep pattern", &options.keep_patterns),
+        ("import pattern", &options.snip_import_patterns),
+        ("custom section pattern", &options.strip_custom_sections),
+    ];
+    let rules = options
+        .strategies
+        .iter()
+        .map(|rule| ("strategy pattern", &rule.patterns));
+    for (what, patterns) in lists.iter().cloned().chain(rules) {
+        for pattern in patterns {
+            regex::RegexBuilder::new(pattern)
+                .case_insensitive(options.ignore_case)
+                .build()
+                .with_context(|_| format!("invalid {} `{}`", what, pattern))?;
+        }
+    }
+
+    let keep_patterns = crate::compile_regex_set(&options.keep_patterns, options.ignore_case)?;
+    let keep = Matcher::new(&options.keep_functions, keep_patterns, options);
+    for name in &options.functions {
+        if keep.is_named(name) || keep.matches_pattern(name) {
+            return Err(SnipError::InvalidOptions(format!(
+                "`{}` is both snipped and kept",
+                name
+            )));
+        }
+    }
+    for pattern in &options.patterns {
+        if options.keep_patterns.contains(pattern) {
+            return Err(SnipError::InvalidOptions(format!(
+                "the pattern `{}` is both snipped and kept",
+                pattern
+            )));
+        }
+    }
+    if let Some(range) = options.index_ranges.iter().find(|range| range.is_empty()) {
+        return Err(SnipError::InvalidOptions(format!(
+            "the index range {}..{} is empty",
+            range.start, range.end
+        )));
+    }
+    if options.keep_export_names && !options.strip_names {
+        return Err(SnipError::InvalidOptions(
+            "keeping export names only makes sense when stripping names".to_string(),
+        ));
+    }
+    if options.threads == Some(0) {
+        return Err(SnipError::InvalidOptions(
+            "there must be at least one thread".to_string(),
+        ));
+    }
+    if options.update_dwarf && options.strip_dwarf {
+        return Err(SnipError::InvalidOptions(
+            "DWARF can't be both updated and stripped".to_string(),
+        ));
+    }
+
+    if options.preset_files.is_empty() {
+        for name in crate::preset_names(options) {
+            if crate::presets::find_builtin(name).is_none() {
+                return Err(SnipError::InvalidOptions(format!(
+                    "unknown preset `{}`",
+                    name
+                )));
+            }
+        }
+    }
+    for signature in &options.signatures {
+        signature.parse::<crate::Signature>()?;
+    }
+    let strategies = options.strategies.iter().map(|rule| &rule.strategy);
+    for strategy in Some(&options.strategy).into_iter().chain(strategies) {
+        match strategy {
+            Strategy::CallImportThenTrap(import) if !import.contains("::") => {
+                return Err(SnipError::InvalidOptions(format!(
+                    "the import to call must be named like `<module>::<name>`, found `{}`",
+                    import
+                )))
+            }
+            Strategy::CustomBody(wat) => {
+                crate::template::Templates::parse(wat)?;
+            }
+            _ => {}
+        }
+    }
+    if let Some(ref wat) = options.replacement_body {
+        crate::template::Templates::parse(wat)?;
+    }
+    Ok(())
+}
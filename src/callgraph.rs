@@ -0,0 +1,233 @@
+//! The static call graph of a module.
+//!
+//! A function calls every function it references, through `call` or otherwise,
+//! and every function in the tables it uses, since `call_indirect` could reach
+//! any of them. The roots are the functions the host can call directly: the
+//! exported functions, the start function, and the functions in exported
+//! tables.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use walrus::ir::Visitor;
+
+/// The functions each function of a module can call.
+#[derive(Clone, Debug)]
+pub struct CallGraph {
+    callees: HashMap<walrus::FunctionId, Vec<walrus::FunctionId>>,
+    roots: Vec<walrus::FunctionId>,
+}
+
+impl CallGraph {
+    /// Build the call graph of the given module.
+    pub fn new(module: &walrus::Module) -> CallGraph {
+        let mut tables: HashMap<walrus::TableId, Vec<walrus::FunctionId>> = HashMap::new();
+        for table in module.tables.iter() {
+            if let walrus::TableKind::Function(ref ft) = table.kind {
+                let elements = ft
+                    .elements
+                    .iter()
+                    .flatten()
+                    .chain(ft.relative_elements.iter().flat_map(|(_, e)| e.iter()));
+                tables.insert(table.id(), elements.cloned().collect());
+            }
+        }
+
+        let callees = module
+            .funcs
+            .iter_local()
+            .map(|(id, func)| {
+                let mut references = References::default();
+                walrus::ir::dfs_in_order(&mut references, func, func.entry_block());
+                let mut callees = references.funcs;
+                for table in references.tables {
+                    callees.extend(tables.get(&table).into_iter().flatten());
+                }
+                let mut seen = HashSet::new();
+                callees.retain(|f| seen.insert(*f));
+                (id, callees)
+            })
+            .collect();
+
+        let mut roots = vec![];
+        for export in module.exports.iter() {
+            match export.item {
+                walrus::ExportItem::Function(f) => roots.push(f),
+                walrus::ExportItem::Table(t) => roots.extend(tables.get(&t).into_iter().flatten()),
+                _ => {}
+            }
+        }
+        roots.extend(module.start);
+
+        CallGraph { callees, roots }
+    }
+
+    /// The functions the host can call directly.
+    pub fn roots(&self) -> &[walrus::FunctionId] {
+        &self.roots
+    }
+
+    /// The functions `f` can call.
+    pub fn callees(&self, f: walrus::FunctionId) -> &[walrus::FunctionId] {
+        self.callees.get(&f).map_or(&[], |callees| &callees[..])
+    }
+
+    /// Find every function reachable from `roots`, without looking at the
+    /// callees of functions for which `stop` returns true. Each reachable
+    /// function maps to the function it was first reached from, or `None` for
+    /// the roots.
+    pub fn reachable(
+        &self,
+        roots: impl IntoIterator<Item = walrus::FunctionId>,
+        stop: impl Fn(walrus::FunctionId) -> bool,
+    ) -> HashMap<walrus::FunctionId, Option<walrus::FunctionId>> {
+        let mut parents = HashMap::new();
+        let mut queue = VecDeque::new();
+        for root in roots {
+            if parents.insert(root, None).is_none() {
+                queue.push_back(root);
+            }
+        }
+        while let Some(f) = queue.pop_front() {
+            if stop(f) {
+                continue;
+            }
+            for &callee in self.callees(f) {
+                if let Entry::Vacant(entry) = parents.entry(callee) {
+                    entry.insert(Some(f));
+                    queue.push_back(callee);
+                }
+            }
+        }
+        parents
+    }
+
+    /// The path from a root to `f` recorded by `reachable`.
+    pub fn path(
+        parents: &HashMap<walrus::FunctionId, Option<walrus::FunctionId>>,
+        f: walrus::FunctionId,
+    ) -> Vec<walrus::FunctionId> {
+        let mut path = vec![f];
+        while let Some(&Some(parent)) = parents.get(path.last().unwrap()) {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Find the immediate dominator of each function reachable from the
+    /// roots: the closest function that every path from a root to it passes
+    /// through, or `None` if there isn't one, as for the roots themselves.
+    /// Snipping a function makes every function it dominates unreachable.
+    pub fn immediate_dominators(&self) -> HashMap<walrus::FunctionId, Option<walrus::FunctionId>> {
+        // This is Cooper, Harvey and Kennedy's "A Simple, Fast Dominance
+        // Algorithm", with a virtual root that calls every root. Nodes are
+        // numbered in postorder, so the virtual root gets the last number.
+        let postorder = self.postorder();
+        let number: HashMap<_, _> = postorder.iter().enumerate().map(|(i, &f)| (f, i)).collect();
+        let root = postorder.len();
+
+        let mut preds = vec![vec![]; root];
+        for (i, &f) in postorder.iter().enumerate() {
+            for callee in self.callees(f) {
+                preds[number[callee]].push(i);
+            }
+        }
+        for f in &self.roots {
+            preds[number[f]].push(root);
+        }
+
+        let mut idoms = vec![None; root + 1];
+        idoms[root] = Some(root);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for node in (0..root).rev() {
+                let mut new_idom = None;
+                for &pred in &preds[node] {
+                    if idoms[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idoms, pred, other),
+                    });
+                }
+                if new_idom.is_some() && idoms[node] != new_idom {
+                    idoms[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        postorder
+            .iter()
+            .enumerate()
+            .map(|(node, &f)| {
+                let idom = idoms[node].unwrap();
+                (
+                    f,
+                    if idom == root {
+                        None
+                    } else {
+                        Some(postorder[idom])
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// The functions reachable from the roots, each after all of the
+    /// functions a depth-first search reaches from it.
+    fn postorder(&self) -> Vec<walrus::FunctionId> {
+        let mut postorder = vec![];
+        let mut visited = HashSet::new();
+        for &root in &self.roots {
+            if !visited.insert(root) {
+                continue;
+            }
+            let mut stack = vec![(root, 0)];
+            while let Some((f, next)) = stack.last_mut() {
+                if let Some(&callee) = self.callees(*f).get(*next) {
+                    *next += 1;
+                    if visited.insert(callee) {
+                        stack.push((callee, 0));
+                    }
+                } else {
+                    postorder.push(*f);
+                    stack.pop();
+                }
+            }
+        }
+        postorder
+    }
+}
+
+/// Find the closest common dominator of the nodes `a` and `b`.
+fn intersect(idoms: &[Option<usize>], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while a < b {
+            a = idoms[a].unwrap();
+        }
+        while b < a {
+            b = idoms[b].unwrap();
+        }
+    }
+    a
+}
+
+/// The functions and tables referenced by a function's body.
+#[derive(Default)]
+struct References {
+    funcs: Vec<walrus::FunctionId>,
+    tables: Vec<walrus::TableId>,
+}
+
+impl<'instr> Visitor<'instr> for References {
+    fn visit_function_id(&mut self, &func: &walrus::FunctionId) {
+        self.funcs.push(func);
+    }
+
+    fn visit_table_id(&mut self, &table: &walrus::TableId) {
+        self.tables.push(table);
+    }
+}
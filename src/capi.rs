@@ -0,0 +1,116 @@
+//! C bindings, for build systems that would rather link `wasm-snip` than run
+//! the executable.
+//!
+//! With the `capi` feature enabled, the library built as a `cdylib`, with
+//! `cargo rustc --lib --features capi --crate-type cdylib`, exports these
+//! functions, which `include/wasm_snip.h` declares:
+//!
+//! ```c
+//! int wasm_snip_run(const uint8_t *input, size_t input_len,
+//!                   const char *options_json,
+//!                   uint8_t **output, size_t *output_len);
+//! void wasm_snip_free(uint8_t *output, size_t output_len);
+//! const char *wasm_snip_last_error(void);
+//! ```
+//!
+//! The options are [`Options`](../struct.Options.html) as JSON, with the same
+//! kebab-case keys as [configuration files](../config/index.html).
+
+use crate::{Options, ResultExt, SnipError};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::{panic, ptr, slice};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Snip the module, component or object file in the `input_len` bytes at
+/// `input` with the options in `options_json`, or the default options if it
+/// is null.
+///
+/// On success, returns 0 and stores the snipped wasm in `*output` and its
+/// length in `*output_len`, to be freed with `wasm_snip_free`. On failure,
+/// returns 1, and `wasm_snip_last_error` describes what went wrong.
+///
+/// # Safety
+///
+/// `input` must point to `input_len` readable bytes, `options_json` must be
+/// null or a nul-terminated string, and `output` and `output_len` must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_snip_run(
+    input: *const u8,
+    input_len: usize,
+    options_json: *const c_char,
+    output: *mut *mut u8,
+    output_len: *mut usize,
+) -> c_int {
+    let input = if input_len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(input, input_len)
+    };
+    let options = if options_json.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(options_json))
+    };
+    let result = panic::catch_unwind(|| {
+        run(input, options).map_err(|e| {
+            e.chain()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(": ")
+        })
+    })
+    .unwrap_or_else(|_| Err("wasm-snip panicked".to_string()));
+    match result {
+        Ok(wasm) => {
+            let wasm = Box::into_raw(wasm.into_boxed_slice());
+            *output_len = wasm.len();
+            *output = wasm as *mut u8;
+            0
+        }
+        Err(message) => {
+            let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+            1
+        }
+    }
+}
+
+/// Free the snipped wasm that `wasm_snip_run` returned.
+///
+/// # Safety
+///
+/// `output` and `output_len` must be what a call to `wasm_snip_run` stored,
+/// and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn wasm_snip_free(output: *mut u8, output_len: usize) {
+    if !output.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            output, output_len,
+        )));
+    }
+}
+
+/// The error of the last call to `wasm_snip_run` on this thread that failed,
+/// or null if none has. The string lives until the next failing call on the
+/// same thread.
+#[no_mangle]
+pub extern "C" fn wasm_snip_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+fn run(input: &[u8], options: Option<&CStr>) -> Result<Vec<u8>, SnipError> {
+    let options: Options = match options {
+        Some(json) => serde_json::from_slice(json.to_bytes()).context("invalid options")?,
+        None => Options::default(),
+    };
+    crate::snip_bytes(input, &options)
+}
@@ -0,0 +1,223 @@
+//! Keeping track of where functions' code moves.
+//!
+//! Debug info refers to code by its offset, which stops lining up as soon as
+//! walrus emits functions in a different place than the input had them. The
+//! code ranges of the input's functions are read into a placeholder custom
+//! section when the module is parsed, so that once the module is emitted,
+//! offsets in the input's code can be moved to where their function ended up.
+
+use crate::SnipError;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// The name of the placeholder custom section.
+const PLACEHOLDER: &str = "wasm-snip.function-ranges";
+
+/// Where the code of each local function of the input is.
+#[derive(Debug, Default)]
+pub(crate) struct FunctionRanges {
+    /// The offset of the code section's contents in the input.
+    start: u64,
+
+    functions: Vec<(walrus::FunctionId, Code)>,
+
+    /// The functions whose code is gone even though the function is still
+    /// there.
+    discarded: HashSet<walrus::FunctionId>,
+}
+
+/// Where a function's code is, relative to the start of the code section's
+/// contents.
+#[derive(Clone, Debug)]
+struct Code {
+    /// The offset of the body's size, which is what precedes the body.
+    size: u64,
+
+    /// The body itself.
+    body: Range<u64>,
+}
+
+impl walrus::CustomSection for FunctionRanges {
+    fn name(&self) -> &str {
+        PLACEHOLDER
+    }
+
+    fn data(&self, _: &walrus::IdsToIndices) -> Cow<'_, [u8]> {
+        // Everything this section is for happens before it's emitted, and
+        // `remove_placeholder` drops it again.
+        Cow::Borrowed(&[])
+    }
+}
+
+/// Read where the local functions of `wasm`, which `module` was just parsed
+/// from, have their code into a placeholder custom section of `module`.
+pub(crate) fn read(module: &mut walrus::Module, wasm: &[u8]) -> Result<(), SnipError> {
+    let ids: Vec<_> = module.funcs.iter_local().map(|(id, _)| id).collect();
+    let (start, codes) = function_codes(wasm)?;
+    module.customs.add(FunctionRanges {
+        start,
+        functions: ids.into_iter().zip(codes).collect(),
+        discarded: HashSet::new(),
+    });
+    Ok(())
+}
+
+/// Treat the code of the given snipped functions as gone, since it is even
+/// though the functions are still there.
+pub(crate) fn discard(module: &mut walrus::Module, functions: &HashSet<walrus::FunctionId>) {
+    if let Some(ranges) = module.customs.get_typed_mut::<FunctionRanges>() {
+        ranges.discarded.extend(functions);
+    }
+}
+
+/// Drop the placeholder section from the emitted `wasm`.
+pub(crate) fn remove_placeholder(mut wasm: Vec<u8>) -> Result<Vec<u8>, SnipError> {
+    // walrus pads the sizes of sections, so the placeholder's start is found by
+    // walking the sections rather than worked out from the size.
+    let mut reader = wasmparser::BinaryReader::new(&wasm, 0);
+    reader.skip(|reader| {
+        reader.read_bytes(8)?;
+        Ok(())
+    })?;
+    let mut placeholder = None;
+    while !reader.eof() {
+        let start = reader.original_position();
+        let id = reader.read_u8()?;
+        let size = reader.read_var_u32()?;
+        let mut contents = reader.skip(|reader| {
+            reader.read_bytes(size as usize)?;
+            Ok(())
+        })?;
+        if id == 0 && contents.read_string()? == PLACEHOLDER {
+            placeholder = Some(start..reader.original_position());
+        }
+    }
+    if let Some(range) = placeholder {
+        wasm.drain(range.start as usize..range.end as usize);
+    }
+    Ok(wasm)
+}
+
+/// Where each local function of `wasm` has its code, in order, along with the
+/// offset of the contents of its code section.
+fn function_codes(wasm: &[u8]) -> Result<(u64, Vec<Code>), SnipError> {
+    let mut codes = vec![];
+    let mut start = 0;
+    let mut end = 0;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload? {
+            wasmparser::Payload::CodeSectionStart { range, .. } => {
+                start = range.start;
+                // Offset 0 is the count of functions, which is at least a
+                // byte, and isn't part of any function.
+                end = 1;
+            }
+            wasmparser::Payload::CodeSectionEntry(body) => {
+                let range = body.range();
+                let body = range.start - start..range.end - start;
+                codes.push(Code { size: end, body });
+                end = range.end - start;
+            }
+            _ => {}
+        }
+    }
+    Ok((start, codes))
+}
+
+/// Moves offsets in the input's code to the output's.
+pub(crate) struct Remap {
+    /// The offsets of the code sections' contents in the input and output.
+    #[cfg_attr(not(feature = "config"), allow(dead_code))]
+    starts: (u64, u64),
+
+    /// Where each function of the input had its code, by offset, and where
+    /// it has its code in the output, if anywhere.
+    functions: Vec<(Code, Option<Code>)>,
+}
+
+impl Remap {
+    /// Compare where functions had their code in the input with the emitted
+    /// `wasm`, whose first `imported` functions are imports. `indices` is the
+    /// index of each function in `wasm`.
+    pub(crate) fn new(
+        ranges: &FunctionRanges,
+        indices: &HashMap<walrus::FunctionId, u32>,
+        imported: u32,
+        wasm: &[u8],
+    ) -> Result<Remap, SnipError> {
+        let (start, codes) = function_codes(wasm)?;
+        let mut functions: Vec<_> = ranges
+            .functions
+            .iter()
+            .map(|(f, old)| {
+                let new = Some(f)
+                    .filter(|f| !ranges.discarded.contains(f))
+                    .and_then(|f| indices.get(f))
+                    .and_then(|&index| index.checked_sub(imported))
+                    .and_then(|index| codes.get(index as usize));
+                (old.clone(), new.cloned())
+            })
+            .collect();
+        functions.sort_by_key(|(old, _)| old.body.start);
+        Ok(Remap {
+            starts: (ranges.start, start),
+            functions,
+        })
+    }
+
+    /// The function whose code contains the given offset of the input. An
+    /// offset just past the end of a function's body belongs to it, since
+    /// that is where ranges of its code end.
+    fn function(&self, offset: u64) -> Option<&(Code, Option<Code>)> {
+        let i = self
+            .functions
+            .partition_point(|(old, _)| old.body.end < offset);
+        self.functions.get(i).filter(|(old, _)| old.size <= offset)
+    }
+
+    /// Where the given offset into the input's code section is in the
+    /// output's. `None` if its code was discarded.
+    pub(crate) fn offset(&self, offset: u64) -> Option<u64> {
+        match self.function(offset) {
+            Some((old, new)) => new.as_ref().map(|new| move_within(offset, old, new)),
+            None => Some(offset),
+        }
+    }
+
+    /// Where the given range of the input's code section is in the output's,
+    /// moving its end along with its start. `None` if its code was discarded.
+    pub(crate) fn range(&self, start: u64, end: u64) -> Option<(u64, u64)> {
+        match self.function(start) {
+            Some((old, Some(new))) => {
+                Some((move_within(start, old, new), move_within(end, old, new)))
+            }
+            Some((_, None)) => None,
+            None => Some((start, end)),
+        }
+    }
+
+    /// Where the given offset into the input file is in the output file, if it
+    /// is in the code section. `None` if its code was discarded.
+    #[cfg_attr(not(feature = "config"), allow(dead_code))]
+    pub(crate) fn file_offset(&self, offset: u64) -> Option<u64> {
+        let (old, new) = self.starts;
+        match offset.checked_sub(old) {
+            Some(offset) => self.offset(offset).map(|offset| offset + new),
+            None => Some(offset),
+        }
+    }
+}
+
+/// Move `offset` from where it is in the old code of a function to the same
+/// place in its new code, as long as that is still in the function.
+///
+/// Offsets keep their distance from the start of the body, which is exact as
+/// long as walrus encodes the body the way the input did.
+fn move_within(offset: u64, old: &Code, new: &Code) -> u64 {
+    if offset < old.body.start {
+        new.body.start.saturating_sub(old.body.start - offset)
+    } else {
+        (new.body.start + (offset - old.body.start)).min(new.body.end)
+    }
+}
@@ -0,0 +1,63 @@
+//! Snipping the core modules of components.
+//!
+//! A component embeds its core modules whole, so each of them is parsed,
+//! snipped and emitted on its own, and the component is re-encoded around
+//! them. Everything else in the component is copied as is.
+
+use crate::{Options, ResultExt, SnipError};
+use wasm_encoder::reencode::{self, Reencode, ReencodeComponent};
+
+/// Snip every core module of the component `wasm`, nested components'
+/// included.
+pub(crate) fn snip(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    let mut snipper = Snipper {
+        options,
+        modules: 0,
+    };
+    let mut component = wasm_encoder::Component::new();
+    match snipper.parse_component(&mut component, wasmparser::Parser::new(0), wasm) {
+        Ok(()) => Ok(component.finish()),
+        Err(reencode::Error::UserError(e)) => Err(e),
+        Err(e) => Err(SnipError::Parse(e.to_string())),
+    }
+}
+
+struct Snipper<'a> {
+    options: &'a Options,
+
+    /// How many core modules have been snipped so far.
+    modules: usize,
+}
+
+impl Reencode for Snipper<'_> {
+    type Error = SnipError;
+}
+
+impl ReencodeComponent for Snipper<'_> {
+    fn parse_component_submodule(
+        &mut self,
+        component: &mut wasm_encoder::Component,
+        _parser: wasmparser::Parser,
+        module: &[u8],
+    ) -> Result<(), reencode::Error<SnipError>> {
+        let index = self.modules;
+        self.modules += 1;
+        let wasm = snip_module(module, self.options)
+            .with_context(|_| format!("failed to snip core module {} of the component", index))
+            .map_err(reencode::Error::UserError)?;
+        component.section(&wasm_encoder::RawSection {
+            id: wasm_encoder::ComponentSectionId::CoreModule.into(),
+            data: &wasm,
+        });
+        Ok(())
+    }
+}
+
+fn snip_module(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    if crate::unsupported_proposal(wasm).is_some() {
+        return crate::snip_in_place(wasm, options);
+    }
+    let mut module = crate::parse_module(wasm, options)?;
+    crate::snip(&mut module, options.clone())?;
+    crate::emit_wasm(&module, options)
+}
@@ -0,0 +1,144 @@
+//! Reading snip settings from a TOML configuration file.
+//!
+//! A configuration file lets a project check its snip policy into the
+//! repository instead of maintaining a long command line:
+//!
+//! ```toml
+//! functions = ["annoying_space_waster"]
+//! patterns = [".*alloc.*"]
+//! keep-functions = ["__rust_alloc_error_handler"]
+//! snip-rust-fmt-code = true
+//! output = "output.wasm"
+//! ```
+//!
+//! Every key is optional. Apart from `output`, the keys are the fields of
+//! [`Options`](../struct.Options.html) spelled in kebab-case. Relative paths are
+//! resolved against the directory containing the configuration file.
+//!
+//! The same settings can instead live in a crate's `Cargo.toml`, under
+//! `[package.metadata.wasm-snip]` (or `[workspace.metadata.wasm-snip]` for a
+//! workspace), which the `wasm-snip` executable picks up automatically when it
+//! isn't given a `--config` file.
+
+use crate::{Options, ResultExt, SnipError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The contents of a `wasm-snip` configuration file.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// The path to write the output wasm file to.
+    pub output: Option<PathBuf>,
+
+    /// The options to snip with.
+    pub options: Options,
+}
+
+impl Config {
+    /// Parse a configuration from a TOML string. Relative paths are left as
+    /// they are.
+    pub fn from_toml_str(toml: &str) -> Result<Config, SnipError> {
+        Config::from_table(toml::from_str(toml)?)
+    }
+
+    fn from_table(mut table: toml::value::Table) -> Result<Config, SnipError> {
+        let output = match table.remove("output") {
+            Some(output) => Some(output.try_into()?),
+            None => None,
+        };
+        let options = toml::Value::Table(table).try_into()?;
+        Ok(Config { output, options })
+    }
+
+    /// Read and parse the configuration file at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, SnipError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|_| format!("failed to read config file {}", path.display()))?;
+        let mut config = Config::from_toml_str(&contents)
+            .with_context(|_| format!("failed to parse config file {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    /// Snip the `.wasm` file at `input` with these options, writing the
+    /// snipped binary to `output`.
+    pub fn snip_file<P: AsRef<Path>>(&self, input: P) -> Result<(), SnipError> {
+        match &self.output {
+            Some(output) => crate::snip_file(input, output, &self.options),
+            None => Err(SnipError::InvalidOptions(
+                "the configuration has no `output` to write to".to_string(),
+            )),
+        }
+    }
+
+    /// Read the `[package.metadata.wasm-snip]` or
+    /// `[workspace.metadata.wasm-snip]` table of the given `Cargo.toml`.
+    ///
+    /// Returns `None` if the manifest doesn't have either table.
+    pub fn from_cargo_manifest<P: AsRef<Path>>(path: P) -> Result<Option<Config>, SnipError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|_| format!("failed to read {}", path.display()))?;
+        let mut manifest: toml::value::Table = toml::from_str(&contents)
+            .with_context(|_| format!("failed to parse {}", path.display()))?;
+
+        let table = ["package", "workspace"].iter().find_map(|key| {
+            manifest
+                .get_mut(*key)?
+                .get_mut("metadata")?
+                .as_table_mut()?
+                .remove("wasm-snip")
+        });
+        let table = match table {
+            Some(toml::Value::Table(table)) => table,
+            Some(_) => {
+                return Err(SnipError::Parse(format!(
+                    "`wasm-snip` metadata in {} must be a table",
+                    path.display()
+                )))
+            }
+            None => return Ok(None),
+        };
+
+        let mut config = Config::from_table(table).with_context(|_| {
+            format!("failed to parse `wasm-snip` metadata in {}", path.display())
+        })?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(Some(config))
+    }
+
+    /// Search `dir` and its ancestors for a `Cargo.toml` with `wasm-snip`
+    /// metadata, and read the first one found.
+    pub fn discover_cargo_manifest<P: AsRef<Path>>(dir: P) -> Result<Option<Config>, SnipError> {
+        for dir in dir.as_ref().ancestors() {
+            let manifest = dir.join("Cargo.toml");
+            if !manifest.is_file() {
+                continue;
+            }
+            if let Some(config) = Config::from_cargo_manifest(&manifest)? {
+                return Ok(Some(config));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Make every relative path in this configuration relative to `dir`
+    /// instead.
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |p: &mut PathBuf| {
+            if p.is_relative() {
+                *p = dir.join(&*p);
+            }
+        };
+        self.output.iter_mut().for_each(resolve);
+        self.options.functions_files.iter_mut().for_each(resolve);
+        self.options.patterns_files.iter_mut().for_each(resolve);
+        self.options.symbols_files.iter_mut().for_each(resolve);
+        self.options.preset_files.iter_mut().for_each(resolve);
+    }
+}
@@ -0,0 +1,540 @@
+//! Finding the data that code refers to by constant addresses, zeroing the
+//! data that nothing refers to any more, and leaving zeroed data out of the
+//! emitted data section.
+//!
+//! Compilers like LLVM place a module's static data at addresses fixed when
+//! it is linked, and code refers to it with `i32.const` addresses, or
+//! `i64.const` ones in a 64-bit memory. Only data segments at a constant
+//! offset are considered, since the code of modules whose data is placed
+//! relative to a global doesn't use absolute addresses.
+//!
+//! A [`Layout`] of the segments in one memory finds the data that is no
+//! longer used, and how much of the memory is, with 64-bit addresses for
+//! either kind of memory. The functions for walrus modules, whose memories are
+//! all 32-bit, look at their data with one.
+
+use crate::SnipError;
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashSet};
+use std::ops::Range;
+use walrus::ir::Visitor;
+
+/// A data segment at a constant offset.
+#[derive(Clone, Debug)]
+pub(crate) struct Segment {
+    pub(crate) id: walrus::DataId,
+    pub(crate) memory: walrus::MemoryId,
+    pub(crate) range: Range<u32>,
+}
+
+/// The data segments at a constant offset, in the order of their addresses.
+/// Those of different memories may overlap.
+pub(crate) fn segments(module: &walrus::Module) -> Vec<Segment> {
+    let mut segments: Vec<_> = module
+        .data
+        .iter()
+        .filter_map(|data| match data.kind {
+            walrus::DataKind::Active(walrus::ActiveData {
+                memory,
+                location: walrus::ActiveDataLocation::Absolute(start),
+            }) => Some(Segment {
+                id: data.id(),
+                memory,
+                range: start..start.saturating_add(data.value.len() as u32),
+            }),
+            _ => None,
+        })
+        .collect();
+    segments.sort_by_key(|s| s.range.start);
+    segments
+}
+
+/// The constant addresses that local functions and the initializers of
+/// globals use: `i32.const` values, and the offsets of loads and stores.
+pub(crate) fn constant_addresses(module: &walrus::Module) -> BTreeSet<u32> {
+    let mut addresses = module
+        .funcs
+        .par_iter_local()
+        .map(|(_, func)| function_addresses(func))
+        .reduce(BTreeSet::new, |mut a, b| {
+            a.extend(b);
+            a
+        });
+    for global in module.globals.iter() {
+        if let walrus::GlobalKind::Local(walrus::InitExpr::Value(walrus::ir::Value::I32(n))) =
+            global.kind
+        {
+            addresses.insert(n as u32);
+        }
+    }
+    addresses
+}
+
+/// The constant addresses that `func` uses.
+pub(crate) fn function_addresses(func: &walrus::LocalFunction) -> BTreeSet<u32> {
+    #[derive(Default)]
+    struct Addresses(BTreeSet<u32>);
+
+    impl<'instr> Visitor<'instr> for Addresses {
+        fn visit_const(&mut self, instr: &walrus::ir::Const) {
+            if let walrus::ir::Value::I32(n) = instr.value {
+                self.0.insert(n as u32);
+            }
+        }
+
+        fn visit_load(&mut self, instr: &walrus::ir::Load) {
+            self.0.insert(instr.arg.offset);
+        }
+
+        fn visit_store(&mut self, instr: &walrus::ir::Store) {
+            self.0.insert(instr.arg.offset);
+        }
+    }
+
+    let mut addresses = Addresses::default();
+    walrus::ir::dfs_in_order(&mut addresses, func, func.entry_block());
+    addresses.0
+}
+
+/// The segment containing `address`, if any.
+pub(crate) fn segment_at(segments: &[Segment], address: u32) -> Option<&Segment> {
+    let i = segments.partition_point(|s| s.range.start <= address);
+    segments[..i].last().filter(|s| s.range.contains(&address))
+}
+
+/// The layout of `segments`, which refers to each by its index in them.
+fn layout<'a>(module: &'a walrus::Module, segments: &[Segment]) -> Layout<'a> {
+    let placed = segments
+        .iter()
+        .enumerate()
+        .map(|(index, segment)| {
+            let len = segment.range.end - segment.range.start;
+            Placed {
+                index,
+                range: u64::from(segment.range.start)..u64::from(segment.range.end),
+                value: &module.data.get(segment.id).value[..len as usize],
+            }
+        })
+        .collect();
+    Layout::new(placed, 4)
+}
+
+/// The data segments at a constant offset in `memory`.
+fn memory_segments(module: &walrus::Module, memory: walrus::MemoryId) -> Vec<Segment> {
+    let mut segments = segments(module);
+    segments.retain(|s| s.memory == memory);
+    segments
+}
+
+/// The pieces of data in each memory that `before`, the addresses code used
+/// before snipping, and `now`, those it still uses, tell apart, and that are
+/// unused. See [`Layout::unused_pieces`]. Addresses aren't told apart by
+/// memory, so each memory's data is looked at with all of them.
+pub(crate) fn unused_pieces(
+    module: &walrus::Module,
+    before: &BTreeSet<u32>,
+    now: &BTreeSet<u32>,
+) -> Vec<(Segment, Range<u32>)> {
+    let widen = |addresses: &BTreeSet<u32>| addresses.iter().map(|&a| u64::from(a)).collect();
+    let (before, now) = (widen(before), widen(now));
+    let mut pieces = vec![];
+    for memory in module.memories.iter() {
+        let segments = memory_segments(module, memory.id());
+        pieces.extend(
+            layout(module, &segments)
+                .unused_pieces(&before, &now)
+                .into_iter()
+                .map(|(i, range)| (segments[i].clone(), range.start as u32..range.end as u32)),
+        );
+    }
+    pieces
+}
+
+/// How much of `memory`, of `size` bytes, its data, the constant addresses
+/// code uses and the pointers in its data need. See [`Layout::needed`].
+pub(crate) fn needed(module: &walrus::Module, memory: walrus::MemoryId, size: u64) -> u64 {
+    let addresses = constant_addresses(module)
+        .into_iter()
+        .map(u64::from)
+        .collect();
+    layout(module, &memory_segments(module, memory)).needed(&addresses, size)
+}
+
+/// The little-endian words at aligned addresses in `segment`, any of which
+/// may be a pointer, along with their addresses.
+pub(crate) fn words<'a>(
+    module: &'a walrus::Module,
+    segment: &Segment,
+) -> impl Iterator<Item = (u32, u32)> + 'a {
+    words_in(module, segment, segment.range.clone())
+}
+
+/// The words at aligned addresses in `range` of `segment`.
+fn words_in<'a>(
+    module: &'a walrus::Module,
+    segment: &Segment,
+    range: Range<u32>,
+) -> impl Iterator<Item = (u32, u32)> + 'a {
+    let value = &module.data.get(segment.id).value;
+    let range = u64::from(range.start)..u64::from(range.end);
+    aligned_words(value, u64::from(segment.range.start), range, 4)
+        .map(|(a, word)| (a as u32, word as u32))
+}
+
+/// The word at `address`, if it is in one of `segments`.
+pub(crate) fn word_at(module: &walrus::Module, segments: &[Segment], address: u32) -> Option<u32> {
+    let segment = segment_at(segments, address)?;
+    if address.checked_add(4)? > segment.range.end {
+        return None;
+    }
+    let value = &module.data.get(segment.id).value;
+    Some(word(value, (address - segment.range.start) as usize, 4) as u32)
+}
+
+/// Zero the bytes in `range` of `segment`, returning how many weren't zero
+/// already.
+pub(crate) fn zero(module: &mut walrus::Module, segment: &Segment, range: Range<u32>) -> usize {
+    let value = &mut module.data.get_mut(segment.id).value;
+    let range = u64::from(range.start)..u64::from(range.end);
+    zero_in(value, u64::from(segment.range.start), range)
+}
+
+/// A data segment at a constant offset, in a [`Layout`].
+pub(crate) struct Placed<'a> {
+    /// Which segment it is, for the caller.
+    pub(crate) index: usize,
+    pub(crate) range: Range<u64>,
+    pub(crate) value: &'a [u8],
+}
+
+/// The data segments at a constant offset in one memory, in the order of
+/// their addresses.
+pub(crate) struct Layout<'a> {
+    segments: Vec<Placed<'a>>,
+
+    /// How many bytes a pointer takes: 4 in a 32-bit memory, and 8 in a
+    /// 64-bit one.
+    pointer_size: u64,
+}
+
+impl<'a> Layout<'a> {
+    pub(crate) fn new(mut segments: Vec<Placed<'a>>, pointer_size: u64) -> Layout<'a> {
+        segments.sort_by_key(|s| s.range.start);
+        Layout {
+            segments,
+            pointer_size,
+        }
+    }
+
+    /// The segment containing `address`, if any.
+    fn segment_at(&self, address: u64) -> Option<&Placed<'a>> {
+        let i = self.segments.partition_point(|s| s.range.start <= address);
+        self.segments[..i]
+            .last()
+            .filter(|s| s.range.contains(&address))
+    }
+
+    /// The pointer-sized words at aligned addresses in `range` of `segment`,
+    /// any of which may be a pointer, along with their addresses.
+    fn words_in<'b>(
+        &self,
+        segment: &'b Placed,
+        range: Range<u64>,
+    ) -> impl Iterator<Item = (u64, u64)> + 'b {
+        aligned_words(segment.value, segment.range.start, range, self.pointer_size)
+    }
+
+    /// The pieces of data that `before`, the addresses code used before
+    /// snipping, and `now`, those it still uses, tell apart and that are
+    /// unused, by the index of their segment.
+    ///
+    /// Each address that code or data refers to starts a piece, which runs up
+    /// to the next such address or the end of its segment. A piece is unused
+    /// if code referred to it before but none of the code left does, and no
+    /// used piece contains a pointer to it, like a formatting table pointing
+    /// at its strings. Pieces that only something else, like a computed
+    /// address, could refer to are kept.
+    pub(crate) fn unused_pieces(
+        &self,
+        before: &BTreeSet<u64>,
+        now: &BTreeSet<u64>,
+    ) -> Vec<(usize, Range<u64>)> {
+        let in_data = |a: &u64| self.segment_at(*a).is_some();
+
+        let pointers = |segment: &Placed, range: Range<u64>| -> Vec<u64> {
+            self.words_in(segment, range)
+                .map(|(_, word)| word)
+                .filter(in_data)
+                .collect()
+        };
+        let pointed: BTreeSet<u64> = self
+            .segments
+            .iter()
+            .flat_map(|s| pointers(s, s.range.clone()))
+            .collect();
+
+        // Empty segments have no pieces.
+        let mut starts: BTreeSet<u64> = self
+            .segments
+            .iter()
+            .map(|s| s.range.start)
+            .filter(in_data)
+            .collect();
+        starts.extend(before.iter().chain(now).filter(|a| in_data(a)));
+        starts.extend(&pointed);
+        let piece = |start: u64| -> (&Placed, Range<u64>) {
+            let segment = self.segment_at(start).unwrap();
+            let end = starts
+                .range(start + 1..)
+                .next()
+                .map_or(segment.range.end, |&next| next.min(segment.range.end));
+            (segment, start..end)
+        };
+
+        let mut used: HashSet<u64> = HashSet::new();
+        let mut stack: Vec<u64> = starts
+            .iter()
+            .cloned()
+            .filter(|a| now.contains(a) || !(before.contains(a) || pointed.contains(a)))
+            .collect();
+        while let Some(start) = stack.pop() {
+            if !used.insert(start) {
+                continue;
+            }
+            let (segment, range) = piece(start);
+            stack.extend(pointers(segment, range));
+        }
+
+        starts
+            .iter()
+            .filter(|a| !used.contains(a))
+            .map(|&a| {
+                let (segment, range) = piece(a);
+                (segment.index, range)
+            })
+            .collect()
+    }
+
+    /// How many of the first `size` bytes of the memory its data, the
+    /// constant addresses in `used` and the pointers in its data need.
+    /// Anything referred to may be accessed up to a `v128` beyond.
+    pub(crate) fn needed(&self, used: &BTreeSet<u64>, size: u64) -> u64 {
+        let data_end = self.segments.iter().map(|s| s.range.end);
+        let pointers = self
+            .segments
+            .iter()
+            .flat_map(|s| self.words_in(s, s.range.clone()).map(|(_, word)| word));
+        let used = used
+            .iter()
+            .cloned()
+            .chain(pointers)
+            .filter(|&a| a < size)
+            .map(|a| a.saturating_add(16));
+        data_end.chain(used).max().unwrap_or(0).min(size)
+    }
+}
+
+/// The little-endian words of `size` bytes at aligned addresses in `range` of
+/// `value`, which starts at the address `start`.
+fn aligned_words(
+    value: &[u8],
+    start: u64,
+    range: Range<u64>,
+    size: u64,
+) -> impl Iterator<Item = (u64, u64)> + '_ {
+    let first = range.start.next_multiple_of(size);
+    (first..range.end.saturating_sub(size - 1))
+        .step_by(size as usize)
+        .map(move |a| (a, word(value, (a - start) as usize, size)))
+}
+
+fn word(value: &[u8], i: usize, size: u64) -> u64 {
+    let mut bytes = [0; 8];
+    bytes[..size as usize].copy_from_slice(&value[i..][..size as usize]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Zero the bytes in `range` of `value`, which starts at the address `start`,
+/// returning how many weren't zero already.
+pub(crate) fn zero_in(value: &mut [u8], start: u64, range: Range<u64>) -> usize {
+    let bytes = &mut value[(range.start - start) as usize..][..(range.end - range.start) as usize];
+    let zeroed = bytes.iter().filter(|&&b| b != 0).count();
+    bytes.iter_mut().for_each(|b| *b = 0);
+    zeroed
+}
+
+/// Remove the zeros at either end of `value`, returning how many were at its
+/// start.
+pub(crate) fn trim_zeros(value: &mut Vec<u8>) -> usize {
+    let leading = value.iter().take_while(|&&b| b == 0).count();
+    value.drain(..leading);
+    let len = value.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    value.truncate(len);
+    leading
+}
+
+/// The address that `expr` is, if it is an `i32.const` or an `i64.const`
+/// alone, like the offsets of data segments that are placed at a constant
+/// address.
+pub(crate) fn constant_address(expr: &wasmparser::ConstExpr) -> Result<Option<u64>, SnipError> {
+    let mut operators = expr.get_operators_reader();
+    let address = match operators.read()? {
+        wasmparser::Operator::I32Const { value } => u64::from(value as u32),
+        wasmparser::Operator::I64Const { value } => value as u64,
+        _ => return Ok(None),
+    };
+    match operators.read()? {
+        wasmparser::Operator::End => Ok(Some(address)),
+        _ => Ok(None),
+    }
+}
+
+/// The shortest run of zeros that splitting a data segment around saves bytes
+/// for, given the few bytes of a segment's header.
+const MIN_ZEROS: usize = 16;
+
+/// Rewrite the data section of `wasm` so that segments at a constant offset
+/// leave out long runs of zeros, which the memory starts out with anyway.
+///
+/// Modules with a data count section are left alone, since their instructions
+/// refer to segments by index, as are the segments of imported memories,
+/// which may not be all zeros.
+pub(crate) fn leave_out_zeros(wasm: Vec<u8>) -> Result<Vec<u8>, SnipError> {
+    Ok(rewrite_data_section(&wasm)?.unwrap_or(wasm))
+}
+
+fn rewrite_data_section(wasm: &[u8]) -> Result<Option<Vec<u8>>, SnipError> {
+    let mut data = None;
+    // Whether each memory is imported, and whether it is 64-bit, for the
+    // offsets of its segments.
+    let mut memories = vec![];
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload? {
+            wasmparser::Payload::DataCountSection { .. } => return Ok(None),
+            wasmparser::Payload::ImportSection(imports) => {
+                for import in imports.into_imports() {
+                    if let wasmparser::TypeRef::Memory(ty) = import?.ty {
+                        memories.push((true, ty.memory64));
+                    }
+                }
+            }
+            wasmparser::Payload::MemorySection(reader) => {
+                for memory in reader {
+                    memories.push((false, memory?.memory64));
+                }
+            }
+            wasmparser::Payload::DataSection(reader) => data = Some(reader),
+            _ => {}
+        }
+    }
+    let reader = match data {
+        Some(reader) => reader,
+        None => return Ok(None),
+    };
+    let contents = reader.range();
+    let contents = contents.start as usize..contents.end as usize;
+
+    let mut section = wasm_encoder::DataSection::new();
+    for datum in reader.clone() {
+        let datum = datum?;
+        let (memory_index, offset) = match datum.kind {
+            wasmparser::DataKind::Active {
+                memory_index,
+                ref offset_expr,
+            } => match constant_address(offset_expr)? {
+                Some(offset) if !matches!(memories.get(memory_index as usize), Some((true, _))) => {
+                    (memory_index, offset)
+                }
+                _ => {
+                    copy_segment(&mut section, datum)?;
+                    continue;
+                }
+            },
+            wasmparser::DataKind::Passive => {
+                copy_segment(&mut section, datum)?;
+                continue;
+            }
+        };
+        let wide = matches!(memories.get(memory_index as usize), Some((_, true)));
+        for run in nonzero_runs(datum.data) {
+            let start = offset.wrapping_add(run.start as u64);
+            section.active(
+                memory_index,
+                &offset_expr(wide, start),
+                datum.data[run].iter().copied(),
+            );
+        }
+    }
+
+    // Splice the new section in place of the old one, so that the other
+    // sections keep their offsets.
+    let header = section_header_start(wasm, contents.start, contents.len())?;
+    let mut rewritten = wasm[..header].to_vec();
+    rewritten.push(wasm_encoder::SectionId::Data as u8);
+    wasm_encoder::Encode::encode(&section, &mut rewritten);
+    rewritten.extend_from_slice(&wasm[contents.end..]);
+    Ok(Some(rewritten))
+}
+
+/// The offset of a data segment at `address`, in a 64-bit memory if `wide`.
+pub(crate) fn offset_expr(wide: bool, address: u64) -> wasm_encoder::ConstExpr {
+    if wide {
+        wasm_encoder::ConstExpr::i64_const(address as i64)
+    } else {
+        wasm_encoder::ConstExpr::i32_const(address as u32 as i32)
+    }
+}
+
+fn copy_segment(
+    section: &mut wasm_encoder::DataSection,
+    datum: wasmparser::Data,
+) -> Result<(), SnipError> {
+    let mut reencoder = wasm_encoder::reencode::RoundtripReencoder;
+    wasm_encoder::reencode::Reencode::parse_data(&mut reencoder, section, datum)?;
+    Ok(())
+}
+
+/// The ranges of `data` left once the runs of at least `MIN_ZEROS` zeros, and
+/// the zeros at either end, are taken out.
+fn nonzero_runs(data: &[u8]) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] == 0 {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i;
+        while i < data.len() {
+            if data[i] != 0 {
+                i += 1;
+                end = i;
+            } else if data[i..].iter().take(MIN_ZEROS).all(|&b| b == 0) {
+                break;
+            } else {
+                i += 1;
+            }
+        }
+        runs.push(start..end);
+    }
+    runs
+}
+
+/// Where the header of the section whose `len` bytes of contents start at
+/// `contents` begins: its id, then its size.
+fn section_header_start(wasm: &[u8], contents: usize, len: usize) -> Result<usize, SnipError> {
+    for size_len in 1..=5 {
+        let header = match contents.checked_sub(size_len + 1) {
+            Some(header) => header,
+            None => break,
+        };
+        let mut reader = wasmparser::BinaryReader::new(&wasm[header + 1..contents], 0);
+        if reader.read_var_u32().ok() == Some(len as u32) && reader.eof() {
+            return Ok(header);
+        }
+    }
+    Err(SnipError::Emit(
+        "failed to find the header of the data section".to_string(),
+    ))
+}
@@ -0,0 +1,254 @@
+//! Updating DWARF debug info for snipped and removed functions.
+//!
+//! Every address in the DWARF sections is moved to where its function's code
+//! ended up, and addresses in the code of functions that were snipped or
+//! removed are replaced with the tombstones `wasm-ld` uses for discarded code.
+//! The addresses in `.debug_info`, `.debug_addr`, `.debug_line`,
+//! `.debug_ranges` and `.debug_loc` are updated; DWARF 5 range and location
+//! lists are left alone.
+
+use crate::code::Remap;
+use crate::SnipError;
+use gimli::Reader;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The address of discarded code in `.debug_ranges` and `.debug_loc`, where
+/// the usual tombstone would end the list.
+const LIST_TOMBSTONE: u64 = u64::MAX - 1;
+
+/// The address of discarded code everywhere else.
+const TOMBSTONE: u64 = u64::MAX;
+
+/// Update every address in the DWARF sections of the emitted `wasm`, in place.
+pub(crate) fn update(wasm: &mut [u8], remap: &Remap) -> Result<(), SnipError> {
+    let mut sections: HashMap<String, Range<usize>> = HashMap::new();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        if let wasmparser::Payload::CustomSection(section) = payload? {
+            if section.name().starts_with(".debug_") {
+                let start = section.data_offset() as usize;
+                let range = start..start + section.data().len();
+                sections.insert(section.name().to_string(), range);
+            }
+        }
+    }
+    let section = |name| sections.get(name).map(|range| &wasm[range.clone()]);
+
+    let mut address_size = 4;
+    let mut patches = vec![];
+    if let Some(info) = section(".debug_info") {
+        let abbrev = section(".debug_abbrev").unwrap_or(&[]);
+        let info = debug_info(remap, info, abbrev, &mut address_size)?;
+        patches.push((".debug_info", info));
+    }
+    if let Some(addr) = section(".debug_addr") {
+        patches.push((".debug_addr", debug_addr(remap, addr)?));
+    }
+    if let Some(line) = section(".debug_line") {
+        patches.push((".debug_line", debug_line(remap, line, address_size)?));
+    }
+    if let Some(ranges) = section(".debug_ranges") {
+        patches.push((".debug_ranges", list(remap, ranges, address_size, false)?));
+    }
+    if let Some(loc) = section(".debug_loc") {
+        patches.push((".debug_loc", list(remap, loc, address_size, true)?));
+    }
+
+    for (name, patches) in patches {
+        let data = &mut wasm[sections[name].clone()];
+        for patch in patches {
+            let bytes = patch.address.to_le_bytes();
+            data[patch.offset..][..patch.size].copy_from_slice(&bytes[..patch.size]);
+        }
+    }
+    Ok(())
+}
+
+/// The addresses of each debugging information entry in `.debug_info`.
+/// Sets `address_size` to the address size of its units.
+fn debug_info(
+    remap: &Remap,
+    info: &[u8],
+    abbrev: &[u8],
+    address_size: &mut u8,
+) -> Result<Vec<Patch>, SnipError> {
+    let info = gimli::DebugInfo::new(info, gimli::LittleEndian);
+    let abbrev = gimli::DebugAbbrev::new(abbrev, gimli::LittleEndian);
+
+    let mut patches = vec![];
+    let mut units = info.units();
+    while let Some(unit) = units.next()? {
+        *address_size = unit.address_size();
+        let start = match unit.offset().as_debug_info_offset() {
+            Some(offset) => offset.0,
+            None => continue,
+        };
+        let abbreviations = unit.abbreviations(&abbrev)?;
+        let mut entries = unit.entries_raw(&abbreviations, None)?;
+        while !entries.is_empty() {
+            let abbreviation = match entries.read_abbreviation()? {
+                Some(abbreviation) => abbreviation,
+                None => continue,
+            };
+            for &spec in abbreviation.attributes() {
+                let offset = start + entries.next_offset().0;
+                let value = entries.read_attribute(spec)?.value();
+                if let (gimli::DW_FORM_addr, gimli::AttributeValue::Addr(address)) =
+                    (spec.form(), value)
+                {
+                    patches.push(Patch {
+                        offset,
+                        size: usize::from(unit.address_size()),
+                        address: remap.offset(address).unwrap_or(TOMBSTONE),
+                    });
+                }
+            }
+        }
+    }
+    Ok(patches)
+}
+
+/// The addresses in each table of `.debug_addr`.
+fn debug_addr(remap: &Remap, addr: &[u8]) -> Result<Vec<Patch>, SnipError> {
+    let section = gimli::EndianSlice::new(addr, gimli::LittleEndian);
+    let mut patches = vec![];
+    let mut tables = section;
+    while !tables.is_empty() {
+        let length = tables.read_u32()?;
+        if length == u32::MAX {
+            return Err(SnipError::Unsupported {
+                message: "64-bit DWARF isn't supported".to_string(),
+                source: None,
+            });
+        }
+        let mut table = tables.split(length as usize)?;
+        let _version = table.read_u16()?;
+        let size = table.read_u8()?;
+        let _segment_selector_size = table.read_u8()?;
+        while !table.is_empty() {
+            let offset = table.offset_from(section);
+            let address = table.read_address(size)?;
+            patches.push(Patch {
+                offset,
+                size: usize::from(size),
+                address: remap.offset(address).unwrap_or(TOMBSTONE),
+            });
+        }
+    }
+    Ok(patches)
+}
+
+/// The `DW_LNE_set_address` instructions of each line number program in
+/// `.debug_line`.
+fn debug_line(remap: &Remap, line: &[u8], address_size: u8) -> Result<Vec<Patch>, SnipError> {
+    let section = gimli::EndianSlice::new(line, gimli::LittleEndian);
+    let debug_line = gimli::DebugLine::from(section);
+
+    let mut patches = vec![];
+    let mut offset = 0;
+    while offset < line.len() {
+        let program =
+            debug_line.program(gimli::DebugLineOffset(offset), address_size, None, None)?;
+        let header = program.header();
+        let lengths = header.standard_opcode_lengths().slice();
+        let mut instructions = header.raw_program_buf();
+        while !instructions.is_empty() {
+            let opcode = instructions.read_u8()?;
+            if opcode >= header.opcode_base() {
+                // A special opcode, which has no operands.
+            } else if opcode == 0 {
+                let length = instructions.read_uleb128()?;
+                let mut instruction = instructions.split(length as usize)?;
+                let size = header.address_size();
+                if instruction.read_u8()? == gimli::DW_LNE_set_address.0
+                    && instruction.len() == usize::from(size)
+                {
+                    let offset = instruction.offset_from(section);
+                    let address = instruction.read_address(size)?;
+                    patches.push(Patch {
+                        offset,
+                        size: usize::from(size),
+                        address: remap.offset(address).unwrap_or(TOMBSTONE),
+                    });
+                }
+            } else if opcode == gimli::DW_LNS_fixed_advance_pc.0 {
+                instructions.skip(2)?;
+            } else {
+                let operands = lengths.get(usize::from(opcode) - 1).cloned().unwrap_or(0);
+                for _ in 0..operands {
+                    instructions.read_uleb128()?;
+                }
+            }
+        }
+        offset = instructions.offset_from(section);
+    }
+    Ok(patches)
+}
+
+/// The address ranges of each list in `.debug_ranges`, or in `.debug_loc`
+/// if the ranges are followed by location `expressions`.
+///
+/// Ranges are relative to a base address, which is assumed to start out
+/// as 0, as it does for the compilation units LLVM emits for wasm.
+fn list(
+    remap: &Remap,
+    data: &[u8],
+    address_size: u8,
+    expressions: bool,
+) -> Result<Vec<Patch>, SnipError> {
+    let section = gimli::EndianSlice::new(data, gimli::LittleEndian);
+    let size = usize::from(address_size);
+    let max = u64::MAX >> (64 - 8 * size);
+
+    let mut patches = vec![];
+    let (mut old_base, mut new_base) = (0, 0);
+    let mut entries = section;
+    while !entries.is_empty() {
+        let offset = entries.offset_from(section);
+        let start = entries.read_address(address_size)?;
+        let end = entries.read_address(address_size)?;
+        if start == 0 && end == 0 {
+            old_base = 0;
+            new_base = 0;
+            continue;
+        }
+        if start == max {
+            old_base = end;
+            new_base = remap.offset(end).unwrap_or(end);
+            patches.push(Patch {
+                offset: offset + size,
+                size,
+                address: new_base,
+            });
+            continue;
+        }
+
+        let (start, end) =
+            match remap.range(old_base.wrapping_add(start), old_base.wrapping_add(end)) {
+                Some((start, end)) => (start.wrapping_sub(new_base), end.wrapping_sub(new_base)),
+                None => (LIST_TOMBSTONE, LIST_TOMBSTONE),
+            };
+        patches.push(Patch {
+            offset,
+            size,
+            address: start,
+        });
+        patches.push(Patch {
+            offset: offset + size,
+            size,
+            address: end,
+        });
+        if expressions {
+            let length = entries.read_u16()?;
+            entries.skip(usize::from(length))?;
+        }
+    }
+    Ok(patches)
+}
+
+/// A new address for the one at `offset` in a section.
+struct Patch {
+    offset: usize,
+    size: usize,
+    address: u64,
+}
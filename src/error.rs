@@ -0,0 +1,199 @@
+//! The errors that snipping fails with.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// An error snipping a module, or reading or writing what it takes.
+///
+/// Errors along the way are wrapped in `Context`, like `failed to read file
+/// input.wasm`, so [`root`](#method.root) finds what went wrong in the first
+/// place:
+///
+/// ```
+/// let options = wasm_snip::Options {
+///     patterns: vec!["(".to_string()],
+///     ..Default::default()
+/// };
+/// let error = wasm_snip::snip_bytes(b"\0asm\x01\0\0\0", &options).unwrap_err();
+/// assert!(matches!(error.root(), wasm_snip::SnipError::InvalidRegex(_)));
+/// ```
+#[derive(Debug)]
+pub enum SnipError {
+    /// A regex in the options, or in a preset, is invalid.
+    InvalidRegex(regex::Error),
+
+    /// The options are invalid, or contradict each other.
+    InvalidOptions(String),
+
+    /// Nothing matched what was asked for, like a function by name.
+    NoMatches(String),
+
+    /// The input, or a file that the options name, couldn't be parsed.
+    Parse(String),
+
+    /// The snipped module couldn't be emitted.
+    Emit(String),
+
+    /// The input uses something that wasm-snip doesn't support, like a
+    /// WebAssembly proposal that walrus can't parse yet.
+    Unsupported {
+        /// What isn't supported.
+        message: String,
+
+        /// Why parsing the input failed, if it did.
+        source: Option<Box<SnipError>>,
+    },
+
+    /// Snipping would break the module, like snipping exported functions
+    /// without `force`, or caused a warning with `strict` set.
+    Refused(String),
+
+    /// Reading or writing a file failed.
+    Io(io::Error),
+
+    /// Something failed while doing what `context` describes.
+    Context {
+        /// What was being done, like `failed to read file input.wasm`.
+        context: String,
+
+        /// Why it failed.
+        source: Box<SnipError>,
+    },
+}
+
+impl SnipError {
+    /// The error that went wrong in the first place, inside any `Context`.
+    pub fn root(&self) -> &SnipError {
+        match self {
+            SnipError::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// This error and the errors it was caused by, in order.
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn Error + 'static)> {
+        std::iter::successors(Some(self as &(dyn Error + 'static)), |&e| e.source())
+    }
+
+    /// Describe a failure of walrus, which reports them with the `failure`
+    /// crate, as a parse error.
+    pub(crate) fn walrus(e: failure::Error) -> SnipError {
+        let causes: Vec<_> = e.iter_chain().map(|c| c.to_string()).collect();
+        SnipError::Parse(causes.join(": "))
+    }
+}
+
+impl fmt::Display for SnipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnipError::InvalidRegex(e) => e.fmt(f),
+            SnipError::InvalidOptions(message)
+            | SnipError::NoMatches(message)
+            | SnipError::Parse(message)
+            | SnipError::Emit(message)
+            | SnipError::Refused(message)
+            | SnipError::Unsupported { message, .. }
+            | SnipError::Context {
+                context: message, ..
+            } => f.write_str(message),
+            SnipError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for SnipError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SnipError::Unsupported {
+                source: Some(source),
+                ..
+            }
+            | SnipError::Context { source, .. } => Some(&**source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnipError {
+    fn from(e: io::Error) -> SnipError {
+        SnipError::Io(e)
+    }
+}
+
+impl From<regex::Error> for SnipError {
+    fn from(e: regex::Error) -> SnipError {
+        SnipError::InvalidRegex(e)
+    }
+}
+
+impl From<wasmparser::BinaryReaderError> for SnipError {
+    fn from(e: wasmparser::BinaryReaderError) -> SnipError {
+        SnipError::Parse(e.to_string())
+    }
+}
+
+impl From<wasm_encoder::reencode::Error> for SnipError {
+    fn from(e: wasm_encoder::reencode::Error) -> SnipError {
+        SnipError::Parse(e.to_string())
+    }
+}
+
+impl From<wat::Error> for SnipError {
+    fn from(e: wat::Error) -> SnipError {
+        SnipError::Parse(e.to_string())
+    }
+}
+
+impl From<gimli::Error> for SnipError {
+    fn from(e: gimli::Error) -> SnipError {
+        SnipError::Parse(e.to_string())
+    }
+}
+
+#[cfg(feature = "serde_json")]
+impl From<serde_json::Error> for SnipError {
+    fn from(e: serde_json::Error) -> SnipError {
+        SnipError::Parse(e.to_string())
+    }
+}
+
+#[cfg(feature = "config")]
+impl From<toml::de::Error> for SnipError {
+    fn from(e: toml::de::Error) -> SnipError {
+        SnipError::Parse(e.to_string())
+    }
+}
+
+/// Adding context to errors, as [`SnipError::Context`].
+pub trait ResultExt<T> {
+    /// Wrap the error, if any, in the given context.
+    fn context<C: fmt::Display>(self, context: C) -> Result<T, SnipError>;
+
+    /// Wrap the error, if any, in the context that `f` describes given the
+    /// error.
+    fn with_context<C, F>(self, f: F) -> Result<T, SnipError>
+    where
+        C: fmt::Display,
+        F: FnOnce(&SnipError) -> C;
+}
+
+impl<T, E: Into<SnipError>> ResultExt<T> for Result<T, E> {
+    fn context<C: fmt::Display>(self, context: C) -> Result<T, SnipError> {
+        self.with_context(|_| context)
+    }
+
+    fn with_context<C, F>(self, f: F) -> Result<T, SnipError>
+    where
+        C: fmt::Display,
+        F: FnOnce(&SnipError) -> C,
+    {
+        self.map_err(|e| {
+            let source = e.into();
+            SnipError::Context {
+                context: f(&source).to_string(),
+                source: Box::new(source),
+            }
+        })
+    }
+}
@@ -0,0 +1,83 @@
+//! Reading `.wasm` files, mapping them into memory with the `mmap` feature.
+
+use crate::{ResultExt, SnipError};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+/// The contents of a `.wasm` file, which dereference to its bytes.
+///
+/// With the `mmap` feature, the file is mapped into memory instead of read,
+/// so its pages are loaded as they are parsed and can be evicted again rather
+/// than counting against the memory of the process. Otherwise, or for bytes
+/// that didn't come from a file, the contents are in a `Vec<u8>`.
+pub struct WasmFile {
+    contents: Contents,
+}
+
+enum Contents {
+    Read(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl WasmFile {
+    /// Open the file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<WasmFile, SnipError> {
+        let path = path.as_ref();
+        let contents =
+            read(path).with_context(|_| format!("failed to read file {}", path.display()))?;
+        Ok(WasmFile { contents })
+    }
+}
+
+#[cfg(feature = "mmap")]
+fn read(path: &Path) -> Result<Contents, io::Error> {
+    let file = fs::File::open(path)?;
+    // Safety: the map is only read, and like any tool reading its input,
+    // wasm-snip can't stop the file from being changed while it snips it.
+    // Doing so is a bug in the caller, like truncating a file being copied.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    Ok(Contents::Mapped(map))
+}
+
+#[cfg(not(feature = "mmap"))]
+fn read(path: &Path) -> Result<Contents, io::Error> {
+    fs::read(path).map(Contents::Read)
+}
+
+impl fmt::Debug for WasmFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WasmFile")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl From<Vec<u8>> for WasmFile {
+    fn from(wasm: Vec<u8>) -> WasmFile {
+        WasmFile {
+            contents: Contents::Read(wasm),
+        }
+    }
+}
+
+impl Deref for WasmFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.contents {
+            Contents::Read(wasm) => wasm,
+            #[cfg(feature = "mmap")]
+            Contents::Mapped(map) => map,
+        }
+    }
+}
+
+impl AsRef<[u8]> for WasmFile {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
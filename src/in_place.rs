@@ -0,0 +1,1560 @@
+//! Snipping modules that use WebAssembly proposals that walrus can't parse.
+//!
+//! Such modules are snipped in place, like object files are. The functions to
+//! snip are selected from a skeleton of the module that walrus can parse: the
+//! same functions, with the same names and exports, whose bodies only refer to
+//! the functions that the real bodies refer to. The module is then re-encoded
+//! without the snipped functions and the functions that only they used. Calls
+//! to snipped functions become `unreachable`, and element segments, exports
+//! and the start section refer to a stub of the same type that traps instead.
+//! The functions that are kept keep their code, so instructions that walrus
+//! doesn't know are copied as they are.
+//!
+//! Active element segments keep their functions alive, and passive ones only
+//! if the code that is kept copies them into a table with `table.init`.
+//! Passive segments that nothing copies keep their place, for `elem.drop`, but
+//! none of their functions, and declared segments only declare the functions
+//! that are still used.
+//!
+//! Tail calls of snipped functions become `unreachable` like calls do.
+//! `ref.func` of a snipped function, in code or in the initial value of a
+//! global or table, refers to the stub instead, which a declared segment of
+//! its own declares for the code's sake.
+//!
+//! `gc_data` and `shrink_memory` look at the data of each memory with the
+//! constant addresses of the code kept and the code before, as they do for
+//! modules that walrus parses, but with 64-bit addresses and pointers in
+//! 64-bit memories. Segments that shrinking leaves empty are moved to address
+//! zero rather than removed, so that the others keep their indices.
+
+use crate::{Options, ResultExt, SnipError, Strategy, TargetFeatures};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use wasm_encoder::reencode::{self, Reencode};
+
+/// Snip functions from `wasm`, which walrus can't parse.
+pub(crate) fn snip(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    check_options(options)?;
+    let module = Module::parse(wasm)?;
+    let skeleton = walrus::ModuleConfig::new()
+        .parse(&module.skeleton())
+        .map_err(SnipError::walrus)?;
+    // Until functions are added or removed, a function's id has the same
+    // index as the function.
+    let ids: Vec<_> = skeleton.funcs.iter().map(|f| f.id()).collect();
+    let imported = module.imports.len();
+    let sizes = module
+        .bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| {
+            let range = body.range();
+            (ids[imported + i], range.end - range.start)
+        })
+        .collect();
+
+    let mut options = options.clone();
+    let to_snip = crate::pipeline::select(&skeleton, &mut options, |_| false, Some(sizes))?;
+    let stubs = crate::find_stub_functions(&skeleton, &options, &to_snip, |f| {
+        module.funcs[f.id().index()]
+    })?;
+    let to_snip = to_snip.iter().map(|f| f.index() as u32).collect();
+    let stubs = stubs
+        .into_iter()
+        .map(|(ty, f)| (ty, f.index() as u32))
+        .collect();
+    let plan = Plan::new(&module, &options, to_snip, stubs)?;
+    let data = DataChanges::new(&module, &options, &plan);
+
+    let mut rewriter = Rewriter {
+        module: &module,
+        options: &options,
+        plan: &plan,
+        data: &data,
+        strip_custom_sections: regex::RegexSet::new(&options.strip_custom_sections)
+            .context("failed to compile custom section regex")?,
+        call_sites: 0,
+    };
+    let wasm = match rewriter.encode(wasm) {
+        Ok(wasm) => wasm,
+        Err(reencode::Error::UserError(e)) => return Err(e),
+        Err(e) => return Err(SnipError::Parse(e.to_string())),
+    };
+    log::info!(
+        "replaced {} calls to snipped functions with `unreachable`",
+        rewriter.call_sites
+    );
+    if options.gc_data {
+        return crate::data::leave_out_zeros(wasm).context("failed to leave zeroed data out");
+    }
+    Ok(wasm)
+}
+
+/// Fail if the options ask for something that only works on modules that
+/// walrus parses. The options are named by their fields, since library callers
+/// see the error too.
+fn check_options(options: &Options) -> Result<(), SnipError> {
+    let unsupported = [
+        (options.strategy != Strategy::Unreachable, "strategy"),
+        (!options.strategies.is_empty(), "strategies"),
+        (options.replacement_body.is_some(), "replacement_body"),
+        (options.keep_imports, "keep_imports"),
+        (options.shrink_tables, "shrink_tables"),
+        (options.snip_panic_strings, "snip_panic_strings"),
+        (options.update_dwarf, "update_dwarf"),
+        (options.update_source_map, "update_source_map"),
+    ];
+    match unsupported.iter().find(|(used, _)| *used) {
+        Some((_, what)) => Err(SnipError::Unsupported {
+            message: format!(
+                "the `{}` option isn't supported for modules that walrus can't parse, which are \
+                 snipped in place",
+                what
+            ),
+            source: None,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// What snipping needs to know about a module.
+#[derive(Default)]
+struct Module<'a> {
+    /// The module's name, from the name section.
+    name: Option<&'a str>,
+
+    /// Each function type, or `None` for types of other kinds.
+    types: Vec<Option<wasmparser::FuncType>>,
+
+    /// The type of each function, imported functions first.
+    funcs: Vec<u32>,
+
+    /// The module and name of each imported function.
+    imports: Vec<(&'a str, &'a str)>,
+
+    /// The body of each local function, and the functions it refers to.
+    bodies: Vec<wasmparser::FunctionBody<'a>>,
+    references: Vec<References>,
+
+    /// The name and function of each function export.
+    exports: Vec<(&'a str, u32)>,
+
+    /// The name of an exported table, if any.
+    exported_table: Option<&'a str>,
+
+    start: Option<u32>,
+
+    /// The element segments, in order.
+    segments: Vec<Segment>,
+
+    /// The functions that globals and tables are initialized with.
+    initial: Vec<u32>,
+
+    /// Each memory, imported memories first, and whether it is imported.
+    memories: Vec<(wasmparser::MemoryType, bool)>,
+
+    /// The data segments, in order.
+    data: Vec<Data<'a>>,
+
+    /// The constant addresses that globals are initialized with.
+    global_addresses: Vec<u64>,
+
+    /// The name of each function that the name section names.
+    names: Vec<(u32, &'a str)>,
+}
+
+/// The functions that a function's body refers to.
+#[derive(Default)]
+struct References {
+    /// The functions it calls directly.
+    calls: Vec<u32>,
+
+    /// The functions it refers to otherwise, with `ref.func`.
+    functions: Vec<u32>,
+
+    /// Does it call functions indirectly, through a table or a reference?
+    indirect: bool,
+
+    /// The element segments it copies into tables with `table.init`.
+    segments: Vec<u32>,
+
+    /// The constant addresses it uses: `i32.const` and `i64.const` values,
+    /// and the offsets of loads and stores.
+    addresses: Vec<u64>,
+}
+
+/// An element segment.
+struct Segment {
+    kind: SegmentKind,
+
+    /// Can its elements be null?
+    nullable: bool,
+
+    /// The functions in it.
+    functions: Vec<u32>,
+}
+
+/// A data segment.
+struct Data<'a> {
+    /// The memory it initializes, if it is active.
+    memory: Option<u32>,
+
+    /// Its offset, if it is active at a constant one.
+    offset: Option<u64>,
+
+    value: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SegmentKind {
+    /// Initializing part of a table.
+    Active,
+
+    /// For `table.init` to copy into a table.
+    Passive,
+
+    /// Only declaring the functions that `ref.func` refers to.
+    Declared,
+}
+
+impl<'a> Module<'a> {
+    fn parse(wasm: &'a [u8]) -> Result<Module<'a>, SnipError> {
+        use wasmparser::Payload;
+
+        let mut module = Module::default();
+        for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+            match payload? {
+                Payload::TypeSection(section) => {
+                    for group in section {
+                        for ty in group?.into_types() {
+                            module.types.push(match ty.composite_type.inner {
+                                wasmparser::CompositeInnerType::Func(ty) => Some(ty),
+                                _ => None,
+                            });
+                        }
+                    }
+                }
+                Payload::ImportSection(section) => {
+                    for import in section.into_imports() {
+                        let import = import?;
+                        match import.ty {
+                            wasmparser::TypeRef::Func(ty) | wasmparser::TypeRef::FuncExact(ty) => {
+                                module.funcs.push(ty);
+                                module.imports.push((import.module, import.name));
+                            }
+                            wasmparser::TypeRef::Memory(ty) => module.memories.push((ty, true)),
+                            _ => {}
+                        }
+                    }
+                }
+                Payload::FunctionSection(section) => {
+                    for ty in section {
+                        module.funcs.push(ty?);
+                    }
+                }
+                Payload::TableSection(section) => {
+                    for table in section {
+                        if let wasmparser::TableInit::Expr(init) = table?.init {
+                            module.initial.extend(expr_functions(&init)?);
+                        }
+                    }
+                }
+                Payload::MemorySection(section) => {
+                    for memory in section {
+                        module.memories.push((memory?, false));
+                    }
+                }
+                Payload::GlobalSection(section) => {
+                    for global in section {
+                        let init = global?.init_expr;
+                        module.initial.extend(expr_functions(&init)?);
+                        module
+                            .global_addresses
+                            .extend(crate::data::constant_address(&init)?);
+                    }
+                }
+                Payload::ExportSection(section) => {
+                    for export in section {
+                        let export = export?;
+                        match export.kind {
+                            wasmparser::ExternalKind::Func
+                            | wasmparser::ExternalKind::FuncExact => {
+                                module.exports.push((export.name, export.index))
+                            }
+                            wasmparser::ExternalKind::Table => {
+                                module.exported_table = module.exported_table.or(Some(export.name))
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Payload::StartSection { func, .. } => module.start = Some(func),
+                Payload::ElementSection(section) => {
+                    for element in section {
+                        let element = element?;
+                        let (nullable, functions) = match element.items {
+                            wasmparser::ElementItems::Functions(funcs) => {
+                                (true, funcs.into_iter().collect::<Result<_, _>>()?)
+                            }
+                            wasmparser::ElementItems::Expressions(ty, exprs) => {
+                                let mut functions = vec![];
+                                for expr in exprs {
+                                    functions.extend(expr_functions(&expr?)?);
+                                }
+                                (ty.is_nullable(), functions)
+                            }
+                        };
+                        module.segments.push(Segment {
+                            kind: match element.kind {
+                                wasmparser::ElementKind::Active { .. } => SegmentKind::Active,
+                                wasmparser::ElementKind::Passive => SegmentKind::Passive,
+                                wasmparser::ElementKind::Declared => SegmentKind::Declared,
+                            },
+                            nullable,
+                            functions,
+                        });
+                    }
+                }
+                Payload::DataSection(section) => {
+                    for datum in section {
+                        let datum = datum?;
+                        let (memory, offset) = match datum.kind {
+                            wasmparser::DataKind::Active {
+                                memory_index,
+                                offset_expr,
+                            } => (
+                                Some(memory_index),
+                                crate::data::constant_address(&offset_expr)?,
+                            ),
+                            wasmparser::DataKind::Passive => (None, None),
+                        };
+                        module.data.push(Data {
+                            memory,
+                            offset,
+                            value: datum.data,
+                        });
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    module.references.push(references(&body)?);
+                    module.bodies.push(body);
+                }
+                Payload::CustomSection(section) => {
+                    if let wasmparser::KnownCustom::Name(names) = section.as_known() {
+                        for name in names {
+                            match name? {
+                                wasmparser::Name::Module { name, .. } => module.name = Some(name),
+                                wasmparser::Name::Function(map) => {
+                                    for naming in map {
+                                        let naming = naming?;
+                                        module.names.push((naming.index, naming.name));
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    /// A module that walrus can parse with the same functions as this one,
+    /// for selecting the functions to snip.
+    ///
+    /// Each function keeps its type, with references of every kind as
+    /// `anyref`, and its body calls the functions it refers to after an
+    /// `unreachable`. The functions in element segments and the initial
+    /// values of globals and tables are put in a single table, which is
+    /// exported if the module exports a table, and which functions that call
+    /// indirectly call into.
+    fn skeleton(&self) -> Vec<u8> {
+        let val_types = |types: &[wasmparser::ValType]| -> Vec<_> {
+            types
+                .iter()
+                .map(|ty| match ty {
+                    wasmparser::ValType::I32 => wasm_encoder::ValType::I32,
+                    wasmparser::ValType::I64 => wasm_encoder::ValType::I64,
+                    wasmparser::ValType::F32 => wasm_encoder::ValType::F32,
+                    wasmparser::ValType::F64 => wasm_encoder::ValType::F64,
+                    wasmparser::ValType::V128 => wasm_encoder::ValType::V128,
+                    wasmparser::ValType::Ref(_) => wasm_encoder::ValType::EXTERNREF,
+                })
+                .collect()
+        };
+
+        let mut module = wasm_encoder::Module::new();
+        let mut types = wasm_encoder::TypeSection::new();
+        for ty in &self.types {
+            match ty {
+                Some(ty) => types
+                    .ty()
+                    .function(val_types(ty.params()), val_types(ty.results())),
+                None => types.ty().function([], []),
+            }
+        }
+        let void = types.len();
+        types.ty().function([], []);
+        module.section(&types);
+
+        let mut imports = wasm_encoder::ImportSection::new();
+        for (&(module, name), &ty) in self.imports.iter().zip(&self.funcs) {
+            imports.import(module, name, wasm_encoder::EntityType::Function(ty));
+        }
+        module.section(&imports);
+
+        let mut functions = wasm_encoder::FunctionSection::new();
+        for &ty in &self.funcs[self.imports.len()..] {
+            functions.function(ty);
+        }
+        module.section(&functions);
+
+        let table: Vec<_> = self
+            .segments
+            .iter()
+            .flat_map(|s| s.functions.iter().cloned())
+            .chain(self.initial.iter().cloned())
+            .collect();
+        let mut tables = wasm_encoder::TableSection::new();
+        tables.table(wasm_encoder::TableType {
+            element_type: wasm_encoder::RefType::FUNCREF,
+            table64: false,
+            minimum: table.len() as u64,
+            maximum: None,
+            shared: false,
+        });
+        module.section(&tables);
+
+        let mut exports = wasm_encoder::ExportSection::new();
+        for &(name, f) in &self.exports {
+            exports.export(name, wasm_encoder::ExportKind::Func, f);
+        }
+        if let Some(name) = self.exported_table {
+            exports.export(name, wasm_encoder::ExportKind::Table, 0);
+        }
+        module.section(&exports);
+
+        if let Some(start) = self.start {
+            module.section(&wasm_encoder::StartSection {
+                function_index: start,
+            });
+        }
+
+        let mut elements = wasm_encoder::ElementSection::new();
+        elements.active(
+            None,
+            &wasm_encoder::ConstExpr::i32_const(0),
+            wasm_encoder::Elements::Functions(table.into()),
+        );
+        module.section(&elements);
+
+        let mut code = wasm_encoder::CodeSection::new();
+        for references in &self.references {
+            let mut body = wasm_encoder::Function::new([]);
+            body.instruction(&wasm_encoder::Instruction::Unreachable);
+            for &f in references.calls.iter().chain(&references.functions) {
+                body.instruction(&wasm_encoder::Instruction::Call(f));
+                if let Some(Some(ty)) = self.types.get(self.funcs[f as usize] as usize) {
+                    for _ in ty.results() {
+                        body.instruction(&wasm_encoder::Instruction::Drop);
+                    }
+                }
+            }
+            if references.indirect {
+                body.instruction(&wasm_encoder::Instruction::CallIndirect {
+                    type_index: void,
+                    table_index: 0,
+                });
+            }
+            body.instruction(&wasm_encoder::Instruction::End);
+            code.function(&body);
+        }
+        module.section(&code);
+
+        let mut names = wasm_encoder::NameSection::new();
+        if let Some(name) = self.name {
+            names.module(name);
+        }
+        let mut functions = wasm_encoder::NameMap::new();
+        let mut sorted = self.names.clone();
+        sorted.sort_by_key(|&(f, _)| f);
+        sorted.dedup_by_key(|&mut (f, _)| f);
+        for (f, name) in sorted {
+            functions.append(f, name);
+        }
+        names.functions(&functions);
+        module.section(&names);
+        module.finish()
+    }
+
+    /// The type of the function `f`.
+    fn ty(&self, f: u32) -> u32 {
+        self.funcs[f as usize]
+    }
+
+    fn is_imported(&self, f: u32) -> bool {
+        (f as usize) < self.imports.len()
+    }
+}
+
+/// The functions that the body `body` refers to.
+fn references(body: &wasmparser::FunctionBody) -> Result<References, SnipError> {
+    use wasmparser::Operator::*;
+
+    let mut references = References::default();
+    let mut operators = body.get_operators_reader()?;
+    while !operators.eof() {
+        match operators.read()? {
+            Call { function_index } | ReturnCall { function_index } => {
+                references.calls.push(function_index)
+            }
+            RefFunc { function_index } => references.functions.push(function_index),
+            CallIndirect { .. }
+            | ReturnCallIndirect { .. }
+            | CallRef { .. }
+            | ReturnCallRef { .. } => references.indirect = true,
+            TableInit { elem_index, .. } => references.segments.push(elem_index),
+            I32Const { value } => references.addresses.push(u64::from(value as u32)),
+            I64Const { value } => references.addresses.push(value as u64),
+            I32Load { memarg }
+            | I64Load { memarg }
+            | F32Load { memarg }
+            | F64Load { memarg }
+            | I32Load8S { memarg }
+            | I32Load8U { memarg }
+            | I32Load16S { memarg }
+            | I32Load16U { memarg }
+            | I64Load8S { memarg }
+            | I64Load8U { memarg }
+            | I64Load16S { memarg }
+            | I64Load16U { memarg }
+            | I64Load32S { memarg }
+            | I64Load32U { memarg }
+            | V128Load { memarg }
+            | I32Store { memarg }
+            | I64Store { memarg }
+            | F32Store { memarg }
+            | F64Store { memarg }
+            | I32Store8 { memarg }
+            | I32Store16 { memarg }
+            | I64Store8 { memarg }
+            | I64Store16 { memarg }
+            | I64Store32 { memarg }
+            | V128Store { memarg } => references.addresses.push(memarg.offset),
+            _ => {}
+        }
+    }
+    Ok(references)
+}
+
+/// The functions that the constant expression `expr` refers to.
+fn expr_functions(expr: &wasmparser::ConstExpr) -> Result<Vec<u32>, SnipError> {
+    let mut functions = vec![];
+    let mut operators = expr.get_operators_reader();
+    while !operators.eof() {
+        if let wasmparser::Operator::RefFunc { function_index } = operators.read()? {
+            functions.push(function_index);
+        }
+    }
+    Ok(functions)
+}
+
+/// The function that the constant expression `expr` is a reference to, if it
+/// is `ref.func`.
+fn expr_function(expr: &wasmparser::ConstExpr) -> Result<Option<u32>, SnipError> {
+    match expr.get_operators_reader().read()? {
+        wasmparser::Operator::RefFunc { function_index } => Ok(Some(function_index)),
+        _ => Ok(None),
+    }
+}
+
+/// Which functions to remove, and where the rest end up.
+struct Plan {
+    /// The functions to snip.
+    snipped: BTreeSet<u32>,
+
+    /// The index of each function in the snipped module, or `None` if it is
+    /// removed.
+    indices: Vec<Option<u32>>,
+
+    /// The stub that traps which references to snipped functions of each type
+    /// refer to instead, by its index in the snipped module.
+    stubs: HashMap<u32, u32>,
+
+    /// The types of the stubs added after the module's functions, in order.
+    new_stubs: Vec<u32>,
+
+    /// Are snipped functions kept, with a body that traps?
+    preserve_indices: bool,
+
+    /// Do snipped functions in element segments become null?
+    table_null: bool,
+
+    /// Is each element segment still used? Passive segments are used if
+    /// `table.init` copies them, and declared segments only keep the
+    /// functions that are still used otherwise.
+    used: Vec<bool>,
+
+    /// The stubs that `ref.func` refers to in the code kept, which a new
+    /// declared element segment declares, since the snipped functions they
+    /// stand in for may have been declared by exports that are removed.
+    declared_stubs: Vec<u32>,
+}
+
+impl Plan {
+    /// Plan snipping `to_snip` from `module`. `stubs` are the functions that
+    /// `options.stub_functions` names, by type.
+    fn new(
+        module: &Module,
+        options: &Options,
+        snipped: BTreeSet<u32>,
+        stubs: HashMap<u32, u32>,
+    ) -> Result<Plan, SnipError> {
+        let count = module.funcs.len();
+        let mut plan = Plan {
+            snipped,
+            indices: vec![],
+            stubs: HashMap::new(),
+            new_stubs: vec![],
+            preserve_indices: options.preserve_indices,
+            table_null: options.table_null && !options.preserve_indices,
+            used: vec![true; module.segments.len()],
+            declared_stubs: vec![],
+        };
+        if plan.preserve_indices {
+            plan.indices = (0..count as u32).map(Some).collect();
+            return Ok(plan);
+        }
+
+        let mut live = Live {
+            module,
+            plan: &plan,
+            stubs: &stubs,
+            live: vec![false; count],
+            work: vec![],
+            stub_types: BTreeSet::new(),
+            declared_types: BTreeSet::new(),
+            used: vec![false; module.segments.len()],
+        };
+        for &(_, f) in &module.exports {
+            if !plan.snipped.contains(&f) || options.stub_exports {
+                live.refer(f);
+            }
+        }
+        if let Some(start) = module.start.filter(|_| !options.snip_start) {
+            live.refer(start);
+        }
+        for (i, segment) in module.segments.iter().enumerate() {
+            // Without the GC, only snipped functions are removed.
+            if segment.kind == SegmentKind::Active || options.skip_gc {
+                live.use_segment(i as u32)?;
+            }
+        }
+        for &f in &module.initial {
+            live.refer(f);
+        }
+        if options.skip_gc {
+            for f in 0..count as u32 {
+                if !plan.snipped.contains(&f) {
+                    live.refer(f);
+                }
+            }
+        }
+        live.run()?;
+
+        let (live, stub_types, declared_types, used) =
+            (live.live, live.stub_types, live.declared_types, live.used);
+        plan.used = used;
+        let mut index = 0;
+        for live in live {
+            plan.indices.push(if live {
+                index += 1;
+                Some(index - 1)
+            } else {
+                None
+            });
+        }
+        for ty in stub_types {
+            let stub = match stubs.get(&ty) {
+                Some(&stub) => plan.indices[stub as usize].unwrap(),
+                None => {
+                    plan.new_stubs.push(ty);
+                    index += 1;
+                    index - 1
+                }
+            };
+            plan.stubs.insert(ty, stub);
+        }
+        plan.declared_stubs = declared_types.iter().map(|ty| plan.stubs[ty]).collect();
+        Ok(plan)
+    }
+
+    /// Do the snipped functions in `segment` become null, rather than stubs?
+    fn nulls(&self, segment: &Segment) -> bool {
+        self.table_null && segment.nullable && segment.kind != SegmentKind::Declared
+    }
+
+    /// The index in the snipped module of the function that references to `f`
+    /// refer to, if any.
+    fn index(&self, module: &Module, f: u32) -> Option<u32> {
+        if self.snipped.contains(&f) && !self.preserve_indices {
+            self.stubs.get(&module.ty(f)).cloned()
+        } else {
+            self.indices.get(f as usize).cloned().flatten()
+        }
+    }
+
+    /// Is the function `f` in the snipped module with the body it has now?
+    fn keeps_body(&self, f: u32) -> bool {
+        self.indices.get(f as usize).copied().flatten().is_some() && !self.snipped.contains(&f)
+    }
+}
+
+/// The changes that `gc_data` and `shrink_memory` make to data segments and
+/// memories.
+#[derive(Default)]
+struct DataChanges {
+    /// The new offset and contents of each data segment that changes, by its
+    /// index.
+    segments: HashMap<u32, (u64, Vec<u8>)>,
+
+    /// The new minimum size in pages of each memory that shrinks, by its
+    /// index.
+    initial: HashMap<u32, u64>,
+}
+
+impl DataChanges {
+    fn new(module: &Module, options: &Options, plan: &Plan) -> DataChanges {
+        let mut changes = DataChanges::default();
+        if !options.gc_data && !options.shrink_memory {
+            return changes;
+        }
+        let imported = module.imports.len();
+        let addresses = |keeps: &dyn Fn(u32) -> bool| -> BTreeSet<u64> {
+            let bodies = module.references.iter().enumerate();
+            bodies
+                .filter(|&(i, _)| keeps((imported + i) as u32))
+                .flat_map(|(_, references)| references.addresses.iter().cloned())
+                .chain(module.global_addresses.iter().cloned())
+                .collect()
+        };
+        let before = addresses(&|_| true);
+        let now = addresses(&|f| plan.keeps_body(f));
+        let passive = module.data.iter().any(|data| data.memory.is_none());
+
+        let mut zeroed = 0;
+        for (m, &(ref memory, imported)) in module.memories.iter().enumerate() {
+            // The segments at a constant offset in this memory, by index.
+            let mut segments: Vec<(u32, u64, Vec<u8>)> = module
+                .data
+                .iter()
+                .enumerate()
+                .filter(|(_, data)| data.memory == Some(m as u32))
+                .filter_map(|(i, data)| Some((i as u32, data.offset?, data.value.to_vec())))
+                .collect();
+
+            if options.gc_data {
+                let unused = layout(&segments, memory.memory64).unused_pieces(&before, &now);
+                for (i, range) in unused {
+                    log::debug!(
+                        "zeroing the {} bytes of unused data at {} in memory {}",
+                        range.end - range.start,
+                        range.start,
+                        m
+                    );
+                    let (_, start, ref mut value) = segments[i];
+                    zeroed += crate::data::zero_in(value, start, range);
+                }
+            }
+
+            // Data placed at an offset from a global or by `memory.init` may
+            // need all of the memory.
+            let all_constant = module
+                .data
+                .iter()
+                .all(|data| data.memory != Some(m as u32) || data.offset.is_some());
+            if options.shrink_memory && !imported && !memory.shared && all_constant && !passive {
+                for (_, start, value) in &mut segments {
+                    *start += crate::data::trim_zeros(value) as u64;
+                    if value.is_empty() {
+                        *start = 0;
+                    }
+                }
+                let page_size = 1u64 << memory.page_size_log2.unwrap_or(16);
+                let size = memory.initial.saturating_mul(page_size);
+                let pages = layout(&segments, memory.memory64)
+                    .needed(&now, size)
+                    .div_ceil(page_size);
+                if pages < memory.initial {
+                    log::info!(
+                        "lowering the minimum size of memory {} from {} to {} pages",
+                        m,
+                        memory.initial,
+                        pages
+                    );
+                    changes.initial.insert(m as u32, pages);
+                }
+            }
+
+            for (i, start, value) in segments {
+                let data = &module.data[i as usize];
+                if data.offset != Some(start) || data.value != &value[..] {
+                    changes.segments.insert(i, (start, value));
+                }
+            }
+        }
+        if options.gc_data {
+            log::info!("zeroed {} bytes of unused data", zeroed);
+        }
+        changes
+    }
+}
+
+/// The layout of `segments`, each with its index, offset and contents, in a
+/// 64-bit memory if `memory64`.
+fn layout(segments: &[(u32, u64, Vec<u8>)], memory64: bool) -> crate::data::Layout<'_> {
+    let placed = segments
+        .iter()
+        .enumerate()
+        .map(|(index, (_, start, value))| crate::data::Placed {
+            index,
+            range: *start..start.saturating_add(value.len() as u64),
+            value,
+        })
+        .collect();
+    crate::data::Layout::new(placed, if memory64 { 8 } else { 4 })
+}
+
+/// Finding the functions that are still used after snipping.
+struct Live<'a> {
+    module: &'a Module<'a>,
+    plan: &'a Plan,
+
+    /// The stub functions that the options name, by type.
+    stubs: &'a HashMap<u32, u32>,
+
+    /// Is each function used?
+    live: Vec<bool>,
+
+    /// The functions found to be used whose bodies are still to be looked at.
+    work: Vec<u32>,
+
+    /// The types of the snipped functions that are still referred to, which
+    /// need a stub.
+    stub_types: BTreeSet<u32>,
+
+    /// The types of the snipped functions that the code kept refers to with
+    /// `ref.func`, whose stubs need declaring.
+    declared_types: BTreeSet<u32>,
+
+    /// Is each element segment used?
+    used: Vec<bool>,
+}
+
+impl Live<'_> {
+    /// Note that `f` is referred to, or a stub of its type if it is snipped.
+    fn refer(&mut self, f: u32) {
+        if !self.plan.snipped.contains(&f) {
+            self.keep(f);
+            return;
+        }
+        let ty = self.module.ty(f);
+        self.stub_types.insert(ty);
+        if let Some(&stub) = self.stubs.get(&ty) {
+            self.keep(stub);
+        }
+    }
+
+    /// Note that the element segment `i` is used, and so the functions in it.
+    fn use_segment(&mut self, i: u32) -> Result<(), SnipError> {
+        let (module, plan) = (self.module, self.plan);
+        let segment = &module.segments[i as usize];
+        if self.used[i as usize] || segment.kind == SegmentKind::Declared {
+            return Ok(());
+        }
+        self.used[i as usize] = true;
+        for &f in &segment.functions {
+            if !plan.snipped.contains(&f) {
+                self.keep(f);
+                continue;
+            }
+            if plan.nulls(segment) {
+                continue;
+            }
+            if !self.stubs.is_empty() && !self.stubs.contains_key(&module.ty(f)) {
+                return Err(SnipError::Refused(format!(
+                    "none of the stub functions has the type `{}` of `{}`, which is in a table",
+                    signature(module, module.ty(f)),
+                    function_name(module, f)
+                )));
+            }
+            self.refer(f);
+        }
+        Ok(())
+    }
+
+    fn keep(&mut self, f: u32) {
+        if !self.live[f as usize] {
+            self.live[f as usize] = true;
+            self.work.push(f);
+        }
+    }
+
+    /// Keep everything that the functions kept so far use.
+    fn run(&mut self) -> Result<(), SnipError> {
+        while let Some(f) = self.work.pop() {
+            let module = self.module;
+            if module.is_imported(f) {
+                continue;
+            }
+            let references = &module.references[f as usize - module.imports.len()];
+            for &callee in &references.calls {
+                if !self.plan.snipped.contains(&callee) {
+                    self.keep(callee);
+                }
+            }
+            for &g in &references.functions {
+                if self.plan.snipped.contains(&g) {
+                    self.declared_types.insert(self.module.ty(g));
+                }
+                self.refer(g);
+            }
+            for &i in &references.segments {
+                self.use_segment(i)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The type `ty`, like `(i32) -> i32`.
+fn signature(module: &Module, ty: u32) -> String {
+    let ty = match module.types.get(ty as usize) {
+        Some(Some(ty)) => ty,
+        _ => return "?".to_string(),
+    };
+    let list = |types: &[wasmparser::ValType]| {
+        let types: Vec<_> = types.iter().map(|&ty| val_type(ty)).collect();
+        types.join(", ")
+    };
+    let params = format!("({})", list(ty.params()));
+    match ty.results() {
+        [] => params,
+        [result] => format!("{} -> {}", params, result),
+        results => format!("{} -> ({})", params, list(results)),
+    }
+}
+
+/// The text format's name for `ty`, with type indices for concrete reference
+/// types where wasmparser would print `(module 0)`.
+fn val_type(ty: wasmparser::ValType) -> String {
+    match ty {
+        wasmparser::ValType::Ref(ty) => {
+            match ty.type_index().and_then(|index| index.as_module_index()) {
+                Some(index) if ty.is_nullable() => format!("(ref null {})", index),
+                Some(index) => format!("(ref {})", index),
+                None => ty.to_string(),
+            }
+        }
+        ty => ty.to_string(),
+    }
+}
+
+/// The name of the function `f`, or its index if it has no name.
+fn function_name(module: &Module, f: u32) -> String {
+    match module.names.iter().find(|&&(g, _)| g == f) {
+        Some(&(_, name)) => name.to_string(),
+        None => format!("function {}", f),
+    }
+}
+
+/// Re-encoding the module as the plan says.
+struct Rewriter<'a> {
+    module: &'a Module<'a>,
+    options: &'a Options,
+    plan: &'a Plan,
+    data: &'a DataChanges,
+
+    /// The custom sections to strip.
+    strip_custom_sections: regex::RegexSet,
+
+    /// How many calls to snipped functions were replaced.
+    call_sites: usize,
+}
+
+type Encoded<T> = Result<T, reencode::Error<SnipError>>;
+
+impl Reencode for Rewriter<'_> {
+    type Error = SnipError;
+
+    fn function_index(&mut self, f: u32) -> Encoded<u32> {
+        self.plan.index(self.module, f).ok_or_else(|| {
+            reencode::Error::UserError(SnipError::Emit(format!(
+                "`{}` is removed, but something still refers to it",
+                function_name(self.module, f)
+            )))
+        })
+    }
+
+    fn parse_instruction<'a>(
+        &mut self,
+        reader: &mut wasmparser::OperatorsReader<'a>,
+    ) -> Encoded<wasm_encoder::Instruction<'a>> {
+        match reader.read()? {
+            wasmparser::Operator::Call { function_index }
+            | wasmparser::Operator::ReturnCall { function_index }
+                if self.plan.snipped.contains(&function_index) =>
+            {
+                self.call_sites += 1;
+                Ok(wasm_encoder::Instruction::Unreachable)
+            }
+            op => self.instruction(op),
+        }
+    }
+}
+
+impl Rewriter<'_> {
+    fn encode(&mut self, wasm: &[u8]) -> Encoded<Vec<u8>> {
+        use wasmparser::Payload;
+
+        let mut output = wasm_encoder::Module::new();
+        let (mut names, mut producers, mut target_features) = (false, false, false);
+        let (mut functions, mut elements, mut code) = (false, false, false);
+        for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+            let payload = payload?;
+            let (id, range) = match payload.as_section() {
+                Some((id, range)) => (id, range.start as usize..range.end as usize),
+                None => continue,
+            };
+            // A module without functions of its own may need some for stubs.
+            if id != 0 && !functions && order(id) > order(3) {
+                functions = true;
+                self.function_section(&mut output, None)?;
+            }
+            if id != 0 && !elements && order(id) > order(9) {
+                elements = true;
+                self.element_section(&mut output, None)?;
+            }
+            if id != 0 && !code && order(id) > order(10) {
+                code = true;
+                self.code_section(&mut output)?;
+            }
+            match payload {
+                Payload::ImportSection(section) => self.import_section(&mut output, section)?,
+                Payload::FunctionSection(section) => {
+                    functions = true;
+                    self.function_section(&mut output, Some(section))?;
+                }
+                Payload::MemorySection(section) => self.memory_section(&mut output, section)?,
+                Payload::ExportSection(section) => self.export_section(&mut output, section)?,
+                Payload::StartSection { func, .. } => {
+                    if !self.options.snip_start {
+                        let function_index = self.function_index(func)?;
+                        output.section(&wasm_encoder::StartSection { function_index });
+                    }
+                }
+                Payload::ElementSection(section) => {
+                    elements = true;
+                    self.element_section(&mut output, Some(section))?;
+                }
+                Payload::CodeSectionStart { .. } => {
+                    code = true;
+                    self.code_section(&mut output)?;
+                }
+                Payload::DataSection(section) => self.data_section(&mut output, section)?,
+                Payload::CustomSection(section) => match section.name() {
+                    "name" => {
+                        names = true;
+                        if let wasmparser::KnownCustom::Name(reader) = section.as_known() {
+                            self.name_section(&mut output, Some(reader))?;
+                        }
+                    }
+                    "producers" => {
+                        producers = true;
+                        if let wasmparser::KnownCustom::Producers(reader) = section.as_known() {
+                            self.producers_section(&mut output, Some(reader))?;
+                        }
+                    }
+                    "target_features" => {
+                        target_features = true;
+                        self.target_features_section(&mut output, Some(&wasm[range]))?;
+                    }
+                    name if !self.strips_custom_section(name) => {
+                        output.section(&wasm_encoder::RawSection {
+                            id,
+                            data: &wasm[range],
+                        });
+                    }
+                    _ => {}
+                },
+                _ => {
+                    output.section(&wasm_encoder::RawSection {
+                        id,
+                        data: &wasm[range],
+                    });
+                }
+            }
+        }
+        if !functions {
+            self.function_section(&mut output, None)?;
+        }
+        if !elements {
+            self.element_section(&mut output, None)?;
+        }
+        if !code {
+            self.code_section(&mut output)?;
+        }
+        if !names {
+            self.name_section(&mut output, None)?;
+        }
+        if !producers {
+            self.producers_section(&mut output, None)?;
+        }
+        if !target_features {
+            self.target_features_section(&mut output, None)?;
+        }
+        Ok(output.finish())
+    }
+
+    fn import_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: wasmparser::ImportSectionReader,
+    ) -> Encoded<()> {
+        let mut imports = wasm_encoder::ImportSection::new();
+        let mut f = 0;
+        for import in section.into_imports() {
+            let import = import?;
+            if let wasmparser::TypeRef::Func(_) | wasmparser::TypeRef::FuncExact(_) = import.ty {
+                f += 1;
+                if self.plan.indices[f - 1].is_none() {
+                    continue;
+                }
+            }
+            self.parse_import(&mut imports, import)?;
+        }
+        output.section(&imports);
+        Ok(())
+    }
+
+    /// Encode the function section, given the module's if it has one, with
+    /// the new stubs at the end.
+    fn function_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: Option<wasmparser::FunctionSectionReader>,
+    ) -> Encoded<()> {
+        if section.is_none() && self.plan.new_stubs.is_empty() {
+            return Ok(());
+        }
+        let mut functions = wasm_encoder::FunctionSection::new();
+        let imported = self.module.imports.len();
+        for (i, ty) in section.into_iter().flatten().enumerate() {
+            let ty = ty?;
+            if self.plan.indices[imported + i].is_some() {
+                functions.function(self.type_index(ty)?);
+            }
+        }
+        for &ty in &self.plan.new_stubs {
+            functions.function(ty);
+        }
+        output.section(&functions);
+        Ok(())
+    }
+
+    /// Encode the code section, if the module has functions of its own or
+    /// stubs to add.
+    fn code_section(&mut self, output: &mut wasm_encoder::Module) -> Encoded<()> {
+        if self.module.bodies.is_empty() && self.plan.new_stubs.is_empty() {
+            return Ok(());
+        }
+        let trap = || {
+            let mut body = wasm_encoder::Function::new([]);
+            body.instruction(&wasm_encoder::Instruction::Unreachable);
+            body.instruction(&wasm_encoder::Instruction::End);
+            body
+        };
+        let mut code = wasm_encoder::CodeSection::new();
+        let imported = self.module.imports.len();
+        for (i, body) in self.module.bodies.iter().enumerate() {
+            let f = (imported + i) as u32;
+            if self.plan.keeps_body(f) {
+                self.parse_function_body(&mut code, body.clone())?;
+            } else if self.plan.indices[f as usize].is_some() {
+                code.function(&trap());
+            }
+        }
+        for _ in &self.plan.new_stubs {
+            code.function(&trap());
+        }
+        output.section(&code);
+        Ok(())
+    }
+
+    fn memory_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: wasmparser::MemorySectionReader,
+    ) -> Encoded<()> {
+        let mut memories = wasm_encoder::MemorySection::new();
+        let imported = self.module.memories.iter().filter(|&&(_, i)| i).count();
+        for (i, memory) in section.into_iter().enumerate() {
+            let mut memory = memory?;
+            if let Some(&pages) = self.data.initial.get(&((imported + i) as u32)) {
+                memory.initial = pages;
+            }
+            memories.memory(self.memory_type(memory)?);
+        }
+        output.section(&memories);
+        Ok(())
+    }
+
+    fn data_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: wasmparser::DataSectionReader,
+    ) -> Encoded<()> {
+        let mut data = wasm_encoder::DataSection::new();
+        for (i, datum) in section.into_iter().enumerate() {
+            let datum = datum?;
+            match (self.data.segments.get(&(i as u32)), &datum.kind) {
+                (Some((start, value)), &wasmparser::DataKind::Active { memory_index, .. }) => {
+                    let wide = self.module.memories[memory_index as usize].0.memory64;
+                    let offset = crate::data::offset_expr(wide, *start);
+                    data.active(memory_index, &offset, value.iter().copied());
+                }
+                _ => self.parse_data(&mut data, datum)?,
+            }
+        }
+        output.section(&data);
+        Ok(())
+    }
+
+    fn export_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: wasmparser::ExportSectionReader,
+    ) -> Encoded<()> {
+        let mut exports = wasm_encoder::ExportSection::new();
+        for export in section {
+            let export = export?;
+            let function = matches!(
+                export.kind,
+                wasmparser::ExternalKind::Func | wasmparser::ExternalKind::FuncExact
+            );
+            if function
+                && self.plan.snipped.contains(&export.index)
+                && !self.plan.preserve_indices
+                && !self.options.stub_exports
+            {
+                continue;
+            }
+            self.parse_export(&mut exports, export)?;
+        }
+        output.section(&exports);
+        Ok(())
+    }
+
+    fn element_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: Option<wasmparser::ElementSectionReader>,
+    ) -> Encoded<()> {
+        let (module, plan) = (self.module, self.plan);
+        if section.is_none() && plan.declared_stubs.is_empty() {
+            return Ok(());
+        }
+        let mut elements = wasm_encoder::ElementSection::new();
+        for (i, element) in section.into_iter().flatten().enumerate() {
+            let element = element?;
+            let segment = &module.segments[i];
+            let null = plan.nulls(segment);
+            let snipped = |f| null && plan.snipped.contains(&f);
+            // Passive segments that no code copies keep their place, for
+            // `elem.drop`, but not their functions, and declarations only
+            // declare the functions that are still there.
+            let kept = |f| match segment.kind {
+                SegmentKind::Active => true,
+                SegmentKind::Passive => plan.used[i],
+                SegmentKind::Declared => plan.index(module, f).is_some(),
+            };
+            let items = match element.items {
+                wasmparser::ElementItems::Functions(funcs) => {
+                    let mut kept_funcs = vec![];
+                    for f in funcs {
+                        let f = f?;
+                        if kept(f) {
+                            kept_funcs.push(f);
+                        }
+                    }
+                    let funcs = kept_funcs;
+                    if funcs.iter().any(|&f| snipped(f)) {
+                        // Only expressions can be null.
+                        let mut exprs = vec![];
+                        for f in funcs {
+                            exprs.push(if snipped(f) {
+                                wasm_encoder::ConstExpr::ref_null(wasm_encoder::HeapType::FUNC)
+                            } else {
+                                wasm_encoder::ConstExpr::ref_func(self.function_index(f)?)
+                            });
+                        }
+                        wasm_encoder::Elements::Expressions(
+                            wasm_encoder::RefType::FUNCREF,
+                            exprs.into(),
+                        )
+                    } else {
+                        let mut indices = vec![];
+                        for f in funcs {
+                            indices.push(self.function_index(f)?);
+                        }
+                        wasm_encoder::Elements::Functions(indices.into())
+                    }
+                }
+                wasmparser::ElementItems::Expressions(ty, items) => {
+                    let mut exprs = vec![];
+                    for expr in items {
+                        let expr = expr?;
+                        let f = expr_function(&expr).map_err(reencode::Error::UserError)?;
+                        if segment.kind == SegmentKind::Passive && !plan.used[i]
+                            || f.is_some_and(|f| !kept(f))
+                        {
+                            continue;
+                        }
+                        exprs.push(match f {
+                            Some(f) if snipped(f) => {
+                                wasm_encoder::ConstExpr::ref_null(self.heap_type(ty.heap_type())?)
+                            }
+                            _ => self.const_expr(expr)?,
+                        });
+                    }
+                    wasm_encoder::Elements::Expressions(self.ref_type(ty)?, exprs.into())
+                }
+            };
+            match element.kind {
+                wasmparser::ElementKind::Active {
+                    table_index,
+                    offset_expr,
+                } => elements.active(table_index, &self.const_expr(offset_expr)?, items),
+                wasmparser::ElementKind::Passive => elements.passive(items),
+                wasmparser::ElementKind::Declared => elements.declared(items),
+            };
+        }
+        if !plan.declared_stubs.is_empty() {
+            let stubs = Cow::Borrowed(&plan.declared_stubs[..]);
+            elements.declared(wasm_encoder::Elements::Functions(stubs));
+        }
+        output.section(&elements);
+        Ok(())
+    }
+
+    /// Encode the name section, given the module's if it has one, without the
+    /// names of removed functions and the locals and labels of snipped ones,
+    /// and with the names of new stubs.
+    fn name_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: Option<wasmparser::NameSectionReader>,
+    ) -> Encoded<()> {
+        let options = self.options;
+        let stub_names = self.stub_names();
+        if section.is_none() && stub_names.is_empty() {
+            return Ok(());
+        }
+        let exported: HashSet<_> = self.module.exports.iter().map(|&(_, f)| f).collect();
+        let mut names = wasm_encoder::NameSection::new();
+        let mut functions = false;
+        for subsection in section.into_iter().flatten() {
+            let subsection = subsection?;
+            if !functions && !matches!(subsection, wasmparser::Name::Module { .. }) {
+                functions = true;
+                let map = match subsection {
+                    wasmparser::Name::Function(ref map) => Some(map.clone()),
+                    _ => None,
+                };
+                let mut sorted = vec![];
+                for naming in map.into_iter().flatten() {
+                    let naming = naming?;
+                    let f = naming.index;
+                    // Names of functions that don't exist are dropped.
+                    match self.plan.indices.get(f as usize).copied().flatten() {
+                        Some(i)
+                            if !options.strip_names
+                                || options.keep_export_names && exported.contains(&f) =>
+                        {
+                            sorted.push((i, naming.name.to_string()))
+                        }
+                        _ => {}
+                    }
+                }
+                sorted.extend(stub_names.iter().cloned());
+                sorted.sort();
+                if !sorted.is_empty() {
+                    let mut map = wasm_encoder::NameMap::new();
+                    for (i, name) in &sorted {
+                        map.append(*i, name);
+                    }
+                    names.functions(&map);
+                }
+            }
+            match subsection {
+                wasmparser::Name::Module { name, .. } => {
+                    if !options.strip_names {
+                        names.module(name);
+                    }
+                }
+                wasmparser::Name::Function(_) => {}
+                wasmparser::Name::Local(_) | wasmparser::Name::Label(_)
+                    if options.strip_names || options.strip_local_names => {}
+                wasmparser::Name::Local(map) => names.locals(&self.indirect_name_map(map)?),
+                wasmparser::Name::Label(map) => names.labels(&self.indirect_name_map(map)?),
+                subsection => self.parse_custom_name_subsection(&mut names, subsection)?,
+            }
+        }
+        if !functions && !stub_names.is_empty() {
+            let mut map = wasm_encoder::NameMap::new();
+            for (i, name) in &stub_names {
+                map.append(*i, name);
+            }
+            names.functions(&map);
+        }
+        output.section(&names);
+        Ok(())
+    }
+
+    /// The names of the new stubs, with `name_stubs`.
+    fn stub_names(&self) -> Vec<(u32, String)> {
+        if !self.options.name_stubs || self.options.strip_names {
+            return vec![];
+        }
+        let first = self.plan.indices.iter().flatten().count() as u32;
+        self.plan
+            .new_stubs
+            .iter()
+            .enumerate()
+            .map(|(i, &ty)| {
+                let name = format!(
+                    "wasm_snip::unreachable_stub::<{}>",
+                    signature(self.module, ty)
+                );
+                (first + i as u32, name)
+            })
+            .collect()
+    }
+
+    /// The names of the locals or labels of the functions that keep their
+    /// bodies.
+    fn indirect_name_map(
+        &self,
+        map: wasmparser::IndirectNameMap,
+    ) -> Encoded<wasm_encoder::IndirectNameMap> {
+        let mut names = wasm_encoder::IndirectNameMap::new();
+        for naming in map {
+            let naming = naming?;
+            let index = match self
+                .plan
+                .indices
+                .get(naming.index as usize)
+                .copied()
+                .flatten()
+            {
+                Some(index) if self.plan.keeps_body(naming.index) => index,
+                _ => continue,
+            };
+            let mut inner = wasm_encoder::NameMap::new();
+            for name in naming.names {
+                let name = name?;
+                inner.append(name.index, name.name);
+            }
+            names.append(index, &inner);
+        }
+        Ok(names)
+    }
+
+    /// Encode the producers section, given the module's if it has one, with
+    /// `wasm-snip` among the tools it was processed by.
+    fn producers_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        section: Option<wasmparser::ProducersSectionReader>,
+    ) -> Encoded<()> {
+        if self.options.skip_producers_section {
+            if let Some(section) = section {
+                let mut producers = wasm_encoder::ProducersSection::new();
+                for field in section {
+                    let field = field?;
+                    let mut values = wasm_encoder::ProducersField::new();
+                    for value in field.values {
+                        let value = value?;
+                        values.value(value.name, value.version);
+                    }
+                    producers.field(field.name, &values);
+                }
+                output.section(&producers);
+            }
+            return Ok(());
+        }
+        let mut producers = wasm_encoder::ProducersSection::new();
+        let mut processed_by = false;
+        for field in section.into_iter().flatten() {
+            let field = field?;
+            let mut values = wasm_encoder::ProducersField::new();
+            for value in field.values {
+                let value = value?;
+                if field.name != "processed-by" || value.name != "wasm-snip" {
+                    values.value(value.name, value.version);
+                }
+            }
+            if field.name == "processed-by" {
+                processed_by = true;
+                values.value("wasm-snip", env!("CARGO_PKG_VERSION"));
+            }
+            producers.field(field.name, &values);
+        }
+        if !processed_by {
+            let mut values = wasm_encoder::ProducersField::new();
+            values.value("wasm-snip", env!("CARGO_PKG_VERSION"));
+            producers.field("processed-by", &values);
+        }
+        output.section(&producers);
+        Ok(())
+    }
+
+    /// Encode the `target_features` section, given the module's contents for
+    /// it if it has one.
+    fn target_features_section(
+        &mut self,
+        output: &mut wasm_encoder::Module,
+        data: Option<&[u8]>,
+    ) -> Encoded<()> {
+        let data = match (&self.options.target_features, data) {
+            (TargetFeatures::Preserve, Some(data)) => Cow::Borrowed(data),
+            (TargetFeatures::Set(features), _) => Cow::Owned(crate::target_features_data(features)),
+            _ => return Ok(()),
+        };
+        output.section(&wasm_encoder::CustomSection {
+            name: Cow::Borrowed("target_features"),
+            data,
+        });
+        Ok(())
+    }
+
+    /// Do the options strip the custom section named `name`?
+    fn strips_custom_section(&self, name: &str) -> bool {
+        self.options.strip_dwarf && name.starts_with(".debug_")
+            || self.strip_custom_sections.is_match(name)
+    }
+}
+
+/// Where sections with the id `id` go relative to the others, since the tag
+/// and data count sections go out of order by id.
+fn order(id: u8) -> u8 {
+    match id {
+        13 => 6,
+        6..=9 => id + 1,
+        12 => 11,
+        10 | 11 => id + 2,
+        _ => id,
+    }
+}
@@ -0,0 +1,152 @@
+//! Carrying label names through snipping.
+//!
+//! walrus keeps the module, function and local names of the name section, but
+//! drops the names of blocks, loops and ifs. So that debuggers still show
+//! them, they are read from the input and kept in a placeholder custom section
+//! that walrus renumbers along with everything else. After emitting, the
+//! placeholder's contents are moved into the name section.
+
+use crate::SnipError;
+use std::borrow::Cow;
+use wasm_encoder::reencode::{self, Reencode};
+
+/// The name of the placeholder custom section.
+const PLACEHOLDER: &str = "wasm-snip.label-names";
+
+/// The id of the label names subsection of the name section.
+const LABELS_SUBSECTION: u8 = 3;
+
+/// The label names of each function, by the index of the label.
+#[derive(Debug, Default)]
+pub(crate) struct LabelNames {
+    functions: Vec<(walrus::FunctionId, Vec<(u32, String)>)>,
+}
+
+impl walrus::CustomSection for LabelNames {
+    fn name(&self) -> &str {
+        PLACEHOLDER
+    }
+
+    fn data(&self, ids_to_indices: &walrus::IdsToIndices) -> Cow<'_, [u8]> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(f, labels)| (ids_to_indices.get_func_index(*f), labels))
+            .collect();
+        functions.sort_by_key(|&(index, _)| index);
+
+        let mut map = wasm_encoder::IndirectNameMap::new();
+        for (index, labels) in functions {
+            let mut names = wasm_encoder::NameMap::new();
+            for (label, name) in labels {
+                names.append(*label, name);
+            }
+            map.append(index, &names);
+        }
+        let mut data = vec![];
+        wasm_encoder::Encode::encode(&map, &mut data);
+        data.into()
+    }
+}
+
+/// Read the label names of `wasm`, which `module` was just parsed from, into a
+/// placeholder custom section of `module`.
+pub(crate) fn read(module: &mut walrus::Module, wasm: &[u8]) -> Result<(), SnipError> {
+    // Until functions are added or removed, a function's id has the same
+    // index as the function.
+    let ids: Vec<_> = module.funcs.iter().map(|f| f.id()).collect();
+
+    let mut labels = LabelNames::default();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        let section = match payload? {
+            wasmparser::Payload::CustomSection(section) => section,
+            _ => continue,
+        };
+        let names = match section.as_known() {
+            wasmparser::KnownCustom::Name(names) => names,
+            _ => continue,
+        };
+        for subsection in names {
+            let map = match subsection? {
+                wasmparser::Name::Label(map) => map,
+                _ => continue,
+            };
+            for naming in map {
+                let naming = naming?;
+                let f = match ids.get(naming.index as usize) {
+                    Some(&f) => f,
+                    None => continue,
+                };
+                let mut names = vec![];
+                for name in naming.names {
+                    let name = name?;
+                    names.push((name.index, name.name.to_string()));
+                }
+                labels.functions.push((f, names));
+            }
+        }
+    }
+    if !labels.functions.is_empty() {
+        module.customs.add(labels);
+    }
+    Ok(())
+}
+
+/// Forget the label names of the functions for which `keep` returns false.
+pub(crate) fn retain(module: &mut walrus::Module, keep: impl Fn(walrus::FunctionId) -> bool) {
+    if let Some(labels) = module.customs.get_typed_mut::<LabelNames>() {
+        labels.functions.retain(|(f, _)| keep(*f));
+    }
+}
+
+/// Forget every label name.
+pub(crate) fn clear(module: &mut walrus::Module) {
+    module.customs.delete_typed::<LabelNames>();
+}
+
+/// Move the label names in the placeholder section of the emitted `wasm` into
+/// its name section, if it has both.
+pub(crate) fn move_into_name_section(wasm: Vec<u8>) -> Result<Vec<u8>, SnipError> {
+    let mut labels = None;
+    for payload in wasmparser::Parser::new(0).parse_all(&wasm) {
+        if let wasmparser::Payload::CustomSection(section) = payload? {
+            if section.name() == PLACEHOLDER {
+                labels = Some(section.data().to_vec());
+            }
+        }
+    }
+    let labels = match labels {
+        Some(labels) => labels,
+        None => return Ok(wasm),
+    };
+
+    let mut mover = Mover { labels };
+    let mut module = wasm_encoder::Module::new();
+    mover.parse_core_module(&mut module, wasmparser::Parser::new(0), &wasm)?;
+    Ok(module.finish())
+}
+
+struct Mover {
+    labels: Vec<u8>,
+}
+
+impl Reencode for Mover {
+    type Error = std::convert::Infallible;
+
+    fn parse_custom_section(
+        &mut self,
+        module: &mut wasm_encoder::Module,
+        section: wasmparser::CustomSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        match section.as_known() {
+            wasmparser::KnownCustom::Name(names) => {
+                let mut names = self.custom_name_section(names)?;
+                names.raw(LABELS_SUBSECTION, &self.labels);
+                module.section(&names);
+            }
+            _ if section.name() == PLACEHOLDER => {}
+            _ => reencode::utils::parse_custom_section(self, module, section)?,
+        }
+        Ok(())
+    }
+}
@@ -49,21 +49,199 @@ And you'll get the most up-to-date help text, like:
 Replace a wasm function with an `unreachable`.
 
 USAGE:
-wasm-snip [FLAGS] [OPTIONS] <input> [--] [function]...
+wasm-snip [FLAGS] [OPTIONS] <input> [function]...
+wasm-snip <SUBCOMMAND>
 
 FLAGS:
--h, --help                    Prints help information
---snip-rust-fmt-code          Snip Rust's `std::fmt` and `core::fmt` code.
---snip-rust-panicking-code    Snip Rust's `std::panicking` and `core::panicking` code.
--V, --version                 Prints version information
+    --demangle                       Also match function names and patterns against demangled Rust symbol names,
+                                     e.g. `core::fmt::write::h0123456789abcdef`.
+    --demangle-cpp                   Also match function names and patterns against demangled C++ symbol names, e.g.
+                                     `std::__2::basic_string<...>::append(char const*)`.
+    --force                          Snip functions even if they are exported.
+    --gc-data                        Remove the data that only snipped functions referred to, like their panic
+                                     messages, assuming that code refers to data by the address it starts at.
+-h, --help                           Prints help information
+-i, --ignore-case                    Match function names and patterns regardless of case.
+    --ignore-hash                    Match exact function names against Rust symbols regardless of their trailing
+                                     hash, so that `hello::snip_me` matches `hello::snip_me::h0123456789abcdef`.
+    --keep-export-names              With --strip-names, keep the names of exported functions.
+    --keep-imports                   Turn snipped imported functions into local functions that trap, instead of
+                                     removing them and the calls to them.
+    --list-presets                   List the built-in presets and exit.
+    --name-stubs                     Name the stubs that replace snipped functions in tables, exports and the start
+                                     section after their type, like `wasm_snip::unreachable_stub::<(i32) -> i32>`.
+    --no-cargo-metadata              Do not read snip settings from the `[package.metadata.wasm-snip]` table of the
+                                     nearest Cargo.toml when no --config file is given.
+    --no-gc                          Don't remove the functions and other items that are unused after snipping.
+    --no-progress                    Don't show which phase snipping is in on stderr. Progress is only shown when
+                                     stderr is a terminal, snipping takes a while, and -v isn't given.
+    --preserve-indices               Keep function indices stable: give snipped functions bodies that trap instead
+                                     of removing them, and don't remove unused functions.
+    --report                         Print each snipped function to stderr, along with the functions that were
+                                     removed because snipping it left them unused.
+    --shrink-memory                  Lower the minimum size of memory to what its data and the constant addresses
+                                     that code uses need.
+    --shrink-tables                  Remove the table elements and element segments that are all stubs, and shrink
+                                     the tables that end in them where that is safe.
+    --skip-producers-section         Do not emit the 'producers' custom section.
+    --snip-assemblyscript-abort      Snip AssemblyScript's `abort` and `trace` builtins and the string concatenation
+                                     helpers they use.
+    --snip-emscripten-exceptions     Snip the C++ exception runtime, e.g. `__cxa_throw`.
+    --snip-emscripten-sbrk           Snip Emscripten's `sbrk` and heap resizing code.
+    --snip-emscripten-syscalls       Snip Emscripten's `__syscall_*` stubs.
+    --snip-panic-strings             Blank the messages passed to Rust's `core::panicking::panic*` functions, leaving
+                                     them out of the data section.
+    --snip-rust-alloc-error          Snip Rust's allocation error and out-of-memory handling code.
+    --snip-rust-backtrace            Snip Rust's backtrace capture and symbolization code.
+    --snip-rust-float-fmt            Snip Rust's float formatting and parsing code.
+    --snip-rust-fmt-code             Snip Rust's `std::fmt` and `core::fmt` code.
+    --snip-rust-panicking-code       Snip Rust's `std::panicking` and `core::panicking` code.
+    --snip-rust-unicode              Snip Rust's Unicode tables and case conversion code.
+    --snip-start                     Remove the start section, so the start function doesn't run on instantiation.
+    --snip-tinygo-prints             Snip TinyGo's `runtime.print*` functions.
+    --snip-tinygo-reflect            Snip TinyGo's `reflect` and `internal/reflectlite` packages.
+    --snip-wasm-bindgen-internals    Snip wasm-bindgen's `__wbindgen_describe_*` functions and other internals that
+                                     are only used by the `wasm-bindgen` CLI.
+    --stats                          Print the sizes of the input and output, and of their code, data, name and
+                                     other custom sections, to stderr.
+    --strict                         Fail instead of warning when the snipped module would be broken.
+    --strip-dwarf                    Do not emit the DWARF `.debug_*` custom sections.
+    --strip-local-names              Do not emit the names of locals and labels.
+    --strip-names                    Do not emit the 'name' custom section.
+    --stub-exports                   Keep the exports of snipped functions, pointing them at stubs that trap,
+                                     instead of removing them.
+    --suggest                        Instead of snipping, suggest snips that are likely to be safe: matching
+                                     presets, functions that always trap, and large leaf functions, with how much
+                                     code each would remove.
+    --table-null                     Empty the table elements of snipped functions, so calling them traps, instead
+                                     of pointing them at stubs.
+    --timings                        Print how long each step took to stderr: parsing, each pass of snipping, like
+                                     matching functions, rewriting calls and table elements, and removing unused
+                                     items, and emitting.
+    --update-dwarf                   Move DWARF addresses to where their functions end up, and tombstone those of
+                                     snipped and removed functions.
+    --update-source-map              Update the source map named by the `sourceMappingURL` section for the snipped
+                                     module, and write it next to the output as `<output>.map`.
+-v, --verbose                        Log what snipping does to stderr: `-v` for a summary of each pass, `-vv` for
+                                     why each function is snipped and what is removed, and `-vvv` for what walrus
+                                     does too. `RUST_LOG` takes precedence.
+-V, --version                        Prints version information
+    --warn-reachable                 Warn about snipped functions that kept functions can still call, showing how
+                                     each is reached.
 
 OPTIONS:
--o, --output <output>         The path to write the output wasm file to. Defaults to stdout.
--p, --pattern <pattern>...    Snip any function that matches the given regular expression.
+    --baseline <baseline>
+        Warn about functions in the snipped module that are new or bigger than in the given baseline module. With
+        `--strict`, fail instead.
+-c, --config <config>
+        Read snip settings from the given TOML file. Flags on the command line take precedence over the file's
+        settings.
+    --emit-remap <emit_remap>
+        Write a JSON file mapping each function's index in the input module to its index in the output module, or to
+        "removed".
+    --emit-removed-graph <emit_removed_graph>
+        Write a Graphviz DOT graph of the functions removed from the module, with an edge for each call between
+        them.
+-x, --exclude-pattern <exclude_pattern>...
+        Never snip a function that matches the given regular expression, even if it matches a -p pattern or a
+        --snip-rust-* preset.
+    --functions-from-file <functions_from_file>...
+        Snip the functions listed in the given file, one exact name per line. Blank lines and lines starting with
+        '#' are ignored.
+    --index <index>...
+        Snip the function with the given index, counting imported functions first. This works without a name
+        section.
+    --index-range <index_range>...
+        Snip the functions with indices in the given range, like `100..250`.
+
+    --keep-function <keep_function>...
+        Never snip the given function, no matter how else it was selected. This must match exactly.
+
+    --keep-pattern <keep_pattern>...
+        Never snip any function that matches the given regular expression, no matter how else it was selected.
+
+    --max-output-size <max_output_size>
+        Fail if the snipped module is bigger than the given number of bytes. The size may end in `K` or `M`, like
+        `512K`.
+    --min-size <min_size>
+        Only snip functions selected by patterns, crates or presets if their body is at least the given number of
+        bytes. The size may end in `K` or `M`.
+-o, --output <output>
+        The path to write the output wasm file to. Defaults to stdout.
+
+-p, --pattern <pattern>...                              Snip any function that matches the given regular expression.
+    --patterns-from-file <patterns_from_file>...
+        Snip any function that matches a regular expression listed in the given file, one per line. Blank lines and
+        lines starting with '#' are ignored.
+    --preset <preset>...
+        Snip the functions matched by the given built-in preset. The --snip-* preset flags are aliases for this.
+
+    --preset-file <preset_file>...
+        Read additional presets for --preset from the given TOML or JSON file. See https://docs.rs/wasm-
+        snip/latest/wasm_snip/presets/index.html for the format.
+    --replace-body <replace_body>
+        Read WAT functions from the given file, and use their bodies for snipped functions of the same type instead
+        of --replace-with.
+    --replace-with <replace_with>
+        How to replace snipped functions: remove them and make their callers trap (`unreachable`), keep them and
+        make them return zero or null (`default`), or keep them and make them pass their index in the input module
+        to the given import before trapping (`call-import=env::snipped_hit`), or keep them and give them the body of
+        the WAT function of their type (`custom-body=(func i32.const -1)`). Defaults to `unreachable`.
+    --signature <signature>...
+        Only snip functions selected by patterns, crates or presets if they have the given type, like `(i32, i32) ->
+        i64`. Without those, snip every function with the given type.
+    --simulate <simulate>...
+        Instead of snipping, print which functions snipping the given function would remove, and how much code they
+        contain.
+    --snip-crate <snip_crate>...
+        Snip every Rust function belonging to the given crate, e.g. `serde_json`.
+
+    --snip-import <snip_import>...
+        Snip the function imported from the given module and field, e.g. `wasi_snapshot_preview1::fd_write`.
+
+    --snip-import-pattern <snip_import_pattern>...
+        Snip the imported functions whose `module::field` name matches the regex.
+
+    --strip-custom-section <strip_custom_section>...
+        Remove the custom sections whose names match the given regular expression. Other custom sections are kept
+        byte for byte.
+    --stub-function <stub_function>...
+        Point table elements of snipped functions at the given function, like one that reports the hit before
+        trapping, instead of a new stub. Give one function for each type of snipped function in a table.
+    --symbols <symbols>...
+        Name the functions of a module without a name section with the given symbol map, which has `index:name`
+        lines, or with a `.json` file mapping indices to names.
+    --target-features <target_features>
+        What to do with the `target_features` section: keep it (`preserve`), remove it (`strip`), or replace it with
+        the given features (`set=+mutable-globals,+sign-ext`). Defaults to `preserve`.
+    --threads <threads>
+        Parse, snip and emit with the given number of threads instead of one for each CPU. With 1, everything
+        happens on one thread, which makes debugging deterministic.
 
 ARGS:
-<input>          The input wasm file containing the function(s) to snip.
+<input>          The input wasm file containing the function(s) to snip, or `-` for stdin.
 <function>...    The specific function(s) to snip. These must match exactly. Use the -p flag for fuzzy matching.
+
+SUBCOMMANDS:
+help    Prints this message or the help of the given subcommand(s)
+list    List the functions in a wasm file with their indices and body sizes, from largest to smallest.
+why     Print the chain of calls, starting at an export, the start function or an exported table, that keeps a
+        function alive.
+```
+
+Snip settings can also be kept in a TOML file and passed with `--config`, or in
+the `[package.metadata.wasm-snip]` table of the crate's `Cargo.toml`, so that a
+project can check its snip policy into the repository. See the [`config`
+module](https://docs.rs/wasm-snip/latest/wasm_snip/config/index.html) for the file
+format.
+
+Installing `wasm-snip` also installs a `cargo snip` subcommand. It snips the
+most recently built wasm artifact of the current crate with the settings from
+its `Cargo.toml`, and writes the result next to the artifact:
+
+```text
+$ cargo build --release --target wasm32-unknown-unknown
+$ cargo snip --release
 ```
 
 ## Library
@@ -80,6 +258,119 @@ See [docs.rs/wasm-snip][docs] for API documentation.
 
 [docs]: https://docs.rs/wasm-snip
 
+### C bindings
+
+Build systems that aren't written in Rust can link `wasm-snip` instead of
+running the executable. Built as a `cdylib` with the `capi` feature, the
+library exports `wasm_snip_run`, which snips wasm in memory with options given
+as JSON, along with `wasm_snip_free` and `wasm_snip_last_error`:
+
+```text
+$ cargo rustc --release --lib --features capi --crate-type cdylib
+```
+
+`include/wasm_snip.h` declares them, and the
+[`capi`](https://docs.rs/wasm-snip/latest/wasm_snip/capi/index.html) module documents
+them.
+
+### Node.js
+
+`bindings/node` is a Node.js addon, built with [napi-rs], for bundler plugins
+to snip wasm in-process instead of spawning `wasm-snip`:
+
+```js
+const { snip } = require("wasm-snip");
+const snipped = snip(fs.readFileSync("input.wasm"), { snipRustFmtCode: true });
+```
+
+Options are the fields of `Options`, in camelCase. Build it with `npm run
+build` in that directory.
+
+[napi-rs]: https://napi.rs
+
+### Python
+
+`bindings/python` is a Python module, built with [PyO3] and [maturin], for
+build orchestration written in Python:
+
+```python
+import wasm_snip
+snipped = wasm_snip.snip(wasm, snip_rust_fmt_code=True)
+```
+
+The keyword arguments are the fields of `Options`. Build and install it with
+`pip install .` in that directory.
+
+[PyO3]: https://pyo3.rs
+[maturin]: https://www.maturin.rs
+
+## WebAssembly
+
+`wasm-snip` itself builds for WASI, for running in sandboxes and browser-based
+build tools:
+
+```text
+$ rustup target add wasm32-wasip1
+$ cargo build --release --target wasm32-wasip1
+```
+
+Release builds of `wasm-snip.wasm` are attached to each GitHub release. Given
+`-` as its input, it reads the module from stdin and writes the snipped module
+to stdout, so it needs no access to the filesystem:
+
+```text
+$ wasmtime wasm-snip.wasm - --snip-rust-fmt-code < input.wasm > snipped.wasm
+```
+
+The library builds for any wasm target, and parses, snips and emits modules
+without touching the filesystem, unless options such as `functions_files`
+name files to read.
+
+## Components
+
+`wasm-snip` snips components too, such as those built for WASI preview 2, with
+no need to extract their core modules first. Functions are snipped from each
+of the component's core modules, including those of nested components, and
+the rest of the component is kept as is. `--simulate`, `--suggest`, `--report`,
+`--stats` and the other options that look at a single module aren't supported
+for components.
+
+## Object files
+
+`wasm-snip` also snips relocatable object files, like the `.o` files that
+`clang -c` and `rustc --emit obj` produce, so that snipping can happen before
+linking. Functions are selected by the names of their symbols. Their bodies are
+replaced with `unreachable` in place, and the relocations of the rest of the
+code are updated, so the object file still links with `wasm-ld`, which then
+removes whatever is no longer used. The options that look at a single module
+aren't supported for object files either.
+
+## Newer proposals
+
+walrus, which `wasm-snip` parses modules with, doesn't know the reference
+types, bulk memory, tail call, function references, exception handling,
+memory64 and multi-memory proposals yet. Modules that use them are snipped in
+place instead: functions are selected as usual, then the module is re-encoded
+without the snipped functions and the functions that only they used, with
+calls to snipped functions replaced with `unreachable`, and the rest of the
+code copied as it is. Only the `unreachable` strategy is supported for them,
+and of the options that look at a single module only `--gc-data` and
+`--shrink-memory` are, which handle 64-bit memories as well.
+
+Modules built with `-C target-feature=+atomics`, which use shared memories,
+atomic instructions and passive data segments, are parsed by walrus and can
+be snipped as any other module.
+
+## Large modules
+
+With the `mmap` feature, `wasm-snip` maps its input into memory instead of
+reading it, which roughly halves how much memory snipping a large module
+takes, since the pages of the input can be evicted again after parsing:
+
+```text
+$ cargo install wasm-snip --features mmap
+```
+
 ## License
 
 Licensed under either of
@@ -105,22 +396,58 @@ dual licensed as above, without any additional terms or conditions.
 #![deny(missing_docs)]
 #![deny(missing_debug_implementations)]
 
-use failure::ResultExt;
 use rayon::prelude::*;
+use std::borrow::Cow;
+use std::cmp;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
 use std::path;
-use walrus::ir::VisitorMut;
+use std::str;
+use std::time::{Duration, Instant};
+
+pub mod builder;
+pub mod callgraph;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod code;
+mod component;
+#[cfg(feature = "config")]
+pub mod config;
+mod data;
+mod dwarf;
+mod error;
+mod file;
+mod in_place;
+mod labels;
+mod object;
+pub mod passes;
+pub mod pipeline;
+pub mod presets;
+mod proposals;
+mod reorder;
+#[cfg(feature = "config")]
+mod source_map;
+pub mod suggest;
+mod template;
+
+pub use error::{ResultExt, SnipError};
+pub use file::WasmFile;
 
 /// Input configuration.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum Input {
     /// The input `.wasm` file that should have its function snipped.
     File(path::PathBuf),
     /// The input WebAssembly blob that should have its function snipped.
     Buffer(Vec<u8>),
-    // TODO: Support Walrus module directly.
-    // Module(walrus::Module),
+    /// A module that was already parsed or built with walrus, such as by a
+    /// post-processor that snips as one of its steps.
+    Module(Box<walrus::Module>),
 }
 
 impl Default for Input {
@@ -129,9 +456,38 @@ impl Default for Input {
     }
 }
 
+impl Input {
+    /// The module to [`snip`](fn.snip.html), parsing files and blobs with
+    /// [`parse_module`](fn.parse_module.html). A `Module` is returned as it
+    /// is, without a round trip through the binary format.
+    ///
+    /// Since a `Module` wasn't parsed by `parse_module`, `options` can't make
+    /// it keep label names or update its DWARF or source map.
+    pub fn into_module(self, options: &Options) -> Result<walrus::Module, SnipError> {
+        match self {
+            Input::File(path) => parse_module(&WasmFile::open(path)?, options),
+            Input::Buffer(wasm) => parse_module(&wasm, options),
+            Input::Module(module) => Ok(*module),
+        }
+    }
+}
+
 /// Options for controlling which functions in what `.wasm` file should be
 /// snipped.
+///
+/// [`Options::builder`](#method.builder) builds options that are checked up
+/// front, instead of when they are first used to snip.
+///
+/// With the `serde` feature enabled, `Options` can be serialized and
+/// deserialized. Field names are spelled in kebab-case, just like in
+/// [configuration files](config/index.html), and missing fields take their
+/// default values.
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, deny_unknown_fields, rename_all = "kebab-case")
+)]
 pub struct Options {
     /// The functions that should be snipped from the `.wasm` file.
     pub functions: Vec<String>,
@@ -140,210 +496,2216 @@ pub struct Options {
     /// file.
     pub patterns: Vec<String>,
 
+    /// The indices of functions that should be snipped, counting imported
+    /// functions first, as in the `.wasm` file. Like `functions`, these work
+    /// when the module has no name section.
+    pub indices: Vec<u32>,
+
+    /// Ranges of indices of functions that should be snipped, like `indices`.
+    pub index_ranges: Vec<std::ops::Range<u32>>,
+
+    /// Files naming the functions of a module without a name section, say
+    /// from a separate build with names. Each line is `index:name`, as written
+    /// by Emscripten's `--emit-symbol-map`, or `index name`. Files ending in
+    /// `.json` instead hold an object mapping indices to names, and reading
+    /// them requires the `config` feature. Names are only used to select
+    /// functions that the module doesn't name itself.
+    pub symbols_files: Vec<path::PathBuf>,
+
+    /// Files containing additional functions to snip, one exact name per line.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub functions_files: Vec<path::PathBuf>,
+
+    /// Files containing additional regex patterns to snip, one pattern per
+    /// line. Blank lines and lines starting with `#` are ignored.
+    pub patterns_files: Vec<path::PathBuf>,
+
+    /// The regex patterns whose matches should never be snipped, even if they
+    /// also match one of `patterns` or a Rust preset. Functions listed in
+    /// `functions` by exact name are still snipped.
+    pub exclude_patterns: Vec<String>,
+
+    /// Functions that must never be snipped. This overrides every other way of
+    /// selecting functions, including `functions`, the Rust presets, `indices`
+    /// and `snip_imports`. Functions without a name in the name section are
+    /// matched by the same fallback names as `functions` matches them by.
+    pub keep_functions: Vec<String>,
+
+    /// The regex patterns whose matches must never be snipped. Like
+    /// `keep_functions`, this overrides every other way of selecting
+    /// functions.
+    pub keep_patterns: Vec<String>,
+
+    /// The crates whose functions should all be snipped, e.g. `serde_json`.
+    /// A mangled Rust symbol belongs to the crate at the start of its path, or
+    /// for trait impls on types that aren't paths, to the trait's crate. Like
+    /// `patterns`, these are subject to `exclude_patterns`.
+    pub snip_crates: Vec<String>,
+
+    /// Imported functions that should be snipped, named by the module and
+    /// field they are imported from, like `wasi_snapshot_preview1::fd_write`.
+    /// These work even when the module has no name section.
+    pub snip_imports: Vec<String>,
+
+    /// The regex patterns over `module::field` import names whose matching
+    /// imported functions should be snipped.
+    pub snip_import_patterns: Vec<String>,
+
+    /// The size in bytes that a function's body must have at least to be
+    /// snipped by one of `patterns`, `snip_crates` or the presets. Functions
+    /// listed in `functions` by exact name are snipped whatever their size.
+    pub min_size: Option<u64>,
+
+    /// Function types, like `(i32, i32) -> i64` or `() -> (f32, f32)`. If
+    /// `patterns`, `snip_crates` or the presets select functions, only those
+    /// with one of these types are snipped. Otherwise every function with one
+    /// of these types is snipped, named or not. Functions listed in
+    /// `functions` by exact name are snipped whatever their type.
+    pub signatures: Vec<String>,
+
+    /// The names of the [presets](presets/index.html) to snip, e.g.
+    /// `rust-fmt-code`. Each of the `snip_*` flags below is an alias for the
+    /// preset of the same name.
+    pub presets: Vec<String>,
+
+    /// Files defining additional presets that `presets` can name. Presets
+    /// defined in these files take precedence over built-in presets with the
+    /// same name. Reading them requires the `config` feature.
+    pub preset_files: Vec<path::PathBuf>,
+
+    /// Should exact function names and patterns be matched regardless of
+    /// case?
+    pub ignore_case: bool,
+
+    /// Should exact function names and patterns also be matched against
+    /// demangled Rust symbol names? This lets you write `core::fmt::write`
+    /// instead of `_ZN4core3fmt5write17h...E`.
+    pub demangle: bool,
+
+    /// Should exact function names and patterns also be matched against
+    /// demangled C++ symbol names? This lets you write
+    /// `std::__2::basic_string<...>::append(char const*)` for modules compiled
+    /// with clang or Emscripten.
+    pub demangle_cpp: bool,
+
+    /// Should exact function names match Rust symbols regardless of their
+    /// trailing `::h<hash>`? This lets you write `hello::snip_me`, which keeps
+    /// matching when the hash changes between builds.
+    pub ignore_hash: bool,
+
     /// Should Rust `std::fmt` and `core::fmt` functions be snipped?
     pub snip_rust_fmt_code: bool,
 
     /// Should Rust `std::panicking` and `core::panicking` functions be snipped?
     pub snip_rust_panicking_code: bool,
 
+    /// Should Rust's float formatting and parsing machinery be snipped? This
+    /// covers `core::fmt::float`, `flt2dec`, `dec2flt` and the `ryu` crate.
+    pub snip_rust_float_fmt: bool,
+
+    /// Should Rust's Unicode tables and case conversion code be snipped? This
+    /// covers `core::unicode` and the `to_lowercase`/`to_uppercase` methods of
+    /// `char` and `str`.
+    pub snip_rust_unicode: bool,
+
+    /// Should Rust's backtrace support be snipped? This covers
+    /// `std::backtrace` and the `backtrace`, `gimli` and `addr2line` crates,
+    /// but not the `__rust_begin_short_backtrace` markers that every thread
+    /// starts in.
+    pub snip_rust_backtrace: bool,
+
+    /// Should Rust's allocation error and out-of-memory handling be snipped?
+    /// This covers `handle_alloc_error`, the default alloc error hook, the
+    /// `__rust_alloc_error_handler` shims and `capacity_overflow`.
+    pub snip_rust_alloc_error: bool,
+
+    /// Should wasm-bindgen's describe functions be snipped? These exist only
+    /// for the `wasm-bindgen` CLI to interpret, so only snip them after it has
+    /// run, or from modules that are never passed to it.
+    pub snip_wasm_bindgen_internals: bool,
+
+    /// Should TinyGo's runtime printing functions, which implement the `print`
+    /// and `println` builtins, be snipped?
+    pub snip_tinygo_prints: bool,
+
+    /// Should TinyGo's `reflect` and `internal/reflectlite` packages be
+    /// snipped?
+    pub snip_tinygo_reflect: bool,
+
+    /// Should AssemblyScript's `abort` and `trace` builtins, and the string
+    /// concatenation helpers that build their messages, be snipped? Note that
+    /// the helpers are also used by any user code that concatenates strings.
+    pub snip_assemblyscript_abort: bool,
+
+    /// Should Emscripten's syscall stubs, like `__syscall_openat`, be snipped?
+    pub snip_emscripten_syscalls: bool,
+
+    /// Should the C++ exception runtime, like `__cxa_throw`, be snipped?
+    pub snip_emscripten_exceptions: bool,
+
+    /// Should Emscripten's heap growth paths, like `sbrk` and
+    /// `emscripten_resize_heap`, be snipped? Only do this for modules whose
+    /// initial memory is big enough for everything they allocate.
+    pub snip_emscripten_sbrk: bool,
+
+    /// How to replace the snipped functions.
+    pub strategy: Strategy,
+
+    /// Rules choosing a different strategy for some of the snipped functions.
+    /// The first rule matching a function decides its strategy. Rules only
+    /// decide how functions are replaced, not which functions are snipped.
+    pub strategies: Vec<StrategyRule>,
+
+    /// WAT functions, like `(func (result i32) i32.const -1)`, to use as the
+    /// bodies of snipped functions with the same type. Snipped functions
+    /// matched by one of `strategies`, or without a matching template, are
+    /// replaced according to their strategy instead.
+    ///
+    /// The functions may use their parameters and locals, the module's
+    /// memories (declare `(memory 1)` to use its first memory), and functions
+    /// they import, which are added to the module's imports. Either list
+    /// functions and imports directly, or wrap them in a `(module ...)`.
+    pub replacement_body: Option<String>,
+
+    /// Should exports of functions replaced with `Strategy::Unreachable` be
+    /// kept, pointing at a stub that traps, instead of being removed? This
+    /// keeps the module's exports the same for JavaScript glue that expects
+    /// them.
+    pub stub_exports: bool,
+
+    /// The names of functions in the module, like a function that reports the
+    /// snipped function being hit before trapping, to point table elements of
+    /// snipped functions at instead of new stubs that trap. Each must have a
+    /// different type, and every snipped function in a table must have the
+    /// type of one of them. They stand in for stubs of their type in exports
+    /// and the start section too.
+    pub stub_functions: Vec<String>,
+
+    /// Should table elements of functions replaced with
+    /// `Strategy::Unreachable` be emptied, instead of pointed at stubs?
+    /// Calling an empty element traps like a stub does, and no stubs need to
+    /// be added, while the element segments are split around the empty
+    /// elements. Segments placed at an offset from a global can't be split,
+    /// so only their trailing elements are removed, and the rest are stubbed.
+    pub table_null: bool,
+
+    /// Should runs of table elements that are all stubs, and the element
+    /// segments they make up, be removed once the snipped functions are
+    /// replaced? Calling the empty elements left behind traps like calling
+    /// the stubs does. Tables that aren't imported or exported, and that only
+    /// `call_indirect` uses, also shrink to end at their last element. The
+    /// functions that `stub_functions` names are kept.
+    pub shrink_tables: bool,
+
+    /// Should data that only the snipped functions, and the functions removed
+    /// along with them, referred to be zeroed, and left out of the emitted
+    /// data section? Data counts as referred to by the constant addresses
+    /// that code uses, and by pointers in other data that is still referred
+    /// to, like a formatting table pointing at its strings. This assumes that
+    /// code refers to each piece of data by the address it starts at, rather
+    /// than computing it from the address of something before it.
+    pub gc_data: bool,
+
+    /// Should the messages that code passes to Rust's
+    /// `core::panicking::panic*` functions as a constant pointer and length be
+    /// zeroed, and left out of the emitted data section? Panics then have an
+    /// empty message. Messages that anything else refers to are kept.
+    pub snip_panic_strings: bool,
+
+    /// Should the minimum size of each of the module's memories be lowered to
+    /// what its data and the constant addresses its code uses need, once data
+    /// is removed? Memory that the code reaches without a constant address, like
+    /// the heap, must be grown first, as allocators do when they run out.
+    /// Imported and shared memories are left alone.
+    pub shrink_memory: bool,
+
+    /// Should imported functions replaced with `Strategy::Unreachable` become
+    /// local functions that trap, instead of being removed along with the
+    /// calls to them? Calls, exports and table elements keep referring to the
+    /// same function.
+    pub keep_imports: bool,
+
+    /// Should function indices be kept stable, for profiles and source maps
+    /// that refer to functions by index? Functions replaced with
+    /// `Strategy::Unreachable` keep their place and get a body that traps,
+    /// imported ones are left in place, and unused functions aren't removed.
+    /// Strategies that add imports, like `CallImportThenTrap`, still shift
+    /// the indices of local functions. Emit the module with
+    /// [`emit_wasm`](fn.emit_wasm.html) to keep local functions in order.
+    pub preserve_indices: bool,
+
+    /// Should the module's start section be removed, so that the start
+    /// function doesn't run on instantiation? Unless something else uses it,
+    /// the start function itself is then removed as well.
+    pub snip_start: bool,
+
+    /// Should exported functions be snipped? Unless this is set, `snip` fails
+    /// instead of turning part of the module's API into traps.
+    pub force: bool,
+
+    /// Should we warn about snipped functions that the functions we keep can
+    /// still call, along with an example of how they are reached? Calling
+    /// them will trap. The warnings are logged with `log::warn!`.
+    pub warn_reachable: bool,
+
+    /// Should warnings about the snipped module, like the start function being
+    /// snipped, be errors instead? Otherwise they are logged with `log::warn!`.
+    pub strict: bool,
+
+    /// Should the stubs that trap, which replace snipped functions in tables,
+    /// exports and the start section, be named after their type, like
+    /// `wasm_snip::unreachable_stub::<(i32) -> i32>`? Otherwise they have no
+    /// name in the name section.
+    pub name_stubs: bool,
+
+    /// Should we skip removing the functions, globals, data and so on that are
+    /// no longer used after snipping? Calls to snipped functions are still
+    /// replaced, for a later `wasm-opt` or `wasm-gc` run to clean up after.
+    pub skip_gc: bool,
+
+    /// Should the name section be left out of the snipped module? It is only
+    /// needed for debugging and profiling, so production builds can save its
+    /// size.
+    pub strip_names: bool,
+
+    /// With `strip_names`, should exported functions keep their names?
+    pub keep_export_names: bool,
+
+    /// Should the names of locals and labels be left out of the name section?
+    /// Otherwise they are kept for the functions that aren't snipped, as long
+    /// as the module is parsed with [`parse_module`](fn.parse_module.html).
+    pub strip_local_names: bool,
+
+    /// Should the DWARF `.debug_*` custom sections be left out of the snipped
+    /// module? Otherwise they are copied as they are when the module is parsed
+    /// with [`parse_module`](fn.parse_module.html).
+    pub strip_dwarf: bool,
+
+    /// Should the addresses in the DWARF sections be moved to where their
+    /// functions' code ends up, and those of snipped and removed functions be
+    /// tombstoned? Otherwise DWARF is copied as it is, and points at the wrong
+    /// code once functions move. Needs the module to be parsed with
+    /// [`parse_module`](fn.parse_module.html).
+    pub update_dwarf: bool,
+
+    /// Should the module be parsed so that
+    /// [`update_source_map`](fn.update_source_map.html) can update a source map
+    /// for it once it is snipped?
+    pub update_source_map: bool,
+
+    /// What to do with the `target_features` custom section.
+    pub target_features: TargetFeatures,
+
+    /// The regex patterns matching the names of custom sections to remove.
+    /// Every other custom section is copied byte for byte, although they all
+    /// end up after the module's other sections. The name and producers
+    /// sections aren't affected; see `strip_names` and
+    /// `skip_producers_section` for those.
+    pub strip_custom_sections: Vec<String>,
+
     /// Should we skip generating [the "producers" custom
     /// section](https://github.com/WebAssembly/tool-conventions/blob/master/ProducersSection.md)?
     pub skip_producers_section: bool,
+
+    /// How many threads to parse, snip and emit with, instead of rayon's
+    /// global thread pool, which has a thread for each CPU. `Some(1)` does
+    /// everything on one thread, which makes debugging deterministic.
+    pub threads: Option<usize>,
+}
+
+impl Options {
+    /// Start building options, from the defaults.
+    pub fn builder() -> builder::OptionsBuilder {
+        builder::OptionsBuilder::default()
+    }
+}
+
+/// How to replace a snipped function.
+///
+/// A `Strategy` is spelled the same way on the command line and in
+/// configuration files: `unreachable`, `default`,
+/// `call-import=<module>::<name>` or `custom-body=<wat>`.
+///
+/// ```
+/// let options = wasm_snip::Options {
+///     functions: vec!["log_event".to_string()],
+///     strategy: wasm_snip::Strategy::CallImportThenTrap("env::snipped_hit".to_string()),
+///     ..Default::default()
+/// };
+/// # let _ = options;
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub enum Strategy {
+    /// Replace every call to the function with an `unreachable`, and remove
+    /// the function entirely. This is the default.
+    #[default]
+    Unreachable,
+
+    /// Keep the function, but replace its body with one that returns zero for
+    /// numeric results and null for references.
+    ReturnDefault,
+
+    /// Keep the function, but replace its body with one that calls the given
+    /// imported function and then traps. The import is named like
+    /// `env::snipped_hit`, and is passed the index of the snipped function in
+    /// the input module as an `i32`, so the host can report which snipped
+    /// function was unexpectedly called.
+    CallImportThenTrap(String),
+
+    /// Keep the function, but give it the body of the WAT function with the
+    /// same type, like `(func (result i32) i32.const -1)`. Functions without a
+    /// template of their type are replaced with `Unreachable`. See
+    /// [`Options::replacement_body`](struct.Options.html#structfield.replacement_body)
+    /// for what the WAT may contain.
+    CustomBody(String),
+}
+
+/// A rule replacing the functions it matches with its own strategy, e.g.
+///
+/// ```toml
+/// [[strategies]]
+/// patterns = [".*7logging.*"]
+/// strategy = "default"
+/// ```
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, deny_unknown_fields, rename_all = "kebab-case")
+)]
+pub struct StrategyRule {
+    /// The names of the functions this rule applies to.
+    pub functions: Vec<String>,
+
+    /// The regex patterns matching the functions this rule applies to.
+    pub patterns: Vec<String>,
+
+    /// How to replace the matching functions.
+    pub strategy: Strategy,
+}
+
+impl str::FromStr for Strategy {
+    type Err = SnipError;
+
+    fn from_str(s: &str) -> Result<Strategy, SnipError> {
+        match s {
+            "unreachable" => Ok(Strategy::Unreachable),
+            "default" => Ok(Strategy::ReturnDefault),
+            _ if s.starts_with("call-import=") => {
+                let import = &s["call-import=".len()..];
+                if !import.contains("::") {
+                    return Err(SnipError::InvalidOptions(format!(
+                        "the import to call must be named like `<module>::<name>`, found `{}`",
+                        import
+                    )));
+                }
+                Ok(Strategy::CallImportThenTrap(import.to_string()))
+            }
+            _ if s.starts_with("custom-body=") => {
+                Ok(Strategy::CustomBody(s["custom-body=".len()..].to_string()))
+            }
+            _ => Err(SnipError::InvalidOptions(format!(
+                "unknown replacement strategy `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strategy::Unreachable => f.write_str("unreachable"),
+            Strategy::ReturnDefault => f.write_str("default"),
+            Strategy::CallImportThenTrap(import) => write!(f, "call-import={}", import),
+            Strategy::CustomBody(wat) => write!(f, "custom-body={}", wat),
+        }
+    }
+}
+
+impl TryFrom<String> for Strategy {
+    type Error = SnipError;
+
+    fn try_from(s: String) -> Result<Strategy, SnipError> {
+        s.parse()
+    }
+}
+
+impl From<Strategy> for String {
+    fn from(strategy: Strategy) -> String {
+        strategy.to_string()
+    }
+}
+
+/// What to do with the `target_features` custom section, in which LLVM records
+/// the WebAssembly features a module was compiled with, and which some
+/// toolchains check.
+///
+/// Spelled `preserve`, `strip` or `set=<features>` on the command line and in
+/// configuration files, where `<features>` is a comma-separated list of
+/// features, each prefixed with `+` if the module uses it, `-` if it doesn't,
+/// or `=` if every module linked with it must use it, like
+/// `set=+mutable-globals,+sign-ext`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String", into = "String"))]
+pub enum TargetFeatures {
+    /// Keep the section as it is. This is the default.
+    #[default]
+    Preserve,
+
+    /// Remove the section.
+    Strip,
+
+    /// Replace the section with one listing the given features, each with
+    /// its prefix, like `+sign-ext`.
+    Set(Vec<String>),
+}
+
+impl str::FromStr for TargetFeatures {
+    type Err = SnipError;
+
+    fn from_str(s: &str) -> Result<TargetFeatures, SnipError> {
+        match s {
+            "preserve" => Ok(TargetFeatures::Preserve),
+            "strip" => Ok(TargetFeatures::Strip),
+            _ if s.starts_with("set=") => {
+                let features: Vec<_> = s["set=".len()..]
+                    .split(',')
+                    .filter(|f| !f.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                for feature in &features {
+                    if !feature.starts_with(['+', '-', '=']) || feature.len() == 1 {
+                        return Err(SnipError::InvalidOptions(format!(
+                            "target features must be named like `+<feature>`, `-<feature>` or \
+                             `=<feature>`, found `{}`",
+                            feature
+                        )));
+                    }
+                }
+                Ok(TargetFeatures::Set(features))
+            }
+            _ => Err(SnipError::InvalidOptions(format!(
+                "unknown target features handling `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for TargetFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetFeatures::Preserve => f.write_str("preserve"),
+            TargetFeatures::Strip => f.write_str("strip"),
+            TargetFeatures::Set(features) => write!(f, "set={}", features.join(",")),
+        }
+    }
+}
+
+impl TryFrom<String> for TargetFeatures {
+    type Error = SnipError;
+
+    fn try_from(s: String) -> Result<TargetFeatures, SnipError> {
+        s.parse()
+    }
+}
+
+impl From<TargetFeatures> for String {
+    fn from(features: TargetFeatures) -> String {
+        features.to_string()
+    }
+}
+
+/// What `snip` did to a module.
+#[derive(Clone, Debug, Default)]
+pub struct SnipOutcome {
+    /// The snipped functions, in index order, with the names they had.
+    pub functions: Vec<(walrus::FunctionId, Option<String>)>,
+
+    /// The names of the exports that were removed along with the function
+    /// they exported, in the order they were exported.
+    pub removed_exports: Vec<String>,
+
+    /// The module and name of each imported function that was removed or
+    /// given a body, in the order they were imported.
+    pub removed_imports: Vec<(String, String)>,
+
+    /// How many calls to snipped functions were replaced with `unreachable`.
+    pub call_sites: usize,
+
+    /// How many table elements were pointed at a stub that traps, or emptied,
+    /// instead of referring to a snipped function.
+    pub table_slots: usize,
+
+    /// How many bytes of data were zeroed: data that only snipped functions
+    /// referred to with `gc_data`, and panic messages with
+    /// `snip_panic_strings`.
+    pub zeroed_data: usize,
+
+    /// How long each pass took, in the order they finished. A name like
+    /// `replace/calls` is the part of the `replace` pass that rewrote calls,
+    /// and `replace/tables` the part that pointed table elements at stubs.
+    pub timings: Vec<(String, Duration)>,
+}
+
+impl SnipOutcome {
+    /// Add `duration` to the time taken by `name`.
+    pub(crate) fn add_timing(&mut self, name: &str, duration: Duration) {
+        match self.timings.iter_mut().find(|(n, _)| n == name) {
+            Some((_, total)) => *total += duration,
+            None => self.timings.push((name.to_string(), duration)),
+        }
+    }
 }
 
 /// Snip the functions from the input file described by the options.
-pub fn snip(module: &mut walrus::Module, options: Options) -> Result<(), failure::Error> {
-    if !options.skip_producers_section {
+///
+/// This runs the built-in passes of a [`Pipeline`](pipeline/struct.Pipeline.html),
+/// which can also run passes of your own.
+pub fn snip(module: &mut walrus::Module, options: Options) -> Result<SnipOutcome, SnipError> {
+    snip_with(module, |_| false, options)
+}
+
+/// Snip the functions that `select` returns `true` for, along with the
+/// functions that the options select.
+///
+/// `select` is called once for each function, imported functions included,
+/// for selections that names and patterns can't express. The options still
+/// apply to what it selects: `keep_functions` and `keep_patterns` save
+/// functions from it, and exported functions need `force`. For the sizes of
+/// functions, look them up in [`function_sizes`](fn.function_sizes.html)
+/// beforehand:
+///
+/// ```no_run
+/// # fn main() -> Result<(), wasm_snip::SnipError> {
+/// let options = wasm_snip::Options::default();
+/// let mut module = wasm_snip::Input::File("input.wasm".into()).into_module(&options)?;
+/// let sizes = wasm_snip::function_sizes(&module)?;
+/// wasm_snip::snip_with(
+///     &mut module,
+///     |f| sizes.get(&f.id()).map_or(false, |&size| size > 10_000),
+///     options,
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn snip_with<F>(
+    module: &mut walrus::Module,
+    select: F,
+    options: Options,
+) -> Result<SnipOutcome, SnipError>
+where
+    F: FnMut(&walrus::Function) -> bool,
+{
+    pipeline::Pipeline::with_selection(select).run(module, options)
+}
+
+/// Find the functions that `snip` would snip from the module with the given
+/// options, before any of them are replaced.
+pub fn functions_to_snip(
+    module: &walrus::Module,
+    options: &Options,
+) -> Result<HashSet<walrus::FunctionId>, SnipError> {
+    let (to_snip, _) = select_functions(module, &mut options.clone(), |_| false, None)?;
+    Ok(to_snip)
+}
+
+/// Run `f` on a thread pool with `options.threads` threads, unless it isn't
+/// set or the current thread pool already has that many.
+pub(crate) fn in_thread_pool<T, F>(options: &Options, f: F) -> Result<T, SnipError>
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    match options.threads {
+        Some(threads) if threads != rayon::current_num_threads() => {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(io::Error::other)
+                .with_context(|_| format!("failed to start {} threads", threads))?;
+            Ok(pool.install(f))
+        }
+        _ => Ok(f()),
+    }
+}
+
+/// Select the functions to snip, reading the files of names and patterns into
+/// `options`, along with the functions that `select` selects. Also returns the
+/// matcher that selected them by name. The sizes of the functions' bodies are
+/// measured unless they are given.
+fn select_functions(
+    module: &walrus::Module,
+    options: &mut Options,
+    mut select: impl FnMut(&walrus::Function) -> bool,
+    sizes: Option<HashMap<walrus::FunctionId, u64>>,
+) -> Result<(HashSet<walrus::FunctionId>, Matcher), SnipError> {
+    for path in &options.functions_files {
+        options.functions.extend(read_list_file(path)?);
+    }
+    for path in &options.patterns_files {
+        options.patterns.extend(read_list_file(path)?);
+    }
+
+    let custom_presets = read_preset_files(&options.preset_files)?;
+    let re_set = build_regex_set(options, &custom_presets)?;
+    let exclude_set = compile_regex_set(&options.exclude_patterns, options.ignore_case)
+        .context("failed to compile exclude regex")?;
+    let keep_set = compile_regex_set(&options.keep_patterns, options.ignore_case)
+        .context("failed to compile keep regex")?;
+
+    let matcher = Matcher::new(&options.functions, re_set, options).crates(&options.snip_crates);
+    let exclude = Matcher::new(&[], exclude_set, options);
+    let keep = Matcher::new(&options.keep_functions, keep_set, options);
+
+    let import_set = compile_regex_set(&options.snip_import_patterns, options.ignore_case)
+        .context("failed to compile import regex")?;
+
+    let filter = Filter {
+        min_size: match options.min_size {
+            Some(min_size) => Some((
+                min_size,
+                match sizes {
+                    Some(sizes) => sizes,
+                    None => function_sizes(module)?,
+                },
+            )),
+            None => None,
+        },
+        signatures: options
+            .signatures
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<_, _>>()?,
+    };
+    let symbols = read_symbols_files(&options.symbols_files)?;
+    let fallback_names = fallback_names(module, &symbols);
+    let mut to_snip = in_thread_pool(options, || {
+        find_functions_to_snip(module, &matcher, &exclude, &filter, &fallback_names)
+    })?;
+    log_selected(module, &to_snip, |f| {
         module
-            .producers
-            .add_processed_by("wasm-snip", env!("CARGO_PKG_VERSION"));
+            .funcs
+            .get(f)
+            .name
+            .as_ref()
+            .and_then(|name| matcher.why(name))
+            .unwrap_or_else(|| "it passes the size and signature filters".to_string())
+    });
+    let by_index: Vec<_> = module
+        .funcs
+        .iter()
+        .map(|f| f.id())
+        .filter(|f| {
+            let index = f.index() as u32;
+            options.indices.contains(&index)
+                || options.index_ranges.iter().any(|r| r.contains(&index))
+        })
+        .collect();
+    log_selected(module, &by_index, |_| "its index is selected".to_string());
+    to_snip.extend(by_index);
+    let imports = find_imports_to_snip(module, &options.snip_imports, &import_set);
+    log_selected(module, &imports, |_| "its import is snipped".to_string());
+    to_snip.extend(imports);
+    let selected: Vec<_> = module
+        .funcs
+        .iter()
+        .filter(|f| select(f))
+        .map(|f| f.id())
+        .collect();
+    log_selected(module, &selected, |_| {
+        "the predicate selects it".to_string()
+    });
+    to_snip.extend(selected);
+    retain_unkept_functions(module, &mut to_snip, &keep, &fallback_names);
+    log::info!("selected {} functions to snip", to_snip.len());
+    Ok((to_snip, matcher))
+}
+
+/// Log why each of the given functions is selected, in index order.
+fn log_selected<'a>(
+    module: &walrus::Module,
+    selected: impl IntoIterator<Item = &'a walrus::FunctionId>,
+    why: impl Fn(walrus::FunctionId) -> String,
+) {
+    if !log::log_enabled!(log::Level::Debug) {
+        return;
     }
+    let mut selected: Vec<_> = selected.into_iter().cloned().collect();
+    selected.sort();
+    for f in selected {
+        log::debug!(
+            "selected `{}` because {}",
+            function_name(&module.funcs, f),
+            why(f)
+        );
+    }
+}
 
-    let names: HashSet<String> = options.functions.iter().cloned().collect();
-    let re_set = build_regex_set(options).context("failed to compile regex")?;
-    let to_snip = find_functions_to_snip(&module, &names, &re_set);
+/// Snip functions from the `.wasm` binary `wasm`, returning the snipped
+/// binary.
+///
+/// This parses, snips and emits in one go, for when there is no need to look
+/// at the walrus module along the way. Components and relocatable object
+/// files are snipped with [`snip_component`](fn.snip_component.html) and
+/// [`snip_object_file`](fn.snip_object_file.html).
+pub fn snip_bytes(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    in_thread_pool(options, || snip_bytes_in_pool(wasm, options))?
+}
 
-    replace_calls_with_unreachable(module, &to_snip);
-    unexport_snipped_functions(module, &to_snip);
-    unimport_snipped_functions(module, &to_snip);
-    snip_table_elements(module, &to_snip);
-    delete_functions_to_snip(module, &to_snip);
-    walrus::passes::gc::run(module);
+fn snip_bytes_in_pool(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    if wasmparser::Parser::is_component(wasm) {
+        snip_component(wasm, options)
+    } else if is_object_file(wasm) {
+        snip_object_file(wasm, options)
+    } else if unsupported_proposal(wasm).is_some() {
+        snip_in_place(wasm, options)
+    } else {
+        let mut module = parse_module(wasm, options)?;
+        snip(&mut module, options.clone())?;
+        emit_wasm(&module, options)
+    }
+}
 
+/// Snip functions from the `.wasm` binary read from `input`, writing the
+/// snipped binary to `output`, for servers and pipelines that stream modules.
+///
+/// The whole binary is read before snipping, since a module can't be snipped
+/// until all of it is parsed.
+pub fn snip_reader_to_writer<R: io::Read, W: io::Write>(
+    mut input: R,
+    mut output: W,
+    options: &Options,
+) -> Result<(), SnipError> {
+    let mut wasm = vec![];
+    input
+        .read_to_end(&mut wasm)
+        .context("failed to read wasm")?;
+    let wasm = snip_bytes(&wasm, options)?;
+    output
+        .write_all(&wasm)
+        .and_then(|()| output.flush())
+        .context("failed to write snipped wasm")?;
     Ok(())
 }
 
-fn build_regex_set(mut options: Options) -> Result<regex::RegexSet, failure::Error> {
-    // Snip the Rust `fmt` code, if requested.
-    if options.snip_rust_fmt_code {
-        // Mangled symbols.
-        options.patterns.push(".*4core3fmt.*".into());
-        options.patterns.push(".*3std3fmt.*".into());
+/// Snip functions from the `.wasm` file at `input`, writing the snipped binary
+/// to `output`, as the `wasm-snip` executable does.
+///
+/// The options are used as they are: files that they name, like
+/// `functions_files`, are read relative to the current directory, and
+/// [`Config::from_file`](config/struct.Config.html#method.from_file) resolves
+/// them against the configuration file's directory instead. The source map
+/// isn't updated, even with `options.update_source_map` set.
+pub fn snip_file<P: AsRef<path::Path>, Q: AsRef<path::Path>>(
+    input: P,
+    output: Q,
+    options: &Options,
+) -> Result<(), SnipError> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    // The input is closed before the output is written, which may replace it.
+    let wasm = snip_bytes(&WasmFile::open(input)?, options)
+        .with_context(|_| format!("failed to snip functions from {}", input.display()))?;
+    fs::write(output, wasm)
+        .with_context(|_| format!("failed to emit snipped wasm to {}", output.display()))?;
+    Ok(())
+}
 
-        // Mangled in impl.
-        options.patterns.push(r#".*core\.\.fmt\.\..*"#.into());
-        options.patterns.push(r#".*std\.\.fmt\.\..*"#.into());
+/// Parse a module to snip from a `.wasm` binary, configuring walrus according
+/// to `options`.
+///
+/// Unlike `walrus::Module::from_buffer`, this keeps the names of blocks, loops
+/// and ifs from the name section, which [`emit_wasm`](fn.emit_wasm.html) then
+/// writes back for the functions that aren't snipped, and the DWARF custom
+/// sections unless `options.strip_dwarf` is set.
+pub fn parse_module(wasm: &[u8], options: &Options) -> Result<walrus::Module, SnipError> {
+    let mut config = walrus::ModuleConfig::new();
+    config
+        .generate_producers_section(!options.skip_producers_section)
+        .generate_dwarf(!options.strip_dwarf);
+    let mut module = match in_thread_pool(options, || config.parse(wasm))? {
+        Ok(module) => module,
+        Err(e) => match proposals::unsupported(wasm) {
+            Some(proposal) => {
+                return Err(SnipError::Unsupported {
+                    message: format!(
+                        "the module uses the {} proposal, which walrus can't parse; snip it \
+                         in place with `snip_in_place`",
+                        proposal
+                    ),
+                    source: Some(Box::new(SnipError::walrus(e))),
+                })
+            }
+            None => return Err(SnipError::walrus(e)),
+        },
+    };
+    if !options.strip_names && !options.strip_local_names {
+        labels::read(&mut module, wasm).context("failed to read label names")?;
+    }
+    if options.update_dwarf && !options.strip_dwarf || options.update_source_map {
+        code::read(&mut module, wasm).context("failed to read the code of functions")?;
+    }
+    Ok(module)
+}
 
-        // Demangled symbols.
-        options.patterns.push(".*core::fmt::.*".into());
-        options.patterns.push(".*std::fmt::.*".into());
+/// Emit the snipped module as a `.wasm` binary.
+///
+/// This is `walrus::Module::emit_wasm`, except that when
+/// `options.preserve_indices` is set, local functions keep the order they
+/// have in the module instead of being sorted by size, and when
+/// `options.update_dwarf` is set, the DWARF sections are updated to match.
+pub fn emit_wasm(module: &walrus::Module, options: &Options) -> Result<Vec<u8>, SnipError> {
+    let mut wasm = labels::move_into_name_section(in_thread_pool(options, || module.emit_wasm())?)
+        .context("failed to add label names to the name section")?;
+    let imported = imported_function_count(module);
+    if options.preserve_indices {
+        let indices = emitted_function_indices(module, options);
+        let order: Vec<u32> = walrus_local_order(module)
+            .iter()
+            .map(|id| indices[id] - imported)
+            .collect();
+        wasm = reorder::reorder_functions(&wasm, imported, &order)
+            .context("failed to restore the order of functions")?;
     }
+    if let Some(ranges) = module.customs.get_typed::<code::FunctionRanges>() {
+        if options.update_dwarf {
+            let indices = emitted_function_indices(module, options);
+            let remap = code::Remap::new(ranges, &indices, imported, &wasm)?;
+            dwarf::update(&mut wasm, &remap).context("failed to update DWARF")?;
+        }
+        wasm = code::remove_placeholder(wasm)?;
+    }
+    if options.gc_data || options.snip_panic_strings {
+        wasm = data::leave_out_zeros(wasm).context("failed to leave zeroed data out")?;
+    }
+    Ok(wasm)
+}
+
+/// Snip functions from every core module of the component `wasm`, returning
+/// the snipped component.
+///
+/// Each core module, including those of nested components, is parsed with
+/// [`parse_module`](fn.parse_module.html), snipped with `options` and emitted
+/// with [`emit_wasm`](fn.emit_wasm.html), or snipped with
+/// [`snip_in_place`](fn.snip_in_place.html) if walrus can't parse it. Use
+/// `wasmparser::Parser::is_component` to tell a component from a module.
+pub fn snip_component(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    component::snip(wasm, options)
+}
+
+/// Snip functions from the relocatable object file `wasm`, such as a `.o` file
+/// built for wasm, returning the snipped object file.
+///
+/// Functions are selected by the names of their symbols in the `linking`
+/// section. Their bodies are replaced with `unreachable` in place and their
+/// relocations dropped, and the relocations of the rest of the code are moved
+/// to match, so the result still links with `wasm-ld`. Nothing else is
+/// removed: that is left to the linker. Use
+/// [`is_object_file`](fn.is_object_file.html) to tell an object file from a
+/// linked module.
+pub fn snip_object_file(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    object::snip(wasm, options)
+}
+
+/// Is `wasm` a relocatable object file, which has a `linking` section?
+pub fn is_object_file(wasm: &[u8]) -> bool {
+    object::is_object(wasm)
+}
 
-    // Snip the Rust `panicking` code, if requested.
-    if options.snip_rust_panicking_code {
-        // Mangled symbols.
-        options.patterns.push(".*4core9panicking.*".into());
-        options.patterns.push(".*3std9panicking.*".into());
+/// Snip functions from the module `wasm`, which uses a WebAssembly proposal
+/// that walrus can't parse yet, returning the snipped module.
+///
+/// Functions are selected as [`snip`](fn.snip.html) selects them, from a
+/// module that walrus can parse with the same functions, names and exports.
+/// The module is then re-encoded without the snipped functions and the
+/// functions that only they used, with the calls to snipped functions
+/// replaced with `unreachable`, and with element segments, `ref.func`,
+/// exports and the start section referring to a stub of the same type that
+/// traps instead. The rest of the code is copied as it is. `gc_data` and
+/// `shrink_memory` are supported, but other strategies than
+/// `Strategy::Unreachable`, `strategies`, `replacement_body`, `keep_imports`,
+/// `shrink_tables`, `snip_panic_strings`, `update_dwarf` and
+/// `update_source_map` fail. Use
+/// [`unsupported_proposal`](fn.unsupported_proposal.html) to tell such a
+/// module from one that walrus parses.
+pub fn snip_in_place(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    in_place::snip(wasm, options)
+}
 
-        // Mangled in impl.
-        options.patterns.push(r#".*core\.\.panicking\.\..*"#.into());
-        options.patterns.push(r#".*std\.\.panicking\.\..*"#.into());
+/// The WebAssembly proposal that walrus can't parse yet which the module
+/// `wasm` uses, like `reference types`, if any.
+pub fn unsupported_proposal(wasm: &[u8]) -> Option<&'static str> {
+    proposals::unsupported(wasm)
+}
+
+/// Update the source map of the module that `module` was parsed from, given
+/// as JSON, for the snipped module `wasm` emitted from it, returning the
+/// updated JSON.
+///
+/// Mappings move to where their function's code ended up, and mappings in the
+/// code of functions that were snipped or removed are dropped. The module must
+/// have been parsed with [`parse_module`](fn.parse_module.html) and
+/// `options.update_source_map` set.
+#[cfg(feature = "config")]
+pub fn update_source_map(
+    module: &walrus::Module,
+    options: &Options,
+    wasm: &[u8],
+    map: &str,
+) -> Result<String, SnipError> {
+    let ranges =
+        match module.customs.get_typed::<code::FunctionRanges>() {
+            Some(ranges) => ranges,
+            None => return Err(SnipError::InvalidOptions(
+                "updating a source map requires parsing the module with `update_source_map` set"
+                    .to_string(),
+            )),
+        };
+    let indices = emitted_function_indices(module, options);
+    let remap = code::Remap::new(ranges, &indices, imported_function_count(module), wasm)?;
+    source_map::update(map, &remap).context("failed to update source map")
+}
 
-        // Demangled symbols.
-        options.patterns.push(".*core::panicking::.*".into());
-        options.patterns.push(".*std::panicking::.*".into());
+/// The index each function of the module has in the binary emitted by
+/// [`emit_wasm`](fn.emit_wasm.html).
+///
+/// Before snipping, a function's id has the index of the function in the
+/// input module, so comparing the two maps the input's function indices to
+/// the output's.
+pub fn emitted_function_indices(
+    module: &walrus::Module,
+    options: &Options,
+) -> HashMap<walrus::FunctionId, u32> {
+    let mut indices = HashMap::new();
+    for import in module.imports.iter() {
+        if let walrus::ImportKind::Function(f) = import.kind {
+            indices.insert(f, indices.len() as u32);
+        }
     }
+    let locals = if options.preserve_indices {
+        module.funcs.iter_local().map(|(id, _)| id).collect()
+    } else {
+        walrus_local_order(module)
+    };
+    for id in locals {
+        indices.insert(id, indices.len() as u32);
+    }
+    indices
+}
 
-    Ok(regex::RegexSet::new(options.patterns)?)
+/// The size in bytes of each local function's body when the module is
+/// emitted.
+pub fn function_sizes(
+    module: &walrus::Module,
+) -> Result<HashMap<walrus::FunctionId, u64>, SnipError> {
+    let wasm = module.emit_wasm();
+    let ids: HashMap<u32, walrus::FunctionId> =
+        emitted_function_indices(module, &Options::default())
+            .into_iter()
+            .map(|(id, index)| (index, id))
+            .collect();
+    let imported = imported_function_count(module);
+
+    let mut sizes = HashMap::new();
+    let mut index = imported;
+    for payload in wasmparser::Parser::new(0).parse_all(&wasm) {
+        if let wasmparser::Payload::CodeSectionEntry(body) = payload? {
+            let range = body.range();
+            sizes.insert(ids[&index], range.end - range.start);
+            index += 1;
+        }
+    }
+    Ok(sizes)
 }
 
-fn find_functions_to_snip(
+/// The size in bytes of the code that snipping each function would remove:
+/// the size of its own body, plus the sizes of the functions that only it
+/// keeps alive. Functions that nothing keeps alive retain only their own body.
+pub fn retained_sizes(
     module: &walrus::Module,
-    names: &HashSet<String>,
-    re_set: &regex::RegexSet,
-) -> HashSet<walrus::FunctionId> {
-    module
+) -> Result<HashMap<walrus::FunctionId, u64>, SnipError> {
+    let sizes = function_sizes(module)?;
+    let idoms = callgraph::CallGraph::new(module).immediate_dominators();
+
+    let mut retained: HashMap<_, _> = module
         .funcs
-        .par_iter()
-        .filter_map(|f| {
-            f.name.as_ref().and_then(|name| {
-                if names.contains(name) || re_set.is_match(name) {
-                    Some(f.id())
-                } else {
-                    None
+        .iter()
+        .map(|f| (f.id(), sizes.get(&f.id()).cloned().unwrap_or(0)))
+        .collect();
+    for (&f, &idom) in &idoms {
+        let size = sizes.get(&f).cloned().unwrap_or(0);
+        let mut dominator = idom;
+        while let Some(d) = dominator {
+            *retained.get_mut(&d).unwrap() += size;
+            dominator = idoms[&d];
+        }
+    }
+    Ok(retained)
+}
+
+/// The sizes in bytes of a `.wasm` binary and of the kinds of sections in it
+/// that snipping shrinks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SectionSizes {
+    /// The size of the whole binary.
+    pub total: u64,
+
+    /// The size of the code section, which holds the functions' bodies.
+    pub code: u64,
+
+    /// The size of the data section.
+    pub data: u64,
+
+    /// The size of the `name` custom section.
+    pub names: u64,
+
+    /// The combined size of every other custom section.
+    pub custom: u64,
+}
+
+impl SectionSizes {
+    /// Measure the sections of the given `.wasm` binary.
+    pub fn of(wasm: &[u8]) -> Result<SectionSizes, SnipError> {
+        let mut sizes = SectionSizes {
+            total: wasm.len() as u64,
+            ..SectionSizes::default()
+        };
+        let len = |range: std::ops::Range<u64>| range.end - range.start;
+        for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+            match payload? {
+                wasmparser::Payload::CodeSectionStart { range, .. } => sizes.code += len(range),
+                wasmparser::Payload::DataSection(reader) => sizes.data += len(reader.range()),
+                wasmparser::Payload::CustomSection(reader) if reader.name() == "name" => {
+                    sizes.names += len(reader.range())
                 }
-            })
-        })
-        .collect()
+                wasmparser::Payload::CustomSection(reader) => sizes.custom += len(reader.range()),
+                _ => {}
+            }
+        }
+        Ok(sizes)
+    }
 }
 
-fn delete_functions_to_snip(module: &mut walrus::Module, to_snip: &HashSet<walrus::FunctionId>) {
-    for f in to_snip.iter().cloned() {
-        module.funcs.delete(f);
+/// The functions that snipping some functions would remove.
+#[derive(Clone, Debug, Default)]
+pub struct Removal {
+    /// The removed functions and the sizes of their bodies, from largest to
+    /// smallest. These are the snipped functions, and the functions that
+    /// only they call.
+    pub functions: Vec<(walrus::FunctionId, u64)>,
+
+    /// The total size of the removed functions' bodies, in bytes.
+    pub size: u64,
+}
+
+/// Work out which functions snipping `to_snip` would remove, without changing
+/// the module. Functions that are already unreachable aren't counted.
+pub fn simulate_snip(
+    module: &walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) -> Result<Removal, SnipError> {
+    let graph = callgraph::CallGraph::new(module);
+    let before = graph.reachable(graph.roots().iter().cloned(), |_| false);
+    let roots = graph.roots().iter().filter(|f| !to_snip.contains(f));
+    let after = graph.reachable(roots.cloned(), |f| to_snip.contains(&f));
+
+    let sizes = function_sizes(module)?;
+    let mut functions: Vec<_> = before
+        .keys()
+        .chain(to_snip)
+        .filter(|f| !after.contains_key(f) || to_snip.contains(f))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|&f| (f, sizes.get(&f).cloned().unwrap_or(0)))
+        .collect();
+    functions.sort_by_key(|&(f, size)| (cmp::Reverse(size), f));
+    let size = functions.iter().map(|&(_, size)| size).sum();
+    Ok(Removal { functions, size })
+}
+
+/// Find a chain of calls that keeps `f` alive once the functions in `to_snip`
+/// are snipped, starting at a function the host can call directly, like an
+/// export or the start function, and ending at `f`. Returns `None` if nothing
+/// keeps `f` alive.
+pub fn retention_path(
+    module: &walrus::Module,
+    f: walrus::FunctionId,
+    to_snip: &HashSet<walrus::FunctionId>,
+) -> Option<Vec<walrus::FunctionId>> {
+    let graph = callgraph::CallGraph::new(module);
+    let roots = graph.roots().iter().filter(|f| !to_snip.contains(f));
+    let parents = graph.reachable(roots.cloned(), |f| to_snip.contains(&f));
+    if !parents.contains_key(&f) || to_snip.contains(&f) {
+        return None;
     }
+    Some(callgraph::CallGraph::path(&parents, f))
 }
 
-fn replace_calls_with_unreachable(
-    module: &mut walrus::Module,
+/// Group the functions that snipping removed from a module by the snipped
+/// function that caused their removal.
+///
+/// `graph` is the call graph of the module before snipping. Each removed
+/// function is attributed to the first snipped function, in index order, that
+/// reaches it through removed functions that weren't snipped themselves. Removed functions that no
+/// snipped function reaches, because nothing used them in the first place,
+/// are grouped under `None` at the end.
+pub fn attribute_removals(
+    graph: &callgraph::CallGraph,
     to_snip: &HashSet<walrus::FunctionId>,
-) {
-    struct Replacer<'a> {
-        to_snip: &'a HashSet<walrus::FunctionId>,
+    removed: &HashSet<walrus::FunctionId>,
+) -> Vec<(Option<walrus::FunctionId>, Vec<walrus::FunctionId>)> {
+    let mut snipped: Vec<_> = to_snip.iter().cloned().collect();
+    snipped.sort();
+
+    let mut attributed: HashSet<_> = to_snip.clone();
+    let mut groups = vec![];
+    for f in snipped {
+        let reached = graph.reachable(Some(f), |g| {
+            g != f && (to_snip.contains(&g) || !removed.contains(&g))
+        });
+        let mut group: Vec<_> = reached
+            .keys()
+            .filter(|g| removed.contains(g) && !attributed.contains(g))
+            .cloned()
+            .collect();
+        group.sort();
+        attributed.extend(group.iter().cloned());
+        groups.push((Some(f), group));
+    }
+
+    let mut rest: Vec<_> = removed.difference(&attributed).cloned().collect();
+    if !rest.is_empty() {
+        rest.sort();
+        groups.push((None, rest));
+    }
+    groups
+}
+
+/// The order walrus emits local functions in: from largest to smallest,
+/// breaking ties by id.
+fn walrus_local_order(module: &walrus::Module) -> Vec<walrus::FunctionId> {
+    let mut locals: Vec<_> = module
+        .funcs
+        .iter_local()
+        .map(|(id, func)| (id, func.size()))
+        .collect();
+    locals.sort_by_key(|&(id, size)| (cmp::Reverse(size), id));
+    locals.into_iter().map(|(id, _)| id).collect()
+}
+
+fn imported_function_count(module: &walrus::Module) -> u32 {
+    module
+        .imports
+        .iter()
+        .filter(|i| matches!(i.kind, walrus::ImportKind::Function(_)))
+        .count() as u32
+}
+
+/// Read a file with one entry per line, skipping blank lines and `#` comments.
+fn read_list_file(path: &path::Path) -> Result<Vec<String>, SnipError> {
+    let contents = fs::read_to_string(path)
+        .with_context(|_| format!("failed to read file {}", path.display()))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+/// Read the function names in each of the given symbol files, by index.
+fn read_symbols_files(paths: &[path::PathBuf]) -> Result<HashMap<u32, String>, SnipError> {
+    let mut symbols = HashMap::new();
+    for path in paths {
+        let contents = fs::read_to_string(path)
+            .with_context(|_| format!("failed to read symbols file {}", path.display()))?;
+        let parsed = if path.extension().is_some_and(|e| e == "json") {
+            parse_json_symbols(&contents)
+        } else {
+            parse_symbol_map(&contents)
+        };
+        symbols.extend(
+            parsed.with_context(|_| format!("failed to parse symbols file {}", path.display()))?,
+        );
     }
+    Ok(symbols)
+}
 
-    impl Replacer<'_> {
-        fn should_snip_call(&self, instr: &walrus::ir::Instr) -> bool {
-            if let walrus::ir::Instr::Call(walrus::ir::Call { func }) = instr {
-                if self.to_snip.contains(func) {
-                    return true;
+/// Parse a symbol map with one `index:name` or `index name` entry per line.
+fn parse_symbol_map(contents: &str) -> Result<HashMap<u32, String>, SnipError> {
+    let mut symbols = HashMap::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line
+            .split_once(|c: char| c == ':' || c.is_whitespace())
+            .and_then(|(index, name)| Some((index.parse().ok()?, name.trim())));
+        match entry {
+            Some((index, name)) if !name.is_empty() => {
+                symbols.insert(index, name.to_string());
+            }
+            _ => {
+                return Err(SnipError::Parse(format!(
+                    "expected a line like `42:name`, found `{}`",
+                    line
+                )))
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+#[cfg(feature = "config")]
+fn parse_json_symbols(contents: &str) -> Result<HashMap<u32, String>, SnipError> {
+    Ok(serde_json::from_str(contents)?)
+}
+
+#[cfg(not(feature = "config"))]
+fn parse_json_symbols(_contents: &str) -> Result<HashMap<u32, String>, SnipError> {
+    Err(SnipError::Unsupported {
+        message: "reading JSON symbols files requires the `config` feature".to_string(),
+        source: None,
+    })
+}
+
+/// Read the presets defined in each of the given preset files.
+#[cfg(feature = "config")]
+fn read_preset_files(paths: &[path::PathBuf]) -> Result<Vec<presets::Preset>, SnipError> {
+    let mut presets = vec![];
+    for path in paths {
+        presets.extend(presets::from_file(path)?);
+    }
+    Ok(presets)
+}
+
+#[cfg(not(feature = "config"))]
+fn read_preset_files(paths: &[path::PathBuf]) -> Result<Vec<presets::Preset>, SnipError> {
+    if !paths.is_empty() {
+        return Err(SnipError::Unsupported {
+            message: "reading preset files requires the `config` feature".to_string(),
+            source: None,
+        });
+    }
+    Ok(vec![])
+}
+
+fn build_regex_set(
+    options: &Options,
+    custom_presets: &[presets::Preset],
+) -> Result<regex::RegexSet, SnipError> {
+    let mut patterns = options.patterns.clone();
+
+    for name in preset_names(options) {
+        let preset = custom_presets
+            .iter()
+            .find(|preset| preset.name == name)
+            .cloned()
+            .or_else(|| presets::find_builtin(name))
+            .ok_or_else(|| SnipError::InvalidOptions(format!("unknown preset `{}`", name)))?;
+        patterns.extend(preset.patterns);
+    }
+
+    compile_regex_set(&patterns, options.ignore_case).context("failed to compile regex")
+}
+
+/// Get the names of the presets to snip: those listed in `options.presets`,
+/// followed by those enabled by their `snip_*` aliases.
+fn preset_names(options: &Options) -> Vec<&str> {
+    let aliases = [
+        (options.snip_rust_fmt_code, "rust-fmt-code"),
+        (options.snip_rust_panicking_code, "rust-panicking-code"),
+        (options.snip_rust_float_fmt, "rust-float-fmt"),
+        (options.snip_rust_unicode, "rust-unicode"),
+        (options.snip_rust_backtrace, "rust-backtrace"),
+        (options.snip_rust_alloc_error, "rust-alloc-error"),
+        (
+            options.snip_wasm_bindgen_internals,
+            "wasm-bindgen-internals",
+        ),
+        (options.snip_tinygo_prints, "tinygo-prints"),
+        (options.snip_tinygo_reflect, "tinygo-reflect"),
+        (options.snip_assemblyscript_abort, "assemblyscript-abort"),
+        (options.snip_emscripten_syscalls, "emscripten-syscalls"),
+        (options.snip_emscripten_exceptions, "emscripten-exceptions"),
+        (options.snip_emscripten_sbrk, "emscripten-sbrk"),
+    ];
+    options
+        .presets
+        .iter()
+        .map(|name| name.as_str())
+        .chain(
+            aliases
+                .iter()
+                .filter(|&&(enabled, _)| enabled)
+                .map(|&(_, name)| name),
+        )
+        .collect()
+}
+
+fn compile_regex_set(patterns: &[String], ignore_case: bool) -> Result<regex::RegexSet, SnipError> {
+    Ok(regex::RegexSetBuilder::new(patterns)
+        .case_insensitive(ignore_case)
+        .build()?)
+}
+
+/// Exact names and regex patterns to match function names against, along with
+/// the settings for how to match them.
+struct Matcher {
+    names: HashSet<String>,
+    patterns: regex::RegexSet,
+    crates: HashSet<String>,
+    ignore_case: bool,
+    demangle: bool,
+    demangle_cpp: bool,
+    ignore_hash: bool,
+}
+
+impl Matcher {
+    fn new(names: &[String], patterns: regex::RegexSet, options: &Options) -> Matcher {
+        let names = names
+            .iter()
+            .map(|name| {
+                let name = if options.ignore_hash {
+                    without_hash(name)
+                } else {
+                    name.clone()
+                };
+                if options.ignore_case {
+                    name.to_lowercase()
+                } else {
+                    name
                 }
+            })
+            .collect();
+        Matcher {
+            names,
+            patterns,
+            crates: HashSet::new(),
+            ignore_case: options.ignore_case,
+            demangle: options.demangle,
+            demangle_cpp: options.demangle_cpp,
+            ignore_hash: options.ignore_hash,
+        }
+    }
+
+    /// Also match every mangled Rust symbol belonging to one of the given
+    /// crates.
+    fn crates(mut self, crates: &[String]) -> Matcher {
+        self.crates = crates.iter().map(|c| c.replace('-', "_")).collect();
+        self
+    }
+
+    /// The forms of a function's name to match: the name itself, its
+    /// demangled form when demangling Rust or C++ symbols, and its demangled
+    /// form without the hash when ignoring hashes.
+    fn forms<'a>(&self, name: &'a str) -> Vec<Cow<'a, str>> {
+        let mut forms = vec![Cow::Borrowed(name)];
+        if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+            if self.demangle {
+                forms.push(Cow::Owned(demangled.to_string()));
             }
-            false
+            if self.ignore_hash {
+                forms.push(Cow::Owned(format!("{:#}", demangled)));
+            }
+        } else if self.demangle_cpp {
+            // Legacy Rust symbols are valid Itanium symbols too, so only
+            // demangle as C++ what isn't Rust.
+            if let Some(demangled) = demangle_cpp(name) {
+                forms.push(Cow::Owned(demangled));
+            }
+        }
+        forms
+    }
+
+    /// Does any form of the given function name exactly match one of the
+    /// names?
+    fn is_named(&self, name: &str) -> bool {
+        self.forms(name).iter().any(|form| self.has_name(form))
+    }
+
+    fn has_name(&self, form: &str) -> bool {
+        if self.ignore_case {
+            self.names.contains(&form.to_lowercase())
+        } else {
+            self.names.contains(form)
         }
     }
 
-    impl VisitorMut for Replacer<'_> {
-        fn visit_instr_mut(&mut self, instr: &mut walrus::ir::Instr) {
-            if self.should_snip_call(instr) {
-                *instr = walrus::ir::Unreachable {}.into();
+    /// Describe why the given function name matches, if it does.
+    fn why(&self, name: &str) -> Option<String> {
+        for form in self.forms(name) {
+            if self.has_name(&form) {
+                return Some(format!("it is named `{}`", form));
             }
+            if let Some(i) = self.patterns.matches(&form).iter().next() {
+                return Some(format!(
+                    "`{}` matches the pattern `{}`",
+                    form,
+                    self.patterns.patterns()[i]
+                ));
+            }
+        }
+        if self.in_crate(name) {
+            return Some("it belongs to one of the snipped crates".to_string());
         }
+        None
+    }
+
+    /// Does this matcher select anything besides exact names?
+    fn has_patterns(&self) -> bool {
+        !self.patterns.is_empty() || !self.crates.is_empty()
     }
 
-    module.funcs.par_iter_local_mut().for_each(|(id, func)| {
-        // Don't bother transforming functions that we are snipping.
-        if to_snip.contains(&id) {
-            return;
+    /// Does any form of the given function name match one of the patterns?
+    fn matches_pattern(&self, name: &str) -> bool {
+        self.forms(name)
+            .iter()
+            .any(|form| self.patterns.is_match(form))
+    }
+
+    /// Is the given function a Rust symbol from one of the crates?
+    fn in_crate(&self, name: &str) -> bool {
+        if self.crates.is_empty() {
+            return false;
+        }
+        match rustc_demangle::try_demangle(name) {
+            Ok(demangled) => {
+                let path = format!("{:#}", demangled);
+                path_crate(&path).is_some_and(|c| self.crates.contains(c))
+            }
+            Err(_) => false,
         }
+    }
+}
 
-        let entry = func.entry_block();
-        walrus::ir::dfs_pre_order_mut(&mut Replacer { to_snip }, func, entry);
-    });
+/// Get the crate a demangled Rust path belongs to: its first segment, or for
+/// a qualified path like `<T as Trait>::f`, the crate of `T` if it is a path
+/// and of `Trait` otherwise.
+fn path_crate(path: &str) -> Option<&str> {
+    let path = strip_type_prefixes(path);
+    if let Some(rest) = path.strip_prefix('<') {
+        if let Some(krate) = path_crate(rest) {
+            return Some(krate);
+        }
+        let trait_start = find_top_level(rest, " as ")? + " as ".len();
+        return path_crate(&rest[trait_start..]);
+    }
+    let end = path.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+    if end > 0 && path[end..].starts_with("::") {
+        Some(&path[..end])
+    } else {
+        None
+    }
+}
+
+/// Strip references, pointers, lifetimes and `dyn` from the start of a type.
+fn strip_type_prefixes(mut ty: &str) -> &str {
+    loop {
+        let stripped = ty
+            .strip_prefix('&')
+            .or_else(|| ty.strip_prefix("*const "))
+            .or_else(|| ty.strip_prefix("*mut "))
+            .or_else(|| ty.strip_prefix("mut "))
+            .or_else(|| ty.strip_prefix("dyn "))
+            .or_else(|| {
+                let lifetime = ty.strip_prefix('\'')?;
+                Some(&lifetime[lifetime.find(' ')? + 1..])
+            });
+        match stripped {
+            Some(s) => ty = s,
+            None => return ty,
+        }
+    }
+}
+
+/// Find the first occurrence of `needle` in `haystack` that isn't nested
+/// inside brackets.
+fn find_top_level(haystack: &str, needle: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in haystack.char_indices() {
+        match c {
+            '<' | '[' | '(' => depth += 1,
+            '>' | ']' | ')' => depth = depth.checked_sub(1)?,
+            _ if depth == 0 && haystack[i..].starts_with(needle) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Get the demangled form of a C++ symbol, if it is one.
+fn demangle_cpp(name: &str) -> Option<String> {
+    let symbol = cpp_demangle::Symbol::new(name).ok()?;
+    symbol
+        .demangle(&cpp_demangle::DemangleOptions::default())
+        .ok()
+}
+
+/// Get the demangled form of a Rust symbol without its trailing `::h<hash>`.
+/// Names that are already demangled just have the hash stripped.
+fn without_hash(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return format!("{:#}", demangled);
+    }
+    match name.rfind("::h") {
+        Some(i)
+            if name.len() - i == "::h".len() + 16
+                && name[i + "::h".len()..]
+                    .bytes()
+                    .all(|b| b.is_ascii_hexdigit()) =>
+        {
+            name[..i].to_string()
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// The names that functions without a name in the name section are matched
+/// by: their name in `symbols`, the names they are exported as, and for
+/// imported functions, their `module::field` import name, as `snip_imports`
+/// names them.
+fn fallback_names(
+    module: &walrus::Module,
+    symbols: &HashMap<u32, String>,
+) -> HashMap<walrus::FunctionId, Vec<String>> {
+    let mut fallback_names: HashMap<walrus::FunctionId, Vec<String>> = HashMap::new();
+    for f in module.funcs.iter() {
+        if let Some(name) = symbols.get(&(f.id().index() as u32)) {
+            fallback_names.entry(f.id()).or_default().push(name.clone());
+        }
+    }
+    for export in module.exports.iter() {
+        if let walrus::ExportItem::Function(f) = export.item {
+            fallback_names
+                .entry(f)
+                .or_default()
+                .push(export.name.clone());
+        }
+    }
+    for import in module.imports.iter() {
+        if let walrus::ImportKind::Function(f) = import.kind {
+            let name = format!("{}::{}", import.module, import.name);
+            fallback_names.entry(f).or_default().push(name);
+        }
+    }
+    fallback_names
+}
+
+/// The names that `f` is matched by: its name in the name section, or else
+/// its names in `fallback_names`.
+fn candidate_names<'a>(
+    f: &'a walrus::Function,
+    fallback_names: &'a HashMap<walrus::FunctionId, Vec<String>>,
+) -> &'a [String] {
+    match f.name {
+        Some(ref name) => std::slice::from_ref(name),
+        None => fallback_names
+            .get(&f.id())
+            .map_or(&[][..], |names| &names[..]),
+    }
+}
+
+/// Find the functions `matcher` selects by their
+/// [`candidate_names`](fn.candidate_names.html). Unless they are named
+/// exactly, they also mustn't match `exclude`, and must pass `filter`. If
+/// `matcher` has no patterns but `filter` has signatures, it selects every
+/// function instead.
+fn find_functions_to_snip(
+    module: &walrus::Module,
+    matcher: &Matcher,
+    exclude: &Matcher,
+    filter: &Filter,
+    fallback_names: &HashMap<walrus::FunctionId, Vec<String>>,
+) -> HashSet<walrus::FunctionId> {
+    let select_all = !matcher.has_patterns() && !filter.signatures.is_empty();
+    module
+        .funcs
+        .par_iter()
+        .filter(|f| {
+            let names = candidate_names(f, fallback_names);
+            let selected = select_all
+                || names
+                    .iter()
+                    .any(|n| matcher.matches_pattern(n) || matcher.in_crate(n));
+            let excluded = names.iter().any(|n| exclude.matches_pattern(n));
+            names.iter().any(|n| matcher.is_named(n))
+                || (selected && !excluded && filter.allows(module, f))
+        })
+        .map(|f| f.id())
+        .collect()
+}
+
+/// Restrictions on the functions that patterns, crates and presets select.
+struct Filter {
+    /// The smallest body size allowed, and the size of each function's body.
+    min_size: Option<(u64, HashMap<walrus::FunctionId, u64>)>,
+
+    /// The types allowed, or any type if empty.
+    signatures: Vec<Signature>,
+}
+
+impl Filter {
+    fn allows(&self, module: &walrus::Module, f: &walrus::Function) -> bool {
+        if let Some((min_size, ref sizes)) = self.min_size {
+            if sizes.get(&f.id()).is_none_or(|&size| size < min_size) {
+                return false;
+            }
+        }
+        let ty = module.types.get(f.ty());
+        self.signatures.is_empty()
+            || self
+                .signatures
+                .iter()
+                .any(|s| s.params == ty.params() && s.results == ty.results())
+    }
+}
+
+/// A function type, written like `(i32, i32) -> i64`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Signature {
+    params: Vec<walrus::ValType>,
+    results: Vec<walrus::ValType>,
+}
+
+impl Signature {
+    fn of(ty: &walrus::Type) -> Signature {
+        Signature {
+            params: ty.params().to_vec(),
+            results: ty.results().to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |types: &[walrus::ValType]| {
+            let types: Vec<_> = types.iter().map(|ty| ty.to_string()).collect();
+            types.join(", ")
+        };
+        write!(f, "({})", list(&self.params))?;
+        match self.results.len() {
+            0 => Ok(()),
+            1 => write!(f, " -> {}", self.results[0]),
+            _ => write!(f, " -> ({})", list(&self.results)),
+        }
+    }
+}
+
+impl str::FromStr for Signature {
+    type Err = SnipError;
+
+    fn from_str(s: &str) -> Result<Signature, SnipError> {
+        let (params, results) = match s.find("->") {
+            Some(i) => (&s[..i], &s[i + "->".len()..]),
+            None => (s, "()"),
+        };
+        let types = |list: &str, parens_required: bool| {
+            let list = list.trim();
+            let inner = match list.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+                Some(inner) => inner,
+                None if !parens_required => list,
+                None => {
+                    return Err(SnipError::InvalidOptions(
+                        "the parameters must be in parentheses".to_string(),
+                    ))
+                }
+            };
+            inner
+                .split(',')
+                .map(str::trim)
+                .filter(|ty| !ty.is_empty())
+                .map(|ty| match ty {
+                    "i32" => Ok(walrus::ValType::I32),
+                    "i64" => Ok(walrus::ValType::I64),
+                    "f32" => Ok(walrus::ValType::F32),
+                    "f64" => Ok(walrus::ValType::F64),
+                    "v128" => Ok(walrus::ValType::V128),
+                    "anyref" => Ok(walrus::ValType::Anyref),
+                    _ => Err(SnipError::InvalidOptions(format!("unknown type `{}`", ty))),
+                })
+                .collect::<Result<Vec<_>, SnipError>>()
+        };
+        let signature = || -> Result<Signature, SnipError> {
+            Ok(Signature {
+                params: types(params, true)?,
+                results: types(results, false)?,
+            })
+        };
+        signature().with_context(|_| format!("invalid signature `{}`", s))
+    }
 }
 
-fn unexport_snipped_functions(module: &mut walrus::Module, to_snip: &HashSet<walrus::FunctionId>) {
-    let exports_to_snip: HashSet<walrus::ExportId> = module
+/// Remove the names of the module, its functions and their locals, except for
+/// the names of exported functions if `keep_export_names` is set.
+fn strip_names(module: &mut walrus::Module, keep_export_names: bool) {
+    let exported: HashSet<_> = module
         .exports
         .iter()
         .filter_map(|e| match e.item {
-            walrus::ExportItem::Function(f) if to_snip.contains(&f) => Some(e.id()),
+            walrus::ExportItem::Function(f) if keep_export_names => Some(f),
             _ => None,
         })
         .collect();
+    module.name = None;
+    for f in module.funcs.iter_mut() {
+        if !exported.contains(&f.id()) {
+            f.name = None;
+        }
+    }
+    strip_local_names(module);
+}
 
-    for e in exports_to_snip {
-        module.exports.delete(e);
+/// Replace the `target_features` section with one listing the given features.
+fn set_target_features(module: &mut walrus::Module, features: &[String]) {
+    module.customs.remove_raw("target_features");
+    module.customs.add(walrus::RawCustomSection {
+        name: "target_features".to_string(),
+        data: target_features_data(features),
+    });
+}
+
+/// The contents of a `target_features` section listing the given features.
+pub(crate) fn target_features_data(features: &[String]) -> Vec<u8> {
+    use wasm_encoder::Encode;
+
+    let mut data = vec![];
+    features.len().encode(&mut data);
+    for feature in features {
+        let (prefix, name) = feature.split_at(1);
+        data.push(prefix.as_bytes()[0]);
+        name.encode(&mut data);
     }
+    data
 }
 
-fn unimport_snipped_functions(module: &mut walrus::Module, to_snip: &HashSet<walrus::FunctionId>) {
-    let imports_to_snip: HashSet<walrus::ImportId> = module
-        .imports
+/// Remove the custom sections whose names `remove` returns true for.
+fn remove_custom_sections(module: &mut walrus::Module, remove: impl Fn(&str) -> bool) {
+    let ids: Vec<_> = module
+        .customs
         .iter()
-        .filter_map(|i| match i.kind {
-            walrus::ImportKind::Function(f) if to_snip.contains(&f) => Some(i.id()),
-            _ => None,
+        .filter(|(_, section)| remove(section.name()))
+        .map(|(id, _)| id)
+        .collect();
+    for id in ids {
+        module.customs.delete(id);
+    }
+}
+
+/// Remove the names of locals and labels.
+fn strip_local_names(module: &mut walrus::Module) {
+    let locals: Vec<_> = module.locals.iter().map(|l| l.id()).collect();
+    for local in locals {
+        module.locals.get_mut(local).name = None;
+    }
+    labels::clear(module);
+}
+
+/// Fail if any of the functions to snip are exported.
+fn check_exports(
+    module: &walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) -> Result<(), SnipError> {
+    let exports: Vec<_> = module
+        .exports
+        .iter()
+        .filter(|e| match e.item {
+            walrus::ExportItem::Function(f) => to_snip.contains(&f),
+            _ => false,
         })
+        .map(|e| format!("`{}`", e.name))
         .collect();
+    if !exports.is_empty() {
+        return Err(SnipError::Refused(format!(
+            "refusing to snip exported functions without `--force`: {}",
+            exports.join(", ")
+        )));
+    }
+    Ok(())
+}
 
-    for i in imports_to_snip {
-        module.imports.delete(i);
+/// Warn, or fail if `options.strict` is set, because the start function is
+/// about to be snipped and the module would trap on instantiation.
+fn warn_snipped_start(
+    module: &walrus::Module,
+    start: walrus::FunctionId,
+    matcher: &Matcher,
+    options: &Options,
+) -> Result<(), SnipError> {
+    let func = module.funcs.get(start);
+    let name = func.name.as_deref().unwrap_or("<unnamed>");
+    let why = func
+        .name
+        .as_ref()
+        .and_then(|name| matcher.why(name))
+        .unwrap_or_else(|| "it was selected by its import name".to_string());
+    let message = format!(
+        "the start function `{}` is snipped because {}, so the snipped module will trap when \
+         it is instantiated; pass `--snip-start` to remove the start section too",
+        name, why
+    );
+    if options.strict {
+        return Err(SnipError::Refused(message));
     }
+    log::warn!("{}", message);
+    Ok(())
 }
 
-fn snip_table_elements(module: &mut walrus::Module, to_snip: &HashSet<walrus::FunctionId>) {
-    let mut unreachable_funcs: HashMap<walrus::TypeId, walrus::FunctionId> = Default::default();
+/// Warn, or fail if `options.strict` is set, about each function to snip that
+/// is still reachable from the functions the host can call.
+fn warn_reachable_functions(
+    module: &walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+    options: &Options,
+) -> Result<(), SnipError> {
+    let graph = callgraph::CallGraph::new(module);
+    let roots = graph.roots().iter().filter(|f| !to_snip.contains(f));
+    let parents = graph.reachable(roots.cloned(), |f| to_snip.contains(&f));
 
-    let make_unreachable_func = |ty: walrus::TypeId,
-                                 types: &mut walrus::ModuleTypes,
-                                 locals: &mut walrus::ModuleLocals,
-                                 funcs: &mut walrus::ModuleFunctions|
-     -> walrus::FunctionId {
-        let ty = types.get(ty);
-        let params = ty.params().to_vec();
-        let locals: Vec<_> = params.iter().map(|ty| locals.add(*ty)).collect();
-        let results = ty.results().to_vec();
-        let mut builder = walrus::FunctionBuilder::new(types, &params, &results);
-        builder.func_body().unreachable();
-        builder.finish(locals, funcs)
-    };
+    let mut reachable: Vec<_> = to_snip
+        .iter()
+        .filter(|f| parents.contains_key(f))
+        .cloned()
+        .collect();
+    reachable.sort_by_key(|f| f.index());
+    for &f in &reachable {
+        let path: Vec<_> = callgraph::CallGraph::path(&parents, f)
+            .into_iter()
+            .map(|f| format!("`{}`", function_name(&module.funcs, f)))
+            .collect();
+        log::warn!(
+            "snipped function `{}` is still reachable: {}",
+            function_name(&module.funcs, f),
+            path.join(" -> ")
+        );
+    }
+    if options.strict && !reachable.is_empty() {
+        return Err(SnipError::Refused(format!(
+            "{} snipped functions are still reachable from the functions that are kept",
+            reachable.len()
+        )));
+    }
+    Ok(())
+}
 
-    for t in module.tables.iter_mut() {
-        if let walrus::TableKind::Function(ref mut ft) = t.kind {
-            let types = &mut module.types;
-            let locals = &mut module.locals;
-            let funcs = &mut module.funcs;
-
-            ft.elements
-                .iter_mut()
-                .flat_map(|el| el)
-                .filter(|f| to_snip.contains(f))
-                .for_each(|el| {
-                    let ty = funcs.get(*el).ty();
-                    *el = *unreachable_funcs
-                        .entry(ty)
-                        .or_insert_with(|| make_unreachable_func(ty, types, locals, funcs));
-                });
+/// The name of the given function, or its index if it has no name.
+pub(crate) fn function_name(funcs: &walrus::ModuleFunctions, f: walrus::FunctionId) -> String {
+    match funcs.get(f).name {
+        Some(ref name) => name.clone(),
+        None => format!("function {}", f.index()),
+    }
+}
+
+/// Find the functions named in `options.stub_functions`, by their type as
+/// `ty` gives it.
+pub(crate) fn find_stub_functions<T: Eq + std::hash::Hash>(
+    module: &walrus::Module,
+    options: &Options,
+    to_snip: &HashSet<walrus::FunctionId>,
+    ty: impl Fn(&walrus::Function) -> T,
+) -> Result<HashMap<T, walrus::FunctionId>, SnipError> {
+    let mut stubs = HashMap::new();
+    for name in &options.stub_functions {
+        let matcher = Matcher::new(
+            std::slice::from_ref(name),
+            regex::RegexSet::empty(),
+            options,
+        );
+        let f = module
+            .funcs
+            .iter()
+            .find(|f| f.name.as_ref().is_some_and(|n| matcher.is_named(n)))
+            .ok_or_else(|| {
+                SnipError::NoMatches(format!("no function named `{}` to use as a stub", name))
+            })?;
+        if to_snip.contains(&f.id()) {
+            return Err(SnipError::InvalidOptions(format!(
+                "`{}` is both snipped and a stub function",
+                name
+            )));
+        }
+        if let Some(&other) = stubs.get(&ty(f)) {
+            return Err(SnipError::InvalidOptions(format!(
+                "the stub functions `{}` and `{}` have the same type",
+                function_name(&module.funcs, other),
+                name
+            )));
+        }
+        stubs.insert(ty(f), f.id());
+    }
+    Ok(stubs)
+}
+
+/// Check that every snipped function in a table has the type of one of the
+/// stub functions, rather than needing a new stub.
+fn check_stub_function_types(
+    module: &walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+    stubs: &HashMap<walrus::TypeId, walrus::FunctionId>,
+) -> Result<(), SnipError> {
+    for t in module.tables.iter() {
+        if let walrus::TableKind::Function(ref ft) = t.kind {
+            let elements = ft.elements.iter().flatten();
+            let relative = ft.relative_elements.iter().flat_map(|(_, e)| e.iter());
+            for &f in elements.chain(relative) {
+                let ty = module.funcs.get(f).ty();
+                if to_snip.contains(&f) && !stubs.contains_key(&ty) {
+                    return Err(SnipError::Refused(format!(
+                        "none of the stub functions has the type `{}` of `{}`, which is in a \
+                         table",
+                        Signature::of(module.types.get(ty)),
+                        function_name(&module.funcs, f)
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find the imported functions whose `module::field` name is one of `names` or
+/// matches one of `patterns`.
+fn find_imports_to_snip(
+    module: &walrus::Module,
+    names: &[String],
+    patterns: &regex::RegexSet,
+) -> Vec<walrus::FunctionId> {
+    module
+        .imports
+        .iter()
+        .filter_map(|import| match import.kind {
+            walrus::ImportKind::Function(f) => {
+                let name = format!("{}::{}", import.module, import.name);
+                if names.contains(&name) || patterns.is_match(&name) {
+                    Some(f)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
 
-            ft.relative_elements
-                .iter_mut()
-                .flat_map(|(_, elems)| elems.iter_mut().filter(|f| to_snip.contains(f)))
-                .for_each(|el| {
-                    let ty = funcs.get(*el).ty();
-                    *el = *unreachable_funcs
-                        .entry(ty)
-                        .or_insert_with(|| make_unreachable_func(ty, types, locals, funcs));
+/// Remove every function protected by the keep list from `to_snip`, no matter
+/// how it was selected. The keep list matches the same names that functions
+/// are selected by, including those in `fallback_names`.
+fn retain_unkept_functions(
+    module: &walrus::Module,
+    to_snip: &mut HashSet<walrus::FunctionId>,
+    keep: &Matcher,
+    fallback_names: &HashMap<walrus::FunctionId, Vec<String>>,
+) {
+    to_snip.retain(|&f| {
+        let names = candidate_names(module.funcs.get(f), fallback_names);
+        match names
+            .iter()
+            .find(|name| keep.is_named(name) || keep.matches_pattern(name))
+        {
+            Some(name) => {
+                log::debug!("keeping `{}`, which the keep list protects", name);
+                false
+            }
+            None => true,
+        }
+    });
+}
+
+/// Replace the functions in `to_snip` according to `strategy`, replacing the
+/// calls to them in `callers`. Functions that trap, which table elements,
+/// exports and the start section are pointed at instead of the snipped
+/// functions, are shared through `stubs`.
+fn replace_functions(
+    module: &mut walrus::Module,
+    options: &Options,
+    strategy: &Strategy,
+    to_snip: &HashSet<walrus::FunctionId>,
+    callers: &HashSet<walrus::FunctionId>,
+    stubs: &mut HashMap<walrus::TypeId, walrus::FunctionId>,
+    outcome: &mut SnipOutcome,
+) -> Result<(), SnipError> {
+    match strategy {
+        Strategy::Unreachable if options.preserve_indices => {
+            let started = Instant::now();
+            outcome.call_sites += in_thread_pool(options, || {
+                passes::replace_calls_in(module, to_snip, callers)
+            })?;
+            outcome.add_timing("replace/calls", started.elapsed());
+            for &f in to_snip {
+                if let walrus::FunctionKind::Local(_) = module.funcs.get(f).kind {
+                    rebuild_function(module, f, |body, _| {
+                        body.unreachable();
+                    });
+                }
+            }
+        }
+        Strategy::Unreachable if options.keep_imports => {
+            let (imports, rest): (HashSet<_>, HashSet<_>) = to_snip.iter().partition(|&&f| {
+                matches!(module.funcs.get(f).kind, walrus::FunctionKind::Import(_))
+            });
+            let mut imports: Vec<_> = imports.into_iter().collect();
+            imports.sort();
+            for &f in &imports {
+                rebuild_function(module, f, |body, _| {
+                    body.unreachable();
                 });
+            }
+            // The imports only trap now, so they can stand in for stubs of
+            // their type instead of new functions.
+            for f in imports {
+                stubs.entry(module.funcs.get(f).ty()).or_insert(f);
+            }
+            let options = Options {
+                keep_imports: false,
+                ..options.clone()
+            };
+            replace_functions(module, &options, strategy, &rest, callers, stubs, outcome)?;
         }
+        Strategy::Unreachable => {
+            if options.table_null {
+                outcome.table_slots += passes::empty_table_elements(module, to_snip);
+            }
+            if !options.stub_functions.is_empty() {
+                check_stub_function_types(module, to_snip, stubs)?;
+            }
+            passes::create_stubs(module, to_snip, options.stub_exports, stubs);
+            let started = Instant::now();
+            outcome.call_sites += in_thread_pool(options, || {
+                passes::replace_calls_in(module, to_snip, callers)
+            })?;
+            outcome.add_timing("replace/calls", started.elapsed());
+            if options.stub_exports {
+                passes::stub_snipped_exports(module, to_snip, stubs);
+            } else {
+                passes::unexport_snipped_functions(module, to_snip);
+            }
+            passes::unimport_snipped_functions(module, to_snip);
+            let started = Instant::now();
+            outcome.table_slots += passes::snip_table_elements(module, to_snip, stubs);
+            outcome.add_timing("replace/tables", started.elapsed());
+            passes::snip_start_function(module, to_snip, stubs);
+            passes::delete_functions_to_snip(module, to_snip);
+            if options.name_stubs {
+                for (&ty, &stub) in stubs.iter() {
+                    // Leave the names of snipped functions standing in for
+                    // stubs, and of stubs named already.
+                    let signature = Signature::of(module.types.get(ty));
+                    let name = &mut module.funcs.get_mut(stub).name;
+                    if name.is_none() {
+                        *name = Some(format!("wasm_snip::unreachable_stub::<{}>", signature));
+                    }
+                }
+            }
+        }
+        Strategy::ReturnDefault => {
+            for &f in to_snip {
+                passes::replace_with_default_body(module, f);
+            }
+        }
+        Strategy::CallImportThenTrap(import) => {
+            let handler = snipped_hit_handler(module, import)?;
+            for &f in to_snip {
+                passes::replace_with_handler_call(module, f, handler);
+            }
+        }
+        Strategy::CustomBody(wat) => {
+            let templates = template::Templates::parse(wat)?;
+            let mut rest = HashSet::new();
+            for &f in to_snip {
+                match templates.build(module, f)? {
+                    Some((builder, args)) => replace_body(module, f, builder, args),
+                    None => {
+                        rest.insert(f);
+                    }
+                }
+            }
+            replace_functions(
+                module,
+                options,
+                &Strategy::Unreachable,
+                &rest,
+                callers,
+                stubs,
+                outcome,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Find or add the imported function named like `env::snipped_hit` that
+/// snipped functions call before trapping.
+fn snipped_hit_handler(
+    module: &mut walrus::Module,
+    import: &str,
+) -> Result<walrus::FunctionId, SnipError> {
+    let separator = match import.find("::") {
+        Some(separator) => separator,
+        None => {
+            return Err(SnipError::InvalidOptions(format!(
+                "the import to call must be named like `<module>::<name>`, found `{}`",
+                import
+            )))
+        }
+    };
+    let (import_module, name) = (&import[..separator], &import[separator + "::".len()..]);
+    let ty = module.types.add(&[walrus::ValType::I32], &[]);
+
+    let existing = module.imports.iter().find_map(|i| match i.kind {
+        walrus::ImportKind::Function(f) if i.module == import_module && i.name == name => Some(f),
+        _ => None,
+    });
+    match existing {
+        Some(f) if module.funcs.get(f).ty() == ty => Ok(f),
+        Some(_) => Err(SnipError::InvalidOptions(format!(
+            "the import `{}` must have type `[i32] -> []`",
+            import
+        ))),
+        None => Ok(module.add_import_func(import_module, name, ty).0),
+    }
+}
+
+/// Give the function `f` a new body, built by `fill` given the function's
+/// result types.
+fn rebuild_function(
+    module: &mut walrus::Module,
+    f: walrus::FunctionId,
+    fill: impl FnOnce(&mut walrus::InstrSeqBuilder, &[walrus::ValType]),
+) {
+    let ty = module.types.get(module.funcs.get(f).ty());
+    let params = ty.params().to_vec();
+    let results = ty.results().to_vec();
+
+    let args = params.iter().map(|ty| module.locals.add(*ty)).collect();
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &params, &results);
+    fill(&mut builder.func_body(), &results);
+    replace_body(module, f, builder, args);
+}
+
+/// Give the function `f` the body built by `builder`, keeping its id so that
+/// calls, exports and table elements still refer to it.
+fn replace_body(
+    module: &mut walrus::Module,
+    f: walrus::FunctionId,
+    builder: walrus::FunctionBuilder,
+    args: Vec<walrus::LocalId>,
+) {
+    // `FunctionBuilder` can only create new functions, so create one and move
+    // its body into `f`.
+    let ty = module.funcs.get(f).ty();
+    let new = builder.finish(args, &mut module.funcs);
+    let kind = mem::replace(
+        &mut module.funcs.get_mut(new).kind,
+        walrus::FunctionKind::Uninitialized(ty),
+    );
+    module.funcs.delete(new);
+
+    let old = mem::replace(&mut module.funcs.get_mut(f).kind, kind);
+    if let walrus::FunctionKind::Import(import) = old {
+        module.imports.delete(import.import);
     }
 }
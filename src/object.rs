@@ -0,0 +1,218 @@
+//! Snipping relocatable object files.
+//!
+//! The `.o` files that compilers emit for wasm describe their symbols in a
+//! `linking` custom section, and where each reference to a symbol is, so that
+//! `wasm-ld` can patch it, in `reloc.*` custom sections. Emitting an object
+//! file with walrus would move code out from under its relocations, so object
+//! files are snipped in place instead: the bodies of the snipped functions are
+//! replaced with `unreachable`, their relocations are dropped, and the
+//! relocations of the code after them move along with it. Every other section
+//! is copied as is.
+
+use crate::{Options, ResultExt, SnipError};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::Range;
+
+/// The body of a snipped function, with its size: no locals, `unreachable`
+/// and `end`.
+const SNIPPED_BODY: [u8; 4] = [3, 0, 0x00, 0x0b];
+
+/// Is `wasm` a relocatable object file, as opposed to a linked module?
+pub(crate) fn is_object(wasm: &[u8]) -> bool {
+    wasmparser::Parser::new(0)
+        .parse_all(wasm)
+        .map_while(Result::ok)
+        .any(|payload| match payload {
+            wasmparser::Payload::CustomSection(section) => section.name() == "linking",
+            _ => false,
+        })
+}
+
+/// Snip functions from the object file `wasm`, selecting them by the names of
+/// their symbols.
+pub(crate) fn snip(wasm: &[u8], options: &Options) -> Result<Vec<u8>, SnipError> {
+    let to_snip = functions_to_snip(wasm, options)?;
+
+    let mut output = wasm_encoder::Module::new();
+    let mut code = None;
+    let mut index = 0;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        let payload = payload?;
+        let (id, range) = match payload.as_section() {
+            Some((id, range)) => (id, range.start as usize..range.end as usize),
+            None => continue,
+        };
+        match payload {
+            wasmparser::Payload::CodeSectionStart { .. } => {
+                let (contents, moves) =
+                    snip_code(&wasm[range], &to_snip).context("failed to snip the code section")?;
+                output.section(&wasm_encoder::RawSection {
+                    id,
+                    data: &contents,
+                });
+                code = Some((index, moves));
+            }
+            wasmparser::Payload::CustomSection(section) => match (section.as_known(), &code) {
+                (wasmparser::KnownCustom::Reloc(reloc), Some((code, moves)))
+                    if reloc.section_index() == *code =>
+                {
+                    let data = move_relocations(&reloc, moves)
+                        .with_context(|_| format!("failed to update {}", section.name()))?;
+                    output.section(&wasm_encoder::CustomSection {
+                        name: Cow::Borrowed(section.name()),
+                        data: Cow::Owned(data),
+                    });
+                }
+                _ => {
+                    output.section(&wasm_encoder::RawSection {
+                        id,
+                        data: &wasm[range],
+                    });
+                }
+            },
+            _ => {
+                output.section(&wasm_encoder::RawSection {
+                    id,
+                    data: &wasm[range],
+                });
+            }
+        }
+        index += 1;
+    }
+    Ok(output.finish())
+}
+
+/// The functions of `wasm` to snip, by the position of their code in the code
+/// section.
+///
+/// Object files have no name section, so functions are named after their
+/// symbols for walrus to select them the way it selects any module's.
+fn functions_to_snip(wasm: &[u8], options: &Options) -> Result<HashSet<u32>, SnipError> {
+    let mut module = walrus::ModuleConfig::new()
+        .parse(wasm)
+        .map_err(SnipError::walrus)?;
+    // Until functions are added or removed, a function's id has the same
+    // index as the function.
+    let ids: Vec<_> = module.funcs.iter().map(|f| f.id()).collect();
+    for (index, name) in function_symbols(wasm)? {
+        if let Some(&f) = ids.get(index as usize) {
+            module.funcs.get_mut(f).name = Some(name.to_string());
+        }
+    }
+    let imported = ids.len() - module.funcs.iter_local().count();
+    Ok(crate::functions_to_snip(&module, options)?
+        .into_iter()
+        .filter_map(|f| f.index().checked_sub(imported))
+        .map(|i| i as u32)
+        .collect())
+}
+
+/// The index and name of each function symbol defined by `wasm`.
+fn function_symbols(wasm: &[u8]) -> Result<Vec<(u32, &str)>, SnipError> {
+    let mut symbols = vec![];
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        let section = match payload? {
+            wasmparser::Payload::CustomSection(section) => section,
+            _ => continue,
+        };
+        let linking = match section.as_known() {
+            wasmparser::KnownCustom::Linking(linking) => linking,
+            _ => continue,
+        };
+        for subsection in linking.subsections() {
+            let table = match subsection? {
+                wasmparser::Linking::SymbolTable(table) => table,
+                _ => continue,
+            };
+            for symbol in table {
+                if let wasmparser::SymbolInfo::Func {
+                    flags,
+                    index,
+                    name: Some(name),
+                } = symbol?
+                {
+                    if !flags.contains(wasmparser::SymbolFlags::UNDEFINED) {
+                        symbols.push((index, name));
+                    }
+                }
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+/// Where the code of a local function, size included, was in the code
+/// section, and where it is now, if it wasn't snipped.
+struct Move {
+    old: Range<usize>,
+    new: Option<usize>,
+}
+
+/// Replace the bodies of the functions in `to_snip` in the contents of a code
+/// section, returning the new contents along with where each function's code
+/// moved.
+fn snip_code(contents: &[u8], to_snip: &HashSet<u32>) -> Result<(Vec<u8>, Vec<Move>), SnipError> {
+    let mut reader = wasmparser::BinaryReader::new(contents, 0);
+    let count = reader.read_var_u32()?;
+    let mut code = contents[..reader.current_position()].to_vec();
+    let mut moves = vec![];
+    for i in 0..count {
+        let start = reader.current_position();
+        let size = reader.read_var_u32()?;
+        reader.read_bytes(size as usize)?;
+        let old = start..reader.current_position();
+        if to_snip.contains(&i) {
+            code.extend_from_slice(&SNIPPED_BODY);
+            moves.push(Move { old, new: None });
+        } else {
+            moves.push(Move {
+                new: Some(code.len()),
+                old: old.clone(),
+            });
+            code.extend_from_slice(&contents[old]);
+        }
+    }
+    Ok((code, moves))
+}
+
+/// Re-encode the relocation section `reloc` of the code section, dropping the
+/// relocations in snipped functions and moving the rest along with their
+/// function.
+fn move_relocations(
+    reloc: &wasmparser::RelocSectionReader,
+    moves: &[Move],
+) -> Result<Vec<u8>, SnipError> {
+    use wasm_encoder::Encode;
+
+    let mut entries = vec![];
+    for entry in reloc.entries() {
+        let mut entry = entry?;
+        let offset = entry.offset as usize;
+        let i = moves.partition_point(|m| m.old.end <= offset);
+        match moves.get(i).filter(|m| m.old.start <= offset) {
+            Some(Move { new: None, .. }) => continue,
+            Some(Move {
+                old,
+                new: Some(new),
+            }) => entry.offset = (offset - old.start + new) as u32,
+            None => {}
+        }
+        entries.push(entry);
+    }
+
+    let mut data = vec![];
+    reloc.section_index().encode(&mut data);
+    entries.len().encode(&mut data);
+    for entry in entries {
+        data.push(entry.ty as u8);
+        entry.offset.encode(&mut data);
+        entry.index.encode(&mut data);
+        match entry.ty.addend_kind() {
+            wasmparser::RelocAddendKind::None => {}
+            wasmparser::RelocAddendKind::Addend32 => (entry.addend as i32).encode(&mut data),
+            wasmparser::RelocAddendKind::Addend64 => entry.addend.encode(&mut data),
+        }
+    }
+    Ok(data)
+}
@@ -0,0 +1,821 @@
+//! The passes that snipping is made of, for tools that want to snip with
+//! their own selection of functions, or only do part of what
+//! [`snip`](../fn.snip.html) does.
+//!
+//! Replacing functions with `Strategy::Unreachable` runs these passes in this
+//! order:
+//!
+//! ```
+//! # use std::collections::{BTreeSet, HashMap, HashSet};
+//! # fn run(module: &mut walrus::Module, to_snip: &HashSet<walrus::FunctionId>) {
+//! use wasm_snip::passes;
+//!
+//! let mut stubs = HashMap::new();
+//! passes::create_stubs(module, to_snip, false, &mut stubs);
+//! passes::replace_calls_with_unreachable(module, to_snip);
+//! passes::unexport_snipped_functions(module, to_snip);
+//! passes::unimport_snipped_functions(module, to_snip);
+//! passes::snip_table_elements(module, to_snip, &mut stubs);
+//! passes::snip_start_function(module, to_snip, &mut stubs);
+//! passes::delete_functions_to_snip(module, to_snip);
+//! walrus::passes::gc::run(module);
+//! # }
+//! ```
+//!
+//! Passes that point something at a stub that traps share `stubs`, which maps
+//! each function type to its stub, so that each type gets one stub. The
+//! passes create the stubs they need as they go, in whatever order they need
+//! them, unless `create_stubs` created them first.
+
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use walrus::ir::{Visitor, VisitorMut};
+
+/// Replace the given function's body with one that returns the default value
+/// of each of its results. Imported functions become local functions.
+pub fn replace_with_default_body(module: &mut walrus::Module, f: walrus::FunctionId) {
+    crate::rebuild_function(module, f, |body, results| {
+        for result in results {
+            let value = match result {
+                walrus::ValType::I32 => walrus::ir::Value::I32(0),
+                walrus::ValType::I64 => walrus::ir::Value::I64(0),
+                walrus::ValType::F32 => walrus::ir::Value::F32(0.0),
+                walrus::ValType::F64 => walrus::ir::Value::F64(0.0),
+                walrus::ValType::V128 => walrus::ir::Value::V128(0),
+                walrus::ValType::Anyref => {
+                    body.instr(walrus::ir::RefNull {});
+                    continue;
+                }
+            };
+            body.instr(walrus::ir::Const { value });
+        }
+    });
+}
+
+/// Replace the given function's body with one that passes the function's
+/// index to `handler` and then traps.
+pub fn replace_with_handler_call(
+    module: &mut walrus::Module,
+    f: walrus::FunctionId,
+    handler: walrus::FunctionId,
+) {
+    let index = f.index() as i32;
+    crate::rebuild_function(module, f, |body, _| {
+        body.i32_const(index).call(handler).unreachable();
+    });
+}
+
+/// Delete the functions in `to_snip` from the module. Anything still referring
+/// to them makes the module fail to emit, so this comes last.
+pub fn delete_functions_to_snip(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) {
+    for f in to_snip.iter().cloned() {
+        log::debug!("deleting `{}`", crate::function_name(&module.funcs, f));
+        module.funcs.delete(f);
+    }
+}
+
+/// Replace calls to the functions in `to_snip` with `unreachable`, returning
+/// how many calls were replaced.
+pub fn replace_calls_with_unreachable(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) -> usize {
+    let callers = callers_of(module, to_snip);
+    replace_calls_in(module, to_snip, &callers)
+}
+
+/// Find the local functions that call one of the functions in `to_snip`,
+/// which are the only ones with calls to replace.
+pub fn callers_of(
+    module: &walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) -> HashSet<walrus::FunctionId> {
+    struct Calls<'a> {
+        to_snip: &'a HashSet<walrus::FunctionId>,
+        found: bool,
+    }
+
+    impl<'instr> Visitor<'instr> for Calls<'_> {
+        fn visit_call(&mut self, call: &walrus::ir::Call) {
+            self.found |= self.to_snip.contains(&call.func);
+        }
+    }
+
+    if to_snip.is_empty() {
+        return HashSet::new();
+    }
+    module
+        .funcs
+        .par_iter_local()
+        .filter(|&(_, func)| {
+            let mut calls = Calls {
+                to_snip,
+                found: false,
+            };
+            walrus::ir::dfs_in_order(&mut calls, func, func.entry_block());
+            calls.found
+        })
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Replace calls to the functions in `to_snip` with `unreachable`, like
+/// [`replace_calls_with_unreachable`](fn.replace_calls_with_unreachable.html),
+/// visiting only the functions in `callers`.
+///
+/// So that snipping several sets of functions, say with a strategy each,
+/// doesn't look for their callers each time, `callers` may be the callers of
+/// more functions than `to_snip`, as found by [`callers_of`](fn.callers_of.html)
+/// once for all of them.
+pub fn replace_calls_in(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+    callers: &HashSet<walrus::FunctionId>,
+) -> usize {
+    struct Replacer<'a> {
+        to_snip: &'a HashSet<walrus::FunctionId>,
+        replaced: usize,
+    }
+
+    impl Replacer<'_> {
+        fn should_snip_call(&self, instr: &walrus::ir::Instr) -> bool {
+            if let walrus::ir::Instr::Call(walrus::ir::Call { func }) = instr {
+                if self.to_snip.contains(func) {
+                    return true;
+                }
+            }
+            false
+        }
+    }
+
+    impl VisitorMut for Replacer<'_> {
+        fn visit_instr_mut(&mut self, instr: &mut walrus::ir::Instr) {
+            if self.should_snip_call(instr) {
+                *instr = walrus::ir::Unreachable {}.into();
+                self.replaced += 1;
+            }
+        }
+    }
+
+    if callers.is_empty() {
+        log::info!("replaced 0 calls with `unreachable`");
+        return 0;
+    }
+    let replaced: Vec<_> = module
+        .funcs
+        .par_iter_local_mut()
+        // Don't bother transforming functions that we are snipping.
+        .filter(|(id, _)| callers.contains(id) && !to_snip.contains(id))
+        .map(|(id, func)| {
+            let entry = func.entry_block();
+            let mut replacer = Replacer {
+                to_snip,
+                replaced: 0,
+            };
+            walrus::ir::dfs_pre_order_mut(&mut replacer, func, entry);
+            (id, replacer.replaced)
+        })
+        .collect();
+    for &(f, n) in replaced.iter().filter(|&&(_, n)| n > 0) {
+        log::debug!(
+            "replaced {} calls in `{}` with `unreachable`",
+            n,
+            crate::function_name(&module.funcs, f)
+        );
+    }
+    let replaced = replaced.iter().map(|&(_, n)| n).sum();
+    log::info!("replaced {} calls with `unreachable`", replaced);
+    replaced
+}
+
+/// Remove the exports of the functions in `to_snip`.
+pub fn unexport_snipped_functions(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) {
+    let exports_to_snip: HashSet<walrus::ExportId> = module
+        .exports
+        .iter()
+        .filter_map(|e| match e.item {
+            walrus::ExportItem::Function(f) if to_snip.contains(&f) => Some(e.id()),
+            _ => None,
+        })
+        .collect();
+
+    for e in exports_to_snip {
+        log::debug!("removing the export `{}`", module.exports.get(e).name);
+        module.exports.delete(e);
+    }
+}
+
+/// Point exports of snipped functions at stubs of the same type that trap.
+pub fn stub_snipped_exports(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+    stubs: &mut HashMap<walrus::TypeId, walrus::FunctionId>,
+) {
+    let (types, locals, funcs) = (&mut module.types, &mut module.locals, &mut module.funcs);
+    for e in module.exports.iter_mut() {
+        if let walrus::ExportItem::Function(ref mut f) = e.item {
+            if to_snip.contains(f) {
+                log::debug!("pointing the export `{}` at a stub", e.name);
+                let ty = funcs.get(*f).ty();
+                *f = *stubs
+                    .entry(ty)
+                    .or_insert_with(|| unreachable_func(ty, types, locals, funcs));
+            }
+        }
+    }
+}
+
+/// Point the start section at a stub that traps if the start function is
+/// snipped.
+pub fn snip_start_function(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+    stubs: &mut HashMap<walrus::TypeId, walrus::FunctionId>,
+) {
+    if let Some(start) = module.start.filter(|start| to_snip.contains(start)) {
+        log::debug!(
+            "pointing the start function `{}` at a stub",
+            crate::function_name(&module.funcs, start)
+        );
+        let ty = module.funcs.get(start).ty();
+        let stub = *stubs.entry(ty).or_insert_with(|| {
+            unreachable_func(ty, &mut module.types, &mut module.locals, &mut module.funcs)
+        });
+        module.start = Some(stub);
+    }
+}
+
+/// Remove the imports of the functions in `to_snip`, which leaves them without
+/// a definition until they are deleted.
+pub fn unimport_snipped_functions(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) {
+    let imports_to_snip: HashSet<walrus::ImportId> = module
+        .imports
+        .iter()
+        .filter_map(|i| match i.kind {
+            walrus::ImportKind::Function(f) if to_snip.contains(&f) => Some(i.id()),
+            _ => None,
+        })
+        .collect();
+
+    for i in imports_to_snip {
+        let import = module.imports.get(i);
+        log::debug!("removing the import `{}.{}`", import.module, import.name);
+        module.imports.delete(i);
+    }
+}
+
+/// Create a stub that traps for each type of snipped function that a stub will
+/// replace: in tables, in the start section and, with `stub_exports`, in
+/// exports. Types that already have a stub in `stubs` are skipped, and the
+/// rest get theirs in order of their type, rather than in the order they are
+/// first needed, so that snipping is reproducible.
+pub fn create_stubs(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+    stub_exports: bool,
+    stubs: &mut HashMap<walrus::TypeId, walrus::FunctionId>,
+) {
+    let mut replaced: Vec<walrus::FunctionId> = vec![];
+    for t in module.tables.iter() {
+        if let walrus::TableKind::Function(ref ft) = t.kind {
+            replaced.extend(ft.elements.iter().flatten());
+            replaced.extend(ft.relative_elements.iter().flat_map(|(_, e)| e.iter()));
+        }
+    }
+    replaced.extend(module.start);
+    if stub_exports {
+        replaced.extend(module.exports.iter().filter_map(|e| match e.item {
+            walrus::ExportItem::Function(f) => Some(f),
+            _ => None,
+        }));
+    }
+    let mut types: Vec<_> = replaced
+        .into_iter()
+        .filter(|f| to_snip.contains(f))
+        .map(|f| module.funcs.get(f).ty())
+        .filter(|ty| !stubs.contains_key(ty))
+        .collect();
+    types.sort();
+    types.dedup();
+
+    for ty in types {
+        let stub = unreachable_func(ty, &mut module.types, &mut module.locals, &mut module.funcs);
+        stubs.insert(ty, stub);
+    }
+}
+
+/// Create a function of type `ty` that traps.
+fn unreachable_func(
+    ty: walrus::TypeId,
+    types: &mut walrus::ModuleTypes,
+    locals: &mut walrus::ModuleLocals,
+    funcs: &mut walrus::ModuleFunctions,
+) -> walrus::FunctionId {
+    let ty = types.get(ty);
+    let params = ty.params().to_vec();
+    let locals: Vec<_> = params.iter().map(|ty| locals.add(*ty)).collect();
+    let results = ty.results().to_vec();
+    let mut builder = walrus::FunctionBuilder::new(types, &params, &results);
+    builder.func_body().unreachable();
+    builder.finish(locals, funcs)
+}
+
+/// Empty the table elements that refer to functions in `to_snip`, so that
+/// calling them traps, and return how many were emptied. Elements of segments
+/// at an offset from a global are only removed from the end of their segment.
+pub fn empty_table_elements(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+) -> usize {
+    let mut emptied = 0;
+    for t in module.tables.iter_mut() {
+        let table = t.id().index();
+        if let walrus::TableKind::Function(ref mut ft) = t.kind {
+            for (i, el) in ft.elements.iter_mut().enumerate() {
+                if el.is_some_and(|f| to_snip.contains(&f)) {
+                    log::debug!("emptying slot {} of table {}", i, table);
+                    *el = None;
+                    emptied += 1;
+                }
+            }
+            while ft.elements.last() == Some(&None) {
+                ft.elements.pop();
+            }
+
+            for (_, elems) in ft.relative_elements.iter_mut() {
+                while elems.last().is_some_and(|f| to_snip.contains(f)) {
+                    log::debug!(
+                        "removing a relative element from the end in table {}",
+                        table
+                    );
+                    elems.pop();
+                    emptied += 1;
+                }
+            }
+            ft.relative_elements.retain(|(_, elems)| !elems.is_empty());
+        }
+    }
+    log::info!("emptied {} table elements", emptied);
+    emptied
+}
+
+/// Remove the runs of table elements that are all `stubs`, which trap just
+/// like the empty elements left behind, along with the element segments at an
+/// offset from a global that are all stubs, and return how many elements were
+/// removed.
+///
+/// Tables that are neither imported nor exported, that no instruction uses
+/// directly, and that have no segments at an offset from a global, then shrink
+/// to end at their last element.
+pub fn shrink_tables(module: &mut walrus::Module, stubs: &HashSet<walrus::FunctionId>) -> usize {
+    #[derive(Default)]
+    struct TableUses(HashSet<walrus::TableId>);
+
+    impl<'instr> Visitor<'instr> for TableUses {
+        fn visit_table_get(&mut self, instr: &walrus::ir::TableGet) {
+            self.0.insert(instr.table);
+        }
+
+        fn visit_table_set(&mut self, instr: &walrus::ir::TableSet) {
+            self.0.insert(instr.table);
+        }
+
+        fn visit_table_grow(&mut self, instr: &walrus::ir::TableGrow) {
+            self.0.insert(instr.table);
+        }
+
+        fn visit_table_size(&mut self, instr: &walrus::ir::TableSize) {
+            self.0.insert(instr.table);
+        }
+    }
+
+    let mut used = TableUses::default();
+    for (_, func) in module.funcs.iter_local() {
+        walrus::ir::dfs_in_order(&mut used, func, func.entry_block());
+    }
+    for e in module.exports.iter() {
+        if let walrus::ExportItem::Table(t) = e.item {
+            used.0.insert(t);
+        }
+    }
+
+    let mut removed = 0;
+    for t in module.tables.iter_mut() {
+        let table = t.id().index();
+        let fixed = t.import.is_none() && !used.0.contains(&t.id());
+        if let walrus::TableKind::Function(ref mut ft) = t.kind {
+            let mut start = 0;
+            while start < ft.elements.len() {
+                let len = ft.elements[start..]
+                    .iter()
+                    .take_while(|el| el.is_some())
+                    .count();
+                let run = &mut ft.elements[start..start + len];
+                if len > 0 && run.iter().flatten().all(|f| stubs.contains(f)) {
+                    log::debug!(
+                        "removing the {} stubs at slot {} of table {}",
+                        len,
+                        start,
+                        table
+                    );
+                    run.iter_mut().for_each(|el| *el = None);
+                    removed += len;
+                }
+                start += len + 1;
+            }
+            while ft.elements.last() == Some(&None) {
+                ft.elements.pop();
+            }
+
+            ft.relative_elements.retain(|(_, elems)| {
+                let all_stubs = elems.iter().all(|f| stubs.contains(f));
+                if all_stubs {
+                    log::debug!(
+                        "removing a segment of {} stubs from table {}",
+                        elems.len(),
+                        table
+                    );
+                    removed += elems.len();
+                }
+                !all_stubs
+            });
+
+            let size = ft.elements.len() as u32;
+            if fixed && ft.relative_elements.is_empty() && size < t.initial {
+                log::info!(
+                    "shrinking table {} from {} to {} elements",
+                    table,
+                    t.initial,
+                    size
+                );
+                t.initial = size;
+            }
+        }
+    }
+    log::info!("removed {} stubs from tables", removed);
+    removed
+}
+
+/// Point table elements that are snipped functions at stubs of the same type
+/// that trap, returning how many elements were changed.
+pub fn snip_table_elements(
+    module: &mut walrus::Module,
+    to_snip: &HashSet<walrus::FunctionId>,
+    unreachable_funcs: &mut HashMap<walrus::TypeId, walrus::FunctionId>,
+) -> usize {
+    let mut redirected = 0;
+    for t in module.tables.iter_mut() {
+        let table = t.id().index();
+        if let walrus::TableKind::Function(ref mut ft) = t.kind {
+            let types = &mut module.types;
+            let locals = &mut module.locals;
+            let funcs = &mut module.funcs;
+
+            ft.elements
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, el)| Some((i, el.as_mut()?)))
+                .filter(|(_, f)| to_snip.contains(f))
+                .for_each(|(i, el)| {
+                    log::debug!(
+                        "pointing slot {} of table {} at a stub instead of `{}`",
+                        i,
+                        table,
+                        crate::function_name(funcs, *el)
+                    );
+                    let ty = funcs.get(*el).ty();
+                    *el = *unreachable_funcs
+                        .entry(ty)
+                        .or_insert_with(|| unreachable_func(ty, types, locals, funcs));
+                    redirected += 1;
+                });
+
+            ft.relative_elements
+                .iter_mut()
+                .flat_map(|(_, elems)| elems.iter_mut().filter(|f| to_snip.contains(f)))
+                .for_each(|el| {
+                    log::debug!(
+                        "pointing a relative element of table {} at a stub instead of `{}`",
+                        table,
+                        crate::function_name(funcs, *el)
+                    );
+                    let ty = funcs.get(*el).ty();
+                    *el = *unreachable_funcs
+                        .entry(ty)
+                        .or_insert_with(|| unreachable_func(ty, types, locals, funcs));
+                    redirected += 1;
+                });
+        }
+    }
+    log::info!("pointed {} table elements at stubs", redirected);
+    redirected
+}
+
+/// The constant addresses that the module's code uses, which
+/// [`gc_data`](fn.gc_data.html) compares with the addresses used once
+/// functions are snipped.
+pub fn data_references(module: &walrus::Module) -> BTreeSet<u32> {
+    crate::data::constant_addresses(module)
+}
+
+/// Zero the data that code referred to with the addresses in `before`, but
+/// that nothing refers to any more, and return how many bytes were zeroed.
+/// See [`Options::gc_data`](../struct.Options.html#structfield.gc_data).
+pub fn gc_data(module: &mut walrus::Module, before: &BTreeSet<u32>) -> usize {
+    let now = crate::data::constant_addresses(module);
+    let mut zeroed = 0;
+    for (segment, range) in crate::data::unused_pieces(module, before, &now) {
+        log::debug!(
+            "zeroing the {} bytes of unused data at {}",
+            range.end - range.start,
+            range.start
+        );
+        zeroed += crate::data::zero(module, &segment, range);
+    }
+    log::info!("zeroed {} bytes of unused data", zeroed);
+    zeroed
+}
+
+/// Blank the messages of Rust's `core::panicking::panic*` functions, and
+/// return how many bytes were zeroed.
+///
+/// A message's pointer and length are either constant arguments of the call,
+/// or the first two fields of a constant struct that an argument points at,
+/// as older versions of Rust pass them. The message is zeroed, and its length
+/// made zero, unless anything else refers to the message, even in part, or to
+/// the struct.
+pub fn snip_panic_strings(module: &mut walrus::Module) -> usize {
+    /// Where the length of a panic's message is.
+    #[derive(Clone, Copy)]
+    enum Len {
+        /// In the constant at a position of an instruction sequence.
+        Const(walrus::FunctionId, walrus::ir::InstrSeqId, usize),
+        /// In the struct field at the address.
+        Data(u32),
+    }
+
+    /// A possible panic message, and the constant argument referring to it.
+    struct Message {
+        arg: u32,
+        range: std::ops::Range<u32>,
+        len: Len,
+    }
+
+    #[derive(Default)]
+    struct Finder {
+        panics: HashSet<walrus::FunctionId>,
+        func: Option<walrus::FunctionId>,
+        // The constant arguments of panics, each with the next argument and
+        // where that is, in case it is a length.
+        args: Vec<(u32, Option<(u32, Len)>)>,
+        uses: HashMap<u32, usize>,
+    }
+
+    impl<'instr> Visitor<'instr> for Finder {
+        fn start_instr_seq(&mut self, seq: &'instr walrus::ir::InstrSeq) {
+            for (i, instr) in seq.instrs.iter().enumerate() {
+                let address = match instr {
+                    walrus::ir::Instr::Const(walrus::ir::Const {
+                        value: walrus::ir::Value::I32(n),
+                    }) => *n as u32,
+                    walrus::ir::Instr::Load(load) => load.arg.offset,
+                    walrus::ir::Instr::Store(store) => store.arg.offset,
+                    walrus::ir::Instr::Call(call) if self.panics.contains(&call.func) => {
+                        // The constant arguments right before the call, last
+                        // first.
+                        let args: Vec<_> = seq.instrs[..i]
+                            .iter()
+                            .rev()
+                            .map_while(|instr| match instr {
+                                walrus::ir::Instr::Const(walrus::ir::Const {
+                                    value: walrus::ir::Value::I32(n),
+                                }) => Some(*n as u32),
+                                _ => None,
+                            })
+                            .collect();
+                        let func = self.func.unwrap();
+                        for (j, &arg) in args.iter().enumerate() {
+                            let next = j
+                                .checked_sub(1)
+                                .map(|k| (args[k], Len::Const(func, seq.id(), i - j)));
+                            self.args.push((arg, next));
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+                *self.uses.entry(address).or_default() += 1;
+            }
+        }
+    }
+
+    let panics = module
+        .funcs
+        .iter()
+        .filter(|f| {
+            f.name
+                .as_ref()
+                .is_some_and(|name| crate::without_hash(name).starts_with("core::panicking::panic"))
+        })
+        .map(|f| f.id())
+        .collect();
+    let mut finder = Finder {
+        panics,
+        ..Finder::default()
+    };
+    for (id, func) in module.funcs.iter_local() {
+        finder.func = Some(id);
+        walrus::ir::dfs_in_order(&mut finder, func, func.entry_block());
+    }
+
+    // Only messages that fit in a data segment count as messages.
+    let segments = crate::data::segments(module);
+    let fits = |range: &std::ops::Range<u32>| {
+        crate::data::segment_at(&segments, range.start)
+            .is_some_and(|s| range.start < range.end && range.end <= s.range.end)
+    };
+    let mut messages = vec![];
+    let mut structs = HashSet::new();
+    for &(arg, next) in &finder.args {
+        if let Some((len, at)) = next {
+            let range = arg..arg.saturating_add(len);
+            if fits(&range) {
+                messages.push(Message {
+                    arg,
+                    range,
+                    len: at,
+                });
+            }
+        }
+        let ptr = crate::data::word_at(module, &segments, arg);
+        let len = crate::data::word_at(module, &segments, arg.wrapping_add(4));
+        if let (Some(ptr), Some(len)) = (ptr, len) {
+            let range = ptr..ptr.saturating_add(len);
+            if arg % 4 == 0 && fits(&range) {
+                structs.insert(arg);
+                messages.push(Message {
+                    arg,
+                    range,
+                    len: Len::Data(arg + 4),
+                });
+            }
+        }
+    }
+
+    // The addresses used other than as a panic's argument, including by
+    // globals and by pointers in data other than the fields of the structs
+    // holding messages, which the messages mustn't overlap.
+    let mut uses = finder.uses;
+    for &(arg, _) in &finder.args {
+        if let Some(n) = uses.get_mut(&arg) {
+            *n = n.saturating_sub(1);
+        }
+    }
+    let mut others: BTreeSet<u32> = uses
+        .into_iter()
+        .filter(|&(_, n)| n > 0)
+        .map(|(address, _)| address)
+        .collect();
+    for global in module.globals.iter() {
+        if let walrus::GlobalKind::Local(walrus::InitExpr::Value(walrus::ir::Value::I32(n))) =
+            global.kind
+        {
+            others.insert(n as u32);
+        }
+    }
+    for segment in &segments {
+        others.extend(
+            crate::data::words(module, segment)
+                .filter(|&(a, _)| !structs.contains(&a) && !structs.contains(&a.wrapping_sub(4)))
+                .map(|(_, word)| word),
+        );
+    }
+
+    let mut blanked: HashMap<walrus::FunctionId, Vec<(walrus::ir::InstrSeqId, usize)>> =
+        HashMap::new();
+    let mut zeroed = 0;
+    for message in messages {
+        if others.contains(&message.arg) || others.range(message.range.clone()).next().is_some() {
+            continue;
+        }
+        let segment = crate::data::segment_at(&segments, message.range.start).unwrap();
+        let (start, len) = (message.range.start, message.range.end - message.range.start);
+        let n = crate::data::zero(module, segment, message.range);
+        if n > 0 {
+            log::debug!("blanking the {}-byte panic message at {}", len, start);
+        }
+        zeroed += n;
+        match message.len {
+            Len::Const(f, seq, i) => blanked.entry(f).or_default().push((seq, i)),
+            Len::Data(address) => {
+                let segment = crate::data::segment_at(&segments, address).unwrap();
+                zeroed += crate::data::zero(module, segment, address..address + 4);
+            }
+        }
+    }
+
+    struct Blanker<'a>(&'a [(walrus::ir::InstrSeqId, usize)]);
+
+    impl VisitorMut for Blanker<'_> {
+        fn start_instr_seq_mut(&mut self, seq: &mut walrus::ir::InstrSeq) {
+            let id = seq.id();
+            for &(_, i) in self.0.iter().filter(|(s, _)| *s == id) {
+                seq.instrs[i] = walrus::ir::Const {
+                    value: walrus::ir::Value::I32(0),
+                }
+                .into();
+            }
+        }
+    }
+
+    for (f, lens) in blanked {
+        if let walrus::FunctionKind::Local(ref mut func) = module.funcs.get_mut(f).kind {
+            let entry = func.entry_block();
+            walrus::ir::dfs_pre_order_mut(&mut Blanker(&lens), func, entry);
+        }
+    }
+    log::info!("zeroed {} bytes of panic messages", zeroed);
+    zeroed
+}
+
+/// Lower the minimum size of memories that the module defines to what its
+/// data and the constant addresses its code uses need, and return how many
+/// pages were removed. Zeros at either end of data segments are removed
+/// first, since the memory starts out zeroed anyway.
+///
+/// Memories that are imported or shared, or that have data placed at an
+/// offset from a global or by `memory.init`, are left alone. Code that
+/// reaches beyond what it refers to by a constant address, other than by
+/// growing the memory, like a heap that assumes the memory's initial size,
+/// would break.
+pub fn shrink_memory(module: &mut walrus::Module) -> u32 {
+    let memories: Vec<_> = module
+        .memories
+        .iter()
+        .filter(|m| m.import.is_none() && !m.shared)
+        .map(|m| m.id())
+        .collect();
+    let mut removed = 0;
+    for memory in memories {
+        let mut ids: Vec<_> = module
+            .memories
+            .get(memory)
+            .data_segments
+            .iter()
+            .cloned()
+            .collect();
+        ids.sort();
+        let absolute = ids.iter().all(|&id| {
+            matches!(
+                module.data.get(id).kind,
+                walrus::DataKind::Active(walrus::ActiveData {
+                    location: walrus::ActiveDataLocation::Absolute(_),
+                    ..
+                })
+            )
+        });
+        if !absolute || module.data.iter().any(|data| data.is_passive()) {
+            continue;
+        }
+
+        for &id in &ids {
+            let data = module.data.get_mut(id);
+            let leading = crate::data::trim_zeros(&mut data.value);
+            if let walrus::DataKind::Active(walrus::ActiveData {
+                location: walrus::ActiveDataLocation::Absolute(ref mut start),
+                ..
+            }) = data.kind
+            {
+                *start += leading as u32;
+            }
+            if data.value.is_empty() {
+                module.data.delete(id);
+                module.memories.get_mut(memory).data_segments.remove(&id);
+            }
+        }
+
+        let initial = module.memories.get(memory).initial;
+        let size = u64::from(initial) * 65536;
+        let pages = crate::data::needed(module, memory, size).div_ceil(65536) as u32;
+        if pages < initial {
+            log::info!(
+                "lowering the minimum size of memory {} from {} to {} pages",
+                memory.index(),
+                initial,
+                pages
+            );
+            module.memories.get_mut(memory).initial = pages;
+            removed += initial - pages;
+        }
+    }
+    removed
+}
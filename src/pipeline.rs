@@ -0,0 +1,518 @@
+//! Snipping as a pipeline of passes that callers can add their own passes to.
+//!
+//! [`snip`](../fn.snip.html) runs the built-in passes in order:
+//!
+//! * `producers` adds `wasm-snip` to the producers section.
+//! * `select` selects the functions to snip, checking that none of them are
+//!   exported, and warning about the ones that stay reachable.
+//! * `data-references` notes the addresses of data that code uses, with
+//!   `gc_data`.
+//! * `snip-panic-strings` blanks panic messages, with `snip_panic_strings`.
+//! * `replace` replaces the selected functions according to their strategy.
+//! * `snip-start` removes the start section, with `snip_start`.
+//! * `gc` removes what is no longer used.
+//! * `gc-data` zeroes the data that only removed code referred to, with
+//!   `gc_data`.
+//! * `shrink-memory` lowers the minimum size of memory, with `shrink_memory`.
+//! * `discard` forgets the names and code ranges of snipped functions.
+//! * `strip-names` strips names, with `strip_names` and `strip_local_names`.
+//! * `strip-custom-sections` removes DWARF with `strip_dwarf`, and the custom
+//!   sections that `strip_custom_sections` matches.
+//! * `target-features` updates the `target_features` section.
+//!
+//! A custom pass sees the functions that `select` selected, and can add to
+//! them before `replace` or handle them itself:
+//!
+//! ```
+//! use wasm_snip::pipeline::{Pipeline, SnipContext, SnipPass};
+//!
+//! #[derive(Debug)]
+//! struct Log;
+//!
+//! impl SnipPass for Log {
+//!     fn name(&self) -> &str {
+//!         "log"
+//!     }
+//!
+//!     fn run(
+//!         &mut self,
+//!         _module: &mut walrus::Module,
+//!         context: &mut SnipContext,
+//!     ) -> Result<(), wasm_snip::SnipError> {
+//!         eprintln!("snipping {} functions", context.to_snip.len());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # fn main() -> Result<(), wasm_snip::SnipError> {
+//! # let mut module = walrus::Module::default();
+//! let mut pipeline = Pipeline::new();
+//! pipeline.insert_after("select", Log)?;
+//! let outcome = pipeline.run(&mut module, wasm_snip::Options::default())?;
+//! # let _ = outcome;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Matcher, Options, SnipOutcome, Strategy, TargetFeatures};
+use crate::{ResultExt, SnipError};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// A step of snipping a module.
+pub trait SnipPass {
+    /// The name that [`Pipeline::insert_before`] and [`Pipeline::insert_after`]
+    /// find the pass by.
+    fn name(&self) -> &str;
+
+    /// Run the pass over `module`.
+    fn run(
+        &mut self,
+        module: &mut walrus::Module,
+        context: &mut SnipContext,
+    ) -> Result<(), SnipError>;
+}
+
+/// What the passes of a pipeline share.
+#[derive(Clone, Debug, Default)]
+pub struct SnipContext {
+    /// The options to snip with. `select` reads the files of names and
+    /// patterns into `functions` and `patterns`.
+    pub options: Options,
+
+    /// The functions to snip, which `select` fills in.
+    pub to_snip: HashSet<walrus::FunctionId>,
+
+    /// What snipping has done so far, which the pipeline returns.
+    pub outcome: SnipOutcome,
+}
+
+/// The passes to snip a module with, in order.
+pub struct Pipeline<'a> {
+    passes: Vec<Box<dyn SnipPass + 'a>>,
+}
+
+impl fmt::Debug for Pipeline<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl Default for Pipeline<'_> {
+    fn default() -> Self {
+        Pipeline::new()
+    }
+}
+
+impl<'a> Pipeline<'a> {
+    /// The built-in passes, which snip what the options select.
+    pub fn new() -> Pipeline<'a> {
+        Pipeline::with_selection(|_| false)
+    }
+
+    /// The built-in passes, with `select` selecting functions to snip along
+    /// with the options, as in [`snip_with`](../fn.snip_with.html).
+    pub fn with_selection(select: impl FnMut(&walrus::Function) -> bool + 'a) -> Pipeline<'a> {
+        let references = Rc::new(RefCell::new(BTreeSet::new()));
+        let passes: Vec<Box<dyn SnipPass + 'a>> = vec![
+            Box::new(Builtin("producers", producers)),
+            Box::new(Select(select)),
+            Box::new(DataReferences(references.clone())),
+            Box::new(Builtin("snip-panic-strings", snip_panic_strings)),
+            Box::new(Builtin("replace", replace)),
+            Box::new(Builtin("snip-start", snip_start)),
+            Box::new(Builtin("gc", gc)),
+            Box::new(GcData(references)),
+            Box::new(Builtin("shrink-memory", shrink_memory)),
+            Box::new(Builtin("discard", discard)),
+            Box::new(Builtin("strip-names", strip_names)),
+            Box::new(Builtin("strip-custom-sections", strip_custom_sections)),
+            Box::new(Builtin("target-features", target_features)),
+        ];
+        Pipeline { passes }
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.passes.iter().map(|pass| pass.name())
+    }
+
+    /// Add a pass after all the others.
+    pub fn push(&mut self, pass: impl SnipPass + 'a) {
+        self.passes.push(Box::new(pass));
+    }
+
+    /// Add a pass right before the pass named `name`.
+    pub fn insert_before(&mut self, name: &str, pass: impl SnipPass + 'a) -> Result<(), SnipError> {
+        let i = self.position(name)?;
+        self.passes.insert(i, Box::new(pass));
+        Ok(())
+    }
+
+    /// Add a pass right after the pass named `name`.
+    pub fn insert_after(&mut self, name: &str, pass: impl SnipPass + 'a) -> Result<(), SnipError> {
+        let i = self.position(name)?;
+        self.passes.insert(i + 1, Box::new(pass));
+        Ok(())
+    }
+
+    /// Remove the pass named `name`, say to replace it with one of your own.
+    pub fn remove(&mut self, name: &str) -> Result<(), SnipError> {
+        let i = self.position(name)?;
+        self.passes.remove(i);
+        Ok(())
+    }
+
+    fn position(&self, name: &str) -> Result<usize, SnipError> {
+        match self.names().position(|n| n == name) {
+            Some(i) => Ok(i),
+            None => Err(SnipError::InvalidOptions(format!(
+                "there is no pass named `{}`",
+                name
+            ))),
+        }
+    }
+
+    /// Run the passes over `module` in order.
+    pub fn run(
+        &mut self,
+        module: &mut walrus::Module,
+        options: Options,
+    ) -> Result<SnipOutcome, SnipError> {
+        let mut context = SnipContext {
+            options,
+            ..SnipContext::default()
+        };
+        for pass in &mut self.passes {
+            log::debug!("running the `{}` pass", pass.name());
+            let started = Instant::now();
+            pass.run(module, &mut context)?;
+            context.outcome.add_timing(pass.name(), started.elapsed());
+        }
+        Ok(context.outcome)
+    }
+}
+
+impl<P: SnipPass + ?Sized> SnipPass for &mut P {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn run(
+        &mut self,
+        module: &mut walrus::Module,
+        context: &mut SnipContext,
+    ) -> Result<(), SnipError> {
+        (**self).run(module, context)
+    }
+}
+
+/// A built-in pass that needs nothing but the module and context.
+struct Builtin(
+    &'static str,
+    fn(&mut walrus::Module, &mut SnipContext) -> Result<(), SnipError>,
+);
+
+impl SnipPass for Builtin {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn run(
+        &mut self,
+        module: &mut walrus::Module,
+        context: &mut SnipContext,
+    ) -> Result<(), SnipError> {
+        (self.1)(module, context)
+    }
+}
+
+fn producers(module: &mut walrus::Module, context: &mut SnipContext) -> Result<(), SnipError> {
+    if !context.options.skip_producers_section {
+        module
+            .producers
+            .add_processed_by("wasm-snip", env!("CARGO_PKG_VERSION"));
+    }
+    Ok(())
+}
+
+/// The `select` pass, which also selects the functions its predicate does.
+struct Select<F>(F);
+
+impl<F> SnipPass for Select<F>
+where
+    F: FnMut(&walrus::Function) -> bool,
+{
+    fn name(&self) -> &str {
+        "select"
+    }
+
+    fn run(
+        &mut self,
+        module: &mut walrus::Module,
+        context: &mut SnipContext,
+    ) -> Result<(), SnipError> {
+        let to_snip = select(module, &mut context.options, &mut self.0, None)?;
+        let mut functions: Vec<_> = to_snip
+            .iter()
+            .map(|&f| (f, module.funcs.get(f).name.clone()))
+            .collect();
+        functions.sort();
+        context.outcome.functions = functions;
+        context.to_snip = to_snip;
+        Ok(())
+    }
+}
+
+/// Select the functions to snip as the `select` pass does, checking that none
+/// of them are exported and warning about the ones that stay reachable. The
+/// sizes of the functions' bodies are measured unless they are given.
+pub(crate) fn select(
+    module: &walrus::Module,
+    options: &mut Options,
+    select: impl FnMut(&walrus::Function) -> bool,
+    sizes: Option<HashMap<walrus::FunctionId, u64>>,
+) -> Result<HashSet<walrus::FunctionId>, SnipError> {
+    let (to_snip, matcher) = crate::select_functions(module, options, select, sizes)?;
+    if !options.force {
+        crate::check_exports(module, &to_snip)?;
+    }
+    if let Some(start) = module.start.filter(|start| to_snip.contains(start)) {
+        if !options.snip_start {
+            crate::warn_snipped_start(module, start, &matcher, options)?;
+        }
+    }
+    if options.warn_reachable {
+        crate::warn_reachable_functions(module, &to_snip, options)?;
+    }
+    Ok(to_snip)
+}
+
+/// The `data-references` pass, which notes the addresses that code uses
+/// before snipping for `gc-data` to compare with.
+struct DataReferences(Rc<RefCell<BTreeSet<u32>>>);
+
+impl SnipPass for DataReferences {
+    fn name(&self) -> &str {
+        "data-references"
+    }
+
+    fn run(
+        &mut self,
+        module: &mut walrus::Module,
+        context: &mut SnipContext,
+    ) -> Result<(), SnipError> {
+        if context.options.gc_data {
+            *self.0.borrow_mut() = crate::passes::data_references(module);
+        }
+        Ok(())
+    }
+}
+
+/// The `gc-data` pass.
+struct GcData(Rc<RefCell<BTreeSet<u32>>>);
+
+impl SnipPass for GcData {
+    fn name(&self) -> &str {
+        "gc-data"
+    }
+
+    fn run(
+        &mut self,
+        module: &mut walrus::Module,
+        context: &mut SnipContext,
+    ) -> Result<(), SnipError> {
+        if context.options.gc_data {
+            let before = self.0.borrow();
+            context.outcome.zeroed_data += crate::passes::gc_data(module, &before);
+        }
+        Ok(())
+    }
+}
+
+fn snip_panic_strings(
+    module: &mut walrus::Module,
+    context: &mut SnipContext,
+) -> Result<(), SnipError> {
+    if context.options.snip_panic_strings {
+        context.outcome.zeroed_data += crate::passes::snip_panic_strings(module);
+    }
+    Ok(())
+}
+
+fn replace(module: &mut walrus::Module, context: &mut SnipContext) -> Result<(), SnipError> {
+    let options = &context.options;
+    let rules = options
+        .strategies
+        .iter()
+        .map(|rule| {
+            let patterns = crate::compile_regex_set(&rule.patterns, options.ignore_case)
+                .context("failed to compile strategy regex")?;
+            Ok((
+                Matcher::new(&rule.functions, patterns, options),
+                &rule.strategy,
+            ))
+        })
+        .collect::<Result<Vec<_>, SnipError>>()?;
+    let templates = match options.replacement_body {
+        Some(ref wat) => Some(crate::template::Templates::parse(wat)?),
+        None => None,
+    };
+
+    // Decide how to replace each snipped function: with the first matching
+    // rule's strategy, else with a matching template, else with the default
+    // strategy. Functions are looked at in index order, and strategies kept
+    // in the order they were first used, so that the output doesn't depend
+    // on the order of a `HashSet`.
+    let mut by_strategy: Vec<(&Strategy, HashSet<walrus::FunctionId>)> = vec![];
+    let mut add = |strategy, f| match by_strategy.iter_mut().find(|(s, _)| *s == strategy) {
+        Some((_, to_snip)) => {
+            to_snip.insert(f);
+        }
+        None => by_strategy.push((strategy, Some(f).into_iter().collect())),
+    };
+    let mut to_snip: Vec<_> = context.to_snip.iter().cloned().collect();
+    to_snip.sort();
+    for f in to_snip {
+        let rule = module.funcs.get(f).name.as_ref().and_then(|name| {
+            rules
+                .iter()
+                .find(|(matcher, _)| matcher.is_named(name) || matcher.matches_pattern(name))
+        });
+        if let Some(&(_, strategy)) = rule {
+            add(strategy, f);
+            continue;
+        }
+        if let Some(ref templates) = templates {
+            if let Some((builder, args)) = templates.build(module, f)? {
+                crate::replace_body(module, f, builder, args);
+                continue;
+            }
+        }
+        add(&options.strategy, f);
+    }
+
+    let exports: Vec<_> = module
+        .exports
+        .iter()
+        .filter(|e| matches!(e.item, walrus::ExportItem::Function(_)))
+        .map(|e| (e.id(), e.name.clone()))
+        .collect();
+    let imports: Vec<_> = module
+        .imports
+        .iter()
+        .filter(|i| matches!(i.kind, walrus::ImportKind::Function(_)))
+        .map(|i| (i.id(), i.module.clone(), i.name.clone()))
+        .collect();
+
+    // Find the callers of every snipped function at once, rather than for
+    // each strategy's. The snipped functions may get new bodies, which could
+    // call other snipped functions, so they count as callers too.
+    let (all, outcome) = (&context.to_snip, &mut context.outcome);
+    let started = Instant::now();
+    let mut callers = crate::in_thread_pool(options, || crate::passes::callers_of(module, all))?;
+    callers.extend(all.iter().cloned());
+    outcome.add_timing("replace/calls", started.elapsed());
+    // Every strategy shares the stubs, so that each type gets one.
+    let mut stubs = crate::find_stub_functions(module, options, all, |f| f.ty())?;
+    let named: HashSet<_> = stubs.values().cloned().collect();
+    for (strategy, to_snip) in by_strategy {
+        crate::replace_functions(
+            module, options, strategy, &to_snip, &callers, &mut stubs, outcome,
+        )?;
+    }
+    if options.shrink_tables {
+        let stubs = stubs
+            .values()
+            .filter(|f| !named.contains(f))
+            .cloned()
+            .collect();
+        crate::passes::shrink_tables(module, &stubs);
+    }
+
+    // Stubbed exports keep their name, so only exports that are gone count.
+    let remaining: HashSet<_> = module.exports.iter().map(|e| e.id()).collect();
+    outcome.removed_exports = exports
+        .into_iter()
+        .filter(|(e, _)| !remaining.contains(e))
+        .map(|(_, name)| name)
+        .collect();
+    let remaining: HashSet<_> = module.imports.iter().map(|i| i.id()).collect();
+    outcome.removed_imports = imports
+        .into_iter()
+        .filter(|(i, _, _)| !remaining.contains(i))
+        .map(|(_, module, name)| (module, name))
+        .collect();
+    Ok(())
+}
+
+fn snip_start(module: &mut walrus::Module, context: &mut SnipContext) -> Result<(), SnipError> {
+    if context.options.snip_start {
+        module.start = None;
+    }
+    Ok(())
+}
+
+fn gc(module: &mut walrus::Module, context: &mut SnipContext) -> Result<(), SnipError> {
+    if !context.options.preserve_indices && !context.options.skip_gc {
+        walrus::passes::gc::run(module);
+    }
+    Ok(())
+}
+
+fn shrink_memory(module: &mut walrus::Module, context: &mut SnipContext) -> Result<(), SnipError> {
+    if context.options.shrink_memory {
+        crate::passes::shrink_memory(module);
+    }
+    Ok(())
+}
+
+fn discard(module: &mut walrus::Module, context: &mut SnipContext) -> Result<(), SnipError> {
+    let to_snip = &context.to_snip;
+    let kept: HashSet<_> = module.funcs.iter().map(|f| f.id()).collect();
+    crate::labels::retain(module, |f| kept.contains(&f) && !to_snip.contains(&f));
+    crate::code::discard(module, to_snip);
+    Ok(())
+}
+
+fn strip_names(module: &mut walrus::Module, context: &mut SnipContext) -> Result<(), SnipError> {
+    if context.options.strip_names {
+        crate::strip_names(module, context.options.keep_export_names);
+    } else if context.options.strip_local_names {
+        crate::strip_local_names(module);
+    }
+    Ok(())
+}
+
+fn strip_custom_sections(
+    module: &mut walrus::Module,
+    context: &mut SnipContext,
+) -> Result<(), SnipError> {
+    let options = &context.options;
+    if options.strip_dwarf {
+        crate::remove_custom_sections(module, |name| name.starts_with(".debug_"));
+    }
+    if !options.strip_custom_sections.is_empty() {
+        let patterns = regex::RegexSet::new(&options.strip_custom_sections)
+            .context("failed to compile custom section regex")?;
+        crate::remove_custom_sections(module, |name| patterns.is_match(name));
+    }
+    Ok(())
+}
+
+fn target_features(
+    module: &mut walrus::Module,
+    context: &mut SnipContext,
+) -> Result<(), SnipError> {
+    match context.options.target_features {
+        TargetFeatures::Preserve => {}
+        TargetFeatures::Strip => {
+            module.customs.remove_raw("target_features");
+        }
+        TargetFeatures::Set(ref features) => crate::set_target_features(module, features),
+    }
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! Built-in presets: named groups of patterns matching code that is commonly
+//! dead in wasm modules, like Rust's formatting machinery or Emscripten's
+//! syscall stubs.
+//!
+//! Enable presets with [`Options::presets`](../struct.Options.html#structfield.presets)
+//! or the executable's `--preset` flag, and list them with `--list-presets`.
+//!
+//! With the `config` feature, teams can also share their own presets in a
+//! TOML file, with one table per preset:
+//!
+//! ```toml
+//! [our-logging]
+//! description = "Our logging crate."
+//! patterns = [".*7logging.*"]
+//! ```
+//!
+//! or in a JSON file (one whose name ends in `.json`) with the same shape:
+//!
+//! ```json
+//! { "our-logging": { "description": "Our logging crate.", "patterns": [".*7logging.*"] } }
+//! ```
+
+#[cfg(feature = "config")]
+use crate::{ResultExt, SnipError};
+#[cfg(feature = "config")]
+use std::collections::BTreeMap;
+#[cfg(feature = "config")]
+use std::fs;
+#[cfg(feature = "config")]
+use std::path::Path;
+
+/// A named group of regex patterns to snip.
+#[derive(Clone, Debug)]
+pub struct Preset {
+    /// The name to enable the preset with, e.g. `rust-fmt-code`.
+    pub name: String,
+
+    /// A one-line description of what the preset snips.
+    pub description: String,
+
+    /// The regex patterns matching the functions to snip.
+    pub patterns: Vec<String>,
+}
+
+/// Get every built-in preset.
+pub fn builtin() -> Vec<Preset> {
+    BUILTIN
+        .iter()
+        .map(|&(name, description, patterns)| Preset {
+            name: name.to_string(),
+            description: description.to_string(),
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// Get the built-in preset with the given name, if there is one.
+pub fn find_builtin(name: &str) -> Option<Preset> {
+    builtin().into_iter().find(|preset| preset.name == name)
+}
+
+/// Read the presets defined in a TOML or JSON preset file, sorted by name.
+#[cfg(feature = "config")]
+pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Preset>, SnipError> {
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct PresetDef {
+        #[serde(default)]
+        description: String,
+        patterns: Vec<String>,
+    }
+
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .with_context(|_| format!("failed to read preset file {}", path.display()))?;
+    let defs: BTreeMap<String, PresetDef> = if path.extension().is_some_and(|e| e == "json") {
+        serde_json::from_str(&contents).map_err(SnipError::from)
+    } else {
+        toml::from_str(&contents).map_err(SnipError::from)
+    }
+    .with_context(|_| format!("failed to parse preset file {}", path.display()))?;
+
+    Ok(defs
+        .into_iter()
+        .map(|(name, def)| Preset {
+            name,
+            description: def.description,
+            patterns: def.patterns,
+        })
+        .collect())
+}
+
+const BUILTIN: &[(&str, &str, &[&str])] = &[
+    (
+        "rust-fmt-code",
+        "Snip Rust's `std::fmt` and `core::fmt` code.",
+        &[
+            // Mangled symbols. Because the v0 mangling scheme length-prefixes
+            // identifiers just like the legacy scheme does, these also match v0
+            // symbols such as `_RNvNtCs1QLEhZ2QfLZ_4core3fmt5write`.
+            ".*4core3fmt.*",
+            ".*3std3fmt.*",
+            // Mangled in impl.
+            r#".*core\.\.fmt\.\..*"#,
+            r#".*std\.\.fmt\.\..*"#,
+            // Demangled symbols.
+            ".*core::fmt::.*",
+            ".*std::fmt::.*",
+        ],
+    ),
+    (
+        "rust-panicking-code",
+        "Snip Rust's `std::panicking` and `core::panicking` code.",
+        &[
+            // Mangled symbols, in both the legacy and v0 schemes.
+            ".*4core9panicking.*",
+            ".*3std9panicking.*",
+            // Mangled in impl.
+            r#".*core\.\.panicking\.\..*"#,
+            r#".*std\.\.panicking\.\..*"#,
+            // Demangled symbols.
+            ".*core::panicking::.*",
+            ".*std::panicking::.*",
+        ],
+    ),
+    (
+        "rust-float-fmt",
+        "Snip Rust's float formatting and parsing code.",
+        &[
+            // Mangled symbols.
+            ".*4core3fmt5float.*",
+            ".*4core3num7flt2dec.*",
+            ".*4core3num7dec2flt.*",
+            "^_ZN3ryu.*",
+            "^_RN.*C(s[0-9A-Za-z]*_)?3ryu.*",
+            // Mangled in impl.
+            r#".*core\.\.fmt\.\.float.*"#,
+            // Demangled symbols.
+            ".*core::fmt::float::.*",
+            ".*core::num::flt2dec::.*",
+            ".*core::num::dec2flt::.*",
+            "^ryu::.*",
+        ],
+    ),
+    (
+        "rust-unicode",
+        "Snip Rust's Unicode tables and case conversion code.",
+        &[
+            // Mangled symbols.
+            ".*4core7unicode.*",
+            ".*11std_unicode.*",
+            ".*4core4char.*12to_(lower|upper)case.*",
+            ".*4core4char.*11To(Lower|Upper)case.*",
+            ".*5alloc3str.*12to_(lower|upper)case.*",
+            // Mangled in impl.
+            r#".*core\.\.char\.\.To(Lower|Upper)case.*"#,
+            // Demangled symbols.
+            ".*core::unicode::.*",
+            ".*std_unicode::.*",
+            ".*core::char::.*to_(lower|upper)case.*",
+            ".*core::char::To(Lower|Upper)case.*",
+            ".*alloc::str::.*to_(lower|upper)case.*",
+        ],
+    ),
+    (
+        "rust-backtrace",
+        "Snip Rust's backtrace capture and symbolization code.",
+        &[
+            // Mangled symbols.
+            ".*3std9backtrace.*",
+            ".*3std12backtrace_rs.*",
+            "^_ZN(9backtrace|5gimli|9addr2line).*",
+            "^_RN.*C(s[0-9A-Za-z]*_)?(9backtrace|5gimli|9addr2line).*",
+            // Mangled in impl.
+            r#".*std\.\.backtrace(_rs)?\.\..*"#,
+            r#".*\$LT\$(backtrace|gimli|addr2line)\.\..*"#,
+            // Demangled symbols.
+            ".*std::backtrace(_rs)?::.*",
+            "^<?(backtrace|gimli|addr2line)::.*",
+        ],
+    ),
+    (
+        "rust-alloc-error",
+        "Snip Rust's allocation error and out-of-memory handling code.",
+        &[
+            // Unmangled symbols, and the v0-mangled `__rustc::` symbols newer
+            // toolchains emit for them.
+            "^__(rust_alloc_error_handler|rg_oom|rdl_oom|rust_oom)$",
+            "^_RNvC.*_7___rustc[0-9]+_{3}(rust_alloc_error_handler|rg_oom|rdl_oom)$",
+            // Mangled symbols.
+            ".*5alloc5alloc18handle_alloc_error.*",
+            ".*3std5alloc24default_alloc_error_hook.*",
+            ".*3std5alloc8rust_oom.*",
+            ".*5alloc7raw_vec17capacity_overflow.*",
+            // Demangled symbols.
+            ".*alloc::alloc::handle_alloc_error.*",
+            ".*std::alloc::default_alloc_error_hook.*",
+            ".*std::alloc::rust_oom.*",
+            ".*alloc::raw_vec::capacity_overflow.*",
+        ],
+    ),
+    (
+        "wasm-bindgen-internals",
+        "Snip wasm-bindgen's `__wbindgen_describe_*` functions and other internals that are \
+         only used by the `wasm-bindgen` CLI.",
+        &[
+            // Unmangled symbols.
+            "^__wbindgen_describe.*",
+            "^__wbindgen_skip_interpret_calls$",
+            // Mangled symbols.
+            ".*12wasm_bindgen8describe.*",
+            ".*12wasm_bindgen[0-9]+__wbindgen_describe.*",
+            // Mangled in impl.
+            r#".*wasm_bindgen\.\.describe\.\..*"#,
+            // Demangled symbols.
+            ".*wasm_bindgen::describe::.*",
+            ".*wasm_bindgen::__wbindgen_describe.*",
+        ],
+    ),
+    (
+        "tinygo-prints",
+        "Snip TinyGo's `runtime.print*` functions.",
+        &[r"^runtime\.print.*", r"^runtime\.putchar$"],
+    ),
+    (
+        "tinygo-reflect",
+        "Snip TinyGo's `reflect` and `internal/reflectlite` packages.",
+        &[
+            // Functions, and methods like `(reflect.Value).Kind`.
+            r"^(\(\*?)?reflect\..*",
+            r"^(\(\*?)?internal/reflectlite\..*",
+        ],
+    ),
+    (
+        "assemblyscript-abort",
+        "Snip AssemblyScript's `abort` and `trace` builtins and the string concatenation \
+         helpers they use.",
+        &[
+            "^~lib/builtins/(abort|trace)$",
+            r"^~lib/string/String\.__concat$",
+            r"^~lib/string/String#concat$",
+            "^~lib/util/string/joinStrings$",
+        ],
+    ),
+    (
+        "emscripten-syscalls",
+        "Snip Emscripten's `__syscall_*` stubs.",
+        &["^_{2,3}syscall_?[0-9A-Za-z_]+$"],
+    ),
+    (
+        "emscripten-exceptions",
+        "Snip the C++ exception runtime, e.g. `__cxa_throw`.",
+        &[
+            "^_{2,3}cxa_(throw|rethrow|allocate_exception|free_exception|begin_catch|end_catch\
+             |find_matching_catch.*|uncaught_exceptions?)$",
+            "^_{2,3}resumeException$",
+        ],
+    ),
+    (
+        "emscripten-sbrk",
+        "Snip Emscripten's `sbrk` and heap resizing code.",
+        &["^_?s?brk$", "^_?emscripten_resize_heap$"],
+    ),
+];
@@ -0,0 +1,137 @@
+//! Recognizing the WebAssembly proposals that walrus can't parse yet.
+//!
+//! Modules that use one of them are snipped in place instead of with walrus:
+//! `snip_bytes_in_pool`, the component code and the command line send a
+//! module there when [`unsupported`] finds a proposal in it. When walrus is
+//! asked to parse such a module anyway, saying which proposal the module uses
+//! is more helpful than walrus's error about the section it gave up on.
+
+/// The first proposal that walrus doesn't support which `wasm` uses, if any.
+pub(crate) fn unsupported(wasm: &[u8]) -> Option<&'static str> {
+    find_unsupported(wasm).ok().flatten()
+}
+
+fn find_unsupported(wasm: &[u8]) -> Result<Option<&'static str>, wasmparser::BinaryReaderError> {
+    let mut memories = 0;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload? {
+            wasmparser::Payload::TypeSection(types) => {
+                for group in types {
+                    for ty in group?.types() {
+                        if let wasmparser::CompositeInnerType::Func(ty) = &ty.composite_type.inner {
+                            if ty
+                                .params()
+                                .iter()
+                                .chain(ty.results())
+                                .any(is_typed_reference)
+                            {
+                                return Ok(Some("function references"));
+                            }
+                        }
+                    }
+                }
+            }
+            wasmparser::Payload::ImportSection(imports) => {
+                for import in imports.into_imports() {
+                    match import?.ty {
+                        wasmparser::TypeRef::Memory(ty) if ty.memory64 => {
+                            return Ok(Some("memory64"))
+                        }
+                        wasmparser::TypeRef::Memory(_) => memories += 1,
+                        wasmparser::TypeRef::Table(ty) if ty.table64 => {
+                            return Ok(Some("memory64"))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            wasmparser::Payload::TableSection(tables) => {
+                for table in tables {
+                    if table?.ty.table64 {
+                        return Ok(Some("memory64"));
+                    }
+                }
+            }
+            wasmparser::Payload::MemorySection(section) => {
+                for memory in section {
+                    if memory?.memory64 {
+                        return Ok(Some("memory64"));
+                    }
+                    memories += 1;
+                }
+                if memories > 1 {
+                    return Ok(Some("multi-memory"));
+                }
+            }
+            wasmparser::Payload::TagSection(_) => return Ok(Some("exception handling")),
+            wasmparser::Payload::GlobalSection(globals) => {
+                for global in globals {
+                    let mut operators = global?.init_expr.get_operators_reader();
+                    while !operators.eof() {
+                        if let Some(proposal) = operator_proposal(&operators.read()?) {
+                            return Ok(Some(proposal));
+                        }
+                    }
+                }
+            }
+            wasmparser::Payload::ElementSection(elements) => {
+                for element in elements {
+                    let element = element?;
+                    match element.kind {
+                        wasmparser::ElementKind::Passive => return Ok(Some("bulk memory")),
+                        wasmparser::ElementKind::Declared => return Ok(Some("reference types")),
+                        wasmparser::ElementKind::Active { .. } => {}
+                    }
+                    if let wasmparser::ElementItems::Expressions(..) = element.items {
+                        return Ok(Some("reference types"));
+                    }
+                }
+            }
+            wasmparser::Payload::CodeSectionEntry(body) => {
+                let mut operators = body.get_operators_reader()?;
+                while !operators.eof() {
+                    if let Some(proposal) = operator_proposal(&operators.read()?) {
+                        return Ok(Some(proposal));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// The proposal that walrus doesn't support which introduced `operator`, if
+/// any.
+fn operator_proposal(operator: &wasmparser::Operator) -> Option<&'static str> {
+    use wasmparser::Operator::*;
+
+    match operator {
+        TableInit { .. } | ElemDrop { .. } => Some("bulk memory"),
+        RefFunc { .. } => Some("reference types"),
+        ReturnCall { .. } | ReturnCallIndirect { .. } => Some("tail calls"),
+        CallRef { .. }
+        | ReturnCallRef { .. }
+        | RefAsNonNull
+        | BrOnNull { .. }
+        | BrOnNonNull { .. } => Some("function references"),
+        Try { .. }
+        | Catch { .. }
+        | CatchAll
+        | Throw { .. }
+        | Rethrow { .. }
+        | Delegate { .. }
+        | TryTable { .. }
+        | ThrowRef => Some("exception handling"),
+        _ => None,
+    }
+}
+
+/// Whether `ty` is a reference to a particular type of function, or can't be
+/// null, which only the function references proposal allows.
+fn is_typed_reference(ty: &wasmparser::ValType) -> bool {
+    match ty {
+        wasmparser::ValType::Ref(ty) => ty.is_concrete_type_ref() || !ty.is_nullable(),
+        _ => false,
+    }
+}
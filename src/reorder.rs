@@ -0,0 +1,135 @@
+//! Putting an emitted module's local functions back in their original order.
+//!
+//! walrus emits local functions from largest to smallest, which renumbers
+//! them. When function indices must stay stable, the emitted module is
+//! rewritten with its local functions in the order given, renumbering every
+//! reference to them to match.
+
+use crate::SnipError;
+use wasm_encoder::reencode::{self, Reencode};
+
+/// Rewrite `wasm`, whose first `imported` functions are imports, so that the
+/// local function at position `i` moves to position `order[i]` among the local
+/// functions.
+pub(crate) fn reorder_functions(
+    wasm: &[u8],
+    imported: u32,
+    order: &[u32],
+) -> Result<Vec<u8>, SnipError> {
+    let mut reorderer = Reorderer { imported, order };
+    let mut module = wasm_encoder::Module::new();
+    reorderer.parse_core_module(&mut module, wasmparser::Parser::new(0), wasm)?;
+    Ok(module.finish())
+}
+
+struct Reorderer<'a> {
+    imported: u32,
+    order: &'a [u32],
+}
+
+impl Reorderer<'_> {
+    /// Put `items`, listed in emitted order, in their new order.
+    fn reorder<T>(&self, items: Vec<T>) -> Vec<T> {
+        let mut reordered: Vec<Option<T>> = items.iter().map(|_| None).collect();
+        for (i, item) in items.into_iter().enumerate() {
+            reordered[self.order[i] as usize] = Some(item);
+        }
+        reordered.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl Reorderer<'_> {
+    /// Renumber the functions of a map of locals' or labels' names.
+    fn indirect_name_map(
+        &mut self,
+        map: wasmparser::IndirectNameMap<'_>,
+    ) -> Result<wasm_encoder::IndirectNameMap, reencode::Error> {
+        let mut sorted = vec![];
+        for naming in map {
+            let naming = naming?;
+            let names = reencode::utils::name_map(naming.names, Ok)?;
+            sorted.push((self.function_index(naming.index)?, names));
+        }
+        sorted.sort_by_key(|&(index, _)| index);
+        let mut map = wasm_encoder::IndirectNameMap::new();
+        for (index, names) in &sorted {
+            map.append(*index, names);
+        }
+        Ok(map)
+    }
+}
+
+impl Reencode for Reorderer<'_> {
+    type Error = std::convert::Infallible;
+
+    fn function_index(&mut self, func: u32) -> Result<u32, reencode::Error> {
+        if func < self.imported {
+            return Ok(func);
+        }
+        Ok(self.imported + self.order[(func - self.imported) as usize])
+    }
+
+    fn parse_function_section(
+        &mut self,
+        functions: &mut wasm_encoder::FunctionSection,
+        section: wasmparser::FunctionSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        let types = section
+            .into_iter()
+            .map(|ty| self.type_index(ty?))
+            .collect::<Result<Vec<_>, _>>()?;
+        for ty in self.reorder(types) {
+            functions.function(ty);
+        }
+        Ok(())
+    }
+
+    fn parse_code_section(
+        &mut self,
+        code: &mut wasm_encoder::CodeSection,
+        section: wasmparser::CodeSectionReader<'_>,
+    ) -> Result<(), reencode::Error> {
+        let mut bodies = vec![];
+        for body in section {
+            let body = body?;
+            let mut func = self.new_function_with_parsed_locals(&body)?;
+            let mut reader = body.get_operators_reader()?;
+            while !reader.eof() {
+                func.instruction(&self.parse_instruction(&mut reader)?);
+            }
+            bodies.push(func);
+        }
+        for func in self.reorder(bodies) {
+            code.function(&func);
+        }
+        Ok(())
+    }
+
+    fn parse_custom_name_subsection(
+        &mut self,
+        names: &mut wasm_encoder::NameSection,
+        section: wasmparser::Name<'_>,
+    ) -> Result<(), reencode::Error> {
+        // Name maps must be sorted by index, so sort them again after
+        // renumbering.
+        match section {
+            wasmparser::Name::Function(map) => {
+                let mut sorted = vec![];
+                for naming in map {
+                    let naming = naming?;
+                    sorted.push((self.function_index(naming.index)?, naming.name));
+                }
+                sorted.sort_by_key(|&(index, _)| index);
+                let mut map = wasm_encoder::NameMap::new();
+                for (index, name) in sorted {
+                    map.append(index, name);
+                }
+                names.functions(&map);
+            }
+            wasmparser::Name::Local(map) => names.locals(&self.indirect_name_map(map)?),
+            wasmparser::Name::Label(map) => names.labels(&self.indirect_name_map(map)?),
+            section => reencode::utils::parse_custom_name_subsection(self, names, section)?,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+//! Updating source maps for snipped and removed functions.
+//!
+//! In a source map for a wasm module, the generated column of a mapping is
+//! the offset of an instruction in the `.wasm` file. Mappings are moved to
+//! where their function's code ended up, and mappings in the code of
+//! functions that were snipped or removed are dropped.
+
+use crate::code::Remap;
+use crate::SnipError;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Update the `mappings` of the source map `map`, given as JSON.
+pub(crate) fn update(map: &str, remap: &Remap) -> Result<String, SnipError> {
+    let mut map: serde_json::Value = serde_json::from_str(map)?;
+    let mappings = match map.get("mappings").and_then(|m| m.as_str()) {
+        Some(mappings) => decode(mappings)?,
+        None => return Err(SnipError::Parse("source map has no `mappings`".to_string())),
+    };
+
+    let mappings = mappings
+        .into_iter()
+        .map(|mut line| {
+            line.retain_mut(|segment| match remap.file_offset(segment[0] as u64) {
+                Some(column) => {
+                    segment[0] = column as i64;
+                    true
+                }
+                None => false,
+            });
+            line.sort_by_key(|segment| segment[0]);
+            line
+        })
+        .collect::<Vec<_>>();
+
+    map["mappings"] = encode(&mappings).into();
+    Ok(serde_json::to_string(&map)?)
+}
+
+/// Decode the segments of each line of `mappings`, with every field made
+/// absolute instead of relative to the previous segment's.
+fn decode(mappings: &str) -> Result<Vec<Vec<Vec<i64>>>, SnipError> {
+    let mut previous = [0; 5];
+    let mut lines = vec![];
+    for line in mappings.split(';') {
+        // Only the generated column starts over on each line.
+        previous[0] = 0;
+        let mut segments = vec![];
+        for segment in line.split(',').filter(|s| !s.is_empty()) {
+            let fields = decode_vlqs(segment)?;
+            if ![1, 4, 5].contains(&fields.len()) {
+                return Err(SnipError::Parse(format!("invalid mapping `{}`", segment)));
+            }
+            let fields: Vec<_> = fields
+                .iter()
+                .zip(&mut previous)
+                .map(|(field, previous)| {
+                    *previous += field;
+                    *previous
+                })
+                .collect();
+            segments.push(fields);
+        }
+        lines.push(segments);
+    }
+    Ok(lines)
+}
+
+/// The inverse of `decode`.
+fn encode(lines: &[Vec<Vec<i64>>]) -> String {
+    let mut previous = [0; 5];
+    let mut mappings = String::new();
+    for (i, segments) in lines.iter().enumerate() {
+        if i > 0 {
+            mappings.push(';');
+        }
+        previous[0] = 0;
+        for (j, fields) in segments.iter().enumerate() {
+            if j > 0 {
+                mappings.push(',');
+            }
+            for (field, previous) in fields.iter().zip(&mut previous) {
+                encode_vlq(field - *previous, &mut mappings);
+                *previous = *field;
+            }
+        }
+    }
+    mappings
+}
+
+/// Decode the base64 VLQ numbers of a segment.
+fn decode_vlqs(segment: &str) -> Result<Vec<i64>, SnipError> {
+    let mut fields = vec![];
+    let (mut value, mut shift) = (0i64, 0);
+    for c in segment.bytes() {
+        let digit = match BASE64.iter().position(|&b| b == c) {
+            Some(digit) if shift < 60 => digit as i64,
+            _ => return Err(SnipError::Parse(format!("invalid mapping `{}`", segment))),
+        };
+        value |= (digit & 0x1f) << shift;
+        shift += 5;
+        if digit & 0x20 == 0 {
+            let magnitude = value >> 1;
+            fields.push(if value & 1 == 1 {
+                -magnitude
+            } else {
+                magnitude
+            });
+            value = 0;
+            shift = 0;
+        }
+    }
+    if shift != 0 {
+        return Err(SnipError::Parse(format!("invalid mapping `{}`", segment)));
+    }
+    Ok(fields)
+}
+
+fn encode_vlq(value: i64, out: &mut String) {
+    let mut value = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = value & 0x1f;
+        value >>= 5;
+        if value != 0 {
+            digit |= 0x20;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if value == 0 {
+            break;
+        }
+    }
+}
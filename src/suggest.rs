@@ -0,0 +1,150 @@
+//! Heuristics for finding snips that are likely to be safe.
+//!
+//! None of these are guaranteed to keep a module working, so they are only
+//! ever suggested, never applied:
+//!
+//! * Built-in presets that match functions in the module, like the Rust
+//!   formatting and panicking machinery.
+//!
+//! * Functions that always trap, and that keep other functions alive. Those
+//!   functions only run on the way to a trap anyway, so snipping the trapping
+//!   function mostly loses things like panic messages.
+//!
+//! * Leaf functions, which don't call anything, that are much bigger than the
+//!   module's average function.
+
+use crate::{
+    callgraph, functions_to_snip, presets, retained_sizes, simulate_snip, Options, SnipError,
+};
+use std::cmp;
+use std::collections::HashSet;
+use walrus::ir::Visitor;
+
+/// How many times bigger than the average function a leaf function has to be
+/// to be suggested.
+const LARGE_LEAF_FACTOR: u64 = 8;
+
+/// Why a snip is suggested.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// The built-in preset with this name matches functions in the module.
+    Preset(String),
+
+    /// The function always traps, and keeps other functions alive.
+    AlwaysTraps(walrus::FunctionId),
+
+    /// The function calls nothing, and is much bigger than average.
+    LargeLeaf(walrus::FunctionId),
+}
+
+/// A suggested snip.
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    /// Why the snip is suggested.
+    pub reason: Reason,
+
+    /// How many bytes of code the snip would remove.
+    pub size: u64,
+}
+
+/// Suggest snips for the given module: first matching presets, then
+/// functions that always trap, then large leaf functions, each from the
+/// biggest saving to the smallest.
+pub fn suggest(module: &walrus::Module) -> Result<Vec<Suggestion>, SnipError> {
+    let mut suggestions = vec![];
+
+    let mut by_preset = vec![];
+    for preset in presets::builtin() {
+        let options = Options {
+            presets: vec![preset.name.clone()],
+            ..Options::default()
+        };
+        let to_snip = functions_to_snip(module, &options)?;
+        if to_snip.is_empty() {
+            continue;
+        }
+        let size = simulate_snip(module, &to_snip)?.size;
+        by_preset.push(Suggestion {
+            reason: Reason::Preset(preset.name),
+            size,
+        });
+    }
+    by_preset.sort_by_key(|s| cmp::Reverse(s.size));
+    suggestions.extend(by_preset);
+
+    let graph = callgraph::CallGraph::new(module);
+    let roots: HashSet<_> = graph.roots().iter().cloned().collect();
+    let sizes = crate::function_sizes(module)?;
+    let retained = retained_sizes(module)?;
+    let candidates = || {
+        module
+            .funcs
+            .iter_local()
+            .filter(|(id, _)| !roots.contains(id))
+    };
+
+    let mut trapping: Vec<_> = candidates()
+        .filter(|&(id, func)| always_traps(func) && retained[&id] > sizes[&id])
+        .map(|(id, _)| Suggestion {
+            reason: Reason::AlwaysTraps(id),
+            size: retained[&id],
+        })
+        .collect();
+    trapping.sort_by_key(|s| cmp::Reverse(s.size));
+    suggestions.extend(trapping);
+
+    let total: u64 = sizes.values().sum();
+    let average = total / cmp::max(sizes.len() as u64, 1);
+    let mut leaves: Vec<_> = candidates()
+        .filter(|&(id, _)| graph.callees(id).is_empty())
+        .filter(|(id, _)| sizes[id] > average * LARGE_LEAF_FACTOR)
+        .map(|(id, _)| Suggestion {
+            reason: Reason::LargeLeaf(id),
+            size: sizes[&id],
+        })
+        .collect();
+    leaves.sort_by_key(|s| cmp::Reverse(s.size));
+    suggestions.extend(leaves);
+
+    Ok(suggestions)
+}
+
+/// Whether the function never returns: its body ends in `unreachable`, and
+/// nothing in it returns or branches out of the body early.
+fn always_traps(func: &walrus::LocalFunction) -> bool {
+    let entry = func.entry_block();
+    match func.block(entry).instrs.last() {
+        Some(walrus::ir::Instr::Unreachable(_)) => {}
+        _ => return false,
+    }
+    let mut exits = Exits {
+        entry,
+        exits: false,
+    };
+    walrus::ir::dfs_in_order(&mut exits, func, entry);
+    !exits.exits
+}
+
+/// Looks for ways out of a function's body.
+struct Exits {
+    entry: walrus::ir::InstrSeqId,
+    exits: bool,
+}
+
+impl<'instr> Visitor<'instr> for Exits {
+    fn visit_return(&mut self, _: &walrus::ir::Return) {
+        self.exits = true;
+    }
+
+    fn visit_br(&mut self, br: &walrus::ir::Br) {
+        self.exits |= br.block == self.entry;
+    }
+
+    fn visit_br_if(&mut self, br: &walrus::ir::BrIf) {
+        self.exits |= br.block == self.entry;
+    }
+
+    fn visit_br_table(&mut self, br: &walrus::ir::BrTable) {
+        self.exits |= br.default == self.entry || br.blocks.contains(&self.entry);
+    }
+}
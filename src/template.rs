@@ -0,0 +1,245 @@
+//! Replacement bodies for snipped functions, written as WAT functions.
+//!
+//! A template is a WAT function like
+//!
+//! ```wat
+//! (func (param i32) (result i32)
+//!   i32.const -1)
+//! ```
+//!
+//! whose body replaces the body of every snipped function with the same
+//! type. Templates may use their parameters and locals, the memories of the
+//! module being snipped (in order, so declare `(memory 1)` to use its first
+//! memory), and functions they import, which are imported into the module
+//! being snipped as well.
+
+use crate::{ResultExt, SnipError};
+use std::collections::HashMap;
+use walrus::ir::{Instr, InstrSeqId, InstrSeqType};
+
+/// The functions parsed from a replacement body's WAT.
+pub(crate) struct Templates {
+    module: walrus::Module,
+}
+
+impl Templates {
+    /// Parse templates from WAT, which is either a list of functions or a
+    /// whole module.
+    pub(crate) fn parse(wat: &str) -> Result<Templates, SnipError> {
+        let wat = if wat.trim_start().starts_with("(module") {
+            wat.to_string()
+        } else {
+            format!("(module {})", wat)
+        };
+        let wasm = wat::parse_str(&wat).context("failed to parse replacement body")?;
+        let module = walrus::ModuleConfig::new()
+            .parse(&wasm)
+            .map_err(SnipError::walrus)
+            .context("failed to parse replacement body")?;
+        Ok(Templates { module })
+    }
+
+    /// Build a copy of the template with the same type as `f`, if there is
+    /// one, returning it along with its arguments.
+    pub(crate) fn build(
+        &self,
+        module: &mut walrus::Module,
+        f: walrus::FunctionId,
+    ) -> Result<Option<(walrus::FunctionBuilder, Vec<walrus::LocalId>)>, SnipError> {
+        let ty = module.types.get(module.funcs.get(f).ty());
+        let (params, results) = (ty.params().to_vec(), ty.results().to_vec());
+
+        let template = self.module.funcs.iter_local().find(|(_, func)| {
+            let ty = self.module.types.get(func.ty());
+            ty.params() == &params[..] && ty.results() == &results[..]
+        });
+        let template = match template {
+            Some((_, template)) => template,
+            None => return Ok(None),
+        };
+
+        let mut copier = Copier {
+            from: &self.module,
+            func: template,
+            to: module,
+            builder: None,
+            locals: HashMap::new(),
+            seqs: HashMap::new(),
+        };
+        let args = template.args.iter().map(|&arg| copier.local(arg)).collect();
+        let builder = walrus::FunctionBuilder::new(&mut copier.to.types, &params, &results);
+        copier
+            .seqs
+            .insert(template.entry_block(), builder.func_body_id());
+        copier.builder = Some(builder);
+        copier.copy_seq(template.entry_block())?;
+        Ok(Some((copier.builder.take().unwrap(), args)))
+    }
+}
+
+/// Copies a template function's body from the templates' module into the
+/// module being snipped.
+struct Copier<'a> {
+    from: &'a walrus::Module,
+    func: &'a walrus::LocalFunction,
+    to: &'a mut walrus::Module,
+    builder: Option<walrus::FunctionBuilder>,
+    locals: HashMap<walrus::LocalId, walrus::LocalId>,
+    seqs: HashMap<InstrSeqId, InstrSeqId>,
+}
+
+impl Copier<'_> {
+    fn builder(&mut self) -> &mut walrus::FunctionBuilder {
+        self.builder.as_mut().unwrap()
+    }
+
+    fn local(&mut self, local: walrus::LocalId) -> walrus::LocalId {
+        let (from, to) = (&self.from.locals, &mut self.to.locals);
+        *self
+            .locals
+            .entry(local)
+            .or_insert_with(|| to.add(from.get(local).ty()))
+    }
+
+    fn memory(&self, memory: walrus::MemoryId) -> Result<walrus::MemoryId, SnipError> {
+        let index = self
+            .from
+            .memories
+            .iter()
+            .position(|m| m.id() == memory)
+            .unwrap();
+        match self.to.memories.iter().nth(index) {
+            Some(m) => Ok(m.id()),
+            None => Err(SnipError::InvalidOptions(format!(
+                "replacement body uses memory {}, which the module doesn't have",
+                index
+            ))),
+        }
+    }
+
+    fn function(&mut self, func: walrus::FunctionId) -> Result<walrus::FunctionId, SnipError> {
+        let import = match self.from.funcs.get(func).kind {
+            walrus::FunctionKind::Import(ref import) => self.from.imports.get(import.import),
+            _ => {
+                return Err(SnipError::InvalidOptions(
+                    "replacement bodies can only call imported functions".to_string(),
+                ))
+            }
+        };
+        let ty = self.from.types.get(self.from.funcs.get(func).ty());
+        let ty = self.to.types.add(ty.params(), ty.results());
+
+        let existing = self.to.imports.iter().find_map(|i| match i.kind {
+            walrus::ImportKind::Function(f)
+                if i.module == import.module && i.name == import.name =>
+            {
+                Some(f)
+            }
+            _ => None,
+        });
+        match existing {
+            Some(f) if self.to.funcs.get(f).ty() == ty => Ok(f),
+            Some(_) => Err(SnipError::InvalidOptions(format!(
+                "replacement body imports `{}.{}` with a different type than the module does",
+                import.module, import.name
+            ))),
+            None => Ok(self.to.add_import_func(&import.module, &import.name, ty).0),
+        }
+    }
+
+    fn seq_type(&mut self, ty: InstrSeqType) -> InstrSeqType {
+        match ty {
+            InstrSeqType::Simple(ty) => InstrSeqType::Simple(ty),
+            InstrSeqType::MultiValue(ty) => {
+                let ty = self.from.types.get(ty);
+                InstrSeqType::MultiValue(self.to.types.add(ty.params(), ty.results()))
+            }
+        }
+    }
+
+    /// Copy a nested instruction sequence, returning the id of its copy.
+    fn nested_seq(&mut self, seq: InstrSeqId) -> Result<InstrSeqId, SnipError> {
+        let ty = self.seq_type(self.func.block(seq).ty);
+        let id = self.builder().dangling_instr_seq(ty).id();
+        self.seqs.insert(seq, id);
+        self.copy_seq(seq)?;
+        Ok(id)
+    }
+
+    /// Copy the instructions in `seq` into its already-created copy.
+    fn copy_seq(&mut self, seq: InstrSeqId) -> Result<(), SnipError> {
+        let func = self.func;
+        for instr in &func.block(seq).instrs {
+            let instr = self.instr(instr)?;
+            let id = self.seqs[&seq];
+            self.builder().instr_seq(id).instr(instr);
+        }
+        Ok(())
+    }
+
+    fn instr(&mut self, instr: &Instr) -> Result<Instr, SnipError> {
+        use walrus::ir::*;
+
+        let mut instr = instr.clone();
+        match &mut instr {
+            Instr::Block(Block { seq }) | Instr::Loop(Loop { seq }) => {
+                *seq = self.nested_seq(*seq)?;
+            }
+            Instr::IfElse(IfElse {
+                consequent,
+                alternative,
+            }) => {
+                *consequent = self.nested_seq(*consequent)?;
+                *alternative = self.nested_seq(*alternative)?;
+            }
+            Instr::Br(Br { block }) | Instr::BrIf(BrIf { block }) => {
+                *block = self.seqs[block];
+            }
+            Instr::BrTable(BrTable { blocks, default }) => {
+                for block in blocks.iter_mut() {
+                    *block = self.seqs[block];
+                }
+                *default = self.seqs[default];
+            }
+            Instr::LocalGet(LocalGet { local })
+            | Instr::LocalSet(LocalSet { local })
+            | Instr::LocalTee(LocalTee { local }) => {
+                *local = self.local(*local);
+            }
+            Instr::Call(Call { func }) => {
+                *func = self.function(*func)?;
+            }
+            Instr::MemorySize(MemorySize { memory })
+            | Instr::MemoryGrow(MemoryGrow { memory })
+            | Instr::MemoryFill(MemoryFill { memory })
+            | Instr::Load(Load { memory, .. })
+            | Instr::Store(Store { memory, .. })
+            | Instr::AtomicRmw(AtomicRmw { memory, .. })
+            | Instr::Cmpxchg(Cmpxchg { memory, .. })
+            | Instr::AtomicNotify(AtomicNotify { memory, .. })
+            | Instr::AtomicWait(AtomicWait { memory, .. })
+            | Instr::LoadSplat(LoadSplat { memory, .. }) => {
+                *memory = self.memory(*memory)?;
+            }
+            Instr::MemoryCopy(MemoryCopy { src, dst }) => {
+                *src = self.memory(*src)?;
+                *dst = self.memory(*dst)?;
+            }
+            Instr::CallIndirect(_)
+            | Instr::GlobalGet(_)
+            | Instr::GlobalSet(_)
+            | Instr::MemoryInit(_)
+            | Instr::DataDrop(_)
+            | Instr::TableGet(_)
+            | Instr::TableSet(_)
+            | Instr::TableGrow(_)
+            | Instr::TableSize(_) => {
+                return Err(SnipError::InvalidOptions(
+                    "replacement bodies can't use globals, tables or data segments".to_string(),
+                ))
+            }
+            _ => {}
+        }
+        Ok(instr)
+    }
+}
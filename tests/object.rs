@@ -0,0 +1,30 @@
+#![cfg(target_arch = "wasm32")]
+#![no_std]
+
+static TABLE: [u32; 4] = [1, 2, 3, 5];
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn expensive(x: u32) -> u32 {
+    let mut sum = 0;
+    for i in 0..x {
+        sum += TABLE[i as usize % TABLE.len()] * i;
+    }
+    sum
+}
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn cheap(x: u32) -> u32 {
+    TABLE[x as usize % TABLE.len()]
+}
+
+#[no_mangle]
+pub extern "C" fn run(x: u32) -> u32 {
+    expensive(x) + cheap(x)
+}
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
@@ -1,6 +1,7 @@
 use assert_cmd::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 #[test]
@@ -48,12 +49,80 @@ fn assert_snip<P: AsRef<Path>>(cmd: &mut Command, expected_path: P) {
     }
 }
 
+fn snip_to_module(cmd: &mut Command, name: &str) -> walrus::Module {
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join(name)
+        .with_extension("wasm");
+
+    cmd.arg("-o").arg(&output).assert().success();
+
+    walrus::Module::from_file(&output).expect("should parse snipped wasm file")
+}
+
+fn function_names(module: &walrus::Module) -> HashSet<String> {
+    module.funcs.iter().filter_map(|f| f.name.clone()).collect()
+}
+
+/// Write a module that exports a trivial function for each of the given
+/// names, and return its path.
+fn module_with_functions(name: &str, functions: &[&str]) -> PathBuf {
+    let mut module = walrus::Module::default();
+    for function in functions {
+        let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.func_body().unreachable();
+        let id = builder.finish(vec![], &mut module.funcs);
+        module.funcs.get_mut(id).name = Some(function.to_string());
+        module.exports.add(function, id);
+    }
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.wasm", name));
+    module.emit_wasm_file(&path).unwrap();
+    path
+}
+
+/// Write a module whose start function is named `start`, and return its path.
+fn module_with_start(name: &str) -> PathBuf {
+    let mut module = walrus::Module::default();
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().unreachable();
+    let start = builder.finish(vec![], &mut module.funcs);
+    module.funcs.get_mut(start).name = Some("start".to_string());
+    module.start = Some(start);
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.wasm", name));
+    module.emit_wasm_file(&path).unwrap();
+    path
+}
+
+/// Snip the module written by `module_with_functions` with the given
+/// arguments, and return the names of the functions left over. The functions
+/// are all exported, so this passes `--force`.
+fn snip_functions(functions: &[&str], args: &[&str], name: &str) -> HashSet<String> {
+    let input = module_with_functions(&format!("{}.input", name), functions);
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    function_names(&snip_to_module(
+        cmd.arg(&input).arg("--force").args(args),
+        name,
+    ))
+}
+
+fn demangled_function_names(module: &walrus::Module) -> Vec<String> {
+    module
+        .funcs
+        .iter()
+        .filter_map(|f| f.name.as_ref())
+        .map(|name| format!("{:#}", rustc_demangle::demangle(name)))
+        .collect()
+}
+
 fn wasm_snip() -> Command {
+    wasm_snip_file("hello.wasm")
+}
+
+fn wasm_snip_file(input: &str) -> Command {
     let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
     cmd.arg(
         Path::new(env!("CARGO_MANIFEST_DIR"))
             .join("tests")
-            .join("hello.wasm"),
+            .join(input),
     );
     cmd
 }
@@ -83,3 +152,3824 @@ fn snip_rust_panicking_code() {
         "no_panicking.wasm",
     );
 }
+
+#[test]
+fn ignore_case() {
+    assert_snip(
+        wasm_snip().arg("--ignore-case").arg("-p").arg(".*ALLOC.*"),
+        "no_alloc.wasm",
+    );
+}
+
+#[test]
+fn exclude_patterns() {
+    let module = snip_to_module(
+        wasm_snip()
+            .arg("-p")
+            .arg(".*unwrap_failed.*")
+            .arg("--exclude-pattern")
+            .arg(".*h2289429026f92553E"),
+        "exclude_patterns",
+    );
+    let names = function_names(&module);
+    assert!(names.contains("_ZN4core6result13unwrap_failed17h2289429026f92553E"));
+    assert!(!names.contains("_ZN4core6result13unwrap_failed17h3fa070bc513aa440E"));
+}
+
+#[test]
+fn keep_functions_and_patterns() {
+    let module = snip_to_module(
+        wasm_snip()
+            .arg("--snip-rust-panicking-code")
+            .arg("--keep-pattern")
+            .arg(".*3std9panicking.*")
+            .arg("--keep-function")
+            .arg("_ZN5hello7snip_me17hf15dbd799e7ad6aaE")
+            .arg("_ZN5hello7snip_me17hf15dbd799e7ad6aaE"),
+        "keep_functions_and_patterns",
+    );
+    let names = function_names(&module);
+    assert!(names.contains("_ZN5hello7snip_me17hf15dbd799e7ad6aaE"));
+    assert!(names.contains("_ZN3std9panicking20rust_panic_with_hook17h02b37a5538e89bc2E"));
+    assert!(!names.contains("_ZN4core9panicking5panic17h3b1a363ecbf0c21eE"));
+}
+
+#[test]
+fn functions_from_file() {
+    let list = Path::new(env!("CARGO_TARGET_TMPDIR")).join("functions.txt");
+    fs::write(
+        &list,
+        "# Functions to snip.\n\n_ZN5hello7snip_me17hf15dbd799e7ad6aaE\n",
+    )
+    .unwrap();
+    assert_snip(
+        wasm_snip().arg("--functions-from-file").arg(&list),
+        "snip_me.wasm",
+    );
+}
+
+#[test]
+fn patterns_from_file() {
+    let list = Path::new(env!("CARGO_TARGET_TMPDIR")).join("patterns.txt");
+    fs::write(&list, "# Patterns to snip.\n.*alloc.*\n").unwrap();
+    assert_snip(
+        wasm_snip().arg("--patterns-from-file").arg(&list),
+        "no_alloc.wasm",
+    );
+}
+
+#[test]
+fn config_file() {
+    let config = Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasm-snip.toml");
+    fs::write(
+        &config,
+        "# The -o flag overrides this.\n\
+         output = \"ignored.wasm\"\n\
+         patterns = [\".*alloc.*\"]\n",
+    )
+    .unwrap();
+    assert_snip(wasm_snip().arg("--config").arg(&config), "no_alloc.wasm");
+    assert!(!Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("ignored.wasm")
+        .exists());
+}
+
+#[test]
+fn cargo_metadata() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cargo_metadata");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("Cargo.toml"),
+        "[package]\n\
+         name = \"app\"\n\
+         version = \"0.1.0\"\n\
+         \n\
+         [package.metadata.wasm-snip]\n\
+         patterns = [\".*alloc.*\"]\n",
+    )
+    .unwrap();
+    assert_snip(wasm_snip().current_dir(&dir), "no_alloc.wasm");
+}
+
+#[test]
+fn cargo_snip() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cargo_snip");
+    let artifact_dir = dir.join("target/wasm32-unknown-unknown/release");
+    fs::create_dir_all(&artifact_dir).unwrap();
+    fs::write(
+        dir.join("Cargo.toml"),
+        "[package]\n\
+         name = \"hello\"\n\
+         version = \"0.1.0\"\n\
+         \n\
+         [package.metadata.wasm-snip]\n\
+         patterns = [\".*alloc.*\"]\n\
+         skip-producers-section = true\n",
+    )
+    .unwrap();
+    fs::copy(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm"),
+        artifact_dir.join("hello.wasm"),
+    )
+    .unwrap();
+
+    Command::cargo_bin("cargo-snip")
+        .unwrap()
+        .current_dir(&dir)
+        .arg("snip")
+        .arg("--release")
+        .arg("--target-dir")
+        .arg(dir.join("target"))
+        .assert()
+        .success();
+
+    let expected = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/no_alloc.wasm"))
+        .expect("should open expected wasm file");
+    let actual =
+        fs::read(artifact_dir.join("hello.snipped.wasm")).expect("should open snipped wasm file");
+    assert!(actual == expected, "cargo snip did not snip the artifact");
+}
+
+#[test]
+fn demangle() {
+    assert_snip(
+        wasm_snip()
+            .arg("--demangle")
+            .arg("hello::snip_me::hf15dbd799e7ad6aa"),
+        "snip_me.wasm",
+    );
+}
+
+#[test]
+fn ignore_hash() {
+    assert_snip(
+        wasm_snip().arg("--ignore-hash").arg("hello::snip_me"),
+        "snip_me.wasm",
+    );
+}
+
+#[test]
+fn demangle_cpp() {
+    const APPEND: &str =
+        "_ZNSt3__212basic_stringIcNS_11char_traitsIcEENS_9allocatorIcEEE6appendEPKc";
+    const FOO: &str = "_Z3fooi";
+
+    let names = snip_functions(
+        &[APPEND, FOO],
+        &[
+            "--demangle-cpp",
+            "-p",
+            r"std::__2::basic_string<.*>::append\(char const\*\)",
+        ],
+        "demangle_cpp",
+    );
+    assert!(!names.contains(APPEND));
+    assert!(names.contains(FOO));
+}
+
+#[test]
+fn snip_rust_fmt_code_v0_mangling() {
+    let input = walrus::Module::from_file(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/v0_mangling.wasm"),
+    )
+    .unwrap();
+    assert!(demangled_function_names(&input)
+        .iter()
+        .any(|name| name.contains("core::fmt::")));
+
+    let module = snip_to_module(
+        wasm_snip_file("v0_mangling.wasm").arg("--snip-rust-fmt-code"),
+        "snip_rust_fmt_code_v0_mangling",
+    );
+    for name in demangled_function_names(&module) {
+        assert!(!name.contains("core::fmt::"), "{} was not snipped", name);
+    }
+}
+
+#[test]
+fn snip_rust_panicking_code_v0_mangling() {
+    let module = snip_to_module(
+        wasm_snip_file("v0_mangling.wasm").arg("--snip-rust-panicking-code"),
+        "snip_rust_panicking_code_v0_mangling",
+    );
+    for name in demangled_function_names(&module) {
+        assert!(
+            !name.contains("core::panicking::"),
+            "{} was not snipped",
+            name
+        );
+    }
+}
+
+#[test]
+fn snip_crate() {
+    let names = function_names(&snip_to_module(
+        wasm_snip().arg("--snip-crate").arg("hello"),
+        "snip_crate",
+    ));
+    assert!(!names.contains("_ZN5hello7snip_me17hf15dbd799e7ad6aaE"));
+    assert!(names.contains("_ZN4core9panicking5panic17h3b1a363ecbf0c21eE"));
+
+    let module = snip_to_module(
+        wasm_snip_file("v0_mangling.wasm")
+            .arg("--snip-crate")
+            .arg("v0-mangling"),
+        "snip_crate_v0_mangling",
+    );
+    let names = demangled_function_names(&module);
+    assert!(names.iter().any(|name| name == "format_len"));
+    assert!(names.iter().any(|name| name.starts_with("core::")));
+    for name in names {
+        assert!(!name.contains("v0_mangling::"), "{} was not snipped", name);
+    }
+}
+
+#[test]
+fn snip_rust_float_fmt() {
+    let module = snip_to_module(
+        wasm_snip_file("v0_mangling.wasm").arg("--snip-rust-float-fmt"),
+        "snip_rust_float_fmt",
+    );
+    let names = demangled_function_names(&module);
+    assert!(names.iter().any(|name| name == "core::fmt::write"));
+    for name in names {
+        assert!(
+            !name.contains("flt2dec") && !name.contains("core::fmt::float"),
+            "{} was not snipped",
+            name
+        );
+    }
+}
+
+#[test]
+fn snip_rust_unicode() {
+    let snipped = [
+        "_ZN4core7unicode12unicode_data11conversions8to_lower17h0123456789abcdefE",
+        "_ZN4core4char7methods22_$LT$impl$u20$char$GT$12to_uppercase17h0123456789abcdefE",
+        "_ZN5alloc3str21_$LT$impl$u20$str$GT$12to_lowercase17h0123456789abcdefE",
+        "_RNvNtNtCs1234_4core7unicode9printable12is_printable",
+    ];
+    let kept = "_ZN5hello12to_lowercase17h0123456789abcdefE";
+
+    let mut functions = snipped.to_vec();
+    functions.push(kept);
+    let names = snip_functions(&functions, &["--snip-rust-unicode"], "snip_rust_unicode");
+    for name in &snipped {
+        assert!(!names.contains(*name), "{} was not snipped", name);
+    }
+    assert!(names.contains(kept));
+}
+
+#[test]
+fn snip_rust_backtrace() {
+    let snipped = [
+        "_ZN3std9backtrace9Backtrace7capture17h0123456789abcdefE",
+        "_ZN3std12backtrace_rs9symbolize5gimli7resolve17h0123456789abcdefE",
+        "_ZN5gimli4read4line13parse_file_v517h0123456789abcdefE",
+        "_ZN80_$LT$addr2line..Context$LT$R$GT$$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE",
+        "_RNvNtCs1234_9addr2line8function10find_frame",
+    ];
+    let kept = "_ZN3std10sys_common9backtrace28__rust_begin_short_backtrace17h0123456789abcdefE";
+
+    let mut functions = snipped.to_vec();
+    functions.push(kept);
+    let names = snip_functions(
+        &functions,
+        &["--snip-rust-backtrace"],
+        "snip_rust_backtrace",
+    );
+    for name in &snipped {
+        assert!(!names.contains(*name), "{} was not snipped", name);
+    }
+    assert!(names.contains(kept));
+}
+
+#[test]
+fn snip_rust_alloc_error() {
+    let snipped = [
+        "__rust_alloc_error_handler",
+        "__rdl_oom",
+        "_RNvCsfLfy6EI15iL_7___rustc26___rust_alloc_error_handler",
+        "_ZN5alloc5alloc18handle_alloc_error8rt_error17h0123456789abcdefE",
+        "_ZN3std5alloc24default_alloc_error_hook17h0123456789abcdefE",
+        "_RNvNtCs1234_5alloc7raw_vec17capacity_overflow",
+    ];
+    let kept = "__rust_alloc";
+
+    let mut functions = snipped.to_vec();
+    functions.push(kept);
+    let names = snip_functions(
+        &functions,
+        &["--snip-rust-alloc-error"],
+        "snip_rust_alloc_error",
+    );
+    for name in &snipped {
+        assert!(!names.contains(*name), "{} was not snipped", name);
+    }
+    assert!(names.contains(kept));
+}
+
+#[test]
+fn snip_wasm_bindgen_internals() {
+    let module = snip_to_module(
+        wasm_snip_file("wasm_bindgen.wasm")
+            .arg("--force")
+            .arg("--snip-wasm-bindgen-internals"),
+        "snip_wasm_bindgen_internals",
+    );
+    let names = demangled_function_names(&module);
+    for name in &names {
+        assert!(!name.contains("describe"), "{} was not snipped", name);
+    }
+    assert!(names.iter().any(|name| name.starts_with("greet_")));
+    assert!(names.iter().any(|name| name == "__wbindgen_malloc"));
+    assert!(names
+        .iter()
+        .any(|name| name == "wasm_bindgen::externref::__wbindgen_externref_table_grow"));
+}
+
+#[test]
+fn snip_tinygo_presets() {
+    let functions = [
+        "runtime.printstring",
+        "runtime.printnl",
+        "runtime.putchar",
+        "reflect.ValueOf",
+        "(reflect.Value).Kind",
+        "(*internal/reflectlite.rawType).Elem",
+        "runtime.alloc",
+        "main.main",
+    ];
+
+    let names = snip_functions(&functions, &["--snip-tinygo-prints"], "snip_tinygo_prints");
+    assert!(!names.contains("runtime.printstring"));
+    assert!(!names.contains("runtime.putchar"));
+    assert!(names.contains("reflect.ValueOf"));
+    assert!(names.contains("runtime.alloc"));
+
+    let names = snip_functions(
+        &functions,
+        &["--snip-tinygo-reflect"],
+        "snip_tinygo_reflect",
+    );
+    assert!(!names.contains("reflect.ValueOf"));
+    assert!(!names.contains("(reflect.Value).Kind"));
+    assert!(!names.contains("(*internal/reflectlite.rawType).Elem"));
+    assert!(names.contains("runtime.printnl"));
+    assert!(names.contains("main.main"));
+}
+
+#[test]
+fn snip_assemblyscript_abort() {
+    let functions = [
+        "~lib/builtins/abort",
+        "~lib/builtins/trace",
+        "~lib/string/String.__concat",
+        "~lib/string/String#get:length",
+        "assembly/index/add",
+    ];
+    let names = snip_functions(
+        &functions,
+        &["--snip-assemblyscript-abort"],
+        "snip_assemblyscript_abort",
+    );
+    assert!(!names.contains("~lib/builtins/abort"));
+    assert!(!names.contains("~lib/builtins/trace"));
+    assert!(!names.contains("~lib/string/String.__concat"));
+    assert!(names.contains("~lib/string/String#get:length"));
+    assert!(names.contains("assembly/index/add"));
+}
+
+#[test]
+fn snip_emscripten_presets() {
+    let functions = [
+        "__syscall_openat",
+        "__syscall_fcntl64",
+        "__cxa_throw",
+        "__cxa_allocate_exception",
+        "sbrk",
+        "emscripten_resize_heap",
+        "malloc",
+        "main",
+    ];
+    let names = snip_functions(
+        &functions,
+        &[
+            "--snip-emscripten-syscalls",
+            "--snip-emscripten-exceptions",
+            "--snip-emscripten-sbrk",
+        ],
+        "snip_emscripten_presets",
+    );
+    let expected: HashSet<String> = vec!["malloc".to_string(), "main".to_string()]
+        .into_iter()
+        .collect();
+    assert_eq!(names, expected);
+}
+
+#[test]
+fn presets() {
+    assert_snip(
+        wasm_snip().arg("--preset").arg("rust-fmt-code"),
+        "no_fmt.wasm",
+    );
+
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg("--list-presets")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let list = String::from_utf8(output.stdout).unwrap();
+    assert!(list.contains("rust-panicking-code"));
+    assert!(list.contains("emscripten-sbrk"));
+
+    wasm_snip()
+        .arg("--preset")
+        .arg("no-such-preset")
+        .arg("-o")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_such_preset.wasm"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn preset_files() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let toml = dir.join("presets.toml");
+    fs::write(
+        &toml,
+        "[hello-snip-me]\n\
+         description = \"Snip `hello::snip_me`.\"\n\
+         patterns = [\".*5hello7snip_me.*\"]\n",
+    )
+    .unwrap();
+    let json = dir.join("presets.json");
+    fs::write(
+        &json,
+        r#"{ "hello-main": { "patterns": [".*5hello4main.*"] } }"#,
+    )
+    .unwrap();
+
+    let names = function_names(&snip_to_module(
+        wasm_snip()
+            .arg("--preset-file")
+            .arg(&toml)
+            .arg("--preset-file")
+            .arg(&json)
+            .arg("--preset")
+            .arg("hello-snip-me,hello-main"),
+        "preset_files",
+    ));
+    assert!(!names.contains("_ZN5hello7snip_me17hf15dbd799e7ad6aaE"));
+    assert!(!names.iter().any(|name| name.contains("5hello4main")));
+
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg("--list-presets")
+        .arg("--preset-file")
+        .arg(&toml)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let list = String::from_utf8(output.stdout).unwrap();
+    assert!(list.lines().any(
+        |line| line.starts_with("hello-snip-me ") && line.ends_with(" Snip `hello::snip_me`.")
+    ));
+}
+
+#[test]
+fn replace_with_default() {
+    let module = snip_to_module(
+        wasm_snip()
+            .arg("--replace-with")
+            .arg("default")
+            .arg("_ZN5hello7snip_me17hf15dbd799e7ad6aaE"),
+        "replace_with_default",
+    );
+    let snip_me = module
+        .funcs
+        .iter()
+        .find(|f| f.name.as_deref() == Some("_ZN5hello7snip_me17hf15dbd799e7ad6aaE"))
+        .expect("the snipped function should be kept");
+    let local = match &snip_me.kind {
+        walrus::FunctionKind::Local(local) => local,
+        _ => panic!("the snipped function should be a local function"),
+    };
+    let results = module.types.get(local.ty()).results().len();
+    let body = &local.block(local.entry_block()).instrs;
+    assert_eq!(body.len(), results);
+    assert!(body.iter().all(|instr| instr.is_const()));
+}
+
+#[test]
+fn replace_with_call_import() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let input =
+        walrus::Module::from_file(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm"))
+            .unwrap();
+    let index = input
+        .funcs
+        .iter()
+        .find(|f| f.name.as_deref() == Some(SNIP_ME))
+        .unwrap()
+        .id()
+        .index();
+
+    let module = snip_to_module(
+        wasm_snip()
+            .arg("--replace-with")
+            .arg("call-import=env::snipped_hit")
+            .arg(SNIP_ME),
+        "replace_with_call_import",
+    );
+    let handler = module
+        .imports
+        .iter()
+        .find(|i| i.module == "env" && i.name == "snipped_hit")
+        .expect("the handler should be imported");
+    let handler = match handler.kind {
+        walrus::ImportKind::Function(f) => f,
+        _ => panic!("the handler should be a function"),
+    };
+    let snip_me = module
+        .funcs
+        .iter()
+        .find(|f| f.name.as_deref() == Some(SNIP_ME))
+        .expect("the snipped function should be kept");
+    let local = match &snip_me.kind {
+        walrus::FunctionKind::Local(local) => local,
+        _ => panic!("the snipped function should be a local function"),
+    };
+    match &local.block(local.entry_block()).instrs[..] {
+        [walrus::ir::Instr::Const(walrus::ir::Const {
+            value: walrus::ir::Value::I32(i),
+        }), walrus::ir::Instr::Call(walrus::ir::Call { func }), walrus::ir::Instr::Unreachable(_)] =>
+        {
+            assert_eq!(*i as usize, index);
+            assert_eq!(*func, handler);
+        }
+        body => panic!("unexpected body: {:?}", body),
+    }
+}
+
+#[test]
+fn replace_body() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let stub = Path::new(env!("CARGO_TARGET_TMPDIR")).join("stub.wat");
+    fs::write(
+        &stub,
+        r#"
+            (import "env" "snipped_log" (func $log (param i32)))
+            (func
+              i32.const 7
+              call $log)
+        "#,
+    )
+    .unwrap();
+
+    let module = snip_to_module(
+        wasm_snip().arg("--replace-body").arg(&stub).arg(SNIP_ME),
+        "replace_body",
+    );
+    let log = module
+        .imports
+        .iter()
+        .find_map(|i| match i.kind {
+            walrus::ImportKind::Function(f) if i.module == "env" && i.name == "snipped_log" => {
+                Some(f)
+            }
+            _ => None,
+        })
+        .expect("the template's import should be imported");
+    let snip_me = module
+        .funcs
+        .iter()
+        .find(|f| f.name.as_deref() == Some(SNIP_ME))
+        .expect("the snipped function should be kept");
+    let local = match &snip_me.kind {
+        walrus::FunctionKind::Local(local) => local,
+        _ => panic!("the snipped function should be a local function"),
+    };
+    match &local.block(local.entry_block()).instrs[..] {
+        [walrus::ir::Instr::Const(walrus::ir::Const {
+            value: walrus::ir::Value::I32(7),
+        }), walrus::ir::Instr::Call(walrus::ir::Call { func })] => assert_eq!(*func, log),
+        body => panic!("unexpected body: {:?}", body),
+    }
+}
+
+#[test]
+fn strategy_rules() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let config = Path::new(env!("CARGO_TARGET_TMPDIR")).join("strategy_rules.toml");
+    fs::write(
+        &config,
+        format!(
+            "functions = [\"{}\", \"imported\"]\n\
+             strategy = \"unreachable\"\n\
+             \n\
+             [[strategies]]\n\
+             patterns = [\"5hello7snip_me\"]\n\
+             strategy = \"default\"\n",
+            SNIP_ME
+        ),
+    )
+    .unwrap();
+
+    let module = snip_to_module(wasm_snip().arg("--config").arg(&config), "strategy_rules");
+    assert!(function_names(&module).contains(SNIP_ME));
+    assert!(!module.imports.iter().any(|i| i.name == "imported"));
+}
+
+#[test]
+fn replace_with_custom_body() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let module = snip_to_module(
+        wasm_snip()
+            .arg("--replace-with")
+            .arg("custom-body=(func i32.const 7 drop)")
+            .arg(SNIP_ME),
+        "replace_with_custom_body",
+    );
+    let snip_me = module
+        .funcs
+        .iter()
+        .find(|f| f.name.as_deref() == Some(SNIP_ME))
+        .expect("the snipped function should be kept");
+    let local = match &snip_me.kind {
+        walrus::FunctionKind::Local(local) => local,
+        _ => panic!("the snipped function should be a local function"),
+    };
+    match &local.block(local.entry_block()).instrs[..] {
+        [walrus::ir::Instr::Const(walrus::ir::Const {
+            value: walrus::ir::Value::I32(7),
+        }), walrus::ir::Instr::Drop(_)] => {}
+        body => panic!("unexpected body: {:?}", body),
+    }
+}
+
+#[test]
+fn stub_exports() {
+    let input = module_with_functions("stub_exports.input", &["snip_me", "keep_me"]);
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(
+        cmd.arg(&input)
+            .arg("--force")
+            .arg("--stub-exports")
+            .arg("snip_me"),
+        "stub_exports",
+    );
+    assert!(!function_names(&module).contains("snip_me"));
+    let exports: HashSet<_> = module.exports.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(exports, ["snip_me", "keep_me"].iter().cloned().collect());
+}
+
+#[test]
+fn keep_imports() {
+    let module = snip_to_module(
+        wasm_snip().arg("--keep-imports").arg("imported2"),
+        "keep_imports",
+    );
+    assert!(!module.imports.iter().any(|i| i.name == "imported2"));
+    let imported2 = module
+        .funcs
+        .iter()
+        .find(|f| f.name.as_deref() == Some("imported2"))
+        .expect("the snipped import should be kept");
+    match &imported2.kind {
+        walrus::FunctionKind::Local(local) => match &local.block(local.entry_block()).instrs[..] {
+            [walrus::ir::Instr::Unreachable(_)] => {}
+            body => panic!("unexpected body: {:?}", body),
+        },
+        _ => panic!("the snipped import should be a local function"),
+    }
+}
+
+#[test]
+fn preserve_indices() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let names = |module: &walrus::Module| -> Vec<_> {
+        module.funcs.iter().map(|f| f.name.clone()).collect()
+    };
+    let input = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm")).unwrap();
+    let input = walrus::Module::from_buffer(&input).unwrap();
+
+    let module = snip_to_module(
+        wasm_snip().arg("--preserve-indices").arg(SNIP_ME),
+        "preserve_indices",
+    );
+    assert_eq!(names(&module), names(&input));
+    let snip_me = module
+        .funcs
+        .iter()
+        .find(|f| f.name.as_deref() == Some(SNIP_ME))
+        .unwrap();
+    match &snip_me.kind {
+        walrus::FunctionKind::Local(local) => match &local.block(local.entry_block()).instrs[..] {
+            [walrus::ir::Instr::Unreachable(_)] => {}
+            body => panic!("unexpected body: {:?}", body),
+        },
+        _ => panic!("the snipped function should be a local function"),
+    }
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn emit_remap() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let remap = Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit_remap.json");
+    let module = snip_to_module(
+        wasm_snip().arg("--emit-remap").arg(&remap).arg(SNIP_ME),
+        "emit_remap",
+    );
+    let remap: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&remap).unwrap()).unwrap();
+    let names: Vec<_> = module.funcs.iter().map(|f| f.name.clone()).collect();
+
+    let functions = remap["functions"].as_array().unwrap();
+    for (i, function) in functions.iter().enumerate() {
+        assert_eq!(function["old-index"], i);
+        let name = function["name"].as_str().unwrap();
+        if name == SNIP_ME {
+            assert_eq!(function["new-index"], "removed");
+        } else if let Some(index) = function["new-index"].as_u64() {
+            assert_eq!(names[index as usize].as_deref(), Some(name));
+        }
+    }
+}
+
+#[test]
+fn no_gc() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let module = snip_to_module(wasm_snip().arg("--no-gc").arg(SNIP_ME), "no_gc");
+    let names = function_names(&module);
+    assert!(!names.contains(SNIP_ME));
+    let gced = snip_to_module(wasm_snip().arg(SNIP_ME), "no_gc_gced");
+    assert!(function_names(&gced).is_subset(&names));
+    assert!(function_names(&gced).len() < names.len());
+}
+
+#[test]
+fn snip_import() {
+    // A module without a name section, whose exported `run` calls two
+    // imports.
+    let mut module = walrus::Module::default();
+    let ty = module.types.add(&[], &[]);
+    let (fd_write, _) = module.add_import_func("wasi_snapshot_preview1", "fd_write", ty);
+    let (proc_exit, _) = module.add_import_func("wasi_snapshot_preview1", "proc_exit", ty);
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().call(fd_write).call(proc_exit);
+    let run = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("run", run);
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("snip_import.input.wasm");
+    module.emit_wasm_file(&input).unwrap();
+
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(
+        cmd.arg(&input)
+            .arg("--snip-import")
+            .arg("wasi_snapshot_preview1::fd_write"),
+        "snip_import",
+    );
+    let imports: Vec<_> = module.imports.iter().map(|i| i.name.as_str()).collect();
+    assert_eq!(imports, ["proc_exit"]);
+
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(
+        cmd.arg(&input)
+            .arg("--snip-import-pattern")
+            .arg("^wasi_snapshot_preview1::"),
+        "snip_import_pattern",
+    );
+    assert_eq!(module.imports.iter().count(), 0);
+}
+
+#[test]
+fn snip_start() {
+    let input = module_with_start("snip_start.input");
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(cmd.arg(&input).arg("--snip-start"), "snip_start");
+    assert_eq!(module.start, None);
+    assert!(!function_names(&module).contains("start"));
+}
+
+#[test]
+fn warn_snipped_start() {
+    let input = module_with_start("warn_snipped_start.input");
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("-o")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("warn_snipped_start.wasm"))
+        .arg("--pattern")
+        .arg("^st")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("the start function `start` is snipped"));
+    assert!(stderr.contains("matches the pattern `^st`"));
+
+    Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("-o")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("warn_snipped_start.wasm"))
+        .arg("--strict")
+        .arg("start")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn refuse_exported_functions() {
+    let input = module_with_functions("refuse_exported_functions.input", &["a", "b", "c"]);
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("-o")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("refuse_exported_functions.wasm"))
+        .arg("a")
+        .arg("b")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("refusing to snip exported functions"));
+    assert!(stderr.contains("`a`") && stderr.contains("`b`"));
+    assert!(!stderr.contains("`c`"));
+}
+
+#[test]
+fn warn_reachable() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let output = wasm_snip()
+        .arg("-o")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("warn_reachable.wasm"))
+        .arg("--warn-reachable")
+        .arg(SNIP_ME)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains(&format!(
+        "snipped function `{0}` is still reachable: `quicksilver` -> `{0}`",
+        SNIP_ME
+    )));
+
+    wasm_snip()
+        .arg("-o")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("warn_reachable.wasm"))
+        .arg("--warn-reachable")
+        .arg("--strict")
+        .arg(SNIP_ME)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn simulate() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let output = wasm_snip().arg("--simulate").arg(SNIP_ME).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("snipping would remove 5 functions, "));
+    let removed: HashSet<_> = lines
+        .map(|line| line.split_whitespace().nth(1).unwrap())
+        .collect();
+    assert!(removed.contains(SNIP_ME));
+    assert!(removed.contains("_ZN3std2io5stdio6stdout17h746bf3658d1e5908E"));
+
+    // Snipping the function really does remove those functions.
+    let before = function_names(&snip_to_module(&mut wasm_snip(), "simulate_before"));
+    let after = function_names(&snip_to_module(wasm_snip().arg(SNIP_ME), "simulate_after"));
+    let actually_removed: HashSet<_> = before.difference(&after).map(|s| s.as_str()).collect();
+    assert_eq!(removed, actually_removed);
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn list() {
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg("list")
+        .arg(&hello)
+        .arg("--json")
+        .arg("--demangle")
+        .arg("--filter")
+        .arg("^hello::")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let functions: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let functions = functions.as_array().unwrap();
+    let names: Vec<_> = functions
+        .iter()
+        .map(|f| f["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "hello::snip_me::hf15dbd799e7ad6aa",
+            "hello::main::h2b8b138267cb20a6"
+        ]
+    );
+    assert!(functions[0]["size"].as_u64().unwrap() >= functions[1]["size"].as_u64().unwrap());
+
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg("list")
+        .arg(&hello)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().any(|line| line.ends_with("  quicksilver")));
+}
+
+#[test]
+fn why() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    const STDOUT: &str = "_ZN3std2io5stdio6stdout17h746bf3658d1e5908E";
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let why = |args: &[&str]| {
+        let output = Command::cargo_bin("wasm-snip")
+            .unwrap()
+            .arg("why")
+            .arg(&hello)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let lines: Vec<_> = why(&[STDOUT])
+        .lines()
+        .map(|l| l.trim().to_string())
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            format!("`{}` is kept alive by the export `quicksilver`:", STDOUT),
+            "quicksilver".to_string(),
+            SNIP_ME.to_string(),
+            STDOUT.to_string(),
+        ]
+    );
+    assert!(why(&[STDOUT, SNIP_ME]).contains("is not kept alive by anything"));
+}
+
+#[test]
+fn emit_removed_graph() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let dot = Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit_removed_graph.dot");
+    snip_to_module(
+        wasm_snip()
+            .arg("--emit-removed-graph")
+            .arg(&dot)
+            .arg(SNIP_ME),
+        "emit_removed_graph",
+    );
+    let dot = fs::read_to_string(&dot).unwrap();
+    assert!(dot.starts_with("digraph removed {\n"));
+    let nodes: Vec<_> = dot.lines().filter(|l| l.contains("[label=")).collect();
+    assert_eq!(nodes.len(), 5);
+    let snip_me = nodes
+        .iter()
+        .find(|l| l.contains("\"hello::snip_me::hf15dbd799e7ad6aa\""))
+        .unwrap();
+    let snip_me = snip_me.trim().split(' ').next().unwrap();
+    assert!(dot.contains(&format!("{} -> ", snip_me)));
+}
+
+#[test]
+fn report() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("report.wasm");
+    let result = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&hello)
+        .arg("-o")
+        .arg(&output)
+        .arg("--report")
+        .arg(SNIP_ME)
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    let mut lines = stderr.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        format!("snipped `{}`, which removed 4 more functions:", SNIP_ME)
+    );
+    let removed: Vec<_> = lines.by_ref().take(4).map(|line| line.trim()).collect();
+    assert!(removed.contains(&"`_ZN3std2io5stdio6stdout17h746bf3658d1e5908E`"));
+    assert_eq!(
+        lines.next().unwrap(),
+        "replaced 1 call with `unreachable`, pointed 0 table elements at stubs, and removed 0 \
+         exports and 0 imports"
+    );
+    assert_eq!(lines.next(), None);
+}
+
+#[test]
+fn stats() {
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let result = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&hello)
+        .arg("--stats")
+        .arg("--snip-rust-fmt-code")
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    assert!(result.stdout.starts_with(b"\0asm"));
+
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    let rows: HashMap<_, Vec<i64>> = stderr
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let mut columns = line.split_whitespace();
+            let name = columns.next().unwrap().to_string();
+            (name, columns.map(|c| c.parse().unwrap()).collect())
+        })
+        .collect();
+    assert_eq!(rows["total"][0], fs::metadata(&hello).unwrap().len() as i64);
+    assert_eq!(rows["total"][1], result.stdout.len() as i64);
+    assert!(rows["code"][2] < 0);
+    for row in rows.values() {
+        assert_eq!(row[1] - row[0], row[2]);
+    }
+}
+
+#[test]
+fn max_output_size() {
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let snipped = Path::new(env!("CARGO_TARGET_TMPDIR")).join("max_output_size.wasm");
+    let run = |max: &str| {
+        let _ = fs::remove_file(&snipped);
+        Command::cargo_bin("wasm-snip")
+            .unwrap()
+            .arg(&hello)
+            .arg("-o")
+            .arg(&snipped)
+            .arg("--snip-rust-fmt-code")
+            .arg("--max-output-size")
+            .arg(max)
+            .output()
+            .unwrap()
+    };
+
+    assert!(run("1M").status.success());
+    assert!(run("100000").status.success());
+    assert!(snipped.exists());
+
+    // An oversized module isn't written.
+    let output = run("10K");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("over the limit of 10240 bytes"));
+    assert!(!snipped.exists());
+
+    assert!(!run("lots").status.success());
+    let output = run("99999999999999999M");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("invalid size"));
+}
+
+#[test]
+fn baseline() {
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let baseline = Path::new(env!("CARGO_TARGET_TMPDIR")).join("baseline.wasm");
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&hello)
+        .arg("-o")
+        .arg(&baseline)
+        .arg("--snip-rust-fmt-code")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let compare = |args: &[&str]| {
+        Command::cargo_bin("wasm-snip")
+            .unwrap()
+            .arg(&hello)
+            .arg("--baseline")
+            .arg(&baseline)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // Snipping the same functions again matches the baseline.
+    let output = compare(&["--snip-rust-fmt-code", "--strict"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    // Without snipping them, the formatting code shows up as new.
+    let output = compare(&[]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(
+        "warning: `_ZN4core3fmt5Write9write_fmt17h393e9db2f425d06eE` (103 bytes) is new since the \
+         baseline"
+    ));
+
+    let output = compare(&["--strict"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("grew or appeared since the baseline"));
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn list_retained() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg("list")
+        .arg(&hello)
+        .arg("--retained")
+        .arg("--json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let functions: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let functions = functions.as_array().unwrap();
+
+    let retained: Vec<_> = functions
+        .iter()
+        .map(|f| f["retained"].as_u64().unwrap())
+        .collect();
+    assert!(retained.windows(2).all(|w| w[0] >= w[1]));
+    for f in functions {
+        assert!(f["retained"].as_u64().unwrap() >= f["size"].as_u64().unwrap());
+    }
+
+    // The retained size is what snipping the function would remove.
+    let snip_me = functions.iter().find(|f| f["name"] == SNIP_ME).unwrap();
+    let output = wasm_snip().arg("--simulate").arg(SNIP_ME).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(&format!(
+        "snipping would remove 5 functions, {} bytes of code:",
+        snip_me["retained"]
+    )));
+}
+
+#[test]
+fn suggest() {
+    let output = wasm_snip().arg("--suggest").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let suggestions: Vec<_> = stdout
+        .lines()
+        .map(|line| line.trim_start().splitn(3, ' ').last().unwrap().trim())
+        .collect();
+    assert_eq!(suggestions[0], "--preset rust-fmt-code");
+    assert_eq!(suggestions[1], "--preset rust-panicking-code");
+    assert!(suggestions
+        .contains(&"std::panicking::rust_panic_with_hook::h02b37a5538e89bc2  (always traps)"));
+}
+
+#[test]
+fn min_size() {
+    const PAD: &str = "_ZN4core3fmt9Formatter3pad17hb35b2f03e1c6174aE";
+    const PAD_INTEGRAL: &str = "_ZN4core3fmt9Formatter12pad_integral17ha821d9da14fd6ac2E";
+    const WRITE_CHAR: &str = "_ZN4core3fmt5Write10write_char17h123b67d203048ca6E";
+
+    let module = snip_to_module(
+        wasm_snip()
+            .args(["-p", ".*fmt.*", "--min-size", "1000"])
+            .arg(WRITE_CHAR),
+        "min_size",
+    );
+    let names = function_names(&module);
+
+    // `pad` is 1279 bytes, but `pad_integral` is only 998.
+    assert!(!names.contains(PAD));
+    assert!(names.contains(PAD_INTEGRAL));
+
+    // Functions named exactly are snipped regardless of their size.
+    assert!(!names.contains(WRITE_CHAR));
+}
+
+#[test]
+fn signature() {
+    use walrus::ValType::{I32, I64};
+
+    let mut module = walrus::Module::default();
+    for (name, params, results) in [
+        ("wide_a", &[I32, I32][..], &[I64][..]),
+        ("wide_b", &[I32, I32], &[I64]),
+        ("narrow_b", &[], &[]),
+    ] {
+        let mut builder = walrus::FunctionBuilder::new(&mut module.types, params, results);
+        builder.func_body().unreachable();
+        let args = params.iter().map(|&ty| module.locals.add(ty)).collect();
+        let id = builder.finish(args, &mut module.funcs);
+        module.funcs.get_mut(id).name = Some(name.to_string());
+        module.exports.add(name, id);
+    }
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("signature.input.wasm");
+    module.emit_wasm_file(&input).unwrap();
+
+    let snip = |args: &[&str], name: &str| {
+        let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+        let mut names: Vec<_> = function_names(&snip_to_module(
+            cmd.arg(&input).arg("--force").args(args),
+            name,
+        ))
+        .into_iter()
+        .collect();
+        names.sort();
+        names
+    };
+
+    let signature = "(i32, i32) -> i64";
+    assert_eq!(
+        snip(&["--signature", signature], "signature_only"),
+        ["narrow_b"]
+    );
+    assert_eq!(
+        snip(
+            &["--signature", signature, "-p", "_b$"],
+            "signature_and_pattern"
+        ),
+        ["narrow_b", "wide_a"]
+    );
+    assert_eq!(
+        snip(&["--signature", "()", "-p", "_b$"], "empty_signature"),
+        ["wide_a", "wide_b"]
+    );
+}
+
+#[test]
+fn index() {
+    let left = snip_functions(
+        &["a", "b", "c", "d", "e"],
+        &["--index", "0", "--index-range", "2..4"],
+        "index",
+    );
+    assert_eq!(left, ["b", "e"].iter().map(|s| s.to_string()).collect());
+
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("--index-range")
+        .arg("2-4")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn fallback_names() {
+    // A module without a name section, whose exported `run` calls the
+    // exported `helper` and an import.
+    let mut module = walrus::Module::default();
+    let ty = module.types.add(&[], &[]);
+    let (log, _) = module.add_import_func("env", "log", ty);
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().call(log);
+    let helper = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("helper", helper);
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().call(helper);
+    let run = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("run", run);
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fallback_names.input.wasm");
+    module.emit_wasm_file(&input).unwrap();
+
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(
+        cmd.arg(&input).arg("--force").arg("helper"),
+        "fallback_names_export",
+    );
+    let exports: Vec<_> = module.exports.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(exports, ["run"]);
+    assert_eq!(module.imports.iter().count(), 0);
+
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(
+        cmd.arg(&input).arg("-p").arg("^env::"),
+        "fallback_names_import",
+    );
+    assert_eq!(module.imports.iter().count(), 0);
+    assert_eq!(module.exports.iter().count(), 2);
+}
+
+#[test]
+fn keep_list_matches_fallback_names() {
+    // A module without a name section, whose exported `helper` calls an
+    // import. The keep list protects them by the names they are selected by,
+    // however they are selected.
+    let mut module = walrus::Module::default();
+    let ty = module.types.add(&[], &[]);
+    let (log, _) = module.add_import_func("env", "log", ty);
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().call(log);
+    let helper = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("helper", helper);
+    let wasm = module.emit_wasm();
+
+    let snip = |options: wasm_snip::Options| {
+        let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+        let options = wasm_snip::Options {
+            force: true,
+            ..options
+        };
+        wasm_snip::snip(&mut module, options)
+            .unwrap()
+            .functions
+            .len()
+    };
+    let by_index = wasm_snip::Options {
+        indices: vec![1],
+        ..Default::default()
+    };
+    assert_eq!(snip(by_index.clone()), 1);
+    assert_eq!(
+        snip(wasm_snip::Options {
+            keep_functions: vec!["helper".to_string()],
+            ..by_index
+        }),
+        0
+    );
+    let by_import = wasm_snip::Options {
+        snip_imports: vec!["env::log".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(snip(by_import.clone()), 1);
+    assert_eq!(
+        snip(wasm_snip::Options {
+            keep_patterns: vec!["^env::".to_string()],
+            ..by_import
+        }),
+        0
+    );
+}
+
+#[test]
+fn symbols() {
+    // A module without a name section, whose exported `run` calls two
+    // functions. `run` is the biggest function, so it is emitted first.
+    let mut module = walrus::Module::default();
+    let mut helpers = vec![];
+    for _ in 0..2 {
+        let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.func_body().unreachable();
+        helpers.push(builder.finish(vec![], &mut module.funcs));
+    }
+    let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+    builder.func_body().call(helpers[0]).call(helpers[1]);
+    let run = builder.finish(vec![], &mut module.funcs);
+    module.exports.add("run", run);
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = dir.join("symbols.input.wasm");
+    module.emit_wasm_file(&input).unwrap();
+
+    let map = dir.join("symbols.map");
+    fs::write(&map, "1:helper_one\n2 helper_two\n").unwrap();
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(
+        cmd.arg(&input).arg("--symbols").arg(&map).arg("helper_one"),
+        "symbols_map",
+    );
+    assert_eq!(module.funcs.iter().count(), 2);
+
+    let json = dir.join("symbols.json");
+    fs::write(&json, r#"{ "1": "helper_one", "2": "helper_two" }"#).unwrap();
+    let mut cmd = Command::cargo_bin("wasm-snip").unwrap();
+    let module = snip_to_module(
+        cmd.arg(&input)
+            .arg("--symbols")
+            .arg(&json)
+            .arg("-p")
+            .arg("^helper_"),
+        "symbols_json",
+    );
+    assert_eq!(module.funcs.iter().count(), 1);
+}
+
+#[test]
+fn strip_names() {
+    let module = snip_to_module(wasm_snip().arg("--strip-names"), "strip_names");
+    assert!(function_names(&module).is_empty());
+
+    let module = snip_to_module(
+        wasm_snip().arg("--strip-names").arg("--keep-export-names"),
+        "keep_export_names",
+    );
+    let exported: HashSet<_> = module
+        .exports
+        .iter()
+        .filter(|e| matches!(e.item, walrus::ExportItem::Function(_)))
+        .map(|e| e.name.clone())
+        .collect();
+    assert!(!exported.is_empty());
+    assert_eq!(function_names(&module), exported);
+
+    wasm_snip()
+        .arg("--keep-export-names")
+        .arg("-o")
+        .arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("keep_export_names_alone.wasm"))
+        .assert()
+        .failure();
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn keep_export_names_with_strip_names_from_config() {
+    let config = Path::new(env!("CARGO_TARGET_TMPDIR")).join("strip_names.toml");
+    fs::write(&config, "strip-names = true\n").unwrap();
+    let module = snip_to_module(
+        wasm_snip()
+            .arg("--config")
+            .arg(&config)
+            .arg("--keep-export-names"),
+        "keep_export_names_from_config",
+    );
+    let exported: HashSet<_> = module
+        .exports
+        .iter()
+        .filter(|e| matches!(e.item, walrus::ExportItem::Function(_)))
+        .map(|e| e.name.clone())
+        .collect();
+    assert_eq!(function_names(&module), exported);
+}
+
+#[test]
+fn name_stubs() {
+    let stubs = |args: &[&str], name: &str| {
+        let module = snip_to_module(wasm_snip().arg("--snip-rust-fmt-code").args(args), name);
+        let mut stubs: Vec<_> = function_names(&module)
+            .into_iter()
+            .filter(|name| name.starts_with("wasm_snip::"))
+            .collect();
+        stubs.sort();
+        stubs
+    };
+    assert!(stubs(&[], "unnamed_stubs").is_empty());
+    assert_eq!(
+        stubs(&["--name-stubs"], "name_stubs"),
+        [
+            "wasm_snip::unreachable_stub::<(i32, i32) -> i32>",
+            "wasm_snip::unreachable_stub::<(i32, i32, i32) -> i32>",
+        ]
+    );
+}
+
+#[test]
+fn local_names() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func $keep (export "keep") (param $x i32) (result i32)
+            (block $done (result i32)
+              local.get $x))
+          (func $snip_me (export "snip") (param $y i32)
+            (loop $forever
+              br $forever)))
+        "#,
+    )
+    .unwrap();
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("local_names.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    // The names of each function's locals and labels, by function name.
+    let names = |args: &[&str], name: &str| {
+        let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.wasm", name));
+        Command::cargo_bin("wasm-snip")
+            .unwrap()
+            .arg(&input)
+            .arg("--force")
+            .arg("-o")
+            .arg(&output)
+            .args(args)
+            .arg("snip_me")
+            .assert()
+            .success();
+        let wasm = fs::read(&output).unwrap();
+
+        let mut functions = HashMap::new();
+        let mut names = vec![];
+        for payload in wasmparser::Parser::new(0).parse_all(&wasm) {
+            let section = match payload.unwrap() {
+                wasmparser::Payload::CustomSection(section) => section,
+                _ => continue,
+            };
+            let subsections = match section.as_known() {
+                wasmparser::KnownCustom::Name(subsections) => subsections,
+                _ => continue,
+            };
+            for subsection in subsections {
+                let (kind, map) = match subsection.unwrap() {
+                    wasmparser::Name::Function(map) => {
+                        for naming in map {
+                            let naming = naming.unwrap();
+                            functions.insert(naming.index, naming.name.to_string());
+                        }
+                        continue;
+                    }
+                    wasmparser::Name::Local(map) => ("local", map),
+                    wasmparser::Name::Label(map) => ("label", map),
+                    _ => continue,
+                };
+                for naming in map {
+                    let naming = naming.unwrap();
+                    for name in naming.names {
+                        names.push((naming.index, kind, name.unwrap().name.to_string()));
+                    }
+                }
+            }
+        }
+        let mut names: Vec<_> = names
+            .into_iter()
+            .map(|(f, kind, name)| (functions[&f].clone(), kind, name))
+            .collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(
+        names(&[], "local_names"),
+        [
+            ("keep".to_string(), "label", "done".to_string()),
+            ("keep".to_string(), "local", "x".to_string()),
+        ]
+    );
+    assert!(names(&["--strip-local-names"], "strip_local_names").is_empty());
+}
+
+#[test]
+fn strip_dwarf() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func (export "run"))
+          (@custom ".debug_info" "info")
+          (@custom ".debug_line" "line"))
+        "#,
+    )
+    .unwrap();
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("strip_dwarf.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    let dwarf = |args: &[&str], name: &str| {
+        let module = snip_to_module(
+            Command::cargo_bin("wasm-snip")
+                .unwrap()
+                .arg(&input)
+                .args(args),
+            name,
+        );
+        let mut sections: Vec<_> = module
+            .customs
+            .iter()
+            .map(|(_, section)| section.name().to_string())
+            .filter(|name| name.starts_with(".debug_"))
+            .collect();
+        sections.sort();
+        sections
+    };
+    assert_eq!(dwarf(&[], "keep_dwarf"), [".debug_info", ".debug_line"]);
+    assert!(dwarf(&["--strip-dwarf"], "strip_dwarf").is_empty());
+}
+
+/// Where each local function of `wasm` has its body, relative to the start of
+/// the code section, along with where the code section starts.
+fn function_bodies(wasm: &[u8]) -> (u64, Vec<std::ops::Range<u64>>) {
+    let mut bodies = vec![];
+    let mut start = 0;
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload.unwrap() {
+            wasmparser::Payload::CodeSectionStart { range, .. } => start = range.start,
+            wasmparser::Payload::CodeSectionEntry(body) => {
+                bodies.push(body.range().start - start..body.range().end - start)
+            }
+            _ => {}
+        }
+    }
+    (start, bodies)
+}
+
+#[test]
+fn update_dwarf() {
+    let mut wasm = wat::parse_str(
+        r#"
+        (module
+          (func $snip_me (export "snip_me"))
+          (func $keep (export "keep") (param i32) (result i32)
+            local.get 0
+            i32.const 1
+            i32.add
+            i32.const 2
+            i32.mul))
+        "#,
+    )
+    .unwrap();
+    let (_, bodies) = function_bodies(&wasm);
+
+    // A compilation unit with a subprogram for each function, and a line
+    // number program with a sequence for each function.
+    let abbrev = [
+        1, 0x11, 1, 0, 0, // DW_TAG_compile_unit with children
+        2, 0x2e, 0, // DW_TAG_subprogram
+        0x03, 0x08, // DW_AT_name, DW_FORM_string
+        0x11, 0x01, // DW_AT_low_pc, DW_FORM_addr
+        0x12, 0x01, // DW_AT_high_pc, DW_FORM_addr
+        0, 0, 0,
+    ];
+    let mut entries = vec![1];
+    let mut lines = vec![];
+    for (name, body) in [("snip_me", &bodies[0]), ("keep", &bodies[1])] {
+        entries.push(2);
+        entries.extend(name.as_bytes());
+        entries.push(0);
+        entries.extend((body.start as u32).to_le_bytes());
+        entries.extend((body.end as u32).to_le_bytes());
+
+        lines.extend([0, 5, 0x02]); // DW_LNE_set_address
+        lines.extend((body.start as u32).to_le_bytes());
+        lines.extend([0x01, 0, 1, 0x01]); // DW_LNS_copy, DW_LNE_end_sequence
+    }
+    entries.push(0);
+    let mut info = vec![];
+    info.extend((7 + entries.len() as u32).to_le_bytes());
+    info.extend([4, 0, 0, 0, 0, 0, 4]); // DWARF 4, abbrevs at 0, 32-bit addresses
+    info.extend(entries);
+
+    let mut header = vec![1, 1, 1, 0xfb, 14, 13]; // `.debug_line` defaults
+    header.extend([0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]); // standard opcode lengths
+    header.extend(b"\0a.rs\0\0\0\0\0"); // no directories, one file
+    let mut line = vec![];
+    line.extend((6 + header.len() as u32 + lines.len() as u32).to_le_bytes());
+    line.extend([4, 0]);
+    line.extend((header.len() as u32).to_le_bytes());
+    line.extend(header);
+    line.extend(lines);
+
+    for (name, data) in [
+        (".debug_abbrev", &abbrev[..]),
+        (".debug_info", &info),
+        (".debug_line", &line),
+    ] {
+        let size = 1 + name.len() + data.len();
+        assert!(size < 0x80);
+        wasm.extend([0, size as u8, name.len() as u8]);
+        wasm.extend(name.as_bytes());
+        wasm.extend(data);
+    }
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("update_dwarf.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("update_dwarf.wasm");
+    Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("--update-dwarf")
+        .arg("--force")
+        .arg("-o")
+        .arg(&output)
+        .arg("snip_me")
+        .assert()
+        .success();
+    let wasm = fs::read(&output).unwrap();
+    let module = walrus::Module::from_buffer(&wasm).unwrap();
+    let keep = module.exports.iter().find(|e| e.name == "keep").unwrap();
+    let keep = match keep.item {
+        walrus::ExportItem::Function(f) => f.index(),
+        _ => unreachable!(),
+    };
+    let keep = &function_bodies(&wasm).1[keep];
+    assert_ne!(keep, &bodies[1], "`keep` should have moved");
+
+    let section = |name| {
+        let section = module
+            .customs
+            .iter()
+            .find(|(_, s)| s.name() == name)
+            .unwrap();
+        section.1.data(&Default::default()).into_owned()
+    };
+    let (abbrev, info, line) = (
+        section(".debug_abbrev"),
+        section(".debug_info"),
+        section(".debug_line"),
+    );
+    let dwarf = gimli::Dwarf {
+        debug_abbrev: gimli::DebugAbbrev::new(&abbrev, gimli::LittleEndian),
+        debug_info: gimli::DebugInfo::new(&info, gimli::LittleEndian),
+        debug_line: gimli::DebugLine::new(&line, gimli::LittleEndian),
+        ..Default::default()
+    };
+
+    let unit = dwarf.units().next().unwrap().unwrap();
+    let abbreviations = unit.abbreviations(&dwarf.debug_abbrev).unwrap();
+    let mut entries = unit.entries(&abbreviations);
+    let mut ranges = HashMap::new();
+    while let Some((_, entry)) = entries.next_dfs().unwrap() {
+        if entry.tag() != gimli::DW_TAG_subprogram {
+            continue;
+        }
+        let attr = |name| match entry.attr_value(name).unwrap() {
+            Some(gimli::AttributeValue::Addr(address)) => address,
+            _ => panic!("missing address"),
+        };
+        let name = match entry.attr_value(gimli::DW_AT_name).unwrap() {
+            Some(gimli::AttributeValue::String(name)) => name.to_string().unwrap().to_string(),
+            _ => panic!("missing name"),
+        };
+        ranges.insert(name, attr(gimli::DW_AT_low_pc)..attr(gimli::DW_AT_high_pc));
+    }
+    assert_eq!(ranges["keep"], keep.clone());
+    assert_eq!(ranges["snip_me"], 0xffff_ffff..0xffff_ffff);
+
+    let program = dwarf
+        .debug_line
+        .program(gimli::DebugLineOffset(0), 4, None, None)
+        .unwrap();
+    let mut rows = program.rows();
+    let mut addresses = vec![];
+    while let Some((_, row)) = rows.next_row().unwrap() {
+        if !row.end_sequence() {
+            addresses.push(row.address());
+        }
+    }
+    // gimli skips the rows of tombstoned sequences.
+    assert_eq!(addresses, [keep.start]);
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn update_source_map() {
+    fn vlq(value: u64, out: &mut String) {
+        const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut value = value << 1;
+        loop {
+            let digit = value & 0x1f;
+            value >>= 5;
+            out.push(BASE64[(digit | if value == 0 { 0 } else { 0x20 }) as usize] as char);
+            if value == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut wasm = wat::parse_str(
+        r#"
+        (module
+          (func $snip_me (export "snip_me"))
+          (func $keep (export "keep") (param i32) (result i32)
+            local.get 0
+            i32.const 1
+            i32.add))
+        "#,
+    )
+    .unwrap();
+    let (start, bodies) = function_bodies(&wasm);
+    let url = b"update_source_map.input.wasm.map";
+    wasm.extend([0, 18 + url.len() as u8, 16]);
+    wasm.extend(b"sourceMappingURL");
+    wasm.push(url.len() as u8);
+    wasm.extend(url);
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = dir.join("update_source_map.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    // A mapping at the start of each function, from a source of its own.
+    let mut mappings = String::new();
+    vlq(start + bodies[0].start, &mut mappings);
+    mappings.push_str("AAA,");
+    vlq(bodies[1].start - bodies[0].start, &mut mappings);
+    mappings.push_str("CAA");
+    let map = format!(
+        r#"{{"version":3,"sources":["snip_me.rs","keep.rs"],"names":[],"mappings":"{}"}}"#,
+        mappings
+    );
+    fs::write(
+        dir.join(url.iter().map(|&b| b as char).collect::<String>()),
+        map,
+    )
+    .unwrap();
+
+    let output = dir.join("update_source_map.wasm");
+    Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("--update-source-map")
+        .arg("--force")
+        .arg("-o")
+        .arg(&output)
+        .arg("snip_me")
+        .assert()
+        .success();
+
+    let wasm = fs::read(&output).unwrap();
+    let module = walrus::Module::from_buffer(&wasm).unwrap();
+    let url = module
+        .customs
+        .iter()
+        .find(|(_, s)| s.name() == "sourceMappingURL")
+        .unwrap()
+        .1
+        .data(&Default::default());
+    assert_eq!(&url[1..], b"update_source_map.wasm.map");
+
+    let keep = module.exports.iter().find(|e| e.name == "keep").unwrap();
+    let keep = match keep.item {
+        walrus::ExportItem::Function(f) => f.index(),
+        _ => unreachable!(),
+    };
+    let (start, bodies) = function_bodies(&wasm);
+    let mut expected = String::new();
+    vlq(start + bodies[keep].start, &mut expected);
+    expected.push_str("CAA");
+
+    let map = fs::read_to_string(dir.join("update_source_map.wasm.map")).unwrap();
+    let map: serde_json::Value = serde_json::from_str(&map).unwrap();
+    assert_eq!(map["sources"][1], "keep.rs");
+    assert_eq!(map["mappings"], expected.as_str());
+}
+
+#[test]
+fn target_features() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (func (export "run"))
+          (@custom "target_features" "\01\2b\0fmutable-globals"))
+        "#,
+    )
+    .unwrap();
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("target_features.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    let features = |args: &[&str], name: &str| {
+        let module = snip_to_module(
+            Command::cargo_bin("wasm-snip")
+                .unwrap()
+                .arg(&input)
+                .args(args),
+            name,
+        );
+        let data = module
+            .customs
+            .iter()
+            .find(|(_, section)| section.name() == "target_features")
+            .map(|(_, section)| section.data(&Default::default()).into_owned());
+        data
+    };
+    assert_eq!(
+        features(&[], "preserve_target_features").unwrap(),
+        b"\x01+\x0fmutable-globals"
+    );
+    assert_eq!(
+        features(&["--target-features", "strip"], "strip_target_features"),
+        None
+    );
+    assert_eq!(
+        features(
+            &["--target-features", "set=+sign-ext,-atomics"],
+            "set_target_features"
+        )
+        .unwrap(),
+        b"\x02+\x08sign-ext-\x07atomics"
+    );
+}
+
+#[test]
+fn custom_sections() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (@custom "build_id" "\de\ad\be\ef")
+          (func (export "run"))
+          (@custom "embedder.meta" (after code) "\00\01\02\ff"))
+        "#,
+    )
+    .unwrap();
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("custom_sections.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    let sections = |args: &[&str], name: &str| {
+        let module = snip_to_module(
+            Command::cargo_bin("wasm-snip")
+                .unwrap()
+                .arg(&input)
+                .args(args),
+            name,
+        );
+        let sections: HashMap<_, _> = module
+            .customs
+            .iter()
+            .map(|(_, section)| {
+                let data = section.data(&Default::default()).into_owned();
+                (section.name().to_string(), data)
+            })
+            .collect();
+        sections
+    };
+
+    let kept = sections(&[], "keep_custom_sections");
+    assert_eq!(kept["build_id"], b"\xde\xad\xbe\xef");
+    assert_eq!(kept["embedder.meta"], b"\x00\x01\x02\xff");
+
+    let stripped = sections(
+        &["--strip-custom-section", "^build_id$"],
+        "strip_custom_sections",
+    );
+    assert!(!stripped.contains_key("build_id"));
+    assert_eq!(stripped["embedder.meta"], b"\x00\x01\x02\xff");
+}
+
+#[test]
+fn proposals_snipped_in_place() {
+    let snip = |name: &str, wat: &str| {
+        let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.wasm", name));
+        fs::write(&input, wat::parse_str(wat).unwrap()).unwrap();
+        let output = Command::cargo_bin("wasm-snip")
+            .unwrap()
+            .arg(&input)
+            .args(["--force", "--pattern", ".*"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", name);
+        validate_all_features(&output.stdout);
+        wasm_snip::unsupported_proposal(&fs::read(&input).unwrap()).unwrap()
+    };
+
+    assert_eq!(
+        snip(
+            "reference_types",
+            r#"(module
+             (table 1 funcref)
+             (func $f)
+             (elem (i32.const 0) funcref (ref.func $f)))"#,
+        ),
+        "reference types"
+    );
+    assert_eq!(
+        snip(
+            "passive_elements",
+            r#"(module
+             (table 1 funcref)
+             (func $f)
+             (elem func $f)
+             (func (export "drop") (elem.drop 0)))"#,
+        ),
+        "bulk memory"
+    );
+    assert_eq!(
+        snip(
+            "declared_elements",
+            r#"(module
+             (func $f)
+             (elem declare func $f))"#,
+        ),
+        "reference types"
+    );
+    assert_eq!(
+        snip(
+            "ref_func_global",
+            r#"(module
+             (func $f)
+             (global funcref (ref.func $f)))"#,
+        ),
+        "reference types"
+    );
+    assert_eq!(
+        snip(
+            "tail_calls",
+            r#"(module
+             (func $f)
+             (func (export "g") (return_call $f)))"#,
+        ),
+        "tail calls"
+    );
+    assert_eq!(
+        snip(
+            "function_references",
+            r#"(module
+             (type $t (func))
+             (func (export "g") (param (ref $t))
+               (call_ref $t (local.get 0))))"#,
+        ),
+        "function references"
+    );
+    assert_eq!(
+        snip(
+            "exception_handling",
+            r#"(module
+             (tag $e)
+             (func (export "g") (throw $e)))"#,
+        ),
+        "exception handling"
+    );
+    assert_eq!(
+        snip(
+            "memory64",
+            r#"(module
+             (memory i64 1)
+             (func (export "g") (result i32)
+               (i32.load (i64.const 0))))"#,
+        ),
+        "memory64"
+    );
+    assert_eq!(
+        snip(
+            "multi_memory",
+            r#"(module
+             (memory 1)
+             (memory $second 1)
+             (func (export "g") (result i32)
+               (i32.load $second (i32.const 0))))"#,
+        ),
+        "multi-memory"
+    );
+}
+
+/// Validate `wasm`, with every proposal that wasmparser knows enabled.
+fn validate_all_features(wasm: &[u8]) {
+    wasmparser::Validator::new_with_features(wasmparser::WasmFeatures::all())
+        .validate_all(wasm)
+        .expect("snipped wasm should be valid");
+}
+
+/// The name of each function that the name section of `wasm` names, by
+/// index.
+fn names_by_index(wasm: &[u8]) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        if let wasmparser::Payload::CustomSection(section) = payload.unwrap() {
+            if let wasmparser::KnownCustom::Name(subsections) = section.as_known() {
+                for subsection in subsections {
+                    if let wasmparser::Name::Function(map) = subsection.unwrap() {
+                        for naming in map {
+                            let naming = naming.unwrap();
+                            names.insert(naming.index, naming.name.to_string());
+                        }
+                    }
+                }
+            }
+        }
+    }
+    names
+}
+
+/// The functions in each element segment of `wasm`, by name, with `None` for
+/// null elements.
+fn element_names(wasm: &[u8]) -> Vec<Vec<Option<String>>> {
+    let names = names_by_index(wasm);
+    let name = |f: u32| Some(names.get(&f).cloned().unwrap_or_else(|| f.to_string()));
+    let mut segments = vec![];
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        if let wasmparser::Payload::ElementSection(section) = payload.unwrap() {
+            for element in section {
+                segments.push(match element.unwrap().items {
+                    wasmparser::ElementItems::Functions(funcs) => {
+                        funcs.into_iter().map(|f| name(f.unwrap())).collect()
+                    }
+                    wasmparser::ElementItems::Expressions(_, exprs) => exprs
+                        .into_iter()
+                        .map(
+                            |expr| match expr.unwrap().get_operators_reader().read().unwrap() {
+                                wasmparser::Operator::RefFunc { function_index } => {
+                                    name(function_index)
+                                }
+                                _ => None,
+                            },
+                        )
+                        .collect(),
+                });
+            }
+        }
+    }
+    segments
+}
+
+#[test]
+fn reference_types_snipped_in_place() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (import "env" "log" (func $log (param i32)))
+          (table $funcs 4 funcref)
+          (table $externs 2 externref)
+          (func $keep (export "keep") (param i32) (result i32)
+            (call $snip_me (local.get 0)))
+          (func $snip_me (param i32) (result i32)
+            (call $log (local.get 0))
+            (i32.const 1))
+          (func $other (param i32) (result i32) (local.get 0))
+          (elem (table $funcs) (i32.const 0) funcref
+            (ref.func $keep) (ref.func $snip_me) (ref.null func))
+          (elem (table $funcs) (i32.const 3) func $other)
+          (func (export "set") (param externref)
+            (table.set $externs (i32.const 0) (local.get 0))))
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        wasm_snip::unsupported_proposal(&wasm),
+        Some("reference types")
+    );
+    let snip = |options: wasm_snip::Options| {
+        let options = wasm_snip::Options {
+            functions: vec!["snip_me".to_string()],
+            name_stubs: true,
+            ..options
+        };
+        let snipped = wasm_snip::snip_bytes(&wasm, &options).unwrap();
+        validate_all_features(&snipped);
+        let mut names: Vec<_> = names_by_index(&snipped).into_values().collect();
+        names.sort();
+        (names, element_names(&snipped))
+    };
+    let some = |name: &str| Some(name.to_string());
+
+    // `log` is only called by the snipped function, so it goes too.
+    let stub = "wasm_snip::unreachable_stub::<(i32) -> i32>";
+    assert_eq!(
+        snip(wasm_snip::Options::default()),
+        (
+            vec!["keep".to_string(), "other".to_string(), stub.to_string()],
+            vec![vec![some("keep"), some(stub), None], vec![some("other")]]
+        )
+    );
+    assert_eq!(
+        snip(wasm_snip::Options {
+            table_null: true,
+            ..Default::default()
+        }),
+        (
+            vec!["keep".to_string(), "other".to_string()],
+            vec![vec![some("keep"), None, None], vec![some("other")]]
+        )
+    );
+    // Snipped functions keep their place, and the table its elements.
+    let (names, elements) = snip(wasm_snip::Options {
+        preserve_indices: true,
+        ..Default::default()
+    });
+    assert_eq!(names, ["keep", "log", "other", "snip_me"]);
+    assert_eq!(elements[0], [some("keep"), some("snip_me"), None]);
+
+    let error = wasm_snip::snip_bytes(
+        &wasm,
+        &wasm_snip::Options {
+            snip_panic_strings: true,
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.root(),
+        wasm_snip::SnipError::Unsupported { .. }
+    ));
+}
+
+/// Snip `input` with `options`, and check that the snipped module is
+/// `expected`, both given as wat.
+fn assert_snips_to(input: &str, options: wasm_snip::Options, expected: &str) {
+    let options = wasm_snip::Options {
+        skip_producers_section: true,
+        ..options
+    };
+    let snipped = wasm_snip::snip_bytes(&wat::parse_str(input).unwrap(), &options).unwrap();
+    validate_all_features(&snipped);
+    assert_eq!(snipped, wat::parse_str(expected).unwrap());
+}
+
+#[test]
+fn passive_and_declared_elements() {
+    let input = r#"
+        (module
+          (type (func (param i32)))
+          (type (func))
+          (type (func (result funcref)))
+          (table $t 2 funcref)
+          (func $keep (export "keep") (type 0)
+            (table.init $t $copied (i32.const 0) (i32.const 0) (local.get 0))
+            (elem.drop $dropped))
+          (func $in_copied (type 1))
+          (func $snip_me (type 1))
+          (func $in_dropped (type 1))
+          (func $refer (export "refer") (type 2) (ref.func $declared))
+          (func $declared (type 1))
+          (func $unused (type 1))
+          (elem $copied func $in_copied $snip_me)
+          (elem $dropped func $in_dropped)
+          (elem declare func $declared $unused $snip_me))
+    "#;
+    let options = || wasm_snip::Options {
+        functions: vec!["snip_me".to_string()],
+        ..Default::default()
+    };
+
+    // Only `elem.drop` uses `$dropped`, so its functions can go, and the
+    // declaration declares the stub instead of the snipped function.
+    assert_snips_to(
+        input,
+        options(),
+        r#"
+        (module
+          (type (func (param i32)))
+          (type (func))
+          (type (func (result funcref)))
+          (table $t 2 funcref)
+          (func $keep (export "keep") (type 0)
+            (table.init $t $copied (i32.const 0) (i32.const 0) (local.get 0))
+            (elem.drop $dropped))
+          (func $in_copied (type 1))
+          (func $refer (export "refer") (type 2) (ref.func $declared))
+          (func $declared (type 1))
+          (func (type 1) unreachable)
+          (elem $copied func $in_copied 4)
+          (elem $dropped func)
+          (elem declare func $declared 4))
+        "#,
+    );
+    assert_snips_to(
+        input,
+        wasm_snip::Options {
+            table_null: true,
+            ..options()
+        },
+        r#"
+        (module
+          (type (func (param i32)))
+          (type (func))
+          (type (func (result funcref)))
+          (table $t 2 funcref)
+          (func $keep (export "keep") (type 0)
+            (table.init $t $copied (i32.const 0) (i32.const 0) (local.get 0))
+            (elem.drop $dropped))
+          (func $in_copied (type 1))
+          (func $refer (export "refer") (type 2) (ref.func $declared))
+          (func $declared (type 1))
+          (elem $copied funcref (ref.func $in_copied) (ref.null func))
+          (elem $dropped func)
+          (elem declare func $declared))
+        "#,
+    );
+}
+
+#[test]
+fn ref_func_snipped_in_place() {
+    // Only the export of `$snip_me` declares it for `ref.func`, so the stub
+    // needs a declaration of its own once the export is gone.
+    assert_snips_to(
+        r#"
+        (module
+          (type (func))
+          (type (func (result funcref)))
+          (global $g (export "g") funcref (ref.func $snip_me))
+          (func $get (export "get") (type 1) (ref.func $snip_me))
+          (func $snip_me (export "snip_me") (type 0) nop))
+        "#,
+        wasm_snip::Options {
+            functions: vec!["snip_me".to_string()],
+            force: true,
+            ..Default::default()
+        },
+        r#"
+        (module
+          (type (func))
+          (type (func (result funcref)))
+          (global $g (export "g") funcref (ref.func 1))
+          (func $get (export "get") (type 1) (ref.func 1))
+          (func (type 0) unreachable)
+          (elem declare func 1))
+        "#,
+    );
+}
+
+#[test]
+fn tail_calls_snipped_in_place() {
+    assert_snips_to(
+        r#"
+        (module
+          (type (func (param i32) (result i32)))
+          (table 1 funcref)
+          (func $tail (export "tail") (type 0)
+            (return_call $snip_me (local.get 0)))
+          (func $indirect (export "indirect") (type 0)
+            (return_call_indirect (type 0) (local.get 0) (i32.const 0)))
+          (func $snip_me (type 0) (local.get 0))
+          (elem (i32.const 0) func $snip_me))
+        "#,
+        wasm_snip::Options {
+            functions: vec!["snip_me".to_string()],
+            ..Default::default()
+        },
+        r#"
+        (module
+          (type (func (param i32) (result i32)))
+          (table 1 funcref)
+          (func $tail (export "tail") (type 0)
+            (local.get 0)
+            unreachable)
+          (func $indirect (export "indirect") (type 0)
+            (return_call_indirect (type 0) (local.get 0) (i32.const 0)))
+          (func (type 0) unreachable)
+          (elem (i32.const 0) func 2))
+        "#,
+    );
+}
+
+#[test]
+fn names_of_missing_functions_snipped_in_place() {
+    // The name section may name functions that don't exist, which walrus
+    // only warns about.
+    let mut wasm = wat::parse_str(
+        r#"
+        (module
+          (func (export "tail") (param i32) (result i32)
+            (return_call 0 (local.get 0))))
+        "#,
+    )
+    .unwrap();
+    let mut functions = wasm_encoder::NameMap::new();
+    functions.append(0, "tail");
+    functions.append(5, "missing");
+    let mut locals = wasm_encoder::NameMap::new();
+    locals.append(0, "x");
+    let mut all_locals = wasm_encoder::IndirectNameMap::new();
+    all_locals.append(5, &locals);
+    let mut names = wasm_encoder::NameSection::new();
+    names.functions(&functions);
+    names.locals(&all_locals);
+    wasm_encoder::Section::append_to(&names, &mut wasm);
+
+    let options = wasm_snip::Options {
+        patterns: vec!["nothing".to_string()],
+        ..Default::default()
+    };
+    let snipped = wasm_snip::snip_bytes(&wasm, &options).unwrap();
+    validate_all_features(&snipped);
+    let names: Vec<_> = names_by_index(&snipped).into_iter().collect();
+    assert_eq!(names, [(0, "tail".to_string())]);
+}
+
+#[test]
+fn typed_function_references_snipped_in_place() {
+    // The stub has the snipped function's own type, so `(ref $t)` can refer
+    // to it, and only the nullable table's element can be emptied.
+    assert_snips_to(
+        r#"
+        (module
+          (type $t (func (param i32) (result i32)))
+          (table $nullable 1 (ref null $t))
+          (table $non_null 1 (ref $t) (ref.func $keep))
+          (func $keep (export "keep") (type $t) (local.get 0))
+          (func $snip_me (type $t) (local.get 0))
+          (func $call (export "call") (param (ref $t)) (result i32)
+            (call_ref $t (i32.const 1) (local.get 0)))
+          (func $get (export "get") (result (ref $t)) (ref.func $snip_me))
+          (elem (table $nullable) (i32.const 0) (ref null $t) (ref.func $snip_me))
+          (elem (table $non_null) (i32.const 0) (ref $t) (ref.func $snip_me)))
+        "#,
+        wasm_snip::Options {
+            functions: vec!["snip_me".to_string()],
+            table_null: true,
+            ..Default::default()
+        },
+        r#"
+        (module
+          (type $t (func (param i32) (result i32)))
+          (table $nullable 1 (ref null $t))
+          (table $non_null 1 (ref $t) (ref.func $keep))
+          (func $keep (export "keep") (type $t) (local.get 0))
+          (func $call (export "call") (param (ref $t)) (result i32)
+            (call_ref $t (i32.const 1) (local.get 0)))
+          (func $get (export "get") (result (ref $t)) (ref.func 3))
+          (func (type $t) unreachable)
+          (elem (table $nullable) (i32.const 0) (ref null $t) (ref.null $t))
+          (elem (table $non_null) (i32.const 0) (ref $t) (ref.func 3))
+          (elem declare func 3))
+        "#,
+    );
+
+    // Functions taking references to different types each get a stub of
+    // their own type, though walrus would see both as `(anyref)`.
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (type $a (func (param i32)))
+          (type $b (func (param i64)))
+          (table 2 funcref)
+          (func $snip_a (param (ref $a)))
+          (func $snip_b (param (ref $b)))
+          (elem (i32.const 0) func $snip_a $snip_b))
+        "#,
+    )
+    .unwrap();
+    let options = wasm_snip::Options {
+        patterns: vec!["snip_.*".to_string()],
+        name_stubs: true,
+        ..Default::default()
+    };
+    let snipped = wasm_snip::snip_bytes(&wasm, &options).unwrap();
+    validate_all_features(&snipped);
+    let mut names: Vec<_> = names_by_index(&snipped).into_values().collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "wasm_snip::unreachable_stub::<((ref 0))>",
+            "wasm_snip::unreachable_stub::<((ref 1))>"
+        ]
+    );
+}
+
+#[test]
+fn exception_handling_snipped_in_place() {
+    // Both the legacy `try` and `try_table` keep their handlers, and only the
+    // calls inside them are replaced.
+    assert_snips_to(
+        r#"
+        (module
+          (type (func (param i32) (result i32)))
+          (type (func (param i32)))
+          (tag $e (type 1))
+          (func $snip_me (type 0) (local.get 0))
+          (func $throw (type 1) (throw $e (local.get 0)))
+          (func $legacy (export "legacy") (type 0)
+            try (result i32)
+              local.get 0
+              call $snip_me
+            catch $e
+            catch_all
+              i32.const 0
+            end)
+          (func $try_table (export "try_table") (type 0)
+            (block $caught (result i32)
+              (try_table (result i32) (catch $e $caught)
+                (call $throw (local.get 0))
+                (call $snip_me (local.get 0))))))
+        "#,
+        wasm_snip::Options {
+            functions: vec!["snip_me".to_string()],
+            ..Default::default()
+        },
+        r#"
+        (module
+          (type (func (param i32) (result i32)))
+          (type (func (param i32)))
+          (tag $e (type 1))
+          (func $throw (type 1) (throw $e (local.get 0)))
+          (func $legacy (export "legacy") (type 0)
+            try (result i32)
+              local.get 0
+              unreachable
+            catch $e
+            catch_all
+              i32.const 0
+            end)
+          (func $try_table (export "try_table") (type 0)
+            (block $caught (result i32)
+              (try_table (result i32) (catch $e $caught)
+                (call $throw (local.get 0))
+                (local.get 0)
+                unreachable))))
+        "#,
+    );
+}
+
+#[test]
+fn memory64_data_snipped_in_place() {
+    // Pointers in a 64-bit memory's data are eight bytes.
+    assert_snips_to(
+        r#"
+        (module
+          (memory (export "memory") i64 4 8)
+          (func $message (export "message") (result i64) (i64.const 1024))
+          (func $table (export "table") (result i64) (i64.const 1088))
+          (func $bss (export "bss") (result i64) (i64.load (i64.const 70000)))
+          (func $call_fail (export "call_fail") (call $fail))
+          (func $fail (drop (i64.const 1056)) (drop (i64.const 131072)))
+          (data (i64.const 1024)
+            "kept: referred to by live code!!"
+            "dead: only the snipped code used"
+            "\48\04\00\00\00\00\00\00"
+            "live: pointed to by live table..")
+          (data (i64.const 131072) "dead: only the snipped code used"))
+        "#,
+        wasm_snip::Options {
+            functions: vec!["fail".to_string()],
+            gc_data: true,
+            shrink_memory: true,
+            ..Default::default()
+        },
+        // The data left ends in the first page, but live code uses the
+        // second.
+        r#"
+        (module
+          (memory (export "memory") i64 2 8)
+          (func $message (export "message") (result i64) (i64.const 1024))
+          (func $table (export "table") (result i64) (i64.const 1088))
+          (func $bss (export "bss") (result i64) (i64.load (i64.const 70000)))
+          (func $call_fail (export "call_fail") unreachable)
+          (data (i64.const 1024) "kept: referred to by live code!!")
+          (data (i64.const 1088)
+            "\48\04\00\00\00\00\00\00"
+            "live: pointed to by live table.."))
+        "#,
+    );
+}
+
+#[test]
+fn multi_memory_data_snipped_in_place() {
+    // Each memory's data is looked at, and each memory shrunk, on its own,
+    // while the data of the imported memory is left as it is.
+    let input = r#"
+        (module
+          (import "env" "memory" (memory $imported 1))
+          (memory $a (export "a") 4)
+          (memory $b (export "b") 4)
+          (func $message (export "message") (result i32)
+            (i32.load $b (i32.const 1024)))
+          (func $call_fail (export "call_fail") (call $fail))
+          (func $fail
+            (drop (i32.load $a (i32.const 131072)))
+            (drop (i32.const 1056)))
+          (data (memory $imported) (i32.const 0)
+            "\ff" "\00\00\00\00\00\00\00\00" "\00\00\00\00\00\00\00\00" "\ff")
+          (data (memory $a) (i32.const 131072) "dead: only the snipped code used")
+          (data (memory $b) (i32.const 1024)
+            "kept: referred to by live code!!"
+            "dead: only the snipped code used"))
+        "#;
+    let expected = r#"
+        (module
+          (import "env" "memory" (memory $imported 1))
+          (memory $a (export "a") 1)
+          (memory $b (export "b") 1)
+          (func $message (export "message") (result i32)
+            (i32.load $b (i32.const 1024)))
+          (func $call_fail (export "call_fail") unreachable)
+          (data (memory $imported) (i32.const 0)
+            "\ff" "\00\00\00\00\00\00\00\00" "\00\00\00\00\00\00\00\00" "\ff")
+          (data (memory $b) (i32.const 1024) "kept: referred to by live code!!"))
+        "#;
+    assert_snips_to(
+        input,
+        wasm_snip::Options {
+            functions: vec!["fail".to_string()],
+            gc_data: true,
+            shrink_memory: true,
+            ..Default::default()
+        },
+        expected,
+    );
+}
+
+#[test]
+fn threads() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+          (memory (export "memory") 1 1 shared)
+          (data $init "hello")
+          (func $init (export "init")
+            (memory.init $init (i32.const 0) (i32.const 0) (i32.const 5))
+            (data.drop $init))
+          (func $add (result i32)
+            (i32.atomic.rmw.add (i32.const 8) (i32.const 1)))
+          (func (export "run") (result i32)
+            (call $add))
+          (func $wake (export "wake") (result i32)
+            (memory.atomic.notify (i32.const 8) (i32.const 1))))
+        "#,
+    )
+    .unwrap();
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("threads.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("threads.wasm");
+    Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("add")
+        .assert()
+        .success();
+
+    let wasm = fs::read(&output).unwrap();
+    wasmparser::validate(&wasm).expect("snipped wasm should be valid");
+    let module = walrus::Module::from_buffer(&wasm).unwrap();
+    assert!(module.memories.iter().all(|memory| memory.shared));
+
+    let mut operators = vec![];
+    for payload in wasmparser::Parser::new(0).parse_all(&wasm) {
+        if let wasmparser::Payload::CodeSectionEntry(body) = payload.unwrap() {
+            let mut reader = body.get_operators_reader().unwrap();
+            while !reader.eof() {
+                operators.push(format!("{:?}", reader.read().unwrap()));
+            }
+        }
+    }
+    assert!(operators.iter().any(|op| op.starts_with("MemoryInit")));
+    assert!(operators.iter().any(|op| op.starts_with("DataDrop")));
+    assert!(operators
+        .iter()
+        .any(|op| op.starts_with("MemoryAtomicNotify")));
+    assert!(!operators.iter().any(|op| op.starts_with("I32AtomicRmwAdd")));
+}
+
+#[test]
+fn components() {
+    let wasm = wat::parse_str(
+        r#"
+        (component
+          (core module $m
+            (func $secret)
+            (func $kept)
+            (func (export "run")
+              (call $secret)
+              (call $kept)))
+          (component $nested
+            (core module $n
+              (func $secret)
+              (func (export "run")
+                (call $secret)))))
+        "#,
+    )
+    .unwrap();
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("components.input.wasm");
+    fs::write(&input, wasm).unwrap();
+
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("components.wasm");
+    Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .arg("secret")
+        .assert()
+        .success();
+
+    let wasm = fs::read(&output).unwrap();
+    assert!(wasmparser::Parser::is_component(&wasm));
+    wasmparser::validate(&wasm).expect("snipped component should be valid");
+    let modules: Vec<_> = wasmparser::Parser::new(0)
+        .parse_all(&wasm)
+        .filter_map(|payload| match payload.unwrap() {
+            wasmparser::Payload::ModuleSection {
+                unchecked_range, ..
+            } => {
+                let range = unchecked_range.start as usize..unchecked_range.end as usize;
+                Some(walrus::Module::from_buffer(&wasm[range]).unwrap())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(modules.len(), 2);
+    for module in &modules {
+        assert!(!function_names(module).contains("secret"));
+    }
+    assert!(function_names(&modules[0]).contains("kept"));
+
+    let output = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&input)
+        .arg("--suggest")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--suggest isn't supported for a component"));
+}
+
+#[test]
+fn object_files() {
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("object_files.o");
+    wasm_snip_file("object.o")
+        .arg("-o")
+        .arg(&output)
+        .arg("expensive")
+        .assert()
+        .success();
+    let wasm = fs::read(&output).unwrap();
+    wasmparser::validate(&wasm).expect("snipped object file should be valid");
+
+    let mut symbols = vec![];
+    let mut imported = 0;
+    let mut code = 0..0;
+    let mut bodies = vec![];
+    let mut relocations = vec![];
+    for payload in wasmparser::Parser::new(0).parse_all(&wasm) {
+        match payload.unwrap() {
+            wasmparser::Payload::ImportSection(imports) => {
+                for import in imports.into_imports() {
+                    if let wasmparser::TypeRef::Func(_) = import.unwrap().ty {
+                        imported += 1;
+                    }
+                }
+            }
+            wasmparser::Payload::CodeSectionStart { range, .. } => {
+                code = range.start as usize..range.end as usize
+            }
+            wasmparser::Payload::CodeSectionEntry(body) => bodies.push(body),
+            wasmparser::Payload::CustomSection(section) => match section.as_known() {
+                wasmparser::KnownCustom::Linking(linking) => {
+                    for subsection in linking.subsections() {
+                        if let wasmparser::Linking::SymbolTable(table) = subsection.unwrap() {
+                            symbols.extend(table.into_iter().map(Result::unwrap));
+                        }
+                    }
+                }
+                wasmparser::KnownCustom::Reloc(reloc) if section.name() == "reloc.CODE" => {
+                    relocations.extend(reloc.entries().into_iter().map(Result::unwrap));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    let function = |name: &str| {
+        symbols
+            .iter()
+            .find_map(|symbol| match *symbol {
+                wasmparser::SymbolInfo::Func {
+                    index,
+                    name: Some(n),
+                    ..
+                } if n == name => Some(index),
+                _ => None,
+            })
+            .unwrap()
+    };
+    let operators = |index: u32| {
+        let mut reader = bodies[(index - imported) as usize]
+            .get_operators_reader()
+            .unwrap();
+        let mut operators = vec![];
+        while !reader.eof() {
+            operators.push(format!("{:?}", reader.read().unwrap()));
+        }
+        operators
+    };
+    assert_eq!(operators(function("expensive")), ["Unreachable", "End"]);
+    assert!(operators(function("cheap")).len() > 2);
+
+    // Every call's relocation still points at the call's function index.
+    let code = &wasm[code];
+    let calls: Vec<_> = relocations
+        .iter()
+        .filter(|r| r.ty == wasmparser::RelocationType::FunctionIndexLeb)
+        .collect();
+    assert!(!calls.is_empty());
+    for call in calls {
+        let offset = call.offset as usize;
+        assert_eq!(code[offset - 1], 0x10, "relocation isn't in a call");
+        let callee = wasmparser::BinaryReader::new(&code[offset..], 0)
+            .read_var_u32()
+            .unwrap();
+        match symbols[call.index as usize] {
+            wasmparser::SymbolInfo::Func { index, .. } => assert_eq!(callee, index),
+            ref symbol => panic!("call of {:?}", symbol),
+        }
+    }
+}
+
+#[test]
+fn stdin() {
+    let input = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm")).unwrap();
+    let assert = assert_cmd::Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg("-")
+        .arg("--snip-rust-fmt-code")
+        .write_stdin(input.clone())
+        .assert()
+        .success();
+    let output = &assert.get_output().stdout;
+    assert!(output.len() < input.len());
+    walrus::Module::from_buffer(output).expect("should parse snipped wasm from stdout");
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn capi() {
+    use std::ffi::CStr;
+    use std::ptr;
+    use wasm_snip::capi::*;
+
+    let input = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm")).unwrap();
+    let mut output = ptr::null_mut();
+    let mut output_len = 0;
+    let options = b"{\"snip-rust-fmt-code\": true}\0";
+    let status = unsafe {
+        wasm_snip_run(
+            input.as_ptr(),
+            input.len(),
+            options.as_ptr().cast(),
+            &mut output,
+            &mut output_len,
+        )
+    };
+    assert_eq!(status, 0);
+    let wasm = unsafe { std::slice::from_raw_parts(output, output_len) };
+    assert!(wasm.len() < input.len());
+    walrus::Module::from_buffer(wasm).expect("should parse snipped wasm");
+    unsafe { wasm_snip_free(output, output_len) };
+
+    let options = b"{\"no-such-option\": true}\0";
+    let status = unsafe {
+        wasm_snip_run(
+            input.as_ptr(),
+            input.len(),
+            options.as_ptr().cast(),
+            &mut output,
+            &mut output_len,
+        )
+    };
+    assert_eq!(status, 1);
+    let error = unsafe { CStr::from_ptr(wasm_snip_last_error()) };
+    assert!(error.to_str().unwrap().starts_with("invalid options"));
+}
+
+#[test]
+fn input_module() {
+    let mut module = walrus::Module::default();
+    for name in &["a", "b"] {
+        let mut builder = walrus::FunctionBuilder::new(&mut module.types, &[], &[]);
+        builder.func_body().unreachable();
+        let id = builder.finish(vec![], &mut module.funcs);
+        module.funcs.get_mut(id).name = Some(name.to_string());
+        module.exports.add(name, id);
+    }
+
+    let options = wasm_snip::Options {
+        functions: vec!["a".to_string()],
+        force: true,
+        ..Default::default()
+    };
+    let mut module = wasm_snip::Input::Module(Box::new(module))
+        .into_module(&options)
+        .unwrap();
+    wasm_snip::snip(&mut module, options.clone()).unwrap();
+    let names = function_names(&module);
+    assert!(!names.contains("a"));
+    assert!(names.contains("b"));
+
+    let wasm = wasm_snip::emit_wasm(&module, &options).unwrap();
+    let module = wasm_snip::Input::Buffer(wasm)
+        .into_module(&options)
+        .unwrap();
+    assert_eq!(function_names(&module), names);
+}
+
+#[test]
+fn snip_bytes() {
+    let input = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm")).unwrap();
+    let options = wasm_snip::Options {
+        snip_rust_fmt_code: true,
+        ..Default::default()
+    };
+    let output = wasm_snip::snip_bytes(&input, &options).unwrap();
+    assert!(output.len() < input.len());
+    let module = walrus::Module::from_buffer(&output).unwrap();
+    assert!(!demangled_function_names(&module)
+        .iter()
+        .any(|name| name.starts_with("core::fmt::write")));
+
+    assert!(wasm_snip::snip_bytes(b"nope", &options).is_err());
+}
+
+#[cfg(feature = "config")]
+#[test]
+fn snip_file() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let expected =
+        fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/no_alloc.wasm")).unwrap();
+
+    let options = wasm_snip::Options {
+        patterns: vec![".*alloc.*".to_string()],
+        skip_producers_section: true,
+        ..Default::default()
+    };
+    let output = dir.join("snip_file.wasm");
+    wasm_snip::snip_file(&input, &output, &options).unwrap();
+    assert_eq!(fs::read(&output).unwrap(), expected);
+
+    let config = dir.join("snip_file.toml");
+    fs::write(
+        &config,
+        "output = \"snip_file_config.wasm\"\n\
+         patterns = [\".*alloc.*\"]\n\
+         skip-producers-section = true\n",
+    )
+    .unwrap();
+    let config = wasm_snip::config::Config::from_file(&config).unwrap();
+    config.snip_file(&input).unwrap();
+    assert_eq!(
+        fs::read(dir.join("snip_file_config.wasm")).unwrap(),
+        expected
+    );
+
+    let error = wasm_snip::snip_file(dir.join("missing.wasm"), &output, &options).unwrap_err();
+    assert!(error.to_string().starts_with("failed to read file"));
+}
+
+#[test]
+fn snip_outcome() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (import "env" "log" (func $log (param i32)))
+            (func $snip_me (export "snip_me") (param i32)
+                local.get 0
+                call $log)
+            (func $keep_me (export "keep_me")
+                i32.const 1
+                call $snip_me
+                i32.const 2
+                call $snip_me)
+            (table 2 funcref)
+            (elem (i32.const 0) $snip_me $keep_me))
+        "#,
+    )
+    .unwrap();
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let options = wasm_snip::Options {
+        functions: vec!["snip_me".to_string(), "log".to_string()],
+        force: true,
+        ..Default::default()
+    };
+    let outcome = wasm_snip::snip(&mut module, options).unwrap();
+
+    let names: Vec<_> = outcome
+        .functions
+        .iter()
+        .map(|(_, name)| name.as_deref().unwrap())
+        .collect();
+    assert_eq!(names, ["log", "snip_me"]);
+    assert_eq!(outcome.removed_exports, ["snip_me"]);
+    assert_eq!(
+        outcome.removed_imports,
+        [("env".to_string(), "log".to_string())]
+    );
+    assert_eq!(outcome.call_sites, 2);
+    assert_eq!(outcome.table_slots, 1);
+}
+
+#[test]
+fn snip_with() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (func $a (export "a") (param i32))
+            (func $b (export "b") (param i32 i32))
+            (func $c (export "c") (param i32))
+            (func $d (export "d")))
+        "#,
+    )
+    .unwrap();
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let one_param = module.types.add(&[walrus::ValType::I32], &[]);
+    let options = wasm_snip::Options {
+        functions: vec!["d".to_string()],
+        keep_functions: vec!["c".to_string()],
+        force: true,
+        ..Default::default()
+    };
+    let outcome = wasm_snip::snip_with(&mut module, |f| f.ty() == one_param, options).unwrap();
+
+    let snipped: HashSet<_> = outcome
+        .functions
+        .iter()
+        .map(|(_, name)| name.as_deref().unwrap())
+        .collect();
+    assert_eq!(snipped, ["a", "d"].iter().cloned().collect());
+    let names = function_names(&module);
+    assert!(names.contains("b"));
+    assert!(names.contains("c"));
+}
+
+#[test]
+fn options_builder() {
+    let options = wasm_snip::Options::builder()
+        .pattern(".*alloc.*")
+        .keep_function("__rust_alloc_error_handler")
+        .skip_producers_section(true)
+        .build()
+        .unwrap();
+    assert_eq!(options.patterns, [".*alloc.*"]);
+    assert_eq!(options.keep_functions, ["__rust_alloc_error_handler"]);
+    let input = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm")).unwrap();
+    let expected =
+        fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/no_alloc.wasm")).unwrap();
+    assert_eq!(wasm_snip::snip_bytes(&input, &options).unwrap(), expected);
+
+    let error = |builder: wasm_snip::builder::OptionsBuilder| {
+        let error = builder.build().unwrap_err();
+        error
+            .chain()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(": ")
+    };
+    let builder = wasm_snip::Options::builder;
+    assert!(error(builder().pattern("(")).starts_with("invalid pattern `(`: "));
+    assert!(error(builder().keep_pattern("[")).starts_with("invalid keep pattern `[`: "));
+    assert_eq!(
+        error(builder().function("a").keep_pattern("^a$")),
+        "`a` is both snipped and kept"
+    );
+    assert_eq!(
+        error(builder().pattern("a.*").keep_pattern("a.*")),
+        "the pattern `a.*` is both snipped and kept"
+    );
+    assert_eq!(
+        error(builder().index_range(3..3)),
+        "the index range 3..3 is empty"
+    );
+    assert_eq!(
+        error(builder().keep_export_names(true)),
+        "keeping export names only makes sense when stripping names"
+    );
+    assert_eq!(
+        error(builder().preset("no-such-preset")),
+        "unknown preset `no-such-preset`"
+    );
+    assert!(error(builder().signature("i32 -> i32")).starts_with("invalid signature"));
+    assert!(
+        error(builder().strategy(wasm_snip::Strategy::CallImportThenTrap(
+            "snipped_hit".to_string()
+        )))
+        .contains("`<module>::<name>`")
+    );
+}
+
+#[test]
+fn passes() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (func $snip_me (export "snip_me") (result i32)
+                i32.const 42)
+            (func $keep_me (export "keep_me") (result i32)
+                call $snip_me)
+            (table 1 funcref)
+            (elem (i32.const 0) $snip_me))
+        "#,
+    )
+    .unwrap();
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let to_snip: HashSet<_> = module
+        .funcs
+        .iter()
+        .filter(|f| f.name.as_deref() == Some("snip_me"))
+        .map(|f| f.id())
+        .collect();
+
+    // Keep the export, but drop the calls and the table element.
+    let mut stubs = HashMap::new();
+    assert_eq!(
+        wasm_snip::passes::replace_calls_with_unreachable(&mut module, &to_snip),
+        1
+    );
+    wasm_snip::passes::stub_snipped_exports(&mut module, &to_snip, &mut stubs);
+    assert_eq!(
+        wasm_snip::passes::snip_table_elements(&mut module, &to_snip, &mut stubs),
+        1
+    );
+    wasm_snip::passes::delete_functions_to_snip(&mut module, &to_snip);
+    assert_eq!(stubs.len(), 1);
+
+    let module = walrus::Module::from_buffer(&module.emit_wasm()).unwrap();
+    assert_eq!(module.exports.iter().count(), 2);
+    assert!(!function_names(&module).contains("snip_me"));
+}
+
+#[test]
+fn callers_of() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (func $snip_me (result i32)
+                i32.const 42)
+            (func $caller (export "caller") (result i32)
+                call $snip_me)
+            (func $other (export "other") (result i32)
+                i32.const 0))
+        "#,
+    )
+    .unwrap();
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let id = |module: &walrus::Module, name| {
+        module
+            .funcs
+            .iter()
+            .find(|f| f.name.as_deref() == Some(name))
+            .unwrap()
+            .id()
+    };
+    let to_snip: HashSet<_> = Some(id(&module, "snip_me")).into_iter().collect();
+
+    let callers = wasm_snip::passes::callers_of(&module, &to_snip);
+    assert_eq!(callers, Some(id(&module, "caller")).into_iter().collect());
+    assert!(wasm_snip::passes::callers_of(&module, &HashSet::new()).is_empty());
+
+    // Only the given callers are visited.
+    let other: HashSet<_> = Some(id(&module, "other")).into_iter().collect();
+    assert_eq!(
+        wasm_snip::passes::replace_calls_in(&mut module, &to_snip, &other),
+        0
+    );
+    assert_eq!(
+        wasm_snip::passes::replace_calls_in(&mut module, &to_snip, &callers),
+        1
+    );
+}
+
+#[test]
+fn pipeline() {
+    use wasm_snip::pipeline::{Pipeline, SnipContext, SnipPass};
+
+    /// Snips `b` too, and counts the functions by the time it runs.
+    struct AlsoB(usize);
+
+    impl SnipPass for AlsoB {
+        fn name(&self) -> &str {
+            "also-b"
+        }
+
+        fn run(
+            &mut self,
+            module: &mut walrus::Module,
+            context: &mut SnipContext,
+        ) -> Result<(), wasm_snip::SnipError> {
+            let b = module.funcs.by_name("b").unwrap();
+            context.to_snip.insert(b);
+            self.0 = module.funcs.iter().count();
+            Ok(())
+        }
+    }
+
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (func $a (export "a"))
+            (func $b (export "b"))
+            (func $c (export "c")))
+        "#,
+    )
+    .unwrap();
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let options = wasm_snip::Options {
+        functions: vec!["a".to_string()],
+        force: true,
+        ..Default::default()
+    };
+
+    let mut count = AlsoB(0);
+    let mut pipeline = Pipeline::new();
+    pipeline.insert_after("select", &mut count).unwrap();
+    assert_eq!(
+        pipeline.names().collect::<Vec<_>>()[..4],
+        ["producers", "select", "also-b", "data-references"]
+    );
+    assert_eq!(
+        pipeline
+            .insert_before("no-such-pass", AlsoB(0))
+            .unwrap_err()
+            .to_string(),
+        "there is no pass named `no-such-pass`"
+    );
+    pipeline.run(&mut module, options).unwrap();
+    drop(pipeline);
+    assert_eq!(count.0, 3);
+    let names = function_names(&module);
+    assert!(!names.contains("a"));
+    assert!(!names.contains("b"));
+    assert!(names.contains("c"));
+}
+
+#[test]
+fn error_kinds() {
+    use wasm_snip::SnipError;
+
+    let wasm = wat::parse_str(r#"(module (func $f (export "f")))"#).unwrap();
+    let snip = |options: wasm_snip::Options| wasm_snip::snip_bytes(&wasm, &options).unwrap_err();
+
+    let error = snip(wasm_snip::Options {
+        patterns: vec!["(".to_string()],
+        ..Default::default()
+    });
+    assert!(matches!(error.root(), SnipError::InvalidRegex(_)));
+
+    let error = snip(wasm_snip::Options {
+        functions: vec!["f".to_string()],
+        ..Default::default()
+    });
+    assert!(matches!(error.root(), SnipError::Refused(_)));
+
+    let error = snip(wasm_snip::Options {
+        strategy: wasm_snip::Strategy::CallImportThenTrap("nodot".to_string()),
+        functions: vec!["f".to_string()],
+        force: true,
+        ..Default::default()
+    });
+    assert!(matches!(error.root(), SnipError::InvalidOptions(_)));
+
+    let error = wasm_snip::snip_bytes(b"not wasm", &Default::default()).unwrap_err();
+    assert!(matches!(error.root(), SnipError::Parse(_)));
+
+    let tail_call = wat::parse_str("(module (func $f return_call $f))").unwrap();
+    let error = wasm_snip::parse_module(&tail_call, &Default::default()).unwrap_err();
+    match error.root() {
+        SnipError::Unsupported { message, source } => {
+            assert!(message.contains("tail calls"));
+            assert!(matches!(source.as_deref(), Some(SnipError::Parse(_))));
+        }
+        error => panic!("unexpected error: {}", error),
+    }
+
+    let missing = Path::new(env!("CARGO_TARGET_TMPDIR")).join("missing.wasm");
+    let error = wasm_snip::snip_file(&missing, &missing, &Default::default()).unwrap_err();
+    let chain: Vec<_> = error.chain().map(|e| e.to_string()).collect();
+    assert!(chain[0].starts_with("failed to read file"));
+    match error.root() {
+        SnipError::Io(e) => assert_eq!(e.kind(), std::io::ErrorKind::NotFound),
+        error => panic!("unexpected error: {}", error),
+    }
+}
+
+#[test]
+fn snip_reader_to_writer() {
+    let input =
+        fs::File::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm")).unwrap();
+    let expected =
+        fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/no_alloc.wasm")).unwrap();
+    let options = wasm_snip::Options {
+        patterns: vec![".*alloc.*".to_string()],
+        skip_producers_section: true,
+        ..Default::default()
+    };
+    let mut output = vec![];
+    wasm_snip::snip_reader_to_writer(input, &mut output, &options).unwrap();
+    assert_eq!(output, expected);
+
+    let error =
+        wasm_snip::snip_reader_to_writer(&b"not wasm"[..], &mut output, &options).unwrap_err();
+    assert!(matches!(error.root(), wasm_snip::SnipError::Parse(_)));
+}
+
+#[test]
+fn verbose() {
+    const SNIP_ME: &str = "_ZN5hello7snip_me17hf15dbd799e7ad6aaE";
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("verbose.wasm");
+    let snip = |verbose: &str| {
+        let result = Command::cargo_bin("wasm-snip")
+            .unwrap()
+            .env_remove("RUST_LOG")
+            .arg(verbose)
+            .arg(&hello)
+            .arg("-o")
+            .arg(&output)
+            .arg(SNIP_ME)
+            .output()
+            .unwrap();
+        assert!(result.status.success());
+        String::from_utf8(result.stderr).unwrap()
+    };
+
+    let stderr = snip("-v");
+    assert!(stderr.contains("[INFO  wasm_snip] selected 1 functions to snip"));
+    assert!(stderr.contains("replaced 1 calls with `unreachable`"));
+    assert!(!stderr.contains("DEBUG"));
+
+    let stderr = snip("-vv");
+    assert!(stderr.contains(&format!(
+        "selected `{}` because it is named `{}`",
+        SNIP_ME, SNIP_ME
+    )));
+    assert!(stderr.contains("running the `gc` pass"));
+    assert!(!stderr.contains("walrus"));
+}
+
+#[test]
+fn timings() {
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("timings.wasm");
+    let result = Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&hello)
+        .arg("-o")
+        .arg(&output)
+        .arg("--timings")
+        .arg("-p")
+        .arg(".*alloc.*")
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    let stderr = String::from_utf8(result.stderr).unwrap();
+    let names: Vec<_> = stderr
+        .lines()
+        .map(|line| line.rsplit_once("  ").unwrap().0.trim_end())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "parse",
+            "producers",
+            "select",
+            "data-references",
+            "snip-panic-strings",
+            "replace",
+            "  calls",
+            "  tables",
+            "snip-start",
+            "gc",
+            "gc-data",
+            "shrink-memory",
+            "discard",
+            "strip-names",
+            "strip-custom-sections",
+            "target-features",
+            "emit",
+            "total",
+        ]
+    );
+}
+
+#[test]
+fn thread_pool() {
+    let input = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm")).unwrap();
+    let options = wasm_snip::Options {
+        patterns: vec![".*alloc.*".to_string()],
+        ..Default::default()
+    };
+    let expected = wasm_snip::snip_bytes(&input, &options).unwrap();
+    let options = wasm_snip::Options {
+        threads: Some(1),
+        ..options
+    };
+    assert_eq!(wasm_snip::snip_bytes(&input, &options).unwrap(), expected);
+
+    let error = wasm_snip::Options::builder()
+        .threads(0)
+        .build()
+        .unwrap_err();
+    assert_eq!(error.to_string(), "there must be at least one thread");
+
+    let hello = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    Command::cargo_bin("wasm-snip")
+        .unwrap()
+        .arg(&hello)
+        .arg("--threads")
+        .arg("0")
+        .assert()
+        .failure()
+        .stderr("error: invalid number of threads `0`\n");
+}
+
+#[test]
+fn wasm_file() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/hello.wasm");
+    let file = wasm_snip::WasmFile::open(&path).unwrap();
+    assert_eq!(&file[..], &fs::read(&path).unwrap()[..]);
+
+    let error = wasm_snip::WasmFile::open("tests/does-not-exist.wasm").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "failed to read file tests/does-not-exist.wasm"
+    );
+    assert!(matches!(error.root(), wasm_snip::SnipError::Io(_)));
+}
+
+#[test]
+fn reproducible() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (type $t0 (func))
+            (type $t1 (func (param i32)))
+            (type $t2 (func (result i32)))
+            (type $t3 (func (param i64) (result i64)))
+            (func $a3 (type $t3) local.get 0)
+            (func $a2 (type $t2) i32.const 0)
+            (func $a1 (type $t1))
+            (func $a0 (type $t0))
+            (func $b2 (type $t2) i32.const 1)
+            (func $b1 (type $t1))
+            (table (export "table") 6 funcref)
+            (elem (i32.const 0) $a3 $a2 $a1 $a0 $b2 $b1))
+        "#,
+    )
+    .unwrap();
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = dir.join("reproducible.input.wasm");
+    fs::write(&input, wasm).unwrap();
+    // Functions that the custom body doesn't fit are stubbed along with
+    // those snipped with the default strategy.
+    let config = dir.join("reproducible.toml");
+    fs::write(
+        &config,
+        "patterns = [\"^[ab]\"]\n\
+         \n\
+         [[strategies]]\n\
+         patterns = [\"^b\"]\n\
+         strategy = \"custom-body=(func (result i32) i32.const 7)\"\n",
+    )
+    .unwrap();
+
+    let snip = |i: usize| {
+        let output = dir.join(format!("reproducible.{}.wasm", i));
+        Command::cargo_bin("wasm-snip")
+            .unwrap()
+            .arg(&input)
+            .arg("--config")
+            .arg(&config)
+            .arg("--name-stubs")
+            .arg("-o")
+            .arg(&output)
+            .assert()
+            .success();
+        fs::read(output).unwrap()
+    };
+    let first = snip(0);
+    for i in 1..5 {
+        assert_eq!(snip(i), first);
+    }
+
+    // The stubs come in the order of their types, with one for each type
+    // however many strategies snip functions of it.
+    let module = walrus::Module::from_buffer(&first).unwrap();
+    let stubs: Vec<_> = module
+        .funcs
+        .iter()
+        .filter_map(|f| f.name.as_deref())
+        .filter_map(|name| name.strip_prefix("wasm_snip::unreachable_stub::"))
+        .collect();
+    assert_eq!(stubs, ["<()>", "<(i32)>", "<() -> i32>", "<(i64) -> i64>"]);
+}
+
+#[test]
+fn reuse_stubs() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (import "env" "trap" (func $trap (param i32)))
+            (func $g (param i32))
+            (table (export "table") 2 funcref)
+            (elem (i32.const 0) $trap $g))
+        "#,
+    )
+    .unwrap();
+    let options = wasm_snip::Options {
+        functions: vec!["g".to_string(), "trap".to_string()],
+        keep_imports: true,
+        ..Default::default()
+    };
+    let snipped = wasm_snip::snip_bytes(&wasm, &options).unwrap();
+
+    // The import that now traps stands in for `g` in the table, rather than
+    // a new stub of the same type.
+    let module = walrus::Module::from_buffer(&snipped).unwrap();
+    assert_eq!(module.funcs.iter().count(), 1);
+    let table = module.tables.iter().next().unwrap();
+    let elements = match table.kind {
+        walrus::TableKind::Function(ref ft) => ft.elements.clone(),
+        _ => unreachable!(),
+    };
+    let only = module.funcs.iter().next().unwrap().id();
+    assert_eq!(elements, [Some(only), Some(only)]);
+}
+
+#[test]
+fn stub_function() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (import "env" "report" (func $report (param i32)))
+            (func $snipped_hit (param i32)
+                i32.const 1
+                call $report
+                unreachable)
+            (func $a (param i32))
+            (func $b (param i32))
+            (func $c (result i32) i32.const 0)
+            (table (export "table") 3 funcref)
+            (elem (i32.const 0) $a $b $c))
+        "#,
+    )
+    .unwrap();
+    let snip = |functions: &[&str], stub_functions: &[&str]| {
+        let options = wasm_snip::Options {
+            functions: functions.iter().map(|f| f.to_string()).collect(),
+            stub_functions: stub_functions.iter().map(|f| f.to_string()).collect(),
+            ..Default::default()
+        };
+        wasm_snip::snip_bytes(&wasm, &options)
+    };
+
+    let snipped = snip(&["a", "b"], &["snipped_hit"]).unwrap();
+    let module = walrus::Module::from_buffer(&snipped).unwrap();
+    let table = module.tables.iter().next().unwrap();
+    let elements = match table.kind {
+        walrus::TableKind::Function(ref ft) => ft.elements.clone(),
+        _ => unreachable!(),
+    };
+    let id = |name: &str| {
+        module
+            .funcs
+            .iter()
+            .find(|f| f.name.as_deref() == Some(name))
+            .map(|f| f.id())
+    };
+    assert_eq!(elements, [id("snipped_hit"), id("snipped_hit"), id("c")]);
+    assert_eq!(module.funcs.iter().count(), 3);
+
+    // Every snipped function in a table needs a stub function of its type.
+    let error = snip(&["a", "c"], &["snipped_hit"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "none of the stub functions has the type `() -> i32` of `c`, which is in a table"
+    );
+    let error = snip(&["a"], &["missing"]).unwrap_err();
+    assert!(matches!(error.root(), wasm_snip::SnipError::NoMatches(_)));
+    let error = snip(&["a"], &["snipped_hit", "b"]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the stub functions `snipped_hit` and `b` have the same type"
+    );
+}
+
+#[test]
+fn table_null() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (import "env" "base" (global $base i32))
+            (func $a)
+            (func $b)
+            (func $c)
+            (table (export "table") 8 funcref)
+            (elem (i32.const 0) $a $b $c $a)
+            (elem (global.get $base) $b $a $a))
+        "#,
+    )
+    .unwrap();
+    let options = wasm_snip::Options {
+        functions: vec!["a".to_string()],
+        table_null: true,
+        ..Default::default()
+    };
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let outcome = wasm_snip::snip(&mut module, options.clone()).unwrap();
+    assert_eq!(outcome.table_slots, 4);
+    let snipped = wasm_snip::emit_wasm(&module, &options).unwrap();
+
+    // The segment at a constant offset is split around the empty elements,
+    // and the one at a global offset loses its trailing elements, so no stub
+    // is needed.
+    let module = walrus::Module::from_buffer(&snipped).unwrap();
+    let id = |name: &str| {
+        module
+            .funcs
+            .iter()
+            .find(|f| f.name.as_deref() == Some(name))
+            .map(|f| f.id())
+    };
+    let table = module.tables.iter().next().unwrap();
+    match table.kind {
+        walrus::TableKind::Function(ref ft) => {
+            assert_eq!(ft.elements, [None, id("b"), id("c")]);
+            assert_eq!(ft.relative_elements.len(), 1);
+            assert_eq!(ft.relative_elements[0].1, [id("b").unwrap()]);
+        }
+        _ => unreachable!(),
+    }
+    assert_eq!(table.initial, 8);
+    assert_eq!(module.funcs.iter().count(), 2);
+}
+
+#[test]
+fn shrink_tables() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (type $t (func))
+            (func $keep)
+            (func $a)
+            (func $b)
+            (func (export "call") (param i32)
+                local.get 0
+                call_indirect (type $t))
+            (table 8 funcref)
+            (elem (i32.const 0) $keep $a)
+            (elem (i32.const 4) $a $b))
+        "#,
+    )
+    .unwrap();
+    let snip = |shrink_tables: bool| {
+        let options = wasm_snip::Options {
+            functions: vec!["a".to_string(), "b".to_string()],
+            shrink_tables,
+            ..Default::default()
+        };
+        let snipped = wasm_snip::snip_bytes(&wasm, &options).unwrap();
+        walrus::Module::from_buffer(&snipped).unwrap()
+    };
+    let elements = |module: &walrus::Module| {
+        let table = module.tables.iter().next().unwrap();
+        match table.kind {
+            walrus::TableKind::Function(ref ft) => (table.initial, ft.elements.len()),
+            _ => unreachable!(),
+        }
+    };
+
+    assert_eq!(elements(&snip(false)), (8, 6));
+    // The second segment is all stubs, but the first still has `keep`.
+    assert_eq!(elements(&snip(true)), (2, 2));
+}
+
+#[test]
+fn gc_data() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "message") (result i32) i32.const 1024)
+            (func (export "table") (result i32) i32.const 1128)
+            (func (export "call_fail") call $fail)
+            (func $fail
+                i32.const 1056
+                i32.const 1088
+                drop
+                drop)
+            (data (i32.const 1024)
+                "kept: referred to by live code!!"
+                "dead: only the snipped code used"
+                "\48\04\00\00\20\00\00\00"
+                "dead: pointed to by dead table.."
+                "\70\04\00\00\20\00\00\00"
+                "live: pointed to by live table.."))
+        "#,
+    )
+    .unwrap();
+    let options = wasm_snip::Options {
+        functions: vec!["fail".to_string()],
+        gc_data: true,
+        ..Default::default()
+    };
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let outcome = wasm_snip::snip(&mut module, options.clone()).unwrap();
+    assert_eq!(outcome.zeroed_data, 67);
+    let snipped = wasm_snip::emit_wasm(&module, &options).unwrap();
+    wasmparser::validate(&snipped).unwrap();
+
+    // The zeroed data is left out, splitting the segment in two.
+    let mut segments = vec![];
+    for payload in wasmparser::Parser::new(0).parse_all(&snipped) {
+        if let wasmparser::Payload::DataSection(reader) = payload.unwrap() {
+            for data in reader {
+                let data = data.unwrap();
+                let offset = match data.kind {
+                    wasmparser::DataKind::Active { offset_expr, .. } => {
+                        match offset_expr.get_operators_reader().read().unwrap() {
+                            wasmparser::Operator::I32Const { value } => value,
+                            op => panic!("unexpected offset: {:?}", op),
+                        }
+                    }
+                    wasmparser::DataKind::Passive => panic!("unexpected passive segment"),
+                };
+                segments.push((offset, data.data.to_vec()));
+            }
+        }
+    }
+    assert_eq!(
+        segments,
+        [
+            (1024, b"kept: referred to by live code!!".to_vec()),
+            (
+                1128,
+                b"\x70\x04\0\0\x20\0\0\0live: pointed to by live table..".to_vec()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn snip_panic_strings() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (memory (export "memory") 1)
+            (func $_ZN4core9panicking5panic17h0123456789abcdefE (param i32 i32 i32)
+                unreachable)
+            (func (export "check") (param i32)
+                local.get 0
+                if
+                    i32.const 1024
+                    i32.const 16
+                    i32.const 1072
+                    call $_ZN4core9panicking5panic17h0123456789abcdefE
+                end
+                i32.const 1040
+                i32.const 16
+                i32.const 1072
+                call $_ZN4core9panicking5panic17h0123456789abcdefE)
+            (func (export "message") (result i32) i32.const 1040)
+            (data (i32.const 1024)
+                "only for a panic"
+                "also used by fn "
+                "................"
+                "\00\00\00\00\00\00\00\00"))
+        "#,
+    )
+    .unwrap();
+    let options = wasm_snip::Options {
+        snip_panic_strings: true,
+        ..Default::default()
+    };
+    let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+    let outcome = wasm_snip::snip(&mut module, options.clone()).unwrap();
+    assert_eq!(outcome.zeroed_data, 16);
+
+    // The message only a panic uses is empty now, and the other is kept.
+    let data = module.data.iter().next().unwrap();
+    assert_eq!(&data.value[..16], [0; 16]);
+    assert_eq!(&data.value[16..32], b"also used by fn ");
+    let snipped = wasm_snip::emit_wasm(&module, &options).unwrap();
+    wasmparser::validate(&snipped).unwrap();
+
+    struct Consts(Vec<i32>);
+
+    impl<'instr> walrus::ir::Visitor<'instr> for Consts {
+        fn visit_const(&mut self, instr: &walrus::ir::Const) {
+            if let walrus::ir::Value::I32(n) = instr.value {
+                self.0.push(n);
+            }
+        }
+    }
+
+    let check = module
+        .exports
+        .iter()
+        .find_map(|e| match e.item {
+            walrus::ExportItem::Function(f) if e.name == "check" => Some(f),
+            _ => None,
+        })
+        .unwrap();
+    let func = module.funcs.get(check).kind.unwrap_local();
+    let mut consts = Consts(vec![]);
+    walrus::ir::dfs_in_order(&mut consts, func, func.entry_block());
+    assert_eq!(consts.0, [1024, 0, 1072, 1040, 16, 1072]);
+}
+
+#[test]
+fn shrink_memory() {
+    let wasm = wat::parse_str(
+        r#"
+        (module
+            (memory (export "memory") 4 8)
+            (func (export "message") (result i32) i32.const 1024)
+            (func (export "bss") (result i32) i32.const 70000 i32.load)
+            (func (export "call_fail") (result i32) call $fail)
+            (func $fail (result i32) i32.const 131072)
+            (data (i32.const 1024) "kept: referred to by live code!!")
+            (data (i32.const 131072) "dead: only the snipped code used"))
+        "#,
+    )
+    .unwrap();
+    let snip = |shrink_memory| {
+        let options = wasm_snip::Options {
+            functions: vec!["fail".to_string()],
+            gc_data: true,
+            shrink_memory,
+            ..Default::default()
+        };
+        let mut module = walrus::Module::from_buffer(&wasm).unwrap();
+        wasm_snip::snip(&mut module, options.clone()).unwrap();
+        let snipped = wasm_snip::emit_wasm(&module, &options).unwrap();
+        wasmparser::validate(&snipped).unwrap();
+        let memory = module.memories.iter().next().unwrap();
+        (memory.initial, memory.maximum, module.data.iter().count())
+    };
+
+    assert_eq!(snip(false), (4, Some(8), 2));
+    // The data left ends in the first page, but live code uses the second.
+    assert_eq!(snip(true), (2, Some(8), 1));
+}
@@ -0,0 +1,31 @@
+#![cfg(target_arch = "wasm32")]
+#![no_std]
+
+use core::fmt::Write;
+
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn format_len(x: u32) -> usize {
+    let mut counter = Counter(0);
+    let _ = write!(counter, "{} {}", x, x as f32);
+    counter.0
+}
+
+#[no_mangle]
+pub extern "C" fn index(x: usize) -> u8 {
+    let a = [1u8, 2, 3];
+    a[x]
+}
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
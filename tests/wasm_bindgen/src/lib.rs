@@ -0,0 +1,30 @@
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    fn alert(s: &str);
+}
+
+#[wasm_bindgen]
+pub fn greet(name: &str) -> String {
+    alert(name);
+    format!("Hello, {}!", name)
+}
+
+#[wasm_bindgen]
+pub struct Counter(u32);
+
+#[wasm_bindgen]
+impl Counter {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Counter {
+        Counter(0)
+    }
+
+    pub fn incr(&mut self, v: JsValue) -> u32 {
+        if v.is_truthy() {
+            self.0 += 1;
+        }
+        self.0
+    }
+}